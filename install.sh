@@ -132,6 +132,15 @@ cp "$SCRIPT_DIR/migrations/"*.sql "$PREFIX/migrations/"
 if [[ -f "$SCRIPT_DIR/scripts/site-create.sh" ]]; then
   install -m 755 "$SCRIPT_DIR/scripts/site-create.sh" "$PREFIX/scripts/site-create.sh"
 fi
+if [[ -f "$SCRIPT_DIR/scripts/site-backup.sh" ]]; then
+  install -m 755 "$SCRIPT_DIR/scripts/site-backup.sh" "$PREFIX/scripts/site-backup.sh"
+fi
+if [[ -f "$SCRIPT_DIR/scripts/site-restore.sh" ]]; then
+  install -m 755 "$SCRIPT_DIR/scripts/site-restore.sh" "$PREFIX/scripts/site-restore.sh"
+fi
+if [[ -f "$SCRIPT_DIR/scripts/site-verify.sh" ]]; then
+  install -m 755 "$SCRIPT_DIR/scripts/site-verify.sh" "$PREFIX/scripts/site-verify.sh"
+fi
 if [[ -f "$SCRIPT_DIR/scripts/install-frankenphp.sh" ]]; then
   install -m 755 "$SCRIPT_DIR/scripts/install-frankenphp.sh" "$PREFIX/scripts/install-frankenphp.sh"
 fi
@@ -166,6 +175,10 @@ SITE_CREATE_SCRIPT=$PREFIX/scripts/site-create.sh
 SITE_DELETE_SCRIPT=$PREFIX/scripts/site-delete.sh
 DB_CREATE_SCRIPT=$PREFIX/scripts/db-create.sh
 DB_DELETE_SCRIPT=$PREFIX/scripts/db-delete.sh
+CERT_READ_SCRIPT=$PREFIX/scripts/cert-read.sh
+SITE_BACKUP_SCRIPT=$PREFIX/scripts/site-backup.sh
+SITE_RESTORE_SCRIPT=$PREFIX/scripts/site-restore.sh
+SITE_VERIFY_SCRIPT=$PREFIX/scripts/site-verify.sh
 EOF
   chmod 600 "$PREFIX/.env"
   echo "==> Wrote $PREFIX/.env with generated values"
@@ -232,11 +245,25 @@ if [[ -f "$PREFIX/scripts/site-create.sh" ]]; then
     [[ -f "$PREFIX/scripts/site-delete.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/site-delete.sh"
     [[ -f "$PREFIX/scripts/db-create.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/db-create.sh"
     [[ -f "$PREFIX/scripts/db-delete.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/db-delete.sh"
+    [[ -f "$PREFIX/scripts/cert-read.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/cert-read.sh"
+    [[ -f "$PREFIX/scripts/site-backup.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/site-backup.sh"
+    [[ -f "$PREFIX/scripts/site-restore.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/site-restore.sh"
+    [[ -f "$PREFIX/scripts/site-verify.sh" ]] && echo "$PANEL_USER ALL=(root) NOPASSWD: $PREFIX/scripts/site-verify.sh"
   } > "$SUDOERS_FILE"
   chmod 440 "$SUDOERS_FILE"
-  echo "==> Configured sudoers: $PANEL_USER may run site-create, site-delete, db-create, db-delete scripts"
+  echo "==> Configured sudoers: $PANEL_USER may run site-create, site-delete, db-create, db-delete, cert-read, site-backup, site-restore, site-verify scripts"
   mkdir -p /etc/caddy/sites
-  echo "==> Created /etc/caddy/sites (Caddy include dir for new sites)"
+  # The panel writes, validates and rolls back the per-site snippets itself
+  chown "$PANEL_USER:$PANEL_USER" /etc/caddy/sites
+  # Uploaded per-site certificates and keys (only the panel user and root can read them)
+  mkdir -p /etc/caddy/tls
+  chown "$PANEL_USER:$PANEL_USER" /etc/caddy/tls
+  chmod 700 /etc/caddy/tls
+  # Site backups (bundles contain database dumps and wp-config.php; the panel prunes them)
+  mkdir -p /var/backups/frankenphp-panel
+  chown "$PANEL_USER:$PANEL_USER" /var/backups/frankenphp-panel
+  chmod 700 /var/backups/frankenphp-panel
+  echo "==> Created /etc/caddy/sites (Caddy include dir for new sites, owned by $PANEL_USER)"
 fi
 
 # --- Install FrankenPHP (Caddy+PHP) so panel-created sites are served on 80/443 ---
@@ -0,0 +1,20 @@
+-- Provisioning jobs (site/database create and delete run in a background worker)
+CREATE TABLE IF NOT EXISTS jobs (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR(32) NOT NULL,
+    summary VARCHAR(512) NOT NULL,
+    payload JSONB NOT NULL,
+    state VARCHAR(16) NOT NULL DEFAULT 'queued',
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    site_id INTEGER REFERENCES sites(id) ON DELETE SET NULL,
+    stdout TEXT NOT NULL DEFAULT '',
+    stderr TEXT NOT NULL DEFAULT '',
+    error TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_state ON jobs(state, id);
+CREATE INDEX IF NOT EXISTS idx_jobs_user ON jobs(user_id, id DESC);
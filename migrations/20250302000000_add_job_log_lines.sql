@@ -0,0 +1,9 @@
+-- Provisioning output, one row per line, in the order it was produced
+CREATE TABLE IF NOT EXISTS job_log_lines (
+    job_id INTEGER NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    seq INTEGER NOT NULL,
+    stream VARCHAR(8) NOT NULL,
+    line TEXT NOT NULL,
+    logged_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (job_id, seq)
+);
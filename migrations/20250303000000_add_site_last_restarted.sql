@@ -0,0 +1,2 @@
+-- When the site was last restarted from the panel (Caddy reload / PHP worker restart)
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS last_restarted_at TIMESTAMPTZ;
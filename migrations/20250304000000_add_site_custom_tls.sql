@@ -0,0 +1,3 @@
+-- Custom (uploaded) TLS certificate per site; NULL means automatic ACME
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS tls_cert_path TEXT;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS tls_key_path TEXT;
@@ -0,0 +1,16 @@
+-- Extra hostnames per site, served as the same site or redirected to the canonical host
+CREATE TABLE IF NOT EXISTS site_aliases (
+    id SERIAL PRIMARY KEY,
+    site_id INTEGER NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+    hostname TEXT NOT NULL UNIQUE,
+    -- serve | redirect
+    mode TEXT NOT NULL DEFAULT 'serve',
+    -- 301 or 308 when mode = redirect
+    redirect_status INTEGER NOT NULL DEFAULT 301,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_site_aliases_site_id ON site_aliases(site_id);
+
+-- Which of www.<apex> / <apex> is canonical: domain (as entered, no counterpart), www, apex
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS canonical_host TEXT NOT NULL DEFAULT 'domain';
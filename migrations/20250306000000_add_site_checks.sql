@@ -0,0 +1,17 @@
+-- Results of the background health checker (one row per site per round)
+CREATE TABLE IF NOT EXISTS site_checks (
+    id BIGSERIAL PRIMARY KEY,
+    site_id INTEGER NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- online | degraded | offline
+    status TEXT NOT NULL,
+    -- HTTP status code; NULL when no response was received
+    status_code INTEGER,
+    latency_ms INTEGER,
+    -- Certificate/handshake problem, recorded even if the site answered
+    tls_error TEXT,
+    -- Connection error or timeout when there was no response
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_site_checks_site_checked ON site_checks(site_id, checked_at DESC);
@@ -0,0 +1,5 @@
+-- Per-site health check settings; NULL falls back to the defaults
+-- (HEALTH_CHECK_INTERVAL, any status below 500, no body keyword)
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS check_interval INTEGER;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS check_expected_status INTEGER;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS check_keyword TEXT;
@@ -0,0 +1,5 @@
+-- Panel user roles (admin | operator | readonly) and disabling accounts.
+-- Existing users keep full access; new users get an explicit role from /users.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'admin';
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'readonly';
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMPTZ;
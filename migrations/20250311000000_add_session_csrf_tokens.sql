@@ -0,0 +1,6 @@
+-- Per-session CSRF token; every form POST must send it back.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS csrf_token VARCHAR(64);
+
+-- Sessions from before this have no token, so they sign in again.
+DELETE FROM sessions WHERE csrf_token IS NULL;
+ALTER TABLE sessions ALTER COLUMN csrf_token SET NOT NULL;
@@ -0,0 +1,12 @@
+-- Sessions keep only a SHA-256 hash of the cookie token; the cookie also
+-- carries an HMAC made with PANEL_SESSION_SECRET. Existing rows hold plain
+-- tokens, so they are dropped and everyone signs in again.
+DELETE FROM sessions;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_name = 'sessions' AND column_name = 'token') THEN
+        ALTER TABLE sessions RENAME COLUMN token TO token_hash;
+    END IF;
+END $$;
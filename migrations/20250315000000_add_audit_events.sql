@@ -0,0 +1,23 @@
+-- Who did what to which site, database or user, from where, and how it ended.
+-- Usernames and target labels are copied so events outlive what they describe.
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    username VARCHAR(64),
+    client_ip TEXT,
+    via VARCHAR(8) NOT NULL CHECK (via IN ('web', 'api', 'cli')),
+    action VARCHAR(64) NOT NULL,
+    target_type VARCHAR(16),
+    target_id BIGINT,
+    target_label VARCHAR(255) NOT NULL DEFAULT '',
+    outcome VARCHAR(16) NOT NULL CHECK (outcome IN ('succeeded', 'failed', 'queued')),
+    job_id INTEGER REFERENCES jobs(id) ON DELETE SET NULL,
+    exit_status INTEGER,
+    detail TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_events_created ON audit_events(created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_audit_events_user ON audit_events(user_id);
+CREATE INDEX IF NOT EXISTS idx_audit_events_target ON audit_events(target_type, target_id);
+CREATE INDEX IF NOT EXISTS idx_audit_events_job ON audit_events(job_id) WHERE job_id IS NOT NULL;
@@ -0,0 +1,23 @@
+-- Per-site backup schedule and retention, and one row per backup bundle.
+-- A backup's state and duration are those of the job that writes it.
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS backup_frequency VARCHAR(16) NOT NULL DEFAULT 'off'
+    CHECK (backup_frequency IN ('off', 'daily', 'weekly'));
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS backup_hour INTEGER NOT NULL DEFAULT 3
+    CHECK (backup_hour BETWEEN 0 AND 23);
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS backup_keep_daily INTEGER NOT NULL DEFAULT 7;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS backup_keep_weekly INTEGER NOT NULL DEFAULT 4;
+
+-- Backups outlive their site (site_id becomes NULL) so a deleted site can still be restored
+CREATE TABLE IF NOT EXISTS site_backups (
+    id SERIAL PRIMARY KEY,
+    site_id INTEGER REFERENCES sites(id) ON DELETE SET NULL,
+    domain VARCHAR(255) NOT NULL,
+    job_id INTEGER NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    scheduled BOOLEAN NOT NULL DEFAULT FALSE,
+    archive_path TEXT NOT NULL UNIQUE,
+    size_bytes BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_site_backups_site ON site_backups(site_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_site_backups_job ON site_backups(job_id);
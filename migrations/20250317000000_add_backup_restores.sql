@@ -0,0 +1,3 @@
+-- Snapshots taken by a restore before it overwrites the site. Their job is the
+-- restore's, and retention leaves them alone.
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS before_restore BOOLEAN NOT NULL DEFAULT FALSE;
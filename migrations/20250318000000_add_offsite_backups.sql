@@ -0,0 +1,6 @@
+-- Off-site copies in the S3 bucket of PANEL_S3_*: the object key and the
+-- SHA-256 checked after upload and download. Downloaded rows are bundles
+-- fetched back from the bucket; like snapshots, retention leaves them alone.
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS remote_key TEXT;
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS sha256 VARCHAR(64);
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS downloaded BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Backups moved into the chunk store (PANEL_BACKUP_DEDUP): bytes of the
+-- chunks the backup added; size_bytes stays the size of the bundle.
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS stored_bytes BIGINT;
@@ -0,0 +1,6 @@
+-- Test restores of a backup (PANEL_BACKUP_VERIFY_DAYS or "Verify"): the job
+-- of the latest one and its outcome, set once that job has run.
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS verify_job_id INTEGER REFERENCES jobs(id) ON DELETE SET NULL;
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS verified_at TIMESTAMPTZ;
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS verify_passed BOOLEAN;
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS verify_message TEXT;
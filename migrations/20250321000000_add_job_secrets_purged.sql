@@ -0,0 +1,3 @@
+-- When the passwords in a job's payload were discarded because it failed or
+-- never ran. Such a job cannot be retried.
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS secrets_purged_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Refreshed while a job runs; a running job whose heartbeat stopped was
+-- abandoned by a panel or CLI process that died, and is failed.
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS heartbeat_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Why the off-site copy of a backup could not be made. The backup itself
+-- stays good on this server.
+ALTER TABLE site_backups ADD COLUMN IF NOT EXISTS upload_error TEXT;
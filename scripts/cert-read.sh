@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Print the certificates Caddy stores for one name (no private keys), for the panel's SSL Details tab.
+# Usage: sudo ./cert-read.sh <name>     (name as stored by Caddy, e.g. example.com or wildcard_.example.com)
+# Output: "==> <path>" followed by the PEM, for every issuer holding a certificate for <name>.
+
+set -e
+
+if [[ $# -ne 1 ]]; then
+  echo "Usage: $0 <name>" >&2
+  exit 1
+fi
+
+NAME="$1"
+if [[ ! "$NAME" =~ ^[A-Za-z0-9._-]+$ ]] || [[ "$NAME" == .* ]]; then
+  echo "Error: invalid certificate name: $NAME" >&2
+  exit 1
+fi
+
+CADDY_DATA_DIR="${CADDY_DATA_DIR:-/var/lib/caddy}"
+
+shopt -s nullglob
+for CRT in "$CADDY_DATA_DIR"/certificates/*/"$NAME"/"$NAME".crt; do
+  echo "==> $CRT"
+  cat "$CRT"
+done
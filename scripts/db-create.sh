@@ -43,9 +43,10 @@ else
   GRANT_SQL="GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'localhost'; GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'127.0.0.1';"
 fi
 
-CMD="CREATE DATABASE IF NOT EXISTS \`$DB_NAME\`; CREATE USER IF NOT EXISTS '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; CREATE USER IF NOT EXISTS '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; $GRANT_SQL FLUSH PRIVILEGES;"
+CMD="CREATE DATABASE IF NOT EXISTS \`$DB_NAME\`; CREATE USER IF NOT EXISTS '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; CREATE USER IF NOT EXISTS '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; ALTER USER '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; ALTER USER '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; $GRANT_SQL FLUSH PRIVILEGES;"
 
-if ! mysql -u root -e "$CMD" 2>"$MYSQL_ERR"; then
+# On stdin, not in arguments: ps shows those, and the SQL holds a password.
+if ! printf '%s\n' "$CMD" | mysql -u root 2>"$MYSQL_ERR"; then
   echo "Error: Could not create database or user." >&2
   cat "$MYSQL_ERR" >&2
   rm -f "$MYSQL_ERR"
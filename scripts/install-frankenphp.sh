@@ -56,6 +56,8 @@ Requires=network-online.target
 Type=simple
 User=root
 Group=root
+# Keep certificates in /var/lib/caddy (not /root) so the panel can inspect them
+Environment=XDG_DATA_HOME=/var/lib
 ExecStartPre=/usr/local/bin/frankenphp validate --config /etc/caddy/Caddyfile
 ExecStart=/usr/local/bin/frankenphp run --config /etc/caddy/Caddyfile
 ExecReload=/usr/local/bin/frankenphp reload --config /etc/caddy/Caddyfile --force
@@ -0,0 +1,58 @@
+#!/usr/bin/env bash
+# Write a site backup: a gzipped tar with manifest.json, one dump per database
+# under databases/ and the site folder under files/.
+# The archive is written next to its final name and renamed when complete; it
+# gets the owner of its directory (the panel user), so the panel can prune it.
+# Usage: sudo ./site-backup.sh <archive> <site_path> <manifest_json> [db_name]...
+# Optional: set MYSQL_ROOT_PASSWORD if MariaDB root has a password.
+
+set -euo pipefail
+
+if [[ $# -lt 3 ]]; then
+  echo "Usage: $0 <archive> <site_path> <manifest_json> [db_name]..." >&2
+  exit 1
+fi
+
+ARCHIVE="$1"
+SITE_PATH="$2"
+MANIFEST="$3"
+shift 3
+
+if [[ ! -d "$SITE_PATH" ]]; then
+  echo "Error: site folder $SITE_PATH does not exist." >&2
+  exit 1
+fi
+
+DEST_DIR=$(dirname "$ARCHIVE")
+mkdir -p "$DEST_DIR"
+STAGING=$(mktemp -d "$DEST_DIR/.backup.XXXXXX")
+trap 'rm -rf "$STAGING" "$ARCHIVE.partial"' EXIT
+
+mkdir "$STAGING/databases"
+printf '%s\n' "$MANIFEST" > "$STAGING/manifest.json"
+
+if [[ $# -gt 0 ]]; then
+  if ! command -v mysqldump &>/dev/null; then
+    echo "Error: mysqldump not found. Install the MariaDB/MySQL client." >&2
+    exit 1
+  fi
+  if [[ -n "${MYSQL_ROOT_PASSWORD:-}" ]]; then
+    export MYSQL_PWD="$MYSQL_ROOT_PASSWORD"
+  fi
+  for DB_NAME in "$@"; do
+    if ! mysqldump -u root --single-transaction --quick --routines --triggers "$DB_NAME" > "$STAGING/databases/$DB_NAME.sql"; then
+      echo "Error: Could not dump database $DB_NAME." >&2
+      exit 1
+    fi
+    echo "Dumped database $DB_NAME ($(du -h "$STAGING/databases/$DB_NAME.sql" | cut -f1))"
+  done
+fi
+
+tar -czf "$ARCHIVE.partial" -C "$STAGING" manifest.json databases \
+  -C "$SITE_PATH" --transform 's,^\.,files,S' .
+echo "Archived $SITE_PATH"
+
+chown --reference="$DEST_DIR" "$ARCHIVE.partial"
+chmod 600 "$ARCHIVE.partial"
+mv "$ARCHIVE.partial" "$ARCHIVE"
+echo "Backup written: $ARCHIVE ($(du -h "$ARCHIVE" | cut -f1))"
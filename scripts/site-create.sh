@@ -1,5 +1,6 @@
 #!/usr/bin/env bash
-# Create site directory and Caddy config, optionally install WordPress.
+# Create site directory, optionally install WordPress.
+# The Caddy snippet is written, validated and reloaded by the panel afterwards.
 # Usage: sudo ./site-create.sh <domain> <site_path> [install_wordpress] [wp_title] [wp_admin_user] [wp_admin_password] [wp_admin_email] [php_version]
 # When install_wordpress=1, args 4–7 are WordPress site title, admin user, password, email (for wp core install).
 # Optional: set MYSQL_ROOT_PASSWORD if MariaDB root has a password (e.g. in panel’s environment).
@@ -20,8 +21,6 @@ WP_ADMIN_PASS="${6:-}"
 WP_ADMIN_EMAIL="${7:-}"
 PHP_VERSION="${8:-8.2}"
 WEB_USER="${WEB_USER:-www-data}"
-CADDY_SITES_DIR="${CADDY_SITES_DIR:-/etc/caddy/sites}"
-CADDYFILE="${CADDYFILE:-/etc/caddy/Caddyfile}"
 
 # Create site directory and set ownership
 mkdir -p "$SITE_PATH"
@@ -53,13 +52,15 @@ if [[ "$INSTALL_WORDPRESS" == "1" ]]; then
   DB_NAME="${DB_NAME:0:64}"
   DB_USER="${DB_NAME:0:32}"
   DB_PASS=$(openssl rand -base64 24 | tr -dc 'a-zA-Z0-9' | head -c 24)
-  # Create DB and user if not exists (idempotent: safe when DB/user already exist from a previous attempt)
-  MYSQL_CMD="CREATE DATABASE IF NOT EXISTS \`$DB_NAME\`; CREATE USER IF NOT EXISTS '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; CREATE USER IF NOT EXISTS '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'localhost'; GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'127.0.0.1'; FLUSH PRIVILEGES;"
+  # Create DB and user if not exists (idempotent: safe when DB/user already exist from a previous attempt);
+  # ALTER USER gives a user left over from one this run's password, which wp-config.php gets
+  MYSQL_CMD="CREATE DATABASE IF NOT EXISTS \`$DB_NAME\`; CREATE USER IF NOT EXISTS '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; CREATE USER IF NOT EXISTS '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; ALTER USER '$DB_USER'@'localhost' IDENTIFIED BY '$DB_PASS'; ALTER USER '$DB_USER'@'127.0.0.1' IDENTIFIED BY '$DB_PASS'; GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'localhost'; GRANT ALL PRIVILEGES ON \`$DB_NAME\`.* TO '$DB_USER'@'127.0.0.1'; FLUSH PRIVILEGES;"
   MYSQL_ERR=$(mktemp)
   if [[ -n "${MYSQL_ROOT_PASSWORD:-}" ]]; then
     export MYSQL_PWD="$MYSQL_ROOT_PASSWORD"
   fi
-  if ! mysql -u root -e "$MYSQL_CMD" 2>"$MYSQL_ERR"; then
+  # On stdin, not in arguments: ps shows those, and the SQL holds a password.
+  if ! printf '%s\n' "$MYSQL_CMD" | mysql -u root 2>"$MYSQL_ERR"; then
     echo "Error: Could not create MySQL database for WordPress." >&2
     cat "$MYSQL_ERR" >&2
     rm -f "$MYSQL_ERR"
@@ -113,33 +114,4 @@ require_once ABSPATH . 'wp-settings.php';
     fi
 fi
 
-# Caddy snippet: FrankenPHP php_server with root
-mkdir -p "$CADDY_SITES_DIR"
-SAFE_DOMAIN="${DOMAIN//\*/_}"
-CONF_FILE="$CADDY_SITES_DIR/${SAFE_DOMAIN}.conf"
-cat > "$CONF_FILE" << EOF
-# Generated by FrankenPHP Panel – do not edit manually (PHP: ${PHP_VERSION:-8.2})
-$DOMAIN {
-	root * $SITE_PATH
-	encode zstd gzip
-	php_server
-	file_server
-}
-EOF
-
-# Reload Caddy/FrankenPHP so the new site is live
-if [[ -n "$CADDY_RELOAD_CMD" ]]; then
-  eval "$CADDY_RELOAD_CMD"
-elif systemctl is-active --quiet frankenphp 2>/dev/null; then
-  systemctl reload frankenphp 2>/dev/null || true
-elif systemctl is-active --quiet caddy 2>/dev/null; then
-  systemctl reload caddy 2>/dev/null || true
-elif command -v frankenphp &>/dev/null && [[ -f "$CADDYFILE" ]]; then
-  frankenphp reload --config "$CADDYFILE" --force 2>/dev/null || true
-elif command -v caddy &>/dev/null && [[ -f "$CADDYFILE" ]]; then
-  caddy reload --config "$CADDYFILE" 2>/dev/null || true
-else
-  echo "Warning: Caddy/FrankenPHP reload skipped (install FrankenPHP or set CADDY_RELOAD_CMD)." >&2
-fi
-
 echo "Site created: $DOMAIN -> $SITE_PATH"
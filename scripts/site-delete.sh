@@ -1,5 +1,6 @@
 #!/usr/bin/env bash
-# Remove site directory, Caddy config, and optionally drop MariaDB databases.
+# Remove site directory and optionally drop MariaDB databases.
+# The panel removes the Caddy snippet and reloads before running this.
 # Usage: sudo ./site-delete.sh <domain> <site_path> [db_name1 db_user1] [db_name2 db_user2] ...
 # Optional: set MYSQL_ROOT_PASSWORD if MariaDB root has a password.
 
@@ -13,16 +14,6 @@ fi
 DOMAIN="$1"
 SITE_PATH="$2"
 shift 2
-CADDY_SITES_DIR="${CADDY_SITES_DIR:-/etc/caddy/sites}"
-CADDYFILE="${CADDYFILE:-/etc/caddy/Caddyfile}"
-
-# Remove Caddy snippet so the site is no longer served
-SAFE_DOMAIN="${DOMAIN//\*/_}"
-CONF_FILE="$CADDY_SITES_DIR/${SAFE_DOMAIN}.conf"
-if [[ -f "$CONF_FILE" ]]; then
-  rm -f "$CONF_FILE"
-  echo "Removed Caddy config: $CONF_FILE"
-fi
 
 # Remove site directory and all files
 if [[ -d "$SITE_PATH" ]]; then
@@ -46,19 +37,4 @@ if [[ $# -ge 2 ]] && command -v mysql &>/dev/null; then
   [[ -n "${MYSQL_PWD:-}" ]] && unset MYSQL_PWD
 fi
 
-# Reload Caddy/FrankenPHP so the site is no longer active
-if [[ -n "$CADDY_RELOAD_CMD" ]]; then
-  eval "$CADDY_RELOAD_CMD"
-elif systemctl is-active --quiet frankenphp 2>/dev/null; then
-  systemctl reload frankenphp 2>/dev/null || true
-elif systemctl is-active --quiet caddy 2>/dev/null; then
-  systemctl reload caddy 2>/dev/null || true
-elif command -v frankenphp &>/dev/null && [[ -f "$CADDYFILE" ]]; then
-  frankenphp reload --config "$CADDYFILE" --force 2>/dev/null || true
-elif command -v caddy &>/dev/null && [[ -f "$CADDYFILE" ]]; then
-  caddy reload --config "$CADDYFILE" 2>/dev/null || true
-else
-  echo "Warning: Caddy/FrankenPHP reload skipped." >&2
-fi
-
 echo "Site removed: $DOMAIN"
@@ -0,0 +1,111 @@
+#!/usr/bin/env bash
+# Restore a site backup written by site-backup.sh: re-import its databases and
+# replace the site folder with the bundle's files/.
+# The new files are unpacked next to the site folder and swapped in with a
+# rename; the previous folder is removed once the swap succeeded.
+# wp_db_*: when set, the DB_NAME/DB_USER/DB_PASSWORD lines of the restored
+# wp-config.php are rewritten to them. old_domain/new_domain: when set,
+# WordPress URLs are rewritten with wp search-replace (needs wp-cli).
+# Each from_db:into_db imports databases/<from_db>.sql into <into_db>, which is
+# dropped and recreated first.
+# Usage: sudo ./site-restore.sh <archive> <site_path> <wp_db_name> <wp_db_user> <wp_db_password> <old_domain> <new_domain> [from_db:into_db]...
+# Optional: set MYSQL_ROOT_PASSWORD if MariaDB root has a password; WEB_USER (default www-data).
+
+set -euo pipefail
+
+if [[ $# -lt 7 ]]; then
+  echo "Usage: $0 <archive> <site_path> <wp_db_name> <wp_db_user> <wp_db_password> <old_domain> <new_domain> [from_db:into_db]..." >&2
+  exit 1
+fi
+
+ARCHIVE="$1"
+SITE_PATH="${2%/}"
+WP_DB_NAME="$3"
+WP_DB_USER="$4"
+WP_DB_PASS="$5"
+OLD_DOMAIN="$6"
+NEW_DOMAIN="$7"
+shift 7
+WEB_USER="${WEB_USER:-www-data}"
+
+if [[ ! -f "$ARCHIVE" ]]; then
+  echo "Error: backup $ARCHIVE does not exist." >&2
+  exit 1
+fi
+
+PARENT=$(dirname "$SITE_PATH")
+NAME=$(basename "$SITE_PATH")
+STAGING="$PARENT/.$NAME.restore"
+PREVIOUS="$PARENT/.$NAME.previous"
+rm -rf "$STAGING" "$PREVIOUS"
+mkdir -p "$STAGING"
+trap 'rm -rf "$STAGING" "$PREVIOUS"' EXIT
+
+# GNU tar drops leading / and refuses members with .. when extracting
+tar -xzf "$ARCHIVE" -C "$STAGING" --no-same-owner
+if [[ ! -f "$STAGING/manifest.json" ]]; then
+  echo "Error: $ARCHIVE is not a site backup (no manifest.json)." >&2
+  exit 1
+fi
+echo "Unpacked $ARCHIVE"
+
+if [[ $# -gt 0 ]]; then
+  if ! command -v mysql &>/dev/null; then
+    echo "Error: mysql client not found. Install the MariaDB/MySQL client." >&2
+    exit 1
+  fi
+  if [[ -n "${MYSQL_ROOT_PASSWORD:-}" ]]; then
+    export MYSQL_PWD="$MYSQL_ROOT_PASSWORD"
+  fi
+  for PAIR in "$@"; do
+    FROM="${PAIR%%:*}"
+    INTO="${PAIR#*:}"
+    DUMP="$STAGING/databases/$FROM.sql"
+    if [[ ! -f "$DUMP" ]]; then
+      echo "Error: the backup has no dump of database $FROM." >&2
+      exit 1
+    fi
+    if ! mysql -u root -e "DROP DATABASE IF EXISTS \`$INTO\`; CREATE DATABASE \`$INTO\`;"; then
+      echo "Error: Could not recreate database $INTO." >&2
+      exit 1
+    fi
+    if ! mysql -u root "$INTO" < "$DUMP"; then
+      echo "Error: Could not import into database $INTO." >&2
+      exit 1
+    fi
+    echo "Imported database $FROM into $INTO"
+  done
+fi
+
+mkdir -p "$STAGING/files"
+WP_CONFIG="$STAGING/files/wp-config.php"
+if [[ -n "$WP_DB_NAME" && -f "$WP_CONFIG" ]]; then
+  # Escape for a PHP single-quoted string, then for the sed replacement
+  quote() { printf '%s' "$1" | sed -e 's/[\\'"'"']/\\&/g' -e 's/[\\&|]/\\&/g'; }
+  sed -i -E \
+    -e "s|^\s*define\(\s*['\"]DB_NAME['\"]\s*,.*$|define('DB_NAME', '$(quote "$WP_DB_NAME")');|" \
+    -e "s|^\s*define\(\s*['\"]DB_USER['\"]\s*,.*$|define('DB_USER', '$(quote "$WP_DB_USER")');|" \
+    -e "s|^\s*define\(\s*['\"]DB_PASSWORD['\"]\s*,.*$|define('DB_PASSWORD', '$(quote "$WP_DB_PASS")');|" \
+    "$WP_CONFIG"
+  echo "Pointed wp-config.php at database $WP_DB_NAME"
+fi
+
+if [[ -e "$SITE_PATH" ]]; then
+  mv "$SITE_PATH" "$PREVIOUS"
+fi
+if ! mv "$STAGING/files" "$SITE_PATH"; then
+  [[ -e "$PREVIOUS" ]] && mv "$PREVIOUS" "$SITE_PATH"
+  echo "Error: could not move the restored files into place." >&2
+  exit 1
+fi
+chown -R "$WEB_USER:$WEB_USER" "$SITE_PATH"
+echo "Restored files into $SITE_PATH"
+
+if [[ -n "$OLD_DOMAIN" && -f "$SITE_PATH/wp-config.php" ]]; then
+  if command -v wp &>/dev/null && wp search-replace "//$OLD_DOMAIN" "//$NEW_DOMAIN" \
+    --all-tables --skip-columns=guid --path="$SITE_PATH" --allow-root; then
+    echo "Replaced $OLD_DOMAIN with $NEW_DOMAIN in the WordPress database."
+  else
+    echo "Warning: could not run wp search-replace; WordPress URLs still point to $OLD_DOMAIN." >&2
+  fi
+fi
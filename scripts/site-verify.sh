@@ -0,0 +1,92 @@
+#!/usr/bin/env bash
+# Test-restore a backup written by site-backup.sh without touching the site:
+# unpack it into a temporary directory and import each database dump into a
+# scratch database, which is dropped afterwards.
+# Fails if the archive is incomplete or corrupt, a dump is missing or does not
+# import, or (wordpress=1) wp-config.php or a WordPress core file is missing.
+# Usage: sudo ./site-verify.sh <archive> <scratch_db> <wordpress 0|1> [db_name]...
+# Optional: set MYSQL_ROOT_PASSWORD if MariaDB root has a password.
+
+set -euo pipefail
+
+if [[ $# -lt 3 ]]; then
+  echo "Usage: $0 <archive> <scratch_db> <wordpress 0|1> [db_name]..." >&2
+  exit 1
+fi
+
+ARCHIVE="$1"
+SCRATCH_DB="$2"
+WORDPRESS="$3"
+shift 3
+
+if [[ ! -f "$ARCHIVE" ]]; then
+  echo "Error: backup $ARCHIVE does not exist." >&2
+  exit 1
+fi
+
+STAGING=$(mktemp -d /tmp/panel-verify.XXXXXXXX)
+cleanup() {
+  rm -rf "$STAGING"
+  if [[ -n "${IMPORTED:-}" ]]; then
+    mysql -u root -e "DROP DATABASE IF EXISTS \`$SCRATCH_DB\`;" || true
+  fi
+}
+trap cleanup EXIT
+
+if ! gzip -t "$ARCHIVE" 2>/dev/null; then
+  echo "Error: the bundle is incomplete or corrupt (gzip check failed)." >&2
+  exit 1
+fi
+# GNU tar drops leading / and refuses members with .. when extracting
+if ! tar -xzf "$ARCHIVE" -C "$STAGING" --no-same-owner; then
+  echo "Error: the bundle is incomplete or corrupt (tar could not unpack it)." >&2
+  exit 1
+fi
+if [[ ! -f "$STAGING/manifest.json" ]]; then
+  echo "Error: $ARCHIVE is not a site backup (no manifest.json)." >&2
+  exit 1
+fi
+echo "Unpacked $ARCHIVE into $STAGING"
+
+if [[ $# -gt 0 ]]; then
+  if ! command -v mysql &>/dev/null; then
+    echo "Error: mysql client not found. Install the MariaDB/MySQL client." >&2
+    exit 1
+  fi
+  if [[ -n "${MYSQL_ROOT_PASSWORD:-}" ]]; then
+    export MYSQL_PWD="$MYSQL_ROOT_PASSWORD"
+  fi
+  IMPORTED=1
+  for DB in "$@"; do
+    DUMP="$STAGING/databases/$DB.sql"
+    if [[ ! -f "$DUMP" ]]; then
+      echo "Error: the backup has no dump of database $DB." >&2
+      exit 1
+    fi
+    echo "Importing the dump of $DB into scratch database $SCRATCH_DB"
+    if ! mysql -u root -e "DROP DATABASE IF EXISTS \`$SCRATCH_DB\`; CREATE DATABASE \`$SCRATCH_DB\`;"; then
+      echo "Error: Could not recreate database $SCRATCH_DB." >&2
+      exit 1
+    fi
+    if ! mysql -u root "$SCRATCH_DB" < "$DUMP"; then
+      echo "Error: Could not import into database $SCRATCH_DB." >&2
+      exit 1
+    fi
+    echo "Dump of $DB imports cleanly"
+  done
+fi
+
+if [[ "$WORDPRESS" == "1" ]]; then
+  MISSING=()
+  for F in wp-config.php index.php wp-load.php wp-settings.php wp-includes/version.php wp-admin/index.php; do
+    [[ -f "$STAGING/files/$F" ]] || MISSING+=("$F")
+  done
+  if [[ ${#MISSING[@]} -gt 0 ]]; then
+    MISSING_LIST=$(printf '%s, ' "${MISSING[@]}")
+    echo "Error: WordPress files missing from the backup: ${MISSING_LIST%, }." >&2
+    exit 1
+  fi
+  echo "wp-config.php and the WordPress core files are present"
+fi
+
+echo "Backup verified"
@@ -0,0 +1,705 @@
+//! Site, database and user changes shared by the HTML forms, the `/api/v1`
+//! JSON API and the CLI: validation, uniqueness checks and enqueuing the
+//! provisioning job.
+//!
+//! Invalid input comes back as the same per-field errors the forms render,
+//! so every entry point accepts exactly the same values.
+
+use validator::Validate;
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::jobs::JobPayload;
+use crate::models::{
+    validate_db_identifier, validate_domain, AddSiteForm, CreateDatabaseForm, Site, SiteAlias,
+    SiteDatabase, SiteDetail, User, SITE_ALIAS_COLUMNS, SITE_COLUMNS, SITE_DATABASE_COLUMNS,
+};
+use crate::provision::{DatabaseRef, NewDatabase, NewSite, WordPressInstall};
+use crate::state::AppState;
+use crate::templates::{AddDatabaseErrors, AddSiteErrors, AddUserErrors};
+
+/// PHP version used when the form leaves it empty.
+pub const DEFAULT_PHP_VERSION: &str = "8.2";
+
+pub fn site_php_version(form: &AddSiteForm) -> String {
+    form.php_version
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .unwrap_or(DEFAULT_PHP_VERSION)
+        .to_string()
+}
+
+fn validate_wp_fields(form: &AddSiteForm) -> AddSiteErrors {
+    let mut e = AddSiteErrors::default();
+    let title = form.wp_title.as_deref().unwrap_or("").trim();
+    let user = form.wp_admin_user.as_deref().unwrap_or("").trim();
+    let pass = form.wp_admin_password.as_deref().unwrap_or("");
+    let email = form.wp_admin_email.as_deref().unwrap_or("").trim();
+    if title.is_empty() {
+        e.wp_title = "Site title is required.".to_string();
+    }
+    if user.is_empty() {
+        e.wp_admin_user = "Admin username is required.".to_string();
+    } else if user.len() > 60 || !user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        e.wp_admin_user = "Username: letters, numbers, and underscores only (max 60).".to_string();
+    }
+    if pass.len() < 8 {
+        e.wp_admin_password = "Password must be at least 8 characters.".to_string();
+    }
+    if email.is_empty() {
+        e.wp_admin_email = "Admin email is required.".to_string();
+    } else if !email.contains('@')
+        || !email
+            .split('@')
+            .nth(1)
+            .is_some_and(|after| after.contains('.'))
+    {
+        e.wp_admin_email = "Enter a valid email address.".to_string();
+    }
+    e
+}
+
+/// Whether a site or alias already uses `domain`, or a site uses `folder_path`.
+pub async fn site_taken(pool: &DbPool, domain: &str, folder_path: &str) -> Result<bool> {
+    let taken = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM sites WHERE domain = $1 OR folder_path = $2 \
+         UNION ALL SELECT site_id FROM site_aliases WHERE hostname = $1",
+    )
+    .bind(domain)
+    .bind(folder_path)
+    .fetch_optional(pool)
+    .await?;
+    Ok(taken.is_some())
+}
+
+/// Validate an Add Site form and queue the `create_site` job. Returns the job id.
+pub async fn create_site(
+    state: &AppState,
+    actor: &Actor,
+    form: &AddSiteForm,
+) -> Result<std::result::Result<i32, AddSiteErrors>> {
+    if let Err(e) = form.validate() {
+        let msg = e
+            .field_errors()
+            .get("domain")
+            .and_then(|errs| errs.first())
+            .map(|err| {
+                err.message
+                    .as_ref()
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "Invalid".to_string())
+            });
+        if let Some(msg) = msg {
+            return Ok(Err(AddSiteErrors {
+                domain: msg,
+                ..Default::default()
+            }));
+        }
+    }
+    if let Err(msg) = validate_domain(&form.domain) {
+        return Ok(Err(AddSiteErrors {
+            domain: msg,
+            ..Default::default()
+        }));
+    }
+
+    let install_wp = form.install_wordpress.as_deref() == Some("1");
+    if install_wp {
+        let wp_errors = validate_wp_fields(form);
+        if !wp_errors.is_empty() {
+            return Ok(Err(wp_errors));
+        }
+    }
+
+    let domain = form.domain.trim().to_string();
+    let new_site = NewSite {
+        folder_path: format!("/var/www/{}", domain),
+        domain,
+        php_version: site_php_version(form),
+        wordpress: install_wp.then(|| WordPressInstall {
+            title: form.wp_title.clone().unwrap_or_default(),
+            admin_user: form.wp_admin_user.clone().unwrap_or_default(),
+            admin_password: form.wp_admin_password.clone().unwrap_or_default(),
+            admin_email: form.wp_admin_email.clone().unwrap_or_default(),
+        }),
+    };
+    if site_taken(&state.pool, &new_site.domain, &new_site.folder_path).await? {
+        return Ok(Err(AddSiteErrors {
+            folder_path:
+                "A site or alias with this domain, or a site with this path, already exists."
+                    .to_string(),
+            ..Default::default()
+        }));
+    }
+
+    let target = Target::site(None, &new_site.domain);
+    let job_id = state
+        .jobs
+        .enqueue(
+            &state.pool,
+            actor.require_user()?,
+            JobPayload::CreateSite { site: new_site },
+        )
+        .await?;
+    Event::new("site.create")
+        .target(target)
+        .queued(job_id)
+        .record(&state.pool, actor)
+        .await;
+    Ok(Ok(job_id))
+}
+
+/// A site with its databases and aliases.
+pub async fn site_detail(pool: &DbPool, site_id: i32) -> Result<SiteDetail> {
+    let site =
+        sqlx::query_as::<_, Site>(&format!("SELECT {} FROM sites WHERE id = $1", SITE_COLUMNS))
+            .bind(site_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(AppError::NotFound("Site not found.".into()))?;
+    let databases = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE site_id = $1 ORDER BY db_name",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(pool)
+    .await?;
+    let aliases = sqlx::query_as::<_, SiteAlias>(&format!(
+        "SELECT {} FROM site_aliases WHERE site_id = $1 ORDER BY hostname",
+        SITE_ALIAS_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(SiteDetail {
+        site,
+        databases,
+        aliases,
+    })
+}
+
+/// Queue the `delete_site` job for a site and all of its databases.
+pub async fn delete_site(state: &AppState, actor: &Actor, site_id: i32) -> Result<i32> {
+    let site =
+        sqlx::query_as::<_, Site>(&format!("SELECT {} FROM sites WHERE id = $1", SITE_COLUMNS))
+            .bind(site_id)
+            .fetch_optional(&state.pool)
+            .await?
+            .ok_or(AppError::NotFound("Site not found.".into()))?;
+
+    let databases = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE site_id = $1",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let db_refs: Vec<DatabaseRef> = databases
+        .into_iter()
+        .map(|db| DatabaseRef {
+            db_name: db.db_name,
+            db_user: db.db_user,
+        })
+        .collect();
+    let target = Target::site(Some(site.id), &site.domain);
+    let job_id = state
+        .jobs
+        .enqueue(
+            &state.pool,
+            actor.require_user()?,
+            JobPayload::DeleteSite {
+                site_id: site.id,
+                domain: site.domain,
+                folder_path: site.folder_path,
+                databases: db_refs,
+            },
+        )
+        .await?;
+    Event::new("site.delete")
+        .target(target)
+        .queued(job_id)
+        .record(&state.pool, actor)
+        .await;
+    Ok(job_id)
+}
+
+pub fn normalize_privileges(privileges: Option<&str>) -> String {
+    match privileges.map(str::trim).unwrap_or("") {
+        "readonly" => "readonly".to_string(),
+        _ => "full".to_string(),
+    }
+}
+
+/// Validate an Add Database form and queue the `create_database` job. Returns the job id.
+pub async fn create_database(
+    state: &AppState,
+    actor: &Actor,
+    form: &CreateDatabaseForm,
+) -> Result<std::result::Result<i32, AddDatabaseErrors>> {
+    let mut errors = AddDatabaseErrors::default();
+    let site_id: i32 = form.site_id.trim().parse().unwrap_or(0);
+
+    if site_id == 0 {
+        errors.site_id = "Please select a site.".to_string();
+    }
+    if let Err(msg) = validate_db_identifier(&form.database_name, "Database name") {
+        errors.database_name = msg;
+    }
+    if let Err(msg) = validate_db_identifier(&form.username, "Username") {
+        errors.username = msg;
+    }
+    if form.database_name.len() > 64 {
+        errors.database_name = "Database name must be 64 characters or less.".to_string();
+    }
+    if form.username.len() > 32 {
+        errors.username = "Username must be 32 characters or less.".to_string();
+    }
+    if form.password.len() < 8 {
+        errors.password = "Password must be at least 8 characters.".to_string();
+    }
+    if !errors.is_empty() {
+        return Ok(Err(errors));
+    }
+
+    let site_exists = sqlx::query_scalar::<_, i32>("SELECT id FROM sites WHERE id = $1")
+        .bind(site_id)
+        .fetch_optional(&state.pool)
+        .await?;
+    if site_exists.is_none() {
+        return Ok(Err(AddDatabaseErrors {
+            site_id: "Site not found.".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    let db_exists = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM site_databases WHERE site_id = $1 AND db_name = $2",
+    )
+    .bind(site_id)
+    .bind(&form.database_name)
+    .fetch_optional(&state.pool)
+    .await?;
+    if db_exists.is_some() {
+        return Ok(Err(AddDatabaseErrors {
+            database_name: "This database name or user already exists for this site.".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    let new_db = NewDatabase {
+        db_name: form.database_name.clone(),
+        db_user: form.username.clone(),
+        password: form.password.clone(),
+        privileges: normalize_privileges(form.privileges.as_deref()),
+    };
+    let target = Target::database(None, &new_db.db_name);
+    let job_id = state
+        .jobs
+        .enqueue(
+            &state.pool,
+            actor.require_user()?,
+            JobPayload::CreateDatabase {
+                site_id,
+                database: new_db,
+            },
+        )
+        .await?;
+    Event::new("database.create")
+        .target(target)
+        .queued(job_id)
+        .record(&state.pool, actor)
+        .await;
+    Ok(Ok(job_id))
+}
+
+/// Queue the `delete_database` job for one database.
+pub async fn delete_database(state: &AppState, actor: &Actor, database_id: i32) -> Result<i32> {
+    let db = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE id = $1",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(database_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Database not found.".into()))?;
+
+    let target = Target::database(Some(db.id), &db.db_name);
+    let job_id = state
+        .jobs
+        .enqueue(
+            &state.pool,
+            actor.require_user()?,
+            JobPayload::DeleteDatabase {
+                site_id: db.site_id,
+                database_id: db.id,
+                database: DatabaseRef {
+                    db_name: db.db_name,
+                    db_user: db.db_user,
+                },
+            },
+        )
+        .await?;
+    Event::new("database.delete")
+        .target(target)
+        .queued(job_id)
+        .record(&state.pool, actor)
+        .await;
+    Ok(job_id)
+}
+
+pub fn validate_username(username: &str) -> std::result::Result<(), String> {
+    if username.is_empty() || username.len() > 64 {
+        return Err("Username must be 1–64 characters.".to_string());
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(
+            "Username may only contain letters, digits, dots, underscores and hyphens.".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Whether an enabled admin other than `id` remains, so the panel never locks itself out.
+pub async fn other_admin_exists(pool: &DbPool, id: i32) -> Result<bool> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE role = 'admin' AND disabled_at IS NULL AND id <> $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Validate and add a panel user. Returns the new user's id.
+pub async fn create_user(
+    pool: &DbPool,
+    actor: &Actor,
+    username: &str,
+    password: &str,
+    role: &str,
+) -> Result<std::result::Result<i32, AddUserErrors>> {
+    let username = username.trim();
+    let mut errors = AddUserErrors::default();
+    if let Err(msg) = validate_username(username) {
+        errors.username = msg;
+    }
+    if password.len() < 8 {
+        errors.password = "Password must be at least 8 characters.".to_string();
+    }
+    let role = Role::parse(role);
+    if role.is_none() {
+        errors.role = "Choose a role.".to_string();
+    }
+    if errors.username.is_empty() {
+        let taken = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+        if taken.is_some() {
+            errors.username = "A user with this username already exists.".to_string();
+        }
+    }
+    let valid = errors.username.is_empty() && errors.password.is_empty();
+    let Some(role) = role.filter(|_| valid) else {
+        return Ok(Err(errors));
+    };
+
+    let hash = bcrypt::hash(password, 12)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("bcrypt: {}", e)))?;
+    let id = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO users (username, password_hash, role) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(username)
+    .bind(&hash)
+    .bind(role.as_str())
+    .fetch_one(pool)
+    .await?;
+    Event::new("user.create")
+        .target(Target::user(Some(id), username))
+        .detail(format!("Role: {}", role.label()))
+        .record(pool, actor)
+        .await;
+    Ok(Ok(id))
+}
+
+/// Disable an account and end its sessions; the user and everything they
+/// created stay. Refuses to disable the last active admin.
+pub async fn disable_user(
+    pool: &DbPool,
+    actor: &Actor,
+    user: &User,
+) -> Result<std::result::Result<(), String>> {
+    if user.role().is_admin() && !other_admin_exists(pool, user.id).await? {
+        return Ok(Err("The panel needs at least one active admin.".to_string()));
+    }
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE users SET disabled_at = NOW() WHERE id = $1 AND disabled_at IS NULL")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Event::new("user.disable")
+        .target(Target::user(Some(user.id), &user.username))
+        .record(pool, actor)
+        .await;
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::types::Json;
+
+    use super::*;
+    use crate::jobs::{self, FAILED, SUCCEEDED};
+    use crate::provision::ProvisionOp;
+    use crate::test_support::TestPanel;
+
+    async fn job(pool: &DbPool, job_id: i32) -> (String, Option<i32>, Json<JobPayload>) {
+        sqlx::query_as("SELECT state, site_id, payload FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    /// action, outcome, target_type, target_id and exit_status of the event for a job.
+    async fn job_event(
+        pool: &DbPool,
+        job_id: i32,
+    ) -> (String, String, Option<String>, Option<i64>, Option<i32>) {
+        sqlx::query_as(
+            "SELECT action, outcome, target_type, target_id, exit_status FROM audit_events \
+             WHERE job_id = $1",
+        )
+        .bind(job_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn run(panel: &TestPanel, job_id: i32) {
+        assert!(jobs::run_job(&panel.state, job_id).await.unwrap());
+    }
+
+    fn database_form(site_id: i32, name: &str) -> CreateDatabaseForm {
+        CreateDatabaseForm {
+            site_id: site_id.to_string(),
+            database_name: name.to_string(),
+            username: name.to_string(),
+            password: "s3cret-db-pass".to_string(),
+            privileges: Some("readonly".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn site_and_database_lifecycle() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        let actor = Actor::cli(Some(panel.admin_id));
+
+        let form = AddSiteForm {
+            domain: "example.test".to_string(),
+            install_wordpress: Some("1".to_string()),
+            php_version: Some("8.3".to_string()),
+            wp_title: Some("Example".to_string()),
+            wp_admin_user: Some("editor".to_string()),
+            wp_admin_password: Some("s3cret-wp-pass".to_string()),
+            wp_admin_email: Some("editor@example.test".to_string()),
+        };
+        let job_id = create_site(&panel.state, &actor, &form).await.unwrap().unwrap();
+        assert_eq!(job(pool, job_id).await.0, jobs::QUEUED);
+        let queued = job_event(pool, job_id).await;
+        assert_eq!((queued.0.as_str(), queued.1.as_str()), ("site.create", "queued"));
+        run(&panel, job_id).await;
+
+        let (site_id, folder_path, wordpress, php_version, owner) =
+            sqlx::query_as::<_, (i32, String, bool, String, i32)>(
+                "SELECT id, folder_path, wordpress_installed, php_version, user_id FROM sites \
+                 WHERE domain = 'example.test'",
+            )
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(folder_path, "/var/www/example.test");
+        assert!(wordpress);
+        assert_eq!((php_version.as_str(), owner), ("8.3", panel.admin_id));
+        let (state, job_site, Json(payload)) = job(pool, job_id).await;
+        assert_eq!((state.as_str(), job_site), (SUCCEEDED, Some(site_id)));
+        let JobPayload::CreateSite { site } = payload else {
+            panic!("not a create_site payload");
+        };
+        assert_eq!(site.wordpress.unwrap().admin_password, "", "password kept after success");
+        assert_eq!(
+            job_event(pool, job_id).await,
+            (
+                "site.create".to_string(),
+                SUCCEEDED.to_string(),
+                Some("site".to_string()),
+                Some(i64::from(site_id)),
+                Some(0)
+            )
+        );
+
+        let job_id = create_database(&panel.state, &actor, &database_form(site_id, "shop"))
+            .await
+            .unwrap()
+            .unwrap();
+        run(&panel, job_id).await;
+        let (database_id, db_user, privileges) = sqlx::query_as::<_, (i32, String, String)>(
+            "SELECT id, db_user, privileges FROM site_databases WHERE site_id = $1 AND db_name = 'shop'",
+        )
+        .bind(site_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        assert_eq!((db_user.as_str(), privileges.as_str()), ("shop", "readonly"));
+        let (state, _, Json(payload)) = job(pool, job_id).await;
+        assert_eq!(state, SUCCEEDED);
+        let JobPayload::CreateDatabase { database, .. } = payload else {
+            panic!("not a create_database payload");
+        };
+        assert_eq!(database.password, "", "password kept after success");
+        let event = job_event(pool, job_id).await;
+        assert_eq!((event.0.as_str(), event.1.as_str()), ("database.create", SUCCEEDED));
+
+        let taken = create_database(&panel.state, &actor, &database_form(site_id, "shop"))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(!taken.database_name.is_empty());
+
+        let job_id = delete_database(&panel.state, &actor, database_id).await.unwrap();
+        run(&panel, job_id).await;
+        assert_eq!(job(pool, job_id).await.0, SUCCEEDED);
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM site_databases WHERE id = $1")
+            .bind(database_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+        assert_eq!(
+            job_event(pool, job_id).await,
+            (
+                "database.delete".to_string(),
+                SUCCEEDED.to_string(),
+                Some("database".to_string()),
+                Some(i64::from(database_id)),
+                Some(0)
+            )
+        );
+
+        let job_id = create_database(&panel.state, &actor, &database_form(site_id, "blog"))
+            .await
+            .unwrap()
+            .unwrap();
+        run(&panel, job_id).await;
+        let job_id = delete_site(&panel.state, &actor, site_id).await.unwrap();
+        run(&panel, job_id).await;
+        assert_eq!(job(pool, job_id).await.0, SUCCEEDED);
+        let sites: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sites")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let databases: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM site_databases")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!((sites, databases), (0, 0));
+        let event = job_event(pool, job_id).await;
+        assert_eq!((event.0.as_str(), event.1.as_str()), ("site.delete", SUCCEEDED));
+        assert_eq!(event.3, Some(i64::from(site_id)));
+
+        let ops = panel.provisioner.operations();
+        assert!(matches!(
+            &ops[..2],
+            [
+                ProvisionOp::CreateSite { domain, wordpress: true, .. },
+                ProvisionOp::ApplySiteConfig { domain: configured, .. },
+            ] if domain == "example.test" && configured == "example.test"
+        ));
+        assert_eq!(
+            ops[2],
+            ProvisionOp::CreateDatabase {
+                db_name: "shop".to_string(),
+                db_user: "shop".to_string(),
+                privileges: "readonly".to_string(),
+            }
+        );
+        assert_eq!(
+            ops[3],
+            ProvisionOp::DeleteDatabase {
+                db_name: "shop".to_string(),
+                db_user: "shop".to_string(),
+            }
+        );
+        // The site stops being served before its files and databases go.
+        assert_eq!(
+            ops[5..],
+            [
+                ProvisionOp::RemoveSiteConfig {
+                    domain: "example.test".to_string(),
+                },
+                ProvisionOp::DeleteSite {
+                    domain: "example.test".to_string(),
+                    folder_path: "/var/www/example.test".to_string(),
+                    databases: vec!["blog".to_string()],
+                },
+            ]
+        );
+        panel.stop().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn failed_record_fails_job_and_event() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        let actor = Actor::cli(Some(panel.admin_id));
+        let site_id: i32 = sqlx::query_scalar(
+            "INSERT INTO sites (domain, folder_path, user_id) VALUES ('example.test', '/var/www/example.test', $1) RETURNING id",
+        )
+        .bind(panel.admin_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+
+        // Both pass validation while neither has run yet.
+        let form = database_form(site_id, "shop");
+        let first = create_database(&panel.state, &actor, &form).await.unwrap().unwrap();
+        let second = create_database(&panel.state, &actor, &form).await.unwrap().unwrap();
+        run(&panel, first).await;
+        run(&panel, second).await;
+
+        assert_eq!(job(pool, first).await.0, SUCCEEDED);
+        let (state, error): (String, Option<String>) =
+            sqlx::query_as("SELECT state, error FROM jobs WHERE id = $1")
+                .bind(second)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        assert_eq!(state, FAILED);
+        assert_eq!(
+            error.as_deref(),
+            Some("This database name or user already exists for this site.")
+        );
+        let event = job_event(pool, second).await;
+        assert_eq!((event.0.as_str(), event.1.as_str()), ("database.create", FAILED));
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM site_databases")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+        panel.stop().await;
+    }
+}
@@ -0,0 +1,34 @@
+//! Alerts about problems nobody is watching a page for, such as a backup that
+//! failed its test restore.
+//!
+//! Every alert is logged; with `PANEL_ALERT_WEBHOOK` set it is also posted
+//! there as `{"text": …}`, which Slack, Mattermost and most chat incoming
+//! webhooks accept.
+
+use std::time::Duration;
+
+use crate::config::Config;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Raise an alert. Failing to deliver it is logged, not returned.
+pub async fn send(config: &Config, text: &str) {
+    tracing::error!("alert: {}", text);
+    let Some(url) = config.alert_webhook.as_deref() else {
+        return;
+    };
+    let sent = async {
+        reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()?
+            .post(url)
+            .json(&serde_json::json!({ "text": text }))
+            .send()
+            .await?
+            .error_for_status()
+    }
+    .await;
+    if let Err(e) = sent {
+        tracing::warn!("alert webhook: {}", e);
+    }
+}
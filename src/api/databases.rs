@@ -0,0 +1,161 @@
+use axum::{
+    extract::{rejection::JsonRejection, Extension, Path, Query, State},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::actions;
+use crate::audit::Actor;
+use crate::error::AppError;
+use crate::models::{CreateDatabaseForm, Job, SiteDatabase, SITE_DATABASE_COLUMNS};
+use crate::state::AppState;
+
+use super::jobs::job_accepted;
+use super::{ApiError, ApiResult, ErrorBody};
+
+/// Body of `POST /api/v1/databases`; the JSON form of [`CreateDatabaseForm`].
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewDatabaseRequest {
+    pub site_id: i32,
+    /// Letters, numbers and underscores, at most 64.
+    #[schema(example = "shop")]
+    pub database_name: String,
+    /// Letters, numbers and underscores, at most 32.
+    #[schema(example = "shop_user")]
+    pub username: String,
+    /// At least 8 characters.
+    pub password: String,
+    /// `full` (default) or `readonly`.
+    #[serde(default)]
+    #[schema(example = "full")]
+    pub privileges: Option<String>,
+}
+
+impl From<NewDatabaseRequest> for CreateDatabaseForm {
+    fn from(req: NewDatabaseRequest) -> Self {
+        CreateDatabaseForm {
+            site_id: req.site_id.to_string(),
+            database_name: req.database_name,
+            username: req.username,
+            password: req.password,
+            privileges: req.privileges,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DatabasesQuery {
+    /// Only databases of this site.
+    pub site_id: Option<i32>,
+}
+
+/// All databases, optionally for one site.
+#[utoipa::path(
+    get,
+    path = "/api/v1/databases",
+    tag = "databases",
+    params(DatabasesQuery),
+    responses(
+        (status = 200, description = "Databases", body = [SiteDatabase]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    )
+)]
+pub async fn list_databases(
+    State(state): State<AppState>,
+    Query(q): Query<DatabasesQuery>,
+) -> ApiResult<Json<Vec<SiteDatabase>>> {
+    let databases = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE $1::INTEGER IS NULL OR site_id = $1 ORDER BY site_id, db_name",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(q.site_id)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(databases))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/databases/{id}",
+    tag = "databases",
+    params(("id" = i32, Path, description = "Database id")),
+    responses(
+        (status = 200, description = "The database", body = SiteDatabase),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such database", body = ErrorBody),
+    )
+)]
+pub async fn get_database(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> ApiResult<Json<SiteDatabase>> {
+    let database = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE id = $1",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Database not found.".into()))?;
+    Ok(Json(database))
+}
+
+/// Queue a `create_database` job. Validated like the Add Database form.
+#[utoipa::path(
+    post,
+    path = "/api/v1/databases",
+    tag = "databases",
+    request_body = NewDatabaseRequest,
+    responses(
+        (status = 202, description = "Job queued; poll the `Location`", body = Job,
+            headers(("Location" = String, description = "URL of the job"))),
+        (status = 400, description = "Malformed JSON", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Read-only token or role", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    )
+)]
+pub async fn create_database(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    body: std::result::Result<Json<NewDatabaseRequest>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(req) = body?;
+    let form = CreateDatabaseForm::from(req);
+    match actions::create_database(&state, &actor, &form).await? {
+        Ok(job_id) => job_accepted(&state, job_id).await,
+        Err(errors) => Err(ApiError::Invalid(
+            errors
+                .fields()
+                .into_iter()
+                .map(|(field, msg)| (field, msg.to_string()))
+                .collect(),
+        )),
+    }
+}
+
+/// Queue a `delete_database` job, which drops the database and its user.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/databases/{id}",
+    tag = "databases",
+    params(("id" = i32, Path, description = "Database id")),
+    responses(
+        (status = 202, description = "Job queued; poll the `Location`", body = Job,
+            headers(("Location" = String, description = "URL of the job"))),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Read-only token or role", body = ErrorBody),
+        (status = 404, description = "No such database", body = ErrorBody),
+    )
+)]
+pub async fn delete_database(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i32>,
+) -> ApiResult<Response> {
+    let job_id = actions::delete_database(&state, &actor, id).await?;
+    job_accepted(&state, job_id).await
+}
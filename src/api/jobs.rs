@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::audit::{Actor, Event, Target};
+use crate::error::AppError;
+use crate::models::{Job, JOB_COLUMNS};
+use crate::state::AppState;
+
+use super::{ApiResult, ErrorBody};
+
+async fn fetch_job(state: &AppState, id: i32) -> ApiResult<Job> {
+    sqlx::query_as::<_, Job>(&format!("SELECT {} FROM jobs WHERE id = $1", JOB_COLUMNS))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Job not found.".into()).into())
+}
+
+/// `202 Accepted` with the queued job and a `Location` to poll.
+pub(super) async fn job_accepted(state: &AppState, job_id: i32) -> ApiResult<Response> {
+    let job = fetch_job(state, job_id).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/api/v1/jobs/{}", job_id))],
+        Json(job),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobsQuery {
+    /// Only jobs for this site.
+    pub site_id: Option<i32>,
+}
+
+/// The 100 most recent jobs, optionally for one site.
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs",
+    tag = "jobs",
+    params(JobsQuery),
+    responses(
+        (status = 200, description = "Jobs, newest first", body = [Job]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    )
+)]
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(q): Query<JobsQuery>,
+) -> ApiResult<Json<Vec<Job>>> {
+    let jobs = sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs WHERE $1::INTEGER IS NULL OR site_id = $1 ORDER BY id DESC LIMIT 100",
+        JOB_COLUMNS
+    ))
+    .bind(q.site_id)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(jobs))
+}
+
+/// A job with its state and output.
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs/{id}",
+    tag = "jobs",
+    params(("id" = i32, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job", body = Job),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such job", body = ErrorBody),
+    )
+)]
+pub async fn get_job(State(state): State<AppState>, Path(id): Path<i32>) -> ApiResult<Json<Job>> {
+    Ok(Json(fetch_job(&state, id).await?))
+}
+
+/// Put a failed job back in the queue.
+#[utoipa::path(
+    post,
+    path = "/api/v1/jobs/{id}/retry",
+    tag = "jobs",
+    params(("id" = i32, Path, description = "Job id")),
+    responses(
+        (status = 202, description = "Job queued again; poll the `Location`", body = Job,
+            headers(("Location" = String, description = "URL of the job"))),
+        (status = 400, description = "The job has not failed, or the passwords it needs were discarded", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Read-only token or role", body = ErrorBody),
+        (status = 404, description = "No such job", body = ErrorBody),
+    )
+)]
+pub async fn retry_job(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i32>,
+) -> ApiResult<Response> {
+    let job = fetch_job(&state, id).await?;
+    if !state.jobs.retry(&state.pool, id).await? {
+        return Err(AppError::Validation("Only failed jobs can be retried.".into()).into());
+    }
+    Event::new("job.retry")
+        .target(Target::job(id, &job.summary))
+        .queued(id)
+        .record(&state.pool, &actor)
+        .await;
+    job_accepted(&state, id).await
+}
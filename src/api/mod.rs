@@ -0,0 +1,283 @@
+//! The `/api/v1` JSON API over sites, databases and jobs.
+//!
+//! Requests authenticate with `Authorization: Bearer <token>`, using a
+//! per-user token created under Account → API tokens; cookies and CSRF tokens
+//! play no part. Read tokens may only GET. Write tokens may also create,
+//! delete and retry, as long as the owner's role allows it at the time of the
+//! request. Creating and deleting go through [`crate::actions`], so the API
+//! accepts exactly what the HTML forms accept, and answer `202 Accepted` with
+//! the queued job.
+//!
+//! Errors are JSON: `{"error": "..."}`, plus a `fields` object mapping each
+//! invalid field to its message on `422 Unprocessable Entity`.
+//!
+//! [`openapi`] describes all of this as an OpenAPI 3 document.
+
+mod databases;
+mod jobs;
+pub mod openapi;
+mod sites;
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{rejection::JsonRejection, ConnectInfo, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use ring::digest;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::audit::{Actor, Via};
+use crate::auth::{client_ip, Role, UserId};
+use crate::csrf;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::state::AppState;
+
+/// Every API token starts with this, so leaked tokens are easy to search for.
+pub const TOKEN_PREFIX: &str = "fpp_";
+/// Characters of the token kept in clear text to tell tokens apart in the UI.
+const TOKEN_DISPLAY_LEN: usize = 12;
+/// `last_used_at` is written at most this often per token.
+const TOKEN_TOUCH_SECS: i64 = 60;
+
+/// What an API token may do. Stored in `api_tokens.scope`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenScope {
+    /// GET requests only.
+    Read,
+    /// Also create, delete and retry (if the owner's role can manage).
+    Write,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 2] = [TokenScope::Read, TokenScope::Write];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        TokenScope::ALL.into_iter().find(|s| s.as_str() == value)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TokenScope::Read => "Read-only",
+            TokenScope::Write => "Read and write",
+        }
+    }
+}
+
+/// What Postgres stores for an API token.
+fn hash_token(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+/// Create a token for `user_id` and return it. This is the only time the
+/// token exists in clear text.
+pub async fn create_token(
+    pool: &DbPool,
+    user_id: i32,
+    name: &str,
+    scope: TokenScope,
+) -> Result<String> {
+    let token = format!("{}{}", TOKEN_PREFIX, csrf::generate_token());
+    sqlx::query(
+        "INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scope) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(hash_token(&token))
+    .bind(&token[..TOKEN_DISPLAY_LEN])
+    .bind(scope.as_str())
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/sites", get(sites::list_sites).post(sites::create_site))
+        .route(
+            "/sites/:id",
+            get(sites::get_site).delete(sites::delete_site),
+        )
+        .route(
+            "/databases",
+            get(databases::list_databases).post(databases::create_database),
+        )
+        .route(
+            "/databases/:id",
+            get(databases::get_database).delete(databases::delete_database),
+        )
+        .route("/jobs", get(jobs::list_jobs))
+        .route("/jobs/:id", get(jobs::get_job))
+        .route("/jobs/:id/retry", post(jobs::retry_job))
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+async fn not_found() -> ApiError {
+    ApiError::App(AppError::NotFound("No such endpoint.".into()))
+}
+
+/// Resolve the bearer token to its user and check the token scope and the
+/// user's role against the request method.
+async fn require_token(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> std::result::Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .ok_or(ApiError::Unauthorized(
+            "Missing API token. Send it as `Authorization: Bearer <token>`.",
+        ))?;
+    let row: Option<(i32, i32, String, String)> = sqlx::query_as(
+        "SELECT t.id, u.id, u.role, t.scope FROM api_tokens t JOIN users u ON u.id = t.user_id \
+         WHERE t.token_hash = $1 AND u.disabled_at IS NULL",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&state.pool)
+    .await?;
+    let (token_id, user_id, role, scope) =
+        row.ok_or(ApiError::Unauthorized("Invalid or revoked API token."))?;
+    let role = Role::from_db(&role);
+
+    let read = matches!(*request.method(), Method::GET | Method::HEAD);
+    if !read && TokenScope::parse(&scope) != Some(TokenScope::Write) {
+        return Err(ApiError::Forbidden("This API token is read-only."));
+    }
+    if !read && !role.can_manage() {
+        return Err(ApiError::Forbidden("Your role does not allow changes."));
+    }
+
+    if let Err(e) = sqlx::query(
+        "UPDATE api_tokens SET last_used_at = NOW() WHERE id = $1 \
+         AND (last_used_at IS NULL OR last_used_at < NOW() - ($2 || ' seconds')::interval)",
+    )
+    .bind(token_id)
+    .bind(TOKEN_TOUCH_SECS)
+    .execute(&state.pool)
+    .await
+    {
+        tracing::warn!("api token {}: could not record use: {}", token_id, e);
+    }
+
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|peer| client_ip(peer.ip(), request.headers(), &state.config.trusted_proxies));
+    request.extensions_mut().insert(UserId(user_id));
+    request.extensions_mut().insert(role);
+    request.extensions_mut().insert(Actor {
+        user_id: Some(user_id),
+        ip,
+        via: Via::Api,
+    });
+    Ok(next.run(request).await)
+}
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    #[schema(example = "Validation failed.")]
+    pub error: String,
+    /// Message per invalid field; only on `422`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<BTreeMap<String, String>>,
+}
+
+impl ErrorBody {
+    fn new(error: impl Into<String>) -> Json<Self> {
+        Json(ErrorBody {
+            error: error.into(),
+            fields: None,
+        })
+    }
+}
+
+/// An API error, rendered as [`ErrorBody`].
+pub enum ApiError {
+    App(AppError),
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    BadRequest(String),
+    /// Field name and message for each invalid field.
+    Invalid(Vec<(&'static str, String)>),
+}
+
+impl From<AppError> for ApiError {
+    fn from(e: AppError) -> Self {
+        ApiError::App(e)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::App(e.into())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::App(e) => {
+                if matches!(e, AppError::Db(_) | AppError::Internal(_)) {
+                    tracing::error!("api: {}", e);
+                }
+                let (status, message) = e.status_and_message();
+                (status, ErrorBody::new(message)).into_response()
+            }
+            ApiError::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                ErrorBody::new(message),
+            )
+                .into_response(),
+            ApiError::Forbidden(message) => {
+                (StatusCode::FORBIDDEN, ErrorBody::new(message)).into_response()
+            }
+            ApiError::BadRequest(message) => {
+                (StatusCode::BAD_REQUEST, ErrorBody::new(message)).into_response()
+            }
+            ApiError::Invalid(fields) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorBody {
+                    error: "Validation failed.".to_string(),
+                    fields: Some(
+                        fields
+                            .into_iter()
+                            .map(|(field, message)| (field.to_string(), message))
+                            .collect(),
+                    ),
+                }),
+            )
+                .into_response(),
+        }
+    }
+}
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
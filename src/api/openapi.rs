@@ -0,0 +1,76 @@
+//! OpenAPI 3 description of the `/api/v1` JSON API.
+//!
+//! The document is generated from the `#[utoipa::path]` annotations on the
+//! handlers and the `ToSchema` model types, so it cannot drift from the code.
+//! It is served at `/api/openapi.json`, with Swagger UI at `/api/docs`. The
+//! Swagger UI assets are compiled into the binary, so the docs work without
+//! network access. Neither needs a token: they describe the API, not data.
+
+use axum::Router;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use super::{databases, jobs, sites};
+
+pub const SPEC_PATH: &str = "/api/openapi.json";
+pub const DOCS_PATH: &str = "/api/docs";
+
+/// Name of the bearer-token security scheme; also used in `security(...)` below.
+const SECURITY_SCHEME: &str = "api_token";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "FrankenPHP Panel API",
+        description = "Sites, databases and background jobs. Authenticate with \
+            `Authorization: Bearer <token>`, using a token from Account → API tokens. \
+            Read-only tokens may only GET. Creating, deleting and retrying queue a job \
+            and answer 202 with the job and a `Location` to poll."
+    ),
+    paths(
+        sites::list_sites,
+        sites::get_site,
+        sites::create_site,
+        sites::delete_site,
+        databases::list_databases,
+        databases::get_database,
+        databases::create_database,
+        databases::delete_database,
+        jobs::list_jobs,
+        jobs::get_job,
+        jobs::retry_job,
+    ),
+    modifiers(&BearerToken),
+    security(("api_token" = [])),
+    tags(
+        (name = "sites", description = "PHP and WordPress sites"),
+        (name = "databases", description = "MariaDB databases belonging to a site"),
+        (name = "jobs", description = "Background provisioning jobs"),
+    )
+)]
+pub struct ApiDoc;
+
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            SECURITY_SCHEME,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("An API token from Account → API tokens."))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// `/api/openapi.json` and the Swagger UI at `/api/docs`.
+pub fn router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    SwaggerUi::new(DOCS_PATH)
+        .url(SPEC_PATH, ApiDoc::openapi())
+        .into()
+}
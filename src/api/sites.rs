@@ -0,0 +1,168 @@
+use axum::{
+    extract::{rejection::JsonRejection, Extension, Path, State},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::actions;
+use crate::audit::Actor;
+use crate::models::{AddSiteForm, Job, Site, SiteDetail, SITE_COLUMNS};
+use crate::state::AppState;
+
+use super::jobs::job_accepted;
+use super::{ApiError, ApiResult, ErrorBody};
+
+/// Body of `POST /api/v1/sites`; the JSON form of [`AddSiteForm`].
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewSiteRequest {
+    /// Hostname to serve, e.g. `example.com`. The site lives in `/var/www/<domain>`.
+    #[schema(example = "example.com")]
+    pub domain: String,
+    /// PHP version; defaults to 8.2.
+    #[serde(default)]
+    #[schema(example = "8.3")]
+    pub php_version: Option<String>,
+    /// Install WordPress with these settings; omit for a plain PHP site.
+    #[serde(default)]
+    pub wordpress: Option<WordPressRequest>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WordPressRequest {
+    #[serde(default)]
+    pub title: String,
+    /// Letters, numbers and underscores, at most 60.
+    #[serde(default)]
+    pub admin_user: String,
+    /// At least 8 characters.
+    #[serde(default)]
+    pub admin_password: String,
+    #[serde(default)]
+    pub admin_email: String,
+}
+
+impl From<NewSiteRequest> for AddSiteForm {
+    fn from(req: NewSiteRequest) -> Self {
+        let wp = req.wordpress;
+        AddSiteForm {
+            domain: req.domain,
+            install_wordpress: wp.as_ref().map(|_| "1".to_string()),
+            php_version: req.php_version,
+            wp_title: wp.as_ref().map(|w| w.title.clone()),
+            wp_admin_user: wp.as_ref().map(|w| w.admin_user.clone()),
+            wp_admin_password: wp.as_ref().map(|w| w.admin_password.clone()),
+            wp_admin_email: wp.map(|w| w.admin_email),
+        }
+    }
+}
+
+/// Form field names in [`crate::templates::AddSiteErrors`] as the API calls them.
+fn api_field(field: &'static str) -> &'static str {
+    match field {
+        // Taken domains are reported on the (derived) folder path.
+        "folder_path" => "domain",
+        "wp_title" => "wordpress.title",
+        "wp_admin_user" => "wordpress.admin_user",
+        "wp_admin_password" => "wordpress.admin_password",
+        "wp_admin_email" => "wordpress.admin_email",
+        other => other,
+    }
+}
+
+/// All sites, by domain.
+#[utoipa::path(
+    get,
+    path = "/api/v1/sites",
+    tag = "sites",
+    responses(
+        (status = 200, description = "All sites", body = [Site]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    )
+)]
+pub async fn list_sites(State(state): State<AppState>) -> ApiResult<Json<Vec<Site>>> {
+    let sites = sqlx::query_as::<_, Site>(&format!(
+        "SELECT {} FROM sites ORDER BY domain",
+        SITE_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(sites))
+}
+
+/// A site with its databases and aliases.
+#[utoipa::path(
+    get,
+    path = "/api/v1/sites/{id}",
+    tag = "sites",
+    params(("id" = i32, Path, description = "Site id")),
+    responses(
+        (status = 200, description = "The site", body = SiteDetail),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such site", body = ErrorBody),
+    )
+)]
+pub async fn get_site(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> ApiResult<Json<SiteDetail>> {
+    Ok(Json(actions::site_detail(&state.pool, id).await?))
+}
+
+/// Queue a `create_site` job. Validated like the Add Site form.
+#[utoipa::path(
+    post,
+    path = "/api/v1/sites",
+    tag = "sites",
+    request_body = NewSiteRequest,
+    responses(
+        (status = 202, description = "Job queued; poll the `Location`", body = Job,
+            headers(("Location" = String, description = "URL of the job"))),
+        (status = 400, description = "Malformed JSON", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Read-only token or role", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    )
+)]
+pub async fn create_site(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    body: std::result::Result<Json<NewSiteRequest>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(req) = body?;
+    let form = AddSiteForm::from(req);
+    match actions::create_site(&state, &actor, &form).await? {
+        Ok(job_id) => job_accepted(&state, job_id).await,
+        Err(errors) => Err(ApiError::Invalid(
+            errors
+                .fields()
+                .into_iter()
+                .map(|(field, msg)| (api_field(field), msg.to_string()))
+                .collect(),
+        )),
+    }
+}
+
+/// Queue a `delete_site` job, which also drops the site's databases.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/sites/{id}",
+    tag = "sites",
+    params(("id" = i32, Path, description = "Site id")),
+    responses(
+        (status = 202, description = "Job queued; poll the `Location`", body = Job,
+            headers(("Location" = String, description = "URL of the job"))),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Read-only token or role", body = ErrorBody),
+        (status = 404, description = "No such site", body = ErrorBody),
+    )
+)]
+pub async fn delete_site(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i32>,
+) -> ApiResult<Response> {
+    let job_id = actions::delete_site(&state, &actor, id).await?;
+    job_accepted(&state, job_id).await
+}
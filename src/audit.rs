@@ -0,0 +1,454 @@
+//! Audit log: who changed what, from where, and how it ended.
+//!
+//! Every mutating handler records an [`Event`] in `audit_events`, as does the
+//! CLI. Actions carried out by a background job are recorded as `queued` with
+//! the job id, and [`finish_job`] fills in the outcome and the provisioning
+//! exit status when the job ends. Input that fails validation changes nothing
+//! and is not recorded.
+//!
+//! Action names are `<target>.<verb>`, e.g. `site.delete` or `user.disable`,
+//! so filtering on `site` finds every site action.
+
+use std::net::IpAddr;
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::models::{AuditEvent, AUDIT_EVENT_COLUMNS};
+
+pub const SUCCEEDED: &str = "succeeded";
+pub const FAILED: &str = "failed";
+pub const QUEUED: &str = "queued";
+
+/// Events per page on `/audit`.
+pub const PAGE_SIZE: i64 = 100;
+/// Most events in one CSV export.
+pub const EXPORT_LIMIT: i64 = 100_000;
+
+/// Where a change came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Via {
+    Web,
+    Api,
+    Cli,
+}
+
+impl Via {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Via::Web => "web",
+            Via::Api => "api",
+            Via::Cli => "cli",
+        }
+    }
+}
+
+/// Who is making a change. Inserted into signed-in and API requests by their
+/// auth middleware.
+#[derive(Clone, Copy, Debug)]
+pub struct Actor {
+    /// `None` for failed logins and for CLI commands not run as a panel user.
+    pub user_id: Option<i32>,
+    pub ip: Option<IpAddr>,
+    pub via: Via,
+}
+
+impl Actor {
+    pub fn cli(user_id: Option<i32>) -> Self {
+        Actor {
+            user_id,
+            ip: None,
+            via: Via::Cli,
+        }
+    }
+
+    /// The acting user, for changes that must belong to one, such as jobs.
+    pub fn require_user(&self) -> Result<i32> {
+        self.user_id.ok_or(AppError::Unauthorized)
+    }
+}
+
+/// What an event is about. The label is copied into the event so it still
+/// reads right after the target is deleted or renamed.
+pub struct Target {
+    kind: &'static str,
+    id: Option<i64>,
+    label: String,
+}
+
+impl Target {
+    /// `id` is `None` for a site that is still being created; [`finish_job`]
+    /// fills it in.
+    pub fn site(id: Option<i32>, domain: &str) -> Self {
+        Self::new("site", id.map(Into::into), domain)
+    }
+
+    pub fn database(id: Option<i32>, db_name: &str) -> Self {
+        Self::new("database", id.map(Into::into), db_name)
+    }
+
+    pub fn user(id: Option<i32>, username: &str) -> Self {
+        Self::new("user", id.map(Into::into), username)
+    }
+
+    pub fn job(id: i32, summary: &str) -> Self {
+        Self::new("job", Some(id.into()), summary)
+    }
+
+    pub fn api_token(id: Option<i32>, name: &str) -> Self {
+        Self::new("api_token", id.map(Into::into), name)
+    }
+
+    pub fn session(id: i64) -> Self {
+        Self::new("session", Some(id), "")
+    }
+
+    fn new(kind: &'static str, id: Option<i64>, label: &str) -> Self {
+        Target {
+            kind,
+            id,
+            label: label.to_string(),
+        }
+    }
+}
+
+/// One audit log entry, built up and then [`record`](Event::record)ed.
+pub struct Event {
+    action: &'static str,
+    target: Option<Target>,
+    outcome: &'static str,
+    job_id: Option<i32>,
+    detail: String,
+}
+
+impl Event {
+    /// A successful `action`.
+    pub fn new(action: &'static str) -> Self {
+        Event {
+            action,
+            target: None,
+            outcome: SUCCEEDED,
+            job_id: None,
+            detail: String::new(),
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = detail.into();
+        self
+    }
+
+    /// The action was attempted but did not happen; `detail` says why.
+    pub fn failed(mut self, detail: impl Into<String>) -> Self {
+        self.outcome = FAILED;
+        self.detail = detail.into();
+        self
+    }
+
+    /// The action was handed to a job; its outcome is filled in by [`finish_job`].
+    pub fn queued(mut self, job_id: i32) -> Self {
+        self.outcome = QUEUED;
+        self.job_id = Some(job_id);
+        self
+    }
+
+    /// Write the event. A failure is logged rather than returned: the change
+    /// it describes has already happened.
+    pub async fn record(self, pool: &DbPool, actor: &Actor) {
+        let (target_type, target_id, target_label) = match self.target {
+            Some(t) => (Some(t.kind), t.id, t.label),
+            None => (None, None, String::new()),
+        };
+        let inserted = sqlx::query(
+            "INSERT INTO audit_events (user_id, username, client_ip, via, action, target_type, \
+             target_id, target_label, outcome, job_id, detail) \
+             VALUES ($1, (SELECT username FROM users WHERE id = $1), $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(actor.user_id)
+        .bind(actor.ip.map(|ip| ip.to_string()))
+        .bind(actor.via.as_str())
+        .bind(self.action)
+        .bind(target_type)
+        .bind(target_id)
+        .bind(&target_label)
+        .bind(self.outcome)
+        .bind(self.job_id)
+        .bind(&self.detail)
+        .execute(pool)
+        .await;
+        if let Err(e) = inserted {
+            tracing::warn!("audit: could not record {}: {}", self.action, e);
+        }
+    }
+}
+
+/// Set the outcome of the events waiting on a job. `outcome` is the job's
+/// final state; `exit_status` that of the provisioning step, if it ran.
+pub async fn finish_job(
+    pool: &DbPool,
+    job_id: i32,
+    outcome: &str,
+    exit_status: Option<i32>,
+    error: Option<&str>,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE audit_events e SET outcome = $2, exit_status = $3, detail = COALESCE($4, e.detail), \
+         target_id = CASE WHEN e.target_type = 'site' THEN COALESCE(e.target_id, j.site_id) ELSE e.target_id END \
+         FROM jobs j WHERE j.id = e.job_id AND e.job_id = $1 AND e.outcome = $5",
+    )
+    .bind(job_id)
+    .bind(outcome)
+    .bind(exit_status)
+    .bind(error)
+    .bind(QUEUED)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Filters on `/audit` and `/audit.csv`. Empty fields are ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub user: String,
+    /// An action (`site.delete`) or a target prefix (`site`).
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub target: String,
+    #[serde(default)]
+    pub outcome: String,
+    /// First day to include, `YYYY-MM-DD` (UTC).
+    #[serde(default)]
+    pub from: String,
+    /// Last day to include, `YYYY-MM-DD` (UTC).
+    #[serde(default)]
+    pub to: String,
+    /// Text in the target label.
+    #[serde(default)]
+    pub q: String,
+    pub page: Option<i64>,
+}
+
+impl AuditQuery {
+    fn field(value: &str) -> Option<&str> {
+        Some(value.trim()).filter(|v| !v.is_empty())
+    }
+
+    fn date(value: &str, name: &str) -> std::result::Result<Option<NaiveDate>, String> {
+        match Self::field(value) {
+            None => Ok(None),
+            Some(v) => NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| format!("{} must be a date like 2025-03-15.", name)),
+        }
+    }
+
+    /// Check the dates, so the page can say what is wrong with them.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        Self::date(&self.from, "From")?;
+        Self::date(&self.to, "To")?;
+        Ok(())
+    }
+
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    /// The filters as a query string, for the CSV link and paging.
+    pub fn query_string(&self) -> String {
+        let pairs: Vec<(&str, &str)> = [
+            ("user", &self.user),
+            ("action", &self.action),
+            ("target", &self.target),
+            ("outcome", &self.outcome),
+            ("from", &self.from),
+            ("to", &self.to),
+            ("q", &self.q),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Self::field(value).map(|v| (name, v)))
+        .collect();
+        serde_urlencoded::to_string(pairs).unwrap_or_default()
+    }
+
+    /// Matching events, newest first. Invalid dates are treated as absent;
+    /// call [`AuditQuery::validate`] to report them.
+    pub async fn fetch(&self, pool: &DbPool, limit: i64, offset: i64) -> Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as::<_, AuditEvent>(&format!(
+            "SELECT {} FROM audit_events \
+             WHERE ($1::TEXT IS NULL OR username = $1) \
+             AND ($2::TEXT IS NULL OR action = $2 OR action LIKE $2 || '.%') \
+             AND ($3::TEXT IS NULL OR target_type = $3) \
+             AND ($4::TEXT IS NULL OR outcome = $4) \
+             AND ($5::DATE IS NULL OR created_at >= $5::DATE::TIMESTAMP AT TIME ZONE 'UTC') \
+             AND ($6::DATE IS NULL OR created_at < ($6::DATE + 1)::TIMESTAMP AT TIME ZONE 'UTC') \
+             AND ($7::TEXT IS NULL OR strpos(lower(target_label), lower($7)) > 0) \
+             ORDER BY id DESC LIMIT $8 OFFSET $9",
+            AUDIT_EVENT_COLUMNS
+        ))
+        .bind(Self::field(&self.user))
+        .bind(Self::field(&self.action))
+        .bind(Self::field(&self.target))
+        .bind(Self::field(&self.outcome))
+        .bind(Self::date(&self.from, "From").ok().flatten())
+        .bind(Self::date(&self.to, "To").ok().flatten())
+        .bind(Self::field(&self.q))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(events)
+    }
+}
+
+/// Distinct values seen so far, for the filter drop-downs.
+pub async fn distinct(pool: &DbPool, column: AuditColumn) -> Result<Vec<String>> {
+    let values = sqlx::query_scalar::<_, String>(&format!(
+        "SELECT DISTINCT {0} FROM audit_events WHERE {0} IS NOT NULL ORDER BY {0}",
+        column.name()
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(values)
+}
+
+/// Columns offered as filter drop-downs.
+#[derive(Clone, Copy)]
+pub enum AuditColumn {
+    Username,
+    Action,
+    TargetType,
+}
+
+impl AuditColumn {
+    fn name(self) -> &'static str {
+        match self {
+            AuditColumn::Username => "username",
+            AuditColumn::Action => "action",
+            AuditColumn::TargetType => "target_type",
+        }
+    }
+}
+
+/// RFC 4180 CSV with a header row.
+pub fn to_csv(events: &[AuditEvent]) -> String {
+    let mut out = String::from(
+        "id,time,user,client_ip,via,action,target_type,target_id,target,outcome,job_id,exit_status,detail\r\n",
+    );
+    for e in events {
+        let fields = [
+            e.id.to_string(),
+            e.created_at.to_rfc3339(),
+            csv_text(e.username.as_deref().unwrap_or("")),
+            csv_text(e.client_ip.as_deref().unwrap_or("")),
+            e.via.clone(),
+            csv_text(&e.action),
+            csv_text(e.target_type.as_deref().unwrap_or("")),
+            e.target_id.map(|v| v.to_string()).unwrap_or_default(),
+            csv_text(&e.target_label),
+            e.outcome.clone(),
+            e.job_id.map(|v| v.to_string()).unwrap_or_default(),
+            e.exit_status.map(|v| v.to_string()).unwrap_or_default(),
+            csv_text(&e.detail),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_quote(f)).collect();
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Free text from users: defuse values a spreadsheet would run as a formula.
+fn csv_text(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_quote(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    #[test]
+    fn csv_quote_escapes_separators() {
+        let cases = [
+            ("plain", "plain"),
+            ("", ""),
+            ("a,b", "\"a,b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("line\r\nbreak", "\"line\r\nbreak\""),
+            ("lf\nonly", "\"lf\nonly\""),
+            ("cr\ronly", "\"cr\ronly\""),
+        ];
+        for (value, quoted) in cases {
+            assert_eq!(csv_quote(value), quoted, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn csv_text_defuses_formulas() {
+        let cases = [
+            ("=SUM(A1:A9)", "'=SUM(A1:A9)"),
+            ("+1", "'+1"),
+            ("-1", "'-1"),
+            ("@cmd", "'@cmd"),
+            ("\tpadded", "'\tpadded"),
+            ("\rreturn", "'\rreturn"),
+            ("a=b", "a=b"),
+            ("example.com", "example.com"),
+            ("", ""),
+        ];
+        for (value, text) in cases {
+            assert_eq!(csv_text(value), text, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn to_csv_rows() {
+        let event = AuditEvent {
+            id: 7,
+            created_at: Utc.with_ymd_and_hms(2025, 3, 15, 12, 0, 0).unwrap(),
+            user_id: Some(1),
+            username: Some("=HYPERLINK(\"http://x\")".to_string()),
+            client_ip: Some("203.0.113.9".to_string()),
+            via: "web".to_string(),
+            action: "site.create".to_string(),
+            target_type: Some("site".to_string()),
+            target_id: None,
+            target_label: "example.com, the shop".to_string(),
+            outcome: "failed".to_string(),
+            job_id: Some(12),
+            exit_status: Some(1),
+            detail: "-exit 1\r\nsee \"output\"".to_string(),
+        };
+        let csv = to_csv(&[event]);
+        let mut lines = csv.splitn(2, "\r\n");
+        assert!(lines.next().unwrap().starts_with("id,time,user,"));
+        assert_eq!(
+            lines.next().unwrap(),
+            "7,2025-03-15T12:00:00+00:00,\"'=HYPERLINK(\"\"http://x\"\")\",203.0.113.9,web,\
+             site.create,site,,\"example.com, the shop\",failed,12,1,\"'-exit 1\r\nsee \"\"output\"\"\"\r\n"
+        );
+        assert_eq!(to_csv(&[]).matches("\r\n").count(), 1);
+    }
+}
@@ -1,42 +1,220 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
 use axum::{
-    extract::{Request, State},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Method},
     middleware::Next,
     response::{IntoResponse, Redirect, Response},
 };
+use ring::{digest, hmac};
 use uuid::Uuid;
 
+use crate::audit::{Actor, Via};
+use crate::csrf::{self, CsrfToken};
 use crate::db::DbPool;
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use crate::state::AppState;
 
 pub const SESSION_COOKIE: &str = "panel_session";
 const SESSION_MAX_AGE_DAYS: i64 = 7;
+/// `last_seen_at` is written at most this often per session.
+const SESSION_TOUCH_SECS: i64 = 60;
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Longer user agents are cut off before they are stored.
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// What Postgres stores for a session token, so a leaked table cannot be
+/// replayed as cookies.
+fn hash_session_token(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+/// The cookie value: `<token>.<HMAC-SHA256 of the token under the session secret>`.
+fn sign_session_token(secret: &str, token: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, token.as_bytes());
+    format!("{}.{}", token, data_encoding::BASE64URL_NOPAD.encode(tag.as_ref()))
+}
+
+/// The token from a cookie value whose signature checks out (in constant time).
+fn verify_session_cookie<'a>(secret: &str, value: &'a str) -> Option<&'a str> {
+    let (token, tag) = value.rsplit_once('.')?;
+    let tag = data_encoding::BASE64URL_NOPAD.decode(tag.as_bytes()).ok()?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, token.as_bytes(), &tag).ok()?;
+    Some(token)
+}
+
+/// Start a session and return the signed cookie value.
+pub async fn create_session(
+    pool: &DbPool,
+    secret: &str,
+    user_id: i32,
+    ip: IpAddr,
+    user_agent: &str,
+) -> Result<String> {
+    let token = csrf::generate_token();
+    let user_agent: String = user_agent.chars().take(MAX_USER_AGENT_LEN).collect();
+    sqlx::query(
+        "INSERT INTO sessions (token_hash, user_id, csrf_token, ip, user_agent, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, NOW() + ($6 || ' days')::interval)",
+    )
+    .bind(hash_session_token(&token))
+    .bind(user_id)
+    .bind(csrf::generate_token())
+    .bind(ip.to_string())
+    .bind(&user_agent)
+    .bind(SESSION_MAX_AGE_DAYS)
+    .execute(pool)
+    .await?;
+    Ok(sign_session_token(secret, &token))
+}
+
+/// A valid session and the user it belongs to.
+pub struct SessionUser {
+    pub session_id: i64,
+    pub user_id: i32,
+    pub role: Role,
+    pub csrf: CsrfToken,
+}
+
+/// The session behind a cookie token; `None` if it expired or the user was disabled.
+async fn get_session_user(pool: &DbPool, token: &str) -> Result<Option<SessionUser>> {
+    let row: Option<(i64, i32, String, String)> = sqlx::query_as(
+        "SELECT s.id, u.id, u.role, s.csrf_token FROM sessions s JOIN users u ON u.id = s.user_id \
+         WHERE s.token_hash = $1 AND s.expires_at > NOW() AND u.disabled_at IS NULL",
+    )
+    .bind(hash_session_token(token))
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|(session_id, user_id, role, csrf)| SessionUser {
+        session_id,
+        user_id,
+        role: Role::from_db(&role),
+        csrf: CsrfToken(csrf),
+    }))
+}
+
+/// Record that a session was just used, and from where.
+async fn touch_session(pool: &DbPool, session_id: i64, ip: Option<IpAddr>) -> Result<()> {
+    sqlx::query(
+        "UPDATE sessions SET last_seen_at = NOW(), ip = COALESCE($2, ip) WHERE id = $1 \
+         AND (last_seen_at < NOW() - ($3 || ' seconds')::interval OR ip IS DISTINCT FROM COALESCE($2, ip))",
+    )
+    .bind(session_id)
+    .bind(ip.map(|ip| ip.to_string()))
+    .bind(SESSION_TOUCH_SECS)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_session(pool: &DbPool, session_id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE id = $1")
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Drop expired sessions and login challenges every hour.
+pub fn spawn_session_purge(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SESSION_PURGE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = purge_expired_sessions(&pool).await {
+                tracing::warn!("session purge: {}", e);
+            }
+        }
+    });
+}
+
+async fn purge_expired_sessions(pool: &DbPool) -> Result<()> {
+    let sessions = sqlx::query("DELETE FROM sessions WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM login_challenges WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    if sessions.rows_affected() > 0 {
+        tracing::info!("session purge: removed {} expired sessions", sessions.rows_affected());
+    }
+    Ok(())
+}
+
+/// The address a request came from. When the peer is a trusted reverse proxy
+/// this is the right-most X-Forwarded-For entry that is not itself a trusted
+/// proxy; entries further left can be forged by the client.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|s| s.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted.contains(ip))
+        .unwrap_or(peer)
+}
+
+/// How long the second login step stays open after the password was accepted.
+const LOGIN_CHALLENGE_MINUTES: i64 = 5;
+/// Wrong codes allowed before the user has to enter their password again.
+pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
 
-pub async fn create_session(pool: &DbPool, user_id: i32) -> Result<String> {
+/// Record that `user_id` passed the password check and still owes a second factor.
+pub async fn create_login_challenge(pool: &DbPool, user_id: i32) -> Result<String> {
+    sqlx::query("DELETE FROM login_challenges WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
     let token = Uuid::new_v4().to_string();
     sqlx::query(
-        "INSERT INTO sessions (token, user_id, expires_at) VALUES ($1, $2, NOW() + ($3 || ' days')::interval)",
+        "INSERT INTO login_challenges (token, user_id, expires_at) VALUES ($1, $2, NOW() + ($3 || ' minutes')::interval)",
     )
     .bind(&token)
     .bind(user_id)
-    .bind(SESSION_MAX_AGE_DAYS)
+    .bind(LOGIN_CHALLENGE_MINUTES)
     .execute(pool)
     .await?;
     Ok(token)
 }
 
-pub async fn get_user_id_from_session(pool: &DbPool, token: &str) -> Result<Option<i32>> {
-    let row: Option<(i32,)> = sqlx::query_as(
-        "SELECT user_id FROM sessions WHERE token = $1 AND expires_at > NOW()",
+/// The user behind an open login challenge, if it has not expired or run out of attempts.
+pub async fn get_login_challenge(pool: &DbPool, token: &str) -> Result<Option<i32>> {
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "SELECT c.user_id FROM login_challenges c JOIN users u ON u.id = c.user_id \
+         WHERE c.token = $1 AND c.expires_at > NOW() AND c.attempts < $2 AND u.disabled_at IS NULL",
     )
     .bind(token)
+    .bind(MAX_CHALLENGE_ATTEMPTS)
     .fetch_optional(pool)
     .await?;
-    Ok(row.map(|r| r.0))
+    Ok(user_id)
+}
+
+/// Count a wrong code; returns the attempts left.
+pub async fn fail_login_challenge(pool: &DbPool, token: &str) -> Result<i32> {
+    let attempts = sqlx::query_scalar::<_, i32>(
+        "UPDATE login_challenges SET attempts = attempts + 1 WHERE token = $1 RETURNING attempts",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(MAX_CHALLENGE_ATTEMPTS);
+    Ok((MAX_CHALLENGE_ATTEMPTS - attempts).max(0))
 }
 
-pub async fn delete_session(pool: &DbPool, token: &str) -> Result<()> {
-    sqlx::query("DELETE FROM sessions WHERE token = $1")
+pub async fn delete_login_challenge(pool: &DbPool, token: &str) -> Result<()> {
+    sqlx::query("DELETE FROM login_challenges WHERE token = $1")
         .bind(token)
         .execute(pool)
         .await?;
@@ -58,19 +236,130 @@ pub async fn require_auth(
                 .and_then(|p| p.strip_prefix(&format!("{}=", SESSION_COOKIE)))
                 .map(|s| s.trim_matches('"').to_string())
         });
-    let token = match cookie {
-        Some(t) if !t.is_empty() => t,
-        _ => return Ok(Redirect::to("/login").into_response()),
+    let token = match cookie
+        .as_deref()
+        .and_then(|c| verify_session_cookie(&state.config.session_secret, c))
+    {
+        Some(t) => t.to_string(),
+        None => return Ok(Redirect::to("/login").into_response()),
     };
-    let user_id = match get_user_id_from_session(&state.pool, &token).await {
-        Ok(Some(id)) => id,
+    let session = match get_session_user(&state.pool, &token).await {
+        Ok(Some(session)) => session,
         _ => return Ok(Redirect::to("/login").into_response()),
     };
+    let role = session.role;
+    if !role.allows(request.method(), request.uri().path()) {
+        return Err(AppError::Forbidden);
+    }
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|peer| client_ip(peer.ip(), request.headers(), &state.config.trusted_proxies));
+    if let Err(e) = touch_session(&state.pool, session.session_id, ip).await {
+        tracing::warn!("session {}: could not record activity: {}", session.session_id, e);
+    }
     let mut request = request;
-    request.extensions_mut().insert(UserId(user_id));
+    request.extensions_mut().insert(SessionId(session.session_id));
+    request.extensions_mut().insert(UserId(session.user_id));
+    request.extensions_mut().insert(role);
+    request.extensions_mut().insert(session.csrf);
+    request.extensions_mut().insert(Actor {
+        user_id: Some(session.user_id),
+        ip,
+        via: Via::Web,
+    });
     Ok(next.run(request).await)
 }
 
+/// What a panel user may do. Stored in `users.role`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Everything, including managing panel users.
+    Admin,
+    /// Create, change and delete sites, databases and jobs.
+    Operator,
+    /// View only.
+    ReadOnly,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Operator, Role::ReadOnly];
+
+    /// Unknown values get the least access.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "admin" => Role::Admin,
+            "operator" => Role::Operator,
+            _ => Role::ReadOnly,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Role::ALL.into_iter().find(|r| r.as_str() == value)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::ReadOnly => "readonly",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Admin => "Admin",
+            Role::Operator => "Operator",
+            Role::ReadOnly => "Read-only",
+        }
+    }
+
+    pub fn can_manage(self) -> bool {
+        matches!(self, Role::Admin | Role::Operator)
+    }
+
+    pub fn is_admin(self) -> bool {
+        self == Role::Admin
+    }
+
+    /// Whether this role may send `method` to `path` at all. Read-only users
+    /// may only look (and manage their own account or sign out); user
+    /// management and the audit log are for admins.
+    pub fn allows(self, method: &Method, path: &str) -> bool {
+        let read = matches!(*method, Method::GET | Method::HEAD);
+        let own_account = path.starts_with("/account") || path == "/logout";
+        let admin_only = path.starts_with("/users") || path.starts_with("/audit");
+        (read || own_account || self.can_manage()) && (!admin_only || self.is_admin())
+    }
+
+    /// For handlers that render forms: refuse read-only users.
+    pub fn require_manage(self) -> Result<()> {
+        if self.can_manage() {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+
+    pub fn require_admin(self) -> Result<()> {
+        if self.is_admin() {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+}
+
+/// The `sessions.id` of the signed-in session.
+#[derive(Clone, Copy)]
+pub struct SessionId(pub i64);
+
+impl SessionId {
+    pub fn value(self) -> i64 {
+        self.0
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct UserId(pub i32);
 
@@ -79,3 +368,88 @@ impl UserId {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "kkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkk";
+    const TOKEN: &str = "0b8f7c2e-session-token";
+
+    #[test]
+    fn role_gating_table() {
+        use Role::*;
+        let cases = [
+            (ReadOnly, Method::GET, "/sites/1", true),
+            (ReadOnly, Method::HEAD, "/", true),
+            (ReadOnly, Method::POST, "/sites/1/delete", false),
+            (ReadOnly, Method::POST, "/sites", false),
+            (ReadOnly, Method::GET, "/users", false),
+            (ReadOnly, Method::GET, "/audit", false),
+            (ReadOnly, Method::POST, "/account/password", true),
+            (ReadOnly, Method::POST, "/logout", true),
+            (Operator, Method::POST, "/sites/1/delete", true),
+            (Operator, Method::GET, "/users", false),
+            (Operator, Method::POST, "/users/2/role", false),
+            (Operator, Method::GET, "/audit", false),
+            (Operator, Method::POST, "/account/totp", true),
+            (Operator, Method::POST, "/logout", true),
+            (Admin, Method::POST, "/sites/1/delete", true),
+            (Admin, Method::POST, "/users/2/role", true),
+            (Admin, Method::GET, "/audit", true),
+            (Admin, Method::POST, "/logout", true),
+        ];
+        for (role, method, path, allowed) in cases {
+            assert_eq!(
+                role.allows(&method, path),
+                allowed,
+                "{:?} {} {}",
+                role,
+                method,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn session_cookie_signature() {
+        // HMAC-SHA256 of the token under the secret, by Python's hmac module.
+        let cookie = sign_session_token(SECRET, TOKEN);
+        assert_eq!(
+            cookie,
+            "0b8f7c2e-session-token.ss4XXoy8le14WYCsIXwxRxOnPpHLa3ehfQnpy7LTS7g"
+        );
+        assert_eq!(verify_session_cookie(SECRET, &cookie), Some(TOKEN));
+        // The tag is the part after the last dot.
+        let dotted = sign_session_token(SECRET, "a.b");
+        assert_eq!(verify_session_cookie(SECRET, &dotted), Some("a.b"));
+        assert_eq!(
+            hash_session_token(TOKEN),
+            "1ce2ff31d76e87925b5a4f55f50560134bcbdd2ccc308e7b7f230c77fa5aced9"
+        );
+    }
+
+    #[test]
+    fn bad_session_cookies() {
+        let cookie = sign_session_token(SECRET, TOKEN);
+        let (token, tag) = cookie.rsplit_once('.').unwrap();
+        let tampered_tag = format!("{}.{}A", token, &tag[..tag.len() - 1]);
+        let tampered_token = format!("{}x.{}", token, tag);
+        let padded = format!("{}=", cookie);
+        for value in [
+            tampered_tag.as_str(),
+            tampered_token.as_str(),
+            padded.as_str(),
+            "",
+            TOKEN,
+            "0b8f7c2e-session-token.",
+            ".ss4XXoy8le14WYCsIXwxRxOnPpHLa3ehfQnpy7LTS7g",
+            "0b8f7c2e-session-token.not*base64",
+        ] {
+            assert_eq!(verify_session_cookie(SECRET, value), None, "{:?}", value);
+        }
+        // A cookie signed under another secret, as after the secret changed.
+        let other = sign_session_token("another secret of at least 32 chars", TOKEN);
+        assert_eq!(verify_session_cookie(SECRET, &other), None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Content-defined chunking for the deduplicating store.
+//!
+//! A gear hash rolls over the stream and a chunk ends where its top bits are
+//! zero, so cut points follow the content: a change in one file only changes
+//! the chunks around it and the rest of the stream chunks as before. Chunks
+//! are cut with a stricter mask below the average size and a looser one above
+//! it (FastCDC's normalized chunking), which keeps sizes close to the average.
+//!
+//! The gear table and sizes decide where chunks are cut; changing them makes
+//! new backups share nothing with the chunks already stored.
+
+use std::io::{self, Read};
+
+/// No chunk is cut before this many bytes.
+pub const MIN_SIZE: usize = 256 * 1024;
+/// Chunk size aimed for.
+pub const AVG_SIZE: usize = 1024 * 1024;
+/// Chunks are cut here at the latest.
+pub const MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// Top 22 bits: used below `AVG_SIZE`, a cut is four times less likely.
+const MASK_SMALL: u64 = !0 << (64 - 22);
+/// Top 18 bits: used above `AVG_SIZE`, a cut is four times more likely.
+const MASK_LARGE: u64 = !0 << (64 - 18);
+
+/// 256 pseudo-random values (splitmix64), one per byte value.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6672_616e_6b65_6e21;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the chunk at the start of `data`, which holds the rest of the
+/// stream or at least `MAX_SIZE` bytes of it.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_SIZE);
+    let normal = AVG_SIZE.min(end);
+    let mut hash = 0u64;
+    let mut i = MIN_SIZE;
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_SMALL == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < end {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_LARGE == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    end
+}
+
+/// Splits a stream into content-defined chunks.
+pub struct Chunker<R> {
+    reader: R,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0; MAX_SIZE],
+            start: 0,
+            end: 0,
+            eof: false,
+        }
+    }
+
+    /// The next chunk, or `None` at the end of the stream.
+    pub fn next_chunk(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.end - self.start < MAX_SIZE && !self.eof {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+            while self.end < self.buf.len() {
+                match self.reader.read(&mut self.buf[self.end..]) {
+                    Ok(0) => {
+                        self.eof = true;
+                        break;
+                    }
+                    Ok(n) => self.end += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if self.start == self.end {
+            return Ok(None);
+        }
+        let len = cut_point(&self.buf[self.start..self.end]);
+        let chunk = &self.buf[self.start..self.start + len];
+        self.start += len;
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `len` bytes that look random and are the same on every run.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(data);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = chunker.next_chunk().unwrap() {
+            chunks.push(chunk.to_vec());
+        }
+        chunks
+    }
+
+    /// A reader handing out at most 1000 bytes at a time.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(1000);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn cut_point_bounds() {
+        // The rest of the stream, up to the minimum, is a single chunk.
+        assert_eq!(cut_point(&[]), 0);
+        assert_eq!(cut_point(&noise(1000, 1)), 1000);
+        assert_eq!(cut_point(&noise(MIN_SIZE, 1)), MIN_SIZE);
+        // Zeros never hash to a cut: cut at the maximum, or where the stream ends.
+        assert_eq!(cut_point(&vec![0; MAX_SIZE + 1000]), MAX_SIZE);
+        assert_eq!(cut_point(&vec![0; MIN_SIZE + 1000]), MIN_SIZE + 1000);
+        assert_eq!(cut_point(&vec![0; AVG_SIZE * 3]), AVG_SIZE * 3);
+        // A content cut is never inside the first MIN_SIZE bytes.
+        let data = noise(MAX_SIZE * 2, 2);
+        let len = cut_point(&data);
+        assert!(len > MIN_SIZE && len <= MAX_SIZE, "{}", len);
+        assert_eq!(cut_point(&data[..len + 1]), len);
+    }
+
+    #[test]
+    fn chunks_cover_the_stream() {
+        let mut data = noise(20 * 1024 * 1024, 3);
+        // A stretch with no cut in it is split at the maximum.
+        data[5 * 1024 * 1024..14 * 1024 * 1024].fill(0);
+        let chunks = chunks(&data);
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert!(chunk.len() > MIN_SIZE && chunk.len() <= MAX_SIZE);
+        }
+        assert!(!last.is_empty() && last.len() <= MAX_SIZE);
+        assert!(rest.iter().any(|c| c.len() == MAX_SIZE));
+        // Sizes stay near the average.
+        let noisy: Vec<usize> = chunks
+            .iter()
+            .map(Vec::len)
+            .filter(|&len| len < MAX_SIZE)
+            .collect();
+        let mean = noisy.iter().sum::<usize>() / noisy.len();
+        assert!(mean > AVG_SIZE / 2 && mean < AVG_SIZE * 2, "{}", mean);
+
+        // How the reader hands out the stream does not matter.
+        let mut chunker = Chunker::new(Trickle(&data));
+        for chunk in &chunks {
+            assert_eq!(chunker.next_chunk().unwrap(), Some(chunk.as_slice()));
+        }
+        assert_eq!(chunker.next_chunk().unwrap(), None);
+        assert!(Chunker::new(&[][..]).next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn insertion_only_changes_its_chunk() {
+        let data = noise(16 * 1024 * 1024, 4);
+        let at = 5 * 1024 * 1024 + 12345;
+        let mut edited = data[..at].to_vec();
+        edited.extend_from_slice(&noise(5000, 5));
+        edited.extend_from_slice(&data[at..]);
+
+        let before = chunks(&data);
+        let after = chunks(&edited);
+        let same_start = before
+            .iter()
+            .zip(&after)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let same_end = before
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        // Everything before the insertion and everything after the chunk
+        // holding it is cut the same as before.
+        assert!(same_start > 0 && same_end > 0);
+        assert_eq!(same_start + same_end + 1, before.len());
+        assert_eq!(before.len(), after.len());
+    }
+}
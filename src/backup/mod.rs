@@ -0,0 +1,416 @@
+//! Site backups.
+//!
+//! A backup is a job ([`JobPayload::BackupSite`]) in which the provisioner
+//! dumps the site's databases and archives them with the site folder into one
+//! bundle, `PANEL_BACKUP_DIR/<domain>/<domain>-<timestamp>.tar.gz`; a row in
+//! `site_backups` points at it. Backups are started from the site's Backups
+//! tab or by the scheduler, which queues one for every site whose slot (daily,
+//! or Sundays, at the site's hour in UTC) has passed since its last scheduled
+//! backup. After each successful backup the site's retention is applied and
+//! the bundles it no longer keeps are deleted.
+//!
+//! A backup is restored by a [`JobPayload::RestoreSite`] job, which first
+//! snapshots the site as it is, or into a new site by
+//! [`JobPayload::RestoreNewSite`]; see [`enqueue_restore`].
+//!
+//! With an S3-compatible bucket configured (`PANEL_S3_*`), each backup job
+//! also uploads its bundle there with a SHA-256 checksum on every part, checks
+//! that the store's checksum of the object it assembled (or its ETag, on
+//! stores without checksums) matches those parts, and applies the site's
+//! retention to the bucket. A failed upload leaves the backup succeeded: it is
+//! a warning in the job's output, shown on the Backups tab and raised as an
+//! alert. Bundles in the bucket can be downloaded again
+//! ([`JobPayload::DownloadBackup`]) and then restored like any other; see
+//! [`list_remote`].
+//!
+//! With `PANEL_BACKUP_DEDUP` set, the job then moves the bundle into a
+//! deduplicating chunk store and keeps only a snapshot manifest of it; see
+//! [`store`].
+//!
+//! A backup is verified by a test restore ([`JobPayload::VerifyBackup`]):
+//! its files are unpacked into a temporary directory and its dumps imported
+//! into a scratch database. The scheduler queues one for each site's newest
+//! backup every `PANEL_BACKUP_VERIFY_DAYS`; a failure is recorded on the
+//! backup and raises an alert. See [`enqueue_verify`].
+
+mod chunker;
+mod remote;
+mod restore;
+mod retention;
+mod s3;
+pub mod store;
+mod verify;
+
+pub use restore::{delete, enqueue_restore, get, load_restorable, Restorable, RestoreTarget};
+pub(crate) use restore::{record_snapshot, snapshot_recorded, verify_restore};
+pub use remote::{
+    describe_remote, enqueue_download, list_remote, Download, RemoteBackup, RemoteDomain,
+};
+pub(crate) use remote::{download, downloaded, upload};
+pub use retention::{kept, Retention};
+pub use store::{bundle_exists, collect_garbage};
+pub(crate) use store::{assemble, deduplicate, discard_assembled};
+pub use verify::{enqueue_verify, failed_verifications, FailedVerification};
+pub(crate) use verify::verified;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Utc};
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::jobs::{JobPayload, FAILED, QUEUED, RUNNING, SUCCEEDED};
+use crate::models::{SiteBackup, SITE_BACKUP_COLUMNS};
+use crate::provision::{wordpress_database, DatabaseRef, NewBackup};
+use crate::state::AppState;
+
+/// Values of `sites.backup_frequency`.
+pub const FREQUENCIES: [&str; 3] = ["off", "daily", "weekly"];
+/// Allowed range for keeping daily backups.
+pub const KEEP_DAILY: std::ops::RangeInclusive<i32> = 1..=90;
+/// Allowed range for keeping weekly backups; 0 keeps none beyond the daily ones.
+pub const KEEP_WEEKLY: std::ops::RangeInclusive<i32> = 0..=52;
+
+/// How often the scheduler looks for sites that are due.
+const TICK: Duration = Duration::from_secs(60);
+
+/// The directory holding the backups of `domain`.
+pub fn archive_dir(config: &Config, domain: &str) -> PathBuf {
+    config.backup_dir.join(domain)
+}
+
+/// Where a backup of `domain` taken at `at` is written.
+pub fn archive_path(config: &Config, domain: &str, at: DateTime<Utc>) -> PathBuf {
+    archive_dir(config, domain).join(format!("{}-{}.tar.gz", domain, at.format("%Y%m%d-%H%M%S")))
+}
+
+/// The most recent time a schedule should have started a backup, or `None`
+/// when backups are off.
+pub fn last_slot(now: DateTime<Utc>, frequency: &str, hour: i32) -> Option<DateTime<Utc>> {
+    let hour = u32::try_from(hour).ok().filter(|h| *h < 24)?;
+    let today = now.date_naive().and_hms_opt(hour, 0, 0)?.and_utc();
+    let (slot, period) = match frequency {
+        "daily" => (today, chrono::Duration::days(1)),
+        "weekly" => {
+            let since_sunday = now.weekday().num_days_from_sunday();
+            (
+                today - chrono::Duration::days(i64::from(since_sunday)),
+                chrono::Duration::weeks(1),
+            )
+        }
+        _ => return None,
+    };
+    Some(if slot > now { slot - period } else { slot })
+}
+
+/// Queue a backup of a site as `user_id` and return the job id. Refused while
+/// another backup of the site is queued or running.
+pub async fn enqueue(state: &AppState, site_id: i32, user_id: i32, scheduled: bool) -> Result<i32> {
+    let (domain, folder_path) = sqlx::query_as::<_, (String, String)>(
+        "SELECT domain, folder_path FROM sites WHERE id = $1",
+    )
+    .bind(site_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Site not found.".into()))?;
+    let busy = sqlx::query_scalar::<_, i32>(
+        "SELECT b.id FROM site_backups b JOIN jobs j ON j.id = b.job_id \
+         WHERE b.site_id = $1 AND j.state IN ($2, $3) LIMIT 1",
+    )
+    .bind(site_id)
+    .bind(QUEUED)
+    .bind(RUNNING)
+    .fetch_optional(&state.pool)
+    .await?;
+    if busy.is_some() {
+        return Err(AppError::Validation(
+            "A backup of this site is already queued or running.".into(),
+        ));
+    }
+    let databases = site_databases(&state.pool, site_id).await?;
+
+    let created_at = Utc::now();
+    let archive_path = archive_path(&state.config, &domain, created_at)
+        .to_string_lossy()
+        .into_owned();
+    let mut tx = state.pool.begin().await?;
+    let job_id = state
+        .jobs
+        .enqueue(
+            &mut *tx,
+            user_id,
+            JobPayload::BackupSite {
+                site_id,
+                backup: NewBackup {
+                    domain: domain.clone(),
+                    folder_path,
+                    databases,
+                    archive_path: archive_path.clone(),
+                    created_at,
+                },
+            },
+        )
+        .await?;
+    sqlx::query(
+        "INSERT INTO site_backups (site_id, domain, job_id, scheduled, archive_path, created_at) \
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(site_id)
+    .bind(&domain)
+    .bind(job_id)
+    .bind(scheduled)
+    .bind(&archive_path)
+    .bind(created_at)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        // Archive names have a resolution of one second.
+        sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+            AppError::Validation("A backup of this site was started a moment ago.".into())
+        }
+        e => e.into(),
+    })?;
+    tx.commit().await?;
+    state.jobs.wake();
+    Ok(job_id)
+}
+
+/// The databases a backup of the site dumps: those on its Databases tab and,
+/// for a WordPress site, the one WordPress was installed with.
+pub async fn site_databases(pool: &DbPool, site_id: i32) -> Result<Vec<DatabaseRef>> {
+    let mut databases: Vec<DatabaseRef> = sqlx::query_as::<_, (String, String)>(
+        "SELECT db_name, db_user FROM site_databases WHERE site_id = $1 ORDER BY db_name",
+    )
+    .bind(site_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(db_name, db_user)| DatabaseRef { db_name, db_user })
+    .collect();
+    let wordpress = sqlx::query_scalar::<_, String>(
+        "SELECT domain FROM sites WHERE id = $1 AND wordpress_installed",
+    )
+    .bind(site_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|domain| wordpress_database(&domain));
+    if let Some(wp) = wordpress {
+        if !databases.iter().any(|db| db.db_name == wp.db_name) {
+            databases.insert(0, wp);
+        }
+    }
+    Ok(databases)
+}
+
+/// A site's backups, newest first.
+pub async fn list(pool: &DbPool, site_id: i32) -> Result<Vec<SiteBackup>> {
+    let backups = sqlx::query_as::<_, SiteBackup>(&format!(
+        "SELECT {} FROM site_backups b JOIN jobs j ON j.id = b.job_id \
+         WHERE b.site_id = $1 ORDER BY b.created_at DESC, b.id DESC",
+        SITE_BACKUP_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(backups)
+}
+
+/// Record the size of a bundle the job wrote, then apply the site's retention.
+pub(crate) async fn written(state: &AppState, site_id: i32, archive_path: &str) -> Result<()> {
+    let pool = &state.pool;
+    // Missing when the dry-run provisioner "wrote" it, or once it went into
+    // the chunk store, which recorded its size.
+    let size = tokio::fs::metadata(archive_path)
+        .await
+        .ok()
+        .and_then(|m| i64::try_from(m.len()).ok());
+    let job_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE site_backups SET size_bytes = COALESCE($1, size_bytes) WHERE archive_path = $2 RETURNING job_id",
+    )
+    .bind(size)
+    .bind(archive_path)
+    .fetch_optional(pool)
+    .await?;
+    let pruned = apply_retention(pool, site_id, job_id).await?;
+    if pruned > 0 {
+        tracing::info!("site {}: pruned {} backups", site_id, pruned);
+    }
+    if let Err(e) = collect_garbage(&state.config).await {
+        tracing::warn!("backup store: could not collect garbage: {}", e);
+    }
+    Ok(())
+}
+
+/// Delete the site's successful backups its retention no longer keeps, and
+/// failed ones older than the oldest backup kept. Returns how many went.
+/// Snapshots taken before a restore and bundles downloaded from off-site
+/// storage are only deleted by hand.
+pub async fn prune(state: &AppState, site_id: i32) -> Result<usize> {
+    let pruned = apply_retention(&state.pool, site_id, None).await?;
+    if pruned > 0 {
+        if let Err(e) = collect_garbage(&state.config).await {
+            tracing::warn!("backup store: could not collect garbage: {}", e);
+        }
+    }
+    Ok(pruned)
+}
+
+/// [`prune`], counting the backup written by `job_id` as succeeded: its job is
+/// still running while the job records it.
+async fn apply_retention(pool: &DbPool, site_id: i32, job_id: Option<i32>) -> Result<usize> {
+    let Some((daily, weekly)) = sqlx::query_as::<_, (i32, i32)>(
+        "SELECT backup_keep_daily, backup_keep_weekly FROM sites WHERE id = $1",
+    )
+    .bind(site_id)
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(0);
+    };
+    let policy = Retention {
+        daily: usize::try_from(daily).unwrap_or(0).max(1),
+        weekly: usize::try_from(weekly).unwrap_or(0),
+    };
+
+    let backups = sqlx::query_as::<_, (i32, DateTime<Utc>, String, String)>(
+        "SELECT b.id, b.created_at, b.archive_path, \
+         CASE WHEN b.job_id = $4 THEN $2 ELSE j.state END AS state FROM site_backups b \
+         JOIN jobs j ON j.id = b.job_id \
+         WHERE b.site_id = $1 AND NOT b.before_restore AND NOT b.downloaded AND (j.state IN ($2, $3) OR b.job_id = $4)",
+    )
+    .bind(site_id)
+    .bind(SUCCEEDED)
+    .bind(FAILED)
+    .bind(job_id)
+    .fetch_all(pool)
+    .await?;
+    let succeeded: Vec<(i32, DateTime<Utc>)> = backups
+        .iter()
+        .filter(|b| b.3 == SUCCEEDED)
+        .map(|b| (b.0, b.1))
+        .collect();
+    let keep = kept(&succeeded, policy);
+    let oldest_kept = succeeded
+        .iter()
+        .filter(|b| keep.contains(&b.0))
+        .map(|b| b.1)
+        .min();
+
+    let mut pruned = 0;
+    for (id, created_at, archive_path, state) in backups {
+        let expired = if state == SUCCEEDED {
+            !keep.contains(&id)
+        } else {
+            oldest_kept.is_some_and(|oldest| created_at < oldest)
+        };
+        if !expired {
+            continue;
+        }
+        // A failed job may have left part of a bundle behind.
+        if let Err(e) = store::remove_bundle(&archive_path).await {
+            tracing::warn!("could not delete backup {}: {}", archive_path, e);
+            continue;
+        }
+        sqlx::query("DELETE FROM site_backups WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+/// Start the scheduler task.
+pub fn spawn_scheduler(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(TICK);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = queue_due(&state).await {
+                tracing::warn!("backup scheduler: {}", e);
+            }
+            if let Err(e) = verify::queue_verifications(&state).await {
+                tracing::warn!("backup scheduler: {}", e);
+            }
+        }
+    });
+}
+
+/// Queue a backup for every site whose last slot has passed since its last
+/// scheduled backup. Backups run as the site's owner.
+async fn queue_due(state: &AppState) -> Result<()> {
+    let sites = sqlx::query_as::<_, (i32, i32, String, i32, Option<DateTime<Utc>>)>(
+        "SELECT s.id, s.user_id, s.backup_frequency, s.backup_hour, \
+         (SELECT MAX(b.created_at) FROM site_backups b WHERE b.site_id = s.id AND b.scheduled) \
+         FROM sites s WHERE s.backup_frequency <> 'off'",
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    let now = Utc::now();
+    for (site_id, user_id, frequency, hour, last) in sites {
+        let Some(slot) = last_slot(now, &frequency, hour) else {
+            continue;
+        };
+        if last.is_some_and(|t| t >= slot) {
+            continue;
+        }
+        match enqueue(state, site_id, user_id, true).await {
+            Ok(job_id) => tracing::info!(
+                "site {}: queued scheduled backup as job {}",
+                site_id,
+                job_id
+            ),
+            // Another backup is still running; try again on a later tick.
+            Err(AppError::Validation(_)) => {}
+            Err(e) => tracing::warn!("site {}: could not queue backup: {}", site_id, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn last_slot_table() {
+        // (now, frequency, hour, expected slot); 16 March 2025 is a Sunday.
+        let cases = [
+            ("2025-03-12 05:00", "daily", 3, Some("2025-03-12 03:00")),
+            ("2025-03-12 03:00", "daily", 3, Some("2025-03-12 03:00")),
+            ("2025-03-12 02:59", "daily", 3, Some("2025-03-11 03:00")),
+            ("2025-03-01 00:30", "daily", 23, Some("2025-02-28 23:00")),
+            ("2025-03-12 05:00", "weekly", 3, Some("2025-03-09 03:00")),
+            ("2025-03-15 23:00", "weekly", 3, Some("2025-03-09 03:00")),
+            // On Sunday, this week's slot once its hour has come, last week's before.
+            ("2025-03-16 05:00", "weekly", 3, Some("2025-03-16 03:00")),
+            ("2025-03-16 02:00", "weekly", 3, Some("2025-03-09 03:00")),
+            ("2025-03-17 01:00", "weekly", 3, Some("2025-03-16 03:00")),
+            ("2025-03-12 05:00", "off", 3, None),
+            ("2025-03-12 05:00", "hourly", 3, None),
+            ("2025-03-12 05:00", "daily", 24, None),
+            ("2025-03-12 05:00", "weekly", -1, None),
+        ];
+        for (now, frequency, hour, expected) in cases {
+            assert_eq!(
+                last_slot(at(now), frequency, hour),
+                expected.map(at),
+                "{} {} at {}",
+                now,
+                frequency,
+                hour
+            );
+        }
+    }
+}
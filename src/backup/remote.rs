@@ -0,0 +1,673 @@
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ring::digest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::alerts;
+use crate::config::ProvisionerKind;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::jobs::{JobPayload, QUEUED, RUNNING, SUCCEEDED};
+use crate::models::{bytes_display, validate_domain, SiteBackup, SITE_BACKUP_COLUMNS};
+use crate::provision::{LogSink, NewBackup, ProvisionError, ProvisionOutput, Transcript};
+use crate::state::AppState;
+
+use super::s3::{self, S3Client, S3Object, UploadedPart};
+use super::{kept, Retention};
+
+/// Size of each multipart upload part; S3 wants at least 5 MiB for all but the last.
+const PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// A bundle in the bucket, with its local copy if there is one.
+pub struct RemoteBackup {
+    pub key: String,
+    pub file_name: String,
+    pub size: i64,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    /// Row for the same bundle on this server
+    pub local: Option<SiteBackup>,
+    /// Whether the local bundle file exists
+    pub on_disk: bool,
+}
+
+impl RemoteBackup {
+    pub fn uploaded_display(&self) -> String {
+        self.uploaded_at
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| "—".to_string())
+    }
+
+    pub fn size_display(&self) -> String {
+        bytes_display(self.size)
+    }
+
+    /// The local copy, when it can be restored from.
+    pub fn restorable(&self) -> Option<i32> {
+        self.local
+            .as_ref()
+            .filter(|b| self.on_disk && b.state == SUCCEEDED)
+            .map(|b| b.id)
+    }
+
+    /// The local copy's job, while it is being written or downloaded.
+    pub fn pending_job(&self) -> Option<i32> {
+        self.local
+            .as_ref()
+            .filter(|b| b.state == QUEUED || b.state == RUNNING)
+            .map(|b| b.job_id)
+    }
+}
+
+/// A domain's bundles in the bucket, newest first.
+pub struct RemoteDomain {
+    pub domain: String,
+    /// The panel's site with this domain, if any
+    pub site_id: Option<i32>,
+    pub backups: Vec<RemoteBackup>,
+}
+
+fn client(state: &AppState) -> Result<Option<S3Client>> {
+    state
+        .config
+        .s3
+        .as_ref()
+        .map(S3Client::new)
+        .transpose()
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("S3 client: {}", e)))
+}
+
+/// Where the bucket is, for display; `None` when off-site backups are off.
+pub fn describe_remote(state: &AppState) -> Option<String> {
+    client(state).ok().flatten().map(|c| c.describe())
+}
+
+/// When a regular bundle of `domain` named `file_name` was taken.
+fn bundle_time(domain: &str, file_name: &str) -> Option<DateTime<Utc>> {
+    let stamp = file_name
+        .strip_prefix(domain)?
+        .strip_prefix('-')?
+        .strip_suffix(".tar.gz")?;
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+fn failed(transcript: Transcript<'_>) -> ProvisionError {
+    ProvisionError::Failed {
+        status: None,
+        output: transcript.into_output(),
+    }
+}
+
+/// SHA-256 of a file, read off the async runtime.
+async fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        let mut file = std::fs::File::open(path)?;
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            context.update(&buf[..n]);
+        }
+        Ok(data_encoding::HEXLOWER.encode(context.finish().as_ref()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Fill `buf` from `file` as far as possible; returns how much was read.
+async fn read_part(file: &mut tokio::fs::File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Copy a bundle the job just wrote to the bucket, check the copy, then apply
+/// the site's retention to its copies in the bucket. A step of the backup job
+/// that never fails it: the bundle is good on this server either way, so a
+/// failed upload is a warning in the transcript, `upload_error` on the backup
+/// and an alert. Without off-site storage configured it does nothing.
+pub(crate) async fn upload(
+    state: &AppState,
+    site_id: i32,
+    backup: &NewBackup,
+    log: &LogSink,
+) -> ProvisionOutput {
+    let mut transcript = Transcript::new(log);
+    let copied = match client(state) {
+        Ok(None) => return transcript.into_output(),
+        // The dry-run provisioner only pretends to write the bundle.
+        Ok(Some(client))
+            if state.config.provisioner == ProvisionerKind::DryRun
+                && !Path::new(&backup.archive_path).exists() =>
+        {
+            transcript.push_stdout(format!("Dry run: not uploading to {}", client.describe()));
+            return transcript.into_output();
+        }
+        Ok(Some(client)) => copy_to_bucket(state, &client, backup, &mut transcript)
+            .await
+            .map(|()| client),
+        Err(e) => Err(e.to_string()),
+    };
+    let client = match copied {
+        Ok(client) => client,
+        Err(e) => {
+            transcript.push_stderr(format!(
+                "Warning: no off-site copy was made, the backup is only on this server: {}",
+                e
+            ));
+            upload_failed(state, backup, &e).await;
+            return transcript.into_output();
+        }
+    };
+
+    // The copy is safe; failing to prune older ones is not this job's failure.
+    if let Err(e) = prune_remote(
+        &state.pool,
+        &client,
+        site_id,
+        &backup.domain,
+        &mut transcript,
+    )
+    .await
+    {
+        transcript.push_stderr(format!(
+            "Warning: could not apply retention off-site: {}",
+            e
+        ));
+    }
+    transcript.into_output()
+}
+
+/// Upload `backup`, check the copy and record it on the backup.
+async fn copy_to_bucket(
+    state: &AppState,
+    client: &S3Client,
+    backup: &NewBackup,
+    transcript: &mut Transcript<'_>,
+) -> std::result::Result<(), String> {
+    let path = Path::new(&backup.archive_path);
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let key = client.key(&format!("{}/{}", backup.domain, file_name));
+
+    let sha256 = file_sha256(path)
+        .await
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let (size, parts) = upload_file(client, &key, path, &sha256, transcript)
+        .await
+        .map_err(|e| format!("upload to {} failed: {}", client.describe(), e))?;
+    let head = match client.head(&key).await {
+        Ok(Some(head)) => head,
+        Ok(None) => return Err("the uploaded copy is not in the bucket".to_string()),
+        Err(e) => return Err(format!("could not check the uploaded copy: {}", e)),
+    };
+    if head.size != size {
+        return Err(format!(
+            "the uploaded copy has {} bytes, not {}",
+            head.size, size
+        ));
+    }
+    // The store's own checksum of the object it assembled, compared with the
+    // parts read from the bundle: SHA-256 where the store keeps checksums,
+    // else the multipart ETag made of MD5s.
+    let check = match (&head.checksum_sha256, &head.etag) {
+        (Some(checksum), _) => Some((
+            "SHA-256 checksum",
+            checksum,
+            s3::multipart_checksum_matches(checksum, &parts),
+        )),
+        (None, Some(etag)) => {
+            s3::multipart_etag_matches(etag, &parts).map(|matches| ("ETag", etag, matches))
+        }
+        (None, None) => None,
+    };
+    match check {
+        Some((what, value, true)) => transcript.push_stdout(format!(
+            "Uploaded {}: the bucket's {} {} matches the {} parts sent (bundle SHA-256 {})",
+            key,
+            what,
+            value,
+            parts.len(),
+            sha256
+        )),
+        Some((what, value, false)) => {
+            return Err(format!(
+                "the uploaded copy does not match: its {} {} is not the one of the {} parts sent",
+                what,
+                value,
+                parts.len()
+            ));
+        }
+        None => {
+            transcript.push_stdout(format!("Uploaded {} (bundle SHA-256 {})", key, sha256));
+            transcript.push_stderr(format!(
+                "Warning: {} reports neither a SHA-256 checksum nor an MD5 ETag of the copy; only its size was checked",
+                client.describe()
+            ));
+        }
+    }
+    sqlx::query(
+        "UPDATE site_backups SET remote_key = $1, sha256 = $2, upload_error = NULL WHERE archive_path = $3",
+    )
+    .bind(&key)
+    .bind(&sha256)
+    .bind(&backup.archive_path)
+    .execute(&state.pool)
+    .await
+    .map_err(|e| format!("could not record the upload: {}", e))?;
+    Ok(())
+}
+
+/// Note on the backup why it has no off-site copy, and raise an alert.
+async fn upload_failed(state: &AppState, backup: &NewBackup, error: &str) {
+    let recorded = sqlx::query("UPDATE site_backups SET upload_error = $1 WHERE archive_path = $2")
+        .bind(error)
+        .bind(&backup.archive_path)
+        .execute(&state.pool)
+        .await;
+    if let Err(e) = recorded {
+        tracing::warn!(
+            "{}: could not record the failed upload: {}",
+            backup.archive_path,
+            e
+        );
+    }
+    let file = backup
+        .archive_path
+        .rsplit('/')
+        .next()
+        .unwrap_or(&backup.archive_path);
+    alerts::send(
+        &state.config,
+        &format!(
+            "Backup {} of {} has no off-site copy: {}",
+            file, backup.domain, error
+        ),
+    )
+    .await;
+}
+
+/// Multipart upload of `path` to `key`, aborted on any error. Returns the size
+/// sent and the parts.
+async fn upload_file(
+    client: &S3Client,
+    key: &str,
+    path: &Path,
+    sha256: &str,
+    transcript: &mut Transcript<'_>,
+) -> std::result::Result<(i64, Vec<UploadedPart>), String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| e.to_string())?;
+    let total = file.metadata().await.map_err(|e| e.to_string())?.len();
+    let parts = total.div_ceil(PART_SIZE as u64).max(1);
+    let upload_id = client
+        .create_multipart(key, sha256)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut uploaded = Vec::new();
+    let mut sent: i64 = 0;
+    let result: std::result::Result<(), String> = async {
+        let mut buf = vec![0u8; PART_SIZE];
+        for number in 1..=parts {
+            let n = read_part(&mut file, &mut buf)
+                .await
+                .map_err(|e| e.to_string())?;
+            let part = client
+                .upload_part(key, &upload_id, number as u32, buf[..n].to_vec())
+                .await
+                .map_err(|e| e.to_string())?;
+            uploaded.push(part);
+            sent += n as i64;
+            transcript.push_stdout(format!(
+                "Uploaded part {} of {} ({})",
+                number,
+                parts,
+                bytes_display(n as i64)
+            ));
+        }
+        client
+            .complete_multipart(key, &upload_id, &uploaded)
+            .await
+            .map_err(|e| e.to_string())
+    }
+    .await;
+    if let Err(e) = result {
+        if let Err(abort) = client.abort_multipart(key, &upload_id).await {
+            tracing::warn!("could not abort upload of {}: {}", key, abort);
+        }
+        return Err(e);
+    }
+    Ok((sent, uploaded))
+}
+
+/// Delete the site's bundles in the bucket that its retention no longer keeps.
+async fn prune_remote(
+    pool: &DbPool,
+    client: &S3Client,
+    site_id: i32,
+    domain: &str,
+    transcript: &mut Transcript<'_>,
+) -> std::result::Result<(), String> {
+    let Some((daily, weekly)) = sqlx::query_as::<_, (i32, i32)>(
+        "SELECT backup_keep_daily, backup_keep_weekly FROM sites WHERE id = $1",
+    )
+    .bind(site_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    else {
+        return Ok(());
+    };
+    let policy = Retention {
+        daily: usize::try_from(daily).unwrap_or(0).max(1),
+        weekly: usize::try_from(weekly).unwrap_or(0),
+    };
+    let objects = client
+        .list(&client.key(&format!("{}/", domain)))
+        .await
+        .map_err(|e| e.to_string())?;
+    let bundles: Vec<(i32, DateTime<Utc>, &S3Object)> = objects
+        .iter()
+        .filter_map(|o| {
+            let name = o.key.rsplit('/').next()?;
+            Some((bundle_time(domain, name)?, o))
+        })
+        .enumerate()
+        .map(|(i, (at, o))| (i as i32, at, o))
+        .collect();
+    let times: Vec<(i32, DateTime<Utc>)> = bundles.iter().map(|b| (b.0, b.1)).collect();
+    let keep = kept(&times, policy);
+    for (id, _, object) in &bundles {
+        if keep.contains(id) {
+            continue;
+        }
+        client
+            .delete(&object.key)
+            .await
+            .map_err(|e| e.to_string())?;
+        sqlx::query("UPDATE site_backups SET remote_key = NULL WHERE remote_key = $1")
+            .bind(&object.key)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        transcript.push_stdout(format!("Deleted off-site copy {}", object.key));
+    }
+    Ok(())
+}
+
+/// Every bundle in the bucket, grouped by domain, with what this server has of
+/// each. `None` when off-site backups are off.
+pub async fn list_remote(state: &AppState) -> Result<Option<Vec<RemoteDomain>>> {
+    let Some(client) = client(state)? else {
+        return Ok(None);
+    };
+    let objects = client.list(&client.key("")).await.map_err(|e| {
+        AppError::Validation(format!("Could not list {}: {}.", client.describe(), e))
+    })?;
+    let local = sqlx::query_as::<_, SiteBackup>(&format!(
+        "SELECT {} FROM site_backups b JOIN jobs j ON j.id = b.job_id",
+        SITE_BACKUP_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    let sites = sqlx::query_as::<_, (i32, String)>("SELECT id, domain FROM sites")
+        .fetch_all(&state.pool)
+        .await?;
+
+    let mut domains: Vec<RemoteDomain> = Vec::new();
+    for object in objects {
+        let Some(rest) = object.key.strip_prefix(&client.key("")) else {
+            continue;
+        };
+        let Some((domain, file_name)) = rest.split_once('/') else {
+            continue;
+        };
+        if bundle_time(domain, file_name).is_none() {
+            continue;
+        }
+        let archive_path = super::archive_dir(&state.config, domain).join(file_name);
+        let archive_path = archive_path.to_string_lossy();
+        let local = local
+            .iter()
+            .find(|b| b.archive_path == archive_path)
+            .cloned();
+        let backup = RemoteBackup {
+            on_disk: super::bundle_exists(&archive_path),
+            key: object.key.clone(),
+            file_name: file_name.to_string(),
+            size: object.size,
+            uploaded_at: object.last_modified,
+            local,
+        };
+        match domains.iter_mut().find(|d| d.domain == domain) {
+            Some(group) => group.backups.push(backup),
+            None => domains.push(RemoteDomain {
+                domain: domain.to_string(),
+                site_id: sites.iter().find(|s| s.1 == domain).map(|s| s.0),
+                backups: vec![backup],
+            }),
+        }
+    }
+    domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+    for group in &mut domains {
+        group.backups.sort_by(|a, b| b.file_name.cmp(&a.file_name));
+    }
+    Ok(Some(domains))
+}
+
+/// A queued download.
+pub struct Download {
+    pub job_id: i32,
+    /// The panel's site with the bundle's domain, if any
+    pub site_id: Option<i32>,
+    pub domain: String,
+    pub file_name: String,
+}
+
+/// Queue the download of a bundle from the bucket into the local backup
+/// directory. Once downloaded it restores like any other backup.
+pub async fn enqueue_download(state: &AppState, key: &str, user_id: i32) -> Result<Download> {
+    let Some(client) = client(state)? else {
+        return Err(AppError::Validation(
+            "Off-site backups are not configured.".into(),
+        ));
+    };
+    let (domain, file_name) = key
+        .strip_prefix(&client.key(""))
+        .and_then(|rest| rest.split_once('/'))
+        .ok_or(AppError::Validation("Not a backup in the bucket.".into()))?;
+    validate_domain(domain).map_err(AppError::Validation)?;
+    let created_at = bundle_time(domain, file_name)
+        .ok_or(AppError::Validation("Not a backup in the bucket.".into()))?;
+    let archive_path = super::archive_dir(&state.config, domain)
+        .join(file_name)
+        .to_string_lossy()
+        .into_owned();
+    if super::bundle_exists(&archive_path) {
+        return Err(AppError::Validation(
+            "This backup is already on this server.".into(),
+        ));
+    }
+    let busy = sqlx::query_scalar::<_, i32>(
+        "SELECT b.id FROM site_backups b JOIN jobs j ON j.id = b.job_id \
+         WHERE b.archive_path = $1 AND j.state IN ($2, $3)",
+    )
+    .bind(&archive_path)
+    .bind(QUEUED)
+    .bind(RUNNING)
+    .fetch_optional(&state.pool)
+    .await?;
+    if busy.is_some() {
+        return Err(AppError::Validation(
+            "This backup is already being downloaded.".into(),
+        ));
+    }
+    let site_id = sqlx::query_scalar::<_, i32>("SELECT id FROM sites WHERE domain = $1")
+        .bind(domain)
+        .fetch_optional(&state.pool)
+        .await?;
+
+    let mut tx = state.pool.begin().await?;
+    let job_id = state
+        .jobs
+        .enqueue(
+            &mut *tx,
+            user_id,
+            JobPayload::DownloadBackup {
+                site_id,
+                key: key.to_string(),
+                archive_path: archive_path.clone(),
+            },
+        )
+        .await?;
+    // A row whose bundle was deleted from disk gets the download's job.
+    sqlx::query(
+        "INSERT INTO site_backups (site_id, domain, job_id, downloaded, archive_path, remote_key, created_at) \
+         VALUES ($1, $2, $3, TRUE, $4, $5, $6) \
+         ON CONFLICT (archive_path) DO UPDATE SET job_id = EXCLUDED.job_id, downloaded = TRUE, \
+         remote_key = EXCLUDED.remote_key, size_bytes = NULL",
+    )
+    .bind(site_id)
+    .bind(domain)
+    .bind(job_id)
+    .bind(&archive_path)
+    .bind(key)
+    .bind(created_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    state.jobs.wake();
+    Ok(Download {
+        job_id,
+        site_id,
+        domain: domain.to_string(),
+        file_name: file_name.to_string(),
+    })
+}
+
+/// Download `key` to `archive_path`, checking it against the SHA-256 stored
+/// with it. Written under a temporary name, mode 0600, and renamed when it matches.
+pub(crate) async fn download(
+    state: &AppState,
+    key: &str,
+    archive_path: &str,
+    log: &LogSink,
+) -> std::result::Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    let client = match client(state) {
+        Ok(Some(client)) => client,
+        Ok(None) => {
+            transcript.push_stderr("Error: off-site backups are not configured (PANEL_S3_*).");
+            return Err(failed(transcript));
+        }
+        Err(e) => {
+            transcript.push_stderr(format!("Error: {}", e));
+            return Err(failed(transcript));
+        }
+    };
+    let partial = format!("{}.partial", archive_path);
+    let result = download_file(&client, key, &partial, &mut transcript).await;
+    let result = match result {
+        Ok(()) => tokio::fs::rename(&partial, archive_path)
+            .await
+            .map_err(|e| format!("could not move the download into place: {}", e)),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            transcript.push_stdout(format!("Downloaded {} to {}", key, archive_path));
+            Ok(transcript.into_output())
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            transcript.push_stderr(format!("Error: {}", e));
+            Err(failed(transcript))
+        }
+    }
+}
+
+async fn download_file(
+    client: &S3Client,
+    key: &str,
+    partial: &str,
+    transcript: &mut Transcript<'_>,
+) -> std::result::Result<(), String> {
+    let head = client
+        .head(key)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("{} is not in the bucket", key))?;
+    let mut response = client.get(key).await.map_err(|e| e.to_string())?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(partial)
+        .await
+        .map_err(|e| format!("could not create {}: {}", partial, e))?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut received: i64 = 0;
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        context.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("could not write {}: {}", partial, e))?;
+        received += chunk.len() as i64;
+    }
+    file.sync_all().await.map_err(|e| e.to_string())?;
+    let sha256 = data_encoding::HEXLOWER.encode(context.finish().as_ref());
+    if received != head.size {
+        return Err(format!(
+            "received {} bytes, the bucket has {}",
+            received, head.size
+        ));
+    }
+    match head.sha256 {
+        Some(expected) if expected != sha256 => {
+            return Err(format!(
+                "checksum mismatch: SHA-256 {} but {} was uploaded",
+                sha256, expected
+            ))
+        }
+        Some(_) => transcript.push_stdout(format!("Checked SHA-256 {}", sha256)),
+        None => transcript.push_stderr(format!(
+            "Warning: {} has no SHA-256 to check against; received {}",
+            key,
+            bytes_display(received)
+        )),
+    }
+    Ok(())
+}
+
+/// Record the size and checksum of a downloaded bundle.
+pub(crate) async fn downloaded(pool: &DbPool, archive_path: &str) -> Result<()> {
+    let size = tokio::fs::metadata(archive_path)
+        .await
+        .ok()
+        .and_then(|m| i64::try_from(m.len()).ok());
+    let sha256 = file_sha256(Path::new(archive_path)).await.ok();
+    sqlx::query("UPDATE site_backups SET size_bytes = $1, sha256 = $2 WHERE archive_path = $3")
+        .bind(size)
+        .bind(sha256)
+        .bind(archive_path)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::actions::{site_taken, DEFAULT_PHP_VERSION};
+use crate::config::{Config, ProvisionerKind};
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::health::{Expectations, HealthChecker, OFFLINE};
+use crate::jobs::{JobPayload, QUEUED, RUNNING, SUCCEEDED};
+use crate::models::{validate_domain, Site, SiteBackup, SITE_BACKUP_COLUMNS, SITE_COLUMNS};
+use crate::provision::{
+    domain_database, wordpress_database, BackupManifest, LogSink, NewBackup, NewDatabase, NewSite,
+    ProvisionOutput, RestoreDatabase, SiteRestore, Transcript, BACKUP_FORMAT,
+};
+use crate::state::AppState;
+
+use super::site_databases;
+
+/// How often a restore requests the site before giving up on it.
+const VERIFY_ATTEMPTS: u32 = 3;
+/// Pause between those requests, while Caddy picks up a new site.
+const VERIFY_DELAY: Duration = Duration::from_secs(3);
+
+/// Where a backup is restored to.
+pub enum RestoreTarget {
+    /// Over the site it was taken from, after snapshotting that site.
+    InPlace,
+    /// Into a new site with this domain and fresh databases.
+    NewSite(String),
+}
+
+/// A finished backup and the manifest read from its bundle.
+pub struct Restorable {
+    pub backup: SiteBackup,
+    pub manifest: BackupManifest,
+}
+
+/// Where the snapshot a restore takes of `domain` at `at` is written.
+fn snapshot_path(config: &Config, domain: &str, at: DateTime<Utc>) -> PathBuf {
+    super::archive_dir(config, domain).join(format!(
+        "{}-{}-before-restore.tar.gz",
+        domain,
+        at.format("%Y%m%d-%H%M%S")
+    ))
+}
+
+/// One backup, whatever its state.
+pub async fn get(pool: &DbPool, backup_id: i32) -> Result<SiteBackup> {
+    sqlx::query_as::<_, SiteBackup>(&format!(
+        "SELECT {} FROM site_backups b JOIN jobs j ON j.id = b.job_id WHERE b.id = $1",
+        SITE_BACKUP_COLUMNS
+    ))
+    .bind(backup_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Backup not found.".into()))
+}
+
+/// Load a backup and read its manifest. Refused unless the backup succeeded
+/// and its bundle is still there and readable.
+pub async fn load_restorable(pool: &DbPool, backup_id: i32) -> Result<Restorable> {
+    let backup = get(pool, backup_id).await?;
+    if backup.state != SUCCEEDED {
+        return Err(AppError::Validation(
+            "Only a backup that succeeded can be restored.".into(),
+        ));
+    }
+    let path = backup.archive_path.clone();
+    let manifest = tokio::task::spawn_blocking(move || super::store::read_manifest(&path))
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("reading manifest: {}", e)))?
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::Validation(format!(
+                "The bundle {} no longer exists.",
+                backup.archive_path
+            )),
+            _ => AppError::Validation(format!("Could not read the bundle: {}.", e)),
+        })?;
+    if manifest.format > BACKUP_FORMAT {
+        return Err(AppError::Validation(format!(
+            "The bundle has format {}; this panel reads up to {}.",
+            manifest.format, BACKUP_FORMAT
+        )));
+    }
+    Ok(Restorable { backup, manifest })
+}
+
+/// Queue a restore of a backup as `user_id` and return the job id.
+pub async fn enqueue_restore(
+    state: &AppState,
+    backup_id: i32,
+    user_id: i32,
+    target: RestoreTarget,
+) -> Result<i32> {
+    let Restorable { backup, manifest } = load_restorable(&state.pool, backup_id).await?;
+    let source = match backup.site_id {
+        Some(site_id) => {
+            sqlx::query_as::<_, Site>(&format!("SELECT {} FROM sites WHERE id = $1", SITE_COLUMNS))
+                .bind(site_id)
+                .fetch_optional(&state.pool)
+                .await?
+        }
+        None => None,
+    };
+    let payload = match target {
+        RestoreTarget::InPlace => {
+            let site = source.ok_or(AppError::Validation(
+                "The site this backup was taken from no longer exists; restore it into a new site."
+                    .into(),
+            ))?;
+            in_place(state, &backup, &manifest, site).await?
+        }
+        RestoreTarget::NewSite(domain) => {
+            let php_version = source
+                .map(|s| s.php_version)
+                .unwrap_or_else(|| DEFAULT_PHP_VERSION.to_string());
+            new_site(state, &backup, &manifest, domain.trim(), php_version).await?
+        }
+    };
+    state.jobs.enqueue(&state.pool, user_id, payload).await
+}
+
+async fn in_place(
+    state: &AppState,
+    backup: &SiteBackup,
+    manifest: &BackupManifest,
+    site: Site,
+) -> Result<JobPayload> {
+    let busy = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM jobs WHERE site_id = $1 AND state IN ($2, $3) LIMIT 1",
+    )
+    .bind(site.id)
+    .bind(QUEUED)
+    .bind(RUNNING)
+    .fetch_optional(&state.pool)
+    .await?;
+    if busy.is_some() {
+        return Err(AppError::Validation(
+            "Wait for the site's queued and running jobs to finish before restoring.".into(),
+        ));
+    }
+    let current = site_databases(&state.pool, site.id).await?;
+    let mut databases = Vec::new();
+    for db in &manifest.databases {
+        if !current.iter().any(|c| c.db_name == db.db_name) {
+            return Err(AppError::Validation(format!(
+                "Database {} in this backup no longer belongs to the site; restore it into a new site.",
+                db.db_name
+            )));
+        }
+        databases.push(RestoreDatabase {
+            from: db.db_name.clone(),
+            into: db.db_name.clone(),
+        });
+    }
+
+    let created_at = Utc::now();
+    Ok(JobPayload::RestoreSite {
+        site_id: site.id,
+        snapshot: NewBackup {
+            domain: site.domain.clone(),
+            folder_path: site.folder_path.clone(),
+            databases: current,
+            archive_path: snapshot_path(&state.config, &site.domain, created_at)
+                .to_string_lossy()
+                .into_owned(),
+            created_at,
+        },
+        restore: SiteRestore {
+            archive_path: backup.archive_path.clone(),
+            domain: site.domain,
+            folder_path: site.folder_path,
+            databases,
+            wp_config: None,
+            moved_from: None,
+        },
+    })
+}
+
+async fn new_site(
+    state: &AppState,
+    backup: &SiteBackup,
+    manifest: &BackupManifest,
+    domain: &str,
+    php_version: String,
+) -> Result<JobPayload> {
+    validate_domain(domain).map_err(AppError::Validation)?;
+    let folder_path = format!("/var/www/{}", domain);
+    if site_taken(&state.pool, domain, &folder_path).await? {
+        return Err(AppError::Validation(
+            "A site or alias with this domain, or a site with this path, already exists.".into(),
+        ));
+    }
+
+    // The WordPress database keeps its role (and its name pattern); the others
+    // are named after the new domain.
+    let source_wp = wordpress_database(&manifest.domain).db_name;
+    let mut databases = Vec::new();
+    let mut restores = Vec::new();
+    let mut wp_config = None;
+    let mut others = 0;
+    for db in &manifest.databases {
+        let names = if db.db_name == source_wp {
+            wordpress_database(domain)
+        } else {
+            others += 1;
+            let suffix = if others == 1 {
+                String::new()
+            } else {
+                format!("_{}", others)
+            };
+            domain_database("db_", domain, &suffix)
+        };
+        let new = NewDatabase {
+            db_name: names.db_name,
+            db_user: names.db_user,
+            password: uuid::Uuid::new_v4()
+                .simple()
+                .to_string()
+                .chars()
+                .take(24)
+                .collect(),
+            privileges: "full".to_string(),
+        };
+        if db.db_name == source_wp {
+            wp_config = Some(new.clone());
+        }
+        restores.push(RestoreDatabase {
+            from: db.db_name.clone(),
+            into: new.db_name.clone(),
+        });
+        databases.push(new);
+    }
+
+    let names: Vec<&str> = databases.iter().map(|d| d.db_name.as_str()).collect();
+    let users: Vec<&str> = databases.iter().map(|d| d.db_user.as_str()).collect();
+    let mut used = sqlx::query_scalar::<_, String>(
+        "SELECT db_name FROM site_databases WHERE db_name = ANY($1) OR db_user = ANY($2)",
+    )
+    .bind(&names)
+    .bind(&users)
+    .fetch_optional(&state.pool)
+    .await?;
+    if used.is_none() {
+        let wordpress_sites =
+            sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE wordpress_installed")
+                .fetch_all(&state.pool)
+                .await?;
+        used = wordpress_sites
+            .iter()
+            .map(|d| wordpress_database(d))
+            .find(|wp| names.contains(&wp.db_name.as_str()) || users.contains(&wp.db_user.as_str()))
+            .map(|wp| wp.db_name);
+    }
+    if let Some(name) = used {
+        return Err(AppError::Validation(format!(
+            "Database {} is already in use; choose another domain.",
+            name
+        )));
+    }
+
+    Ok(JobPayload::RestoreNewSite {
+        site: NewSite {
+            domain: domain.to_string(),
+            folder_path: folder_path.clone(),
+            php_version,
+            wordpress: None,
+        },
+        databases,
+        restore: SiteRestore {
+            archive_path: backup.archive_path.clone(),
+            domain: domain.to_string(),
+            folder_path,
+            databases: restores,
+            wp_config,
+            moved_from: (manifest.domain != domain).then(|| manifest.domain.clone()),
+        },
+    })
+}
+
+/// Delete a backup's bundle and row. Refused while its job is queued or running.
+pub async fn delete(state: &AppState, backup_id: i32) -> Result<SiteBackup> {
+    let pool = &state.pool;
+    let backup = get(pool, backup_id).await?;
+    if !backup.is_finished() {
+        return Err(AppError::Validation(
+            "A backup cannot be deleted while it is being written.".into(),
+        ));
+    }
+    super::store::remove_bundle(&backup.archive_path)
+        .await
+        .map_err(|e| {
+            AppError::Internal(anyhow::anyhow!(
+                "could not delete {}: {}",
+                backup.archive_path,
+                e
+            ))
+        })?;
+    sqlx::query("DELETE FROM site_backups WHERE id = $1")
+        .bind(backup_id)
+        .execute(pool)
+        .await?;
+    if backup.stored_bytes.is_some() {
+        if let Err(e) = super::collect_garbage(&state.config).await {
+            tracing::warn!("backup store: could not collect garbage: {}", e);
+        }
+    }
+    Ok(backup)
+}
+
+/// Whether a snapshot is already recorded, i.e. this is a retried restore
+/// whose first attempt may have changed the site since.
+pub(crate) async fn snapshot_recorded(pool: &DbPool, archive_path: &str) -> Result<bool> {
+    let found = sqlx::query_scalar::<_, i32>("SELECT id FROM site_backups WHERE archive_path = $1")
+        .bind(archive_path)
+        .fetch_optional(pool)
+        .await?;
+    Ok(found.is_some())
+}
+
+/// Add the row of a snapshot the restore job `job_id` has written.
+pub(crate) async fn record_snapshot(
+    pool: &DbPool,
+    site_id: i32,
+    job_id: i32,
+    snapshot: &NewBackup,
+) -> Result<()> {
+    let size = tokio::fs::metadata(&snapshot.archive_path)
+        .await
+        .ok()
+        .and_then(|m| i64::try_from(m.len()).ok());
+    sqlx::query(
+        "INSERT INTO site_backups (site_id, domain, job_id, before_restore, archive_path, size_bytes, created_at) \
+         VALUES ($1, $2, $3, TRUE, $4, $5, $6)",
+    )
+    .bind(site_id)
+    .bind(&snapshot.domain)
+    .bind(job_id)
+    .bind(&snapshot.archive_path)
+    .bind(size)
+    .bind(snapshot.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Request the restored site like the health checker does, a few times while
+/// Caddy catches up. Failing only warns: the restore itself is done.
+pub(crate) async fn verify_restore(
+    state: &AppState,
+    site_id: i32,
+    log: &LogSink,
+) -> ProvisionOutput {
+    let mut transcript = Transcript::new(log);
+    if state.config.provisioner == ProvisionerKind::DryRun {
+        transcript.push_stdout("Dry run: not requesting the site.");
+        return transcript.into_output();
+    }
+    let site = match sqlx::query_as::<_, Site>(&format!(
+        "SELECT {} FROM sites WHERE id = $1",
+        SITE_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_one(&state.pool)
+    .await
+    {
+        Ok(site) => site,
+        Err(e) => {
+            transcript.push_stderr(format!(
+                "Warning: could not load the site to check it: {}",
+                e
+            ));
+            return transcript.into_output();
+        }
+    };
+    let host = site.primary_host();
+    let checker = HealthChecker::new(state.config.health_check_addr);
+    let expect = Expectations::for_site(&site);
+    for attempt in 1..=VERIFY_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(VERIFY_DELAY).await;
+        }
+        let result = checker.check(&host, &expect).await;
+        if result.status() != OFFLINE {
+            transcript.push_stdout(format!(
+                "Checked https://{}/: {} ({}).",
+                host,
+                result.status_code.unwrap_or_default(),
+                result.status()
+            ));
+            return transcript.into_output();
+        }
+        let error = result.error.unwrap_or_else(|| "no response".to_string());
+        if attempt == VERIFY_ATTEMPTS {
+            transcript.push_stderr(format!(
+                "Warning: https://{}/ did not answer as expected after the restore: {}",
+                host, error
+            ));
+        } else {
+            transcript.push_stdout(format!(
+                "Checked https://{}/: {}; trying again.",
+                host, error
+            ));
+        }
+    }
+    transcript.into_output()
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, Utc};
+
+/// How many backups to keep, counted like `restic forget --keep-daily/--keep-weekly`:
+/// the newest backup of each of the last `daily` days that have one, and the
+/// newest of each of the last `weekly` ISO weeks that have one.
+#[derive(Clone, Copy, Debug)]
+pub struct Retention {
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+/// Ids of the backups `policy` keeps. `backups` are the successful ones, in any order.
+pub fn kept(backups: &[(i32, DateTime<Utc>)], policy: Retention) -> HashSet<i32> {
+    let mut newest_first = backups.to_vec();
+    newest_first.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let mut keep = HashSet::new();
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    for (id, created_at) in newest_first {
+        let day = created_at.date_naive();
+        if days.len() < policy.daily && days.insert(day) {
+            keep.insert(id);
+        }
+        let week = created_at.iso_week();
+        if weeks.len() < policy.weekly && weeks.insert((week.year(), week.week())) {
+            keep.insert(id);
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    /// Sorted ids `kept` keeps of backups taken at "YYYY-MM-DD HH:MM".
+    fn keep(backups: &[(i32, &str)], daily: usize, weekly: usize) -> Vec<i32> {
+        let backups: Vec<(i32, DateTime<Utc>)> = backups
+            .iter()
+            .map(|(id, at)| {
+                let at = NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M").unwrap();
+                (*id, at.and_utc())
+            })
+            .collect();
+        let mut ids: Vec<i32> = kept(&backups, Retention { daily, weekly })
+            .into_iter()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn several_backups_on_one_day() {
+        let day = [
+            (1, "2025-03-12 01:00"),
+            (3, "2025-03-12 17:00"),
+            (2, "2025-03-12 09:00"),
+        ];
+        assert_eq!(keep(&day, 1, 0), [3]);
+        assert_eq!(keep(&day, 7, 0), [3]);
+        assert_eq!(keep(&day, 1, 4), [3]);
+        assert_eq!(
+            keep(&[(1, "2025-03-12 03:00"), (2, "2025-03-12 03:00")], 1, 1),
+            [2]
+        );
+    }
+
+    #[test]
+    fn iso_week_boundaries() {
+        // Sunday 16 March closes ISO week 11; Monday 17 March opens week 12.
+        let weeks = [
+            (1, "2025-03-15 03:00"),
+            (2, "2025-03-16 03:00"),
+            (3, "2025-03-16 23:00"),
+            (4, "2025-03-17 00:00"),
+            (5, "2025-03-17 03:00"),
+        ];
+        assert_eq!(keep(&weeks, 0, 2), [3, 5]);
+        assert_eq!(keep(&weeks, 0, 1), [5]);
+        assert_eq!(keep(&weeks, 3, 2), [1, 3, 5]);
+        // 30 December 2024 to 1 January 2025 are all in ISO week 1 of 2025.
+        let new_year = [
+            (1, "2024-12-29 03:00"),
+            (2, "2024-12-30 03:00"),
+            (3, "2025-01-01 03:00"),
+        ];
+        assert_eq!(keep(&new_year, 0, 1), [3]);
+        assert_eq!(keep(&new_year, 0, 2), [1, 3]);
+    }
+
+    #[test]
+    fn weekly_zero_and_gaps() {
+        let backups = [
+            (1, "2025-03-01 03:00"),
+            (2, "2025-03-02 03:00"),
+            (3, "2025-03-10 03:00"),
+            (4, "2025-03-10 04:00"),
+        ];
+        assert_eq!(
+            keep(&backups, 2, 0),
+            [2, 4],
+            "days that have a backup count"
+        );
+        assert_eq!(keep(&backups, 0, 0), [] as [i32; 0]);
+        assert_eq!(keep(&[], 7, 4), [] as [i32; 0]);
+    }
+}
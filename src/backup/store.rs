@@ -0,0 +1,610 @@
+//! Deduplicating backup store (`PANEL_BACKUP_DEDUP`).
+//!
+//! The bundle a backup job wrote is cut into content-defined chunks (see
+//! [`super::chunker`]) of its uncompressed tar stream, which holds the site
+//! folder and the database dumps. Each chunk is stored once under
+//! `PANEL_BACKUP_DIR/.chunks/<ab>/<sha256>`, gzipped, whichever backup or site
+//! it came from. The backup keeps a snapshot manifest listing its chunks,
+//! `<domain>-<timestamp>.snapshot.json`, in place of its `.tar.gz`; files that
+//! did not change since the last backup cost nothing but their entries.
+//!
+//! A restore reassembles the `.tar.gz` at its usual path for the provisioner
+//! and removes it afterwards. Chunks no snapshot lists are removed by
+//! [`collect_garbage`] after retention and deletes; chunks touched in the last
+//! day are left alone so a backup still being written keeps the ones it reuses.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ProvisionerKind};
+use crate::models::bytes_display;
+use crate::provision::{
+    BackupManifest, LogSink, NewBackup, ProvisionError, ProvisionOutput, Transcript,
+};
+use crate::state::AppState;
+
+use super::chunker::Chunker;
+
+/// Version of the snapshot manifest.
+pub const SNAPSHOT_FORMAT: u32 = 1;
+/// Unreferenced chunks younger than this are kept.
+const GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Name of the chunk directory in `PANEL_BACKUP_DIR`; no domain starts with a dot.
+const CHUNK_DIR: &str = ".chunks";
+
+/// What a deduplicated backup is made of.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: u32,
+    /// `manifest.json` of the bundle
+    pub bundle: BackupManifest,
+    /// Length of the uncompressed tar stream
+    pub size: u64,
+    /// The tar stream, in order
+    pub chunks: Vec<ChunkRef>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// SHA-256 of the chunk, hex
+    pub id: String,
+    pub len: u32,
+}
+
+impl Snapshot {
+    fn read(path: &Path) -> io::Result<Self> {
+        let snapshot: Self = serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))
+            .map_err(io::Error::other)?;
+        if snapshot.format > SNAPSHOT_FORMAT {
+            return Err(io::Error::other(format!(
+                "snapshot format {}; this panel reads up to {}",
+                snapshot.format, SNAPSHOT_FORMAT
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Where the chunks of every deduplicated backup are kept.
+pub fn chunk_dir(config: &Config) -> PathBuf {
+    config.backup_dir.join(CHUNK_DIR)
+}
+
+/// The snapshot manifest standing in for the bundle at `archive_path`.
+pub fn snapshot_path(archive_path: &str) -> PathBuf {
+    let stem = archive_path.strip_suffix(".tar.gz").unwrap_or(archive_path);
+    PathBuf::from(format!("{}.snapshot.json", stem))
+}
+
+/// Whether the backup at `archive_path` is on this server, as a bundle or a snapshot.
+pub fn bundle_exists(archive_path: &str) -> bool {
+    Path::new(archive_path).is_file() || snapshot_path(archive_path).is_file()
+}
+
+/// The manifest of the backup at `archive_path`, from its bundle or its snapshot.
+pub fn read_manifest(archive_path: &str) -> io::Result<BackupManifest> {
+    let snapshot = snapshot_path(archive_path);
+    if !Path::new(archive_path).exists() && snapshot.exists() {
+        return Snapshot::read(&snapshot).map(|s| s.bundle);
+    }
+    BackupManifest::read(Path::new(archive_path))
+}
+
+fn chunk_path(chunks: &Path, id: &str) -> PathBuf {
+    chunks.join(&id[..2]).join(id)
+}
+
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Counts from storing one bundle.
+struct Stored {
+    chunks: usize,
+    new_chunks: usize,
+    /// Compressed size of the chunks this backup added
+    new_bytes: u64,
+}
+
+/// Chunk the bundle at `archive` into `chunks` and write its snapshot manifest.
+fn store_bundle(chunks: &Path, archive: &str) -> io::Result<Stored> {
+    let bundle = BackupManifest::read(Path::new(archive))?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(chunks)?;
+    let mut chunker = Chunker::new(GzDecoder::new(io::BufReader::new(fs::File::open(archive)?)));
+    let mut snapshot = Snapshot {
+        format: SNAPSHOT_FORMAT,
+        bundle,
+        size: 0,
+        chunks: Vec::new(),
+    };
+    let mut stored = Stored {
+        chunks: 0,
+        new_chunks: 0,
+        new_bytes: 0,
+    };
+    while let Some(data) = chunker.next_chunk()? {
+        let id = data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, data).as_ref());
+        let path = chunk_path(chunks, &id);
+        match fs::OpenOptions::new().write(true).open(&path) {
+            // Reused: mark it as live for the garbage collector's grace period.
+            Ok(file) => file.set_modified(SystemTime::now())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let dir = path.parent().unwrap_or(chunks);
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)?;
+                let partial = dir.join(format!(".{}.{}", id, uuid::Uuid::new_v4().simple()));
+                let mut gz = GzEncoder::new(create_private(&partial)?, Compression::default());
+                gz.write_all(data)?;
+                let file = gz.finish()?;
+                file.sync_all()?;
+                stored.new_bytes += file.metadata()?.len();
+                fs::rename(&partial, &path)?;
+                stored.new_chunks += 1;
+            }
+            Err(e) => return Err(e),
+        }
+        snapshot.size += data.len() as u64;
+        snapshot.chunks.push(ChunkRef {
+            id,
+            len: data.len() as u32,
+        });
+        stored.chunks += 1;
+    }
+
+    let path = snapshot_path(archive);
+    let partial = path.with_extension("json.partial");
+    let mut file = io::BufWriter::new(create_private(&partial)?);
+    serde_json::to_writer(&mut file, &snapshot).map_err(io::Error::other)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&partial, &path)?;
+    Ok(stored)
+}
+
+/// Write the bundle listed by the snapshot at `snapshot` to `out`, checking
+/// every chunk against its checksum.
+fn assemble_bundle(chunks: &Path, snapshot: &Path, out: &str) -> io::Result<u64> {
+    let snapshot = Snapshot::read(snapshot)?;
+    let partial = format!("{}.partial", out);
+    let result = (|| {
+        let mut gz = GzEncoder::new(
+            io::BufWriter::new(create_private(Path::new(&partial))?),
+            Compression::default(),
+        );
+        let mut data = Vec::new();
+        for chunk in &snapshot.chunks {
+            if chunk.id.len() != 64 || !chunk.id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(io::Error::other(format!("bad chunk id {:?}", chunk.id)));
+            }
+            let file = fs::File::open(chunk_path(chunks, &chunk.id)).map_err(|e| {
+                io::Error::new(e.kind(), format!("chunk {} is missing: {}", chunk.id, e))
+            })?;
+            data.clear();
+            GzDecoder::new(file).read_to_end(&mut data)?;
+            let id =
+                data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, &data).as_ref());
+            if id != chunk.id || data.len() != chunk.len as usize {
+                return Err(io::Error::other(format!("chunk {} is corrupt", chunk.id)));
+            }
+            gz.write_all(&data)?;
+        }
+        gz.finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&partial, out)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result.map(|()| snapshot.size)
+}
+
+/// Counts from a garbage collection.
+pub struct Collected {
+    pub chunks: usize,
+    pub bytes: u64,
+}
+
+/// Remove chunks no snapshot under `backup_dir` lists. Gives up without
+/// removing anything if a snapshot cannot be read.
+fn collect(backup_dir: &Path) -> io::Result<Collected> {
+    let chunks = backup_dir.join(CHUNK_DIR);
+    let mut collected = Collected {
+        chunks: 0,
+        bytes: 0,
+    };
+    if !chunks.is_dir() {
+        return Ok(collected);
+    }
+    let mut live = HashSet::new();
+    for domain in fs::read_dir(backup_dir)? {
+        let domain = domain?.path();
+        if !domain.is_dir() || domain == chunks {
+            continue;
+        }
+        for entry in fs::read_dir(&domain)? {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(".snapshot.json") {
+                let snapshot = Snapshot::read(&path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+                live.extend(snapshot.chunks.into_iter().map(|c| c.id));
+            }
+        }
+    }
+    let now = SystemTime::now();
+    for dir in fs::read_dir(&chunks)? {
+        let dir = dir?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if live.contains(&name) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            let recent = meta
+                .modified()
+                .ok()
+                .and_then(|t| now.duration_since(t).ok())
+                .is_none_or(|age| age < GRACE);
+            if recent {
+                continue;
+            }
+            fs::remove_file(entry.path())?;
+            collected.chunks += 1;
+            collected.bytes += meta.len();
+        }
+    }
+    Ok(collected)
+}
+
+fn failed(transcript: Transcript<'_>) -> ProvisionError {
+    ProvisionError::Failed {
+        status: None,
+        output: transcript.into_output(),
+    }
+}
+
+/// Move the bundle a backup job just wrote into the chunk store and replace
+/// it with its snapshot manifest. A step of the backup job after the upload;
+/// without `PANEL_BACKUP_DEDUP` it does nothing.
+pub(crate) async fn deduplicate(
+    state: &AppState,
+    backup: &NewBackup,
+    log: &LogSink,
+) -> Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    if !state.config.backup_dedup {
+        return Ok(transcript.into_output());
+    }
+    if state.config.provisioner == ProvisionerKind::DryRun {
+        transcript.push_stdout("Dry run: not adding the bundle to the chunk store");
+        return Ok(transcript.into_output());
+    }
+    let chunks = chunk_dir(&state.config);
+    let archive = backup.archive_path.clone();
+    let size = match tokio::fs::metadata(&archive).await {
+        Ok(meta) => meta.len() as i64,
+        Err(e) => {
+            transcript.push_stderr(format!("Error: could not read {}: {}", archive, e));
+            return Err(failed(transcript));
+        }
+    };
+    let stored = match tokio::task::spawn_blocking(move || store_bundle(&chunks, &archive)).await {
+        Ok(Ok(stored)) => stored,
+        Ok(Err(e)) => {
+            transcript.push_stderr(format!(
+                "Error: could not store the bundle in chunks: {}",
+                e
+            ));
+            return Err(failed(transcript));
+        }
+        Err(e) => {
+            transcript.push_stderr(format!("Error: {}", e));
+            return Err(failed(transcript));
+        }
+    };
+    transcript.push_stdout(format!(
+        "Stored the bundle as {} chunks, {} of them new ({})",
+        stored.chunks,
+        stored.new_chunks,
+        bytes_display(stored.new_bytes as i64)
+    ));
+    let recorded = sqlx::query(
+        "UPDATE site_backups SET size_bytes = $1, stored_bytes = $2 WHERE archive_path = $3",
+    )
+    .bind(size)
+    .bind(stored.new_bytes as i64)
+    .bind(&backup.archive_path)
+    .execute(&state.pool)
+    .await;
+    if let Err(e) = recorded {
+        transcript.push_stderr(format!("Error: could not record the snapshot: {}", e));
+        return Err(failed(transcript));
+    }
+    if let Err(e) = tokio::fs::remove_file(&backup.archive_path).await {
+        transcript.push_stderr(format!(
+            "Warning: could not remove {} after storing it: {}",
+            backup.archive_path, e
+        ));
+    }
+    Ok(transcript.into_output())
+}
+
+/// Put the bundle of a deduplicated backup back together at `archive_path`
+/// for a restore or test restore. Does nothing for a backup kept as a bundle.
+pub(crate) async fn assemble(
+    state: &AppState,
+    archive_path: &str,
+    log: &LogSink,
+) -> Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    let snapshot = snapshot_path(archive_path);
+    if Path::new(archive_path).exists() || !snapshot.exists() {
+        return Ok(transcript.into_output());
+    }
+    if state.config.provisioner == ProvisionerKind::DryRun {
+        transcript.push_stdout(format!("Dry run: not assembling {}", archive_path));
+        return Ok(transcript.into_output());
+    }
+    let chunks = chunk_dir(&state.config);
+    let out = archive_path.to_string();
+    match tokio::task::spawn_blocking(move || assemble_bundle(&chunks, &snapshot, &out)).await {
+        Ok(Ok(size)) => {
+            transcript.push_stdout(format!(
+                "Assembled {} from the chunk store ({} unpacked)",
+                archive_path,
+                bytes_display(size as i64)
+            ));
+            Ok(transcript.into_output())
+        }
+        Ok(Err(e)) => {
+            transcript.push_stderr(format!("Error: could not assemble {}: {}", archive_path, e));
+            Err(failed(transcript))
+        }
+        Err(e) => {
+            transcript.push_stderr(format!("Error: {}", e));
+            Err(failed(transcript))
+        }
+    }
+}
+
+/// Remove a bundle [`assemble`] put together, once the restore is done with it.
+pub(crate) async fn discard_assembled(archive_path: &str) {
+    if !snapshot_path(archive_path).exists() {
+        return;
+    }
+    match tokio::fs::remove_file(archive_path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("could not remove assembled bundle {}: {}", archive_path, e),
+    }
+}
+
+/// Remove a backup from disk: its bundle and, if deduplicated, its snapshot.
+pub(crate) async fn remove_bundle(archive_path: &str) -> io::Result<()> {
+    for path in [PathBuf::from(archive_path), snapshot_path(archive_path)] {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove the chunks no snapshot lists any more.
+pub async fn collect_garbage(config: &Config) -> io::Result<Collected> {
+    let backup_dir = config.backup_dir.clone();
+    let collected = tokio::task::spawn_blocking(move || collect(&backup_dir))
+        .await
+        .map_err(io::Error::other)??;
+    if collected.chunks > 0 {
+        tracing::info!(
+            "backup store: removed {} unreferenced chunks ({})",
+            collected.chunks,
+            bytes_display(collected.bytes as i64)
+        );
+    }
+    Ok(collected)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    /// A directory of its own under the system's temporary directory.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("panel-store-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Write a bundle with a manifest and a `size`-byte file at `path`;
+    /// returns its uncompressed tar stream.
+    fn write_bundle(path: &Path, size: usize, seed: u64) -> Vec<u8> {
+        let manifest = BackupManifest {
+            format: crate::provision::BACKUP_FORMAT,
+            domain: "example.test".to_string(),
+            folder_path: "/var/www/example.test".to_string(),
+            created_at: Utc::now(),
+            databases: Vec::new(),
+        };
+        let mut state = seed;
+        let content: Vec<u8> = (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect();
+        let mut tar = tar::Builder::new(Vec::new());
+        for (name, data) in [
+            ("manifest.json", serde_json::to_vec(&manifest).unwrap()),
+            ("site/wp-content/uploads/photo.jpg", content),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, data.as_slice()).unwrap();
+        }
+        let stream = tar.into_inner().unwrap();
+        let mut gz = GzEncoder::new(fs::File::create(path).unwrap(), Compression::fast());
+        gz.write_all(&stream).unwrap();
+        gz.finish().unwrap();
+        stream
+    }
+
+    fn gunzip(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        GzDecoder::new(fs::File::open(path).unwrap())
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    fn chunk_files(chunks: &Path) -> HashSet<String> {
+        fs::read_dir(chunks)
+            .unwrap()
+            .flat_map(|dir| fs::read_dir(dir.unwrap().path()).unwrap())
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn age(path: &Path, hours: u64) {
+        let at = SystemTime::now() - Duration::from_secs(hours * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(at)
+            .unwrap();
+    }
+
+    #[test]
+    fn store_then_assemble() {
+        let dir = TempDir::new();
+        let chunks = dir.0.join(CHUNK_DIR);
+        let archive = dir.0.join("example.test-20250318-030000.tar.gz");
+        let archive = archive.to_str().unwrap();
+        let stream = write_bundle(Path::new(archive), 6 * 1024 * 1024, 1);
+
+        let stored = store_bundle(&chunks, archive).unwrap();
+        assert!(stored.chunks > 1);
+        assert_eq!(stored.new_chunks, stored.chunks);
+        assert_eq!(chunk_files(&chunks).len(), stored.chunks);
+        let snapshot = snapshot_path(archive);
+        assert_eq!(read_manifest(archive).unwrap().domain, "example.test");
+
+        // The same bundle again adds nothing.
+        let again = store_bundle(&chunks, archive).unwrap();
+        assert_eq!((again.chunks, again.new_chunks), (stored.chunks, 0));
+
+        fs::remove_file(archive).unwrap();
+        assert!(bundle_exists(archive));
+        assert_eq!(read_manifest(archive).unwrap().domain, "example.test");
+        let size = assemble_bundle(&chunks, &snapshot, archive).unwrap();
+        assert_eq!(size, stream.len() as u64);
+        assert_eq!(gunzip(Path::new(archive)), stream);
+
+        // A damaged chunk is caught, and leaves no partial bundle behind.
+        fs::remove_file(archive).unwrap();
+        let Snapshot { chunks: refs, .. } = Snapshot::read(&snapshot).unwrap();
+        let damaged = chunk_path(&chunks, &refs[1].id);
+        let mut gz = GzEncoder::new(fs::File::create(&damaged).unwrap(), Compression::fast());
+        gz.write_all(b"something else").unwrap();
+        gz.finish().unwrap();
+        let err = assemble_bundle(&chunks, &snapshot, archive).unwrap_err();
+        assert!(err.to_string().contains("is corrupt"), "{}", err);
+        assert!(!Path::new(archive).exists());
+        assert!(!Path::new(&format!("{}.partial", archive)).exists());
+    }
+
+    #[test]
+    fn collect_keeps_listed_and_recent_chunks() {
+        let dir = TempDir::new();
+        let chunks = dir.0.join(CHUNK_DIR);
+        fs::create_dir(dir.0.join("example.test")).unwrap();
+        let archive = dir
+            .0
+            .join("example.test/example.test-20250318-030000.tar.gz");
+        let archive = archive.to_str().unwrap();
+        write_bundle(Path::new(archive), 3 * 1024 * 1024, 2);
+        store_bundle(&chunks, archive).unwrap();
+        let listed = chunk_files(&chunks);
+        // Listed chunks stay however old they are.
+        for id in &listed {
+            age(&chunk_path(&chunks, id), 48);
+        }
+
+        let unlisted = |name: &str, hours: u64| {
+            let id = data_encoding::HEXLOWER
+                .encode(digest::digest(&digest::SHA256, name.as_bytes()).as_ref());
+            let path = chunk_path(&chunks, &id);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, name).unwrap();
+            age(&path, hours);
+            id
+        };
+        let old = unlisted("old", 25);
+        let recent = unlisted("recent", 23);
+
+        let collected = collect(&dir.0).unwrap();
+        assert_eq!((collected.chunks, collected.bytes), (1, 3));
+        let left = chunk_files(&chunks);
+        assert!(!left.contains(&old));
+        assert!(left.contains(&recent));
+        assert!(listed.is_subset(&left));
+
+        // Once the snapshot is gone its chunks go too, past their grace period.
+        let snapshot = snapshot_path(archive);
+        fs::remove_file(&snapshot).unwrap();
+        assert_eq!(collect(&dir.0).unwrap().chunks, listed.len());
+        assert_eq!(chunk_files(&chunks), HashSet::from([recent]));
+
+        // An unreadable snapshot stops the collection.
+        fs::write(&snapshot, "{").unwrap();
+        age(
+            &chunk_path(&chunks, &chunk_files(&chunks).into_iter().next().unwrap()),
+            48,
+        );
+        assert!(collect(&dir.0).is_err());
+        assert_eq!(chunk_files(&chunks).len(), 1);
+    }
+}
@@ -0,0 +1,221 @@
+use chrono::{DateTime, Utc};
+
+use crate::alerts;
+use crate::config::ProvisionerKind;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::jobs::{JobPayload, SUCCEEDED};
+use crate::provision::{wordpress_database, BackupVerification, ProvisionError, ProvisionOutput};
+use crate::state::AppState;
+
+use super::restore::{load_restorable, Restorable};
+
+/// A site whose latest test restore failed, for the dashboard.
+#[derive(Debug, sqlx::FromRow)]
+pub struct FailedVerification {
+    pub site_id: i32,
+    pub domain: String,
+    pub backup_id: i32,
+    pub archive_path: String,
+    pub verified_at: DateTime<Utc>,
+    pub verify_message: Option<String>,
+    pub verify_job_id: Option<i32>,
+}
+
+impl FailedVerification {
+    pub fn file_name(&self) -> &str {
+        self.archive_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.archive_path)
+    }
+
+    pub fn verified_display(&self) -> String {
+        self.verified_at.format("%Y-%m-%d %H:%M UTC").to_string()
+    }
+
+    pub fn message_display(&self) -> &str {
+        self.verify_message.as_deref().unwrap_or("")
+    }
+}
+
+/// Scratch database a test restore of `backup_id` imports into. Site
+/// databases cannot be named like this: their names have no hyphens.
+fn scratch_database(backup_id: i32) -> String {
+    format!("panel-verify-{}", backup_id)
+}
+
+/// Queue a test restore of a backup as `user_id` and return the job id.
+/// Refused unless the backup can be restored, or while one is in flight.
+pub async fn enqueue_verify(state: &AppState, backup_id: i32, user_id: i32) -> Result<i32> {
+    let Restorable { backup, manifest } = load_restorable(&state.pool, backup_id).await?;
+    if backup.is_verifying() {
+        return Err(AppError::Validation(
+            "A test restore of this backup is already queued or running.".into(),
+        ));
+    }
+    let installed = match backup.site_id {
+        Some(site_id) => {
+            sqlx::query_scalar::<_, bool>("SELECT wordpress_installed FROM sites WHERE id = $1")
+                .bind(site_id)
+                .fetch_optional(&state.pool)
+                .await?
+        }
+        None => None,
+    };
+    // Without the site, go by the database WordPress is installed with.
+    let wordpress = installed.unwrap_or_else(|| {
+        let wp = wordpress_database(&manifest.domain).db_name;
+        manifest.databases.iter().any(|db| db.db_name == wp)
+    });
+
+    let mut tx = state.pool.begin().await?;
+    let job_id = state
+        .jobs
+        .enqueue(
+            &mut *tx,
+            user_id,
+            JobPayload::VerifyBackup {
+                backup_id,
+                site_id: backup.site_id,
+                check: BackupVerification {
+                    archive_path: backup.archive_path.clone(),
+                    databases: manifest
+                        .databases
+                        .into_iter()
+                        .map(|db| db.db_name)
+                        .collect(),
+                    scratch_database: scratch_database(backup_id),
+                    wordpress,
+                },
+            },
+        )
+        .await?;
+    sqlx::query("UPDATE site_backups SET verify_job_id = $1 WHERE id = $2")
+        .bind(job_id)
+        .bind(backup_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    state.jobs.wake();
+    Ok(job_id)
+}
+
+/// Record the outcome of the test restore job `job_id` on the backup, and
+/// raise an alert if it failed. Nothing is recorded for a dry run, which
+/// checked nothing.
+pub(crate) async fn verified(
+    state: &AppState,
+    backup_id: i32,
+    job_id: i32,
+    result: &std::result::Result<ProvisionOutput, ProvisionError>,
+) {
+    if state.config.provisioner == ProvisionerKind::DryRun {
+        return;
+    }
+    let failure = match result {
+        Ok(_) => None,
+        Err(ProvisionError::Failed { status, output }) => Some(
+            output
+                .stderr
+                .lines()
+                .rev()
+                .find_map(|l| l.trim().strip_prefix("Error: "))
+                .map(str::to_string)
+                .unwrap_or_else(|| match status {
+                    Some(code) => format!("The test restore failed with exit status {}.", code),
+                    None => "The test restore failed.".to_string(),
+                }),
+        ),
+        Err(e) => Some(e.to_string()),
+    };
+    if let Err(e) = record(state, backup_id, Some(job_id), failure.as_deref()).await {
+        tracing::warn!(
+            "backup {}: could not record its test restore: {}",
+            backup_id,
+            e
+        );
+    }
+}
+
+/// Set a backup's verification outcome; `failure` is what went wrong, if anything.
+async fn record(
+    state: &AppState,
+    backup_id: i32,
+    job_id: Option<i32>,
+    failure: Option<&str>,
+) -> Result<()> {
+    let row = sqlx::query_as::<_, (String, String)>(
+        "UPDATE site_backups SET verified_at = now(), verify_passed = $1, verify_message = $2 \
+         WHERE id = $3 RETURNING domain, archive_path",
+    )
+    .bind(failure.is_none())
+    .bind(failure)
+    .bind(backup_id)
+    .fetch_optional(&state.pool)
+    .await?;
+    if let (Some((domain, archive_path)), Some(failure)) = (row, failure) {
+        let file = archive_path.rsplit('/').next().unwrap_or(&archive_path);
+        let job = job_id
+            .map(|id| format!(" (job #{})", id))
+            .unwrap_or_default();
+        alerts::send(
+            &state.config,
+            &format!(
+                "Backup {} of {} failed its test restore{}: {}",
+                file, domain, job, failure
+            ),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Queue a test restore of the newest backup of every site that has had none
+/// in `PANEL_BACKUP_VERIFY_DAYS`, as the site's owner. A backup that cannot
+/// even be queued, e.g. because its bundle is gone, fails there and then.
+pub(crate) async fn queue_verifications(state: &AppState) -> Result<()> {
+    let days = state.config.backup_verify_days;
+    if days == 0 {
+        return Ok(());
+    }
+    let due = sqlx::query_as::<_, (i32, i32, i32)>(
+        "SELECT DISTINCT ON (b.site_id) b.id, b.site_id, s.user_id FROM site_backups b \
+         JOIN jobs j ON j.id = b.job_id JOIN sites s ON s.id = b.site_id \
+         WHERE j.state = $1 AND NOT b.before_restore AND NOT b.downloaded \
+         AND NOT EXISTS (SELECT 1 FROM site_backups vb LEFT JOIN jobs vj ON vj.id = vb.verify_job_id \
+         WHERE vb.site_id = b.site_id AND (vb.verified_at > now() - make_interval(days => $2) \
+         OR vj.created_at > now() - make_interval(days => $2))) \
+         ORDER BY b.site_id, b.created_at DESC, b.id DESC",
+    )
+    .bind(SUCCEEDED)
+    .bind(i32::try_from(days).unwrap_or(i32::MAX))
+    .fetch_all(&state.pool)
+    .await?;
+    for (backup_id, site_id, user_id) in due {
+        match enqueue_verify(state, backup_id, user_id).await {
+            Ok(job_id) => tracing::info!(
+                "site {}: queued test restore of backup {} as job {}",
+                site_id,
+                backup_id,
+                job_id
+            ),
+            Err(AppError::Validation(msg)) => record(state, backup_id, None, Some(&msg)).await?,
+            Err(e) => tracing::warn!("site {}: could not queue test restore: {}", site_id, e),
+        }
+    }
+    Ok(())
+}
+
+/// Sites whose most recent test restore failed.
+pub async fn failed_verifications(pool: &DbPool) -> Result<Vec<FailedVerification>> {
+    let failed = sqlx::query_as::<_, FailedVerification>(
+        "SELECT * FROM (SELECT DISTINCT ON (b.site_id) b.site_id, s.domain, b.id AS backup_id, \
+         b.archive_path, b.verified_at, b.verify_passed, b.verify_message, b.verify_job_id \
+         FROM site_backups b JOIN sites s ON s.id = b.site_id WHERE b.verified_at IS NOT NULL \
+         ORDER BY b.site_id, b.verified_at DESC) latest WHERE NOT verify_passed ORDER BY domain",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(failed)
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::config::Config;
+
+#[derive(Error, Debug)]
+pub enum AdminError {
+    #[error("could not reach the Caddy admin API at {url}: {source}")]
+    Unreachable {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("Caddy admin API returned {status}: {message}")]
+    Api { status: u16, message: String },
+
+    #[error("could not read {path}: {source}")]
+    Caddyfile {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Outcome of asking FrankenPHP to restart its PHP workers.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WorkerRestart {
+    Restarted,
+    /// This FrankenPHP build has no worker endpoint, or no workers are configured.
+    NotAvailable,
+}
+
+/// Client for the Caddy/FrankenPHP admin API (`CADDY_ADMIN_URL`, default http://localhost:2019).
+#[derive(Clone)]
+pub struct CaddyAdmin {
+    base_url: String,
+    caddyfile: PathBuf,
+    client: reqwest::Client,
+}
+
+impl CaddyAdmin {
+    pub fn from_config(config: &Config) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap_or_default();
+        Self {
+            base_url: config.caddy_admin_url.trim_end_matches('/').to_string(),
+            caddyfile: config.caddyfile.clone(),
+            client,
+        }
+    }
+
+    /// Load the main Caddyfile (and with it every site snippet) through `POST /load`.
+    /// `Cache-Control: must-revalidate` makes Caddy reload even if nothing changed.
+    pub async fn reload(&self) -> Result<(), AdminError> {
+        let caddyfile = tokio::fs::read_to_string(&self.caddyfile)
+            .await
+            .map_err(|e| AdminError::Caddyfile {
+                path: self.caddyfile.display().to_string(),
+                source: e,
+            })?;
+        let url = format!("{}/load", self.base_url);
+        let res = self
+            .client
+            .post(&url)
+            .header("Content-Type", "text/caddyfile")
+            .header("Cache-Control", "must-revalidate")
+            .body(caddyfile)
+            .send()
+            .await
+            .map_err(|e| AdminError::Unreachable { url, source: e })?;
+        check(res).await.map(|_| ())
+    }
+
+    /// Restart PHP workers through FrankenPHP's `POST /frankenphp/workers/restart`.
+    pub async fn restart_workers(&self) -> Result<WorkerRestart, AdminError> {
+        let url = format!("{}/frankenphp/workers/restart", self.base_url);
+        let res = self
+            .client
+            .post(&url)
+            .send()
+            .await
+            .map_err(|e| AdminError::Unreachable { url, source: e })?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(WorkerRestart::NotAvailable);
+        }
+        check(res).await.map(|_| WorkerRestart::Restarted)
+    }
+}
+
+/// Turn a non-2xx response into [`AdminError::Api`], using Caddy's `{"error": "..."}` body when present.
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, AdminError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| v.get("error").and_then(|e| e.as_str()).map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+    Err(AdminError::Api {
+        status: status.as_u16(),
+        message,
+    })
+}
@@ -0,0 +1,384 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use chrono::{DateTime, Utc};
+use tokio::process::Command;
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem;
+
+use crate::config::Config;
+
+/// Certificates with fewer days left than this are shown as expiring. Caddy
+/// renews ACME certificates with about a third of their lifetime left, so a
+/// certificate this close to expiry means renewal is failing.
+pub const EXPIRING_DAYS: i64 = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertStatus {
+    Active,
+    Expiring,
+    Expired,
+    Missing,
+}
+
+impl CertStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CertStatus::Active => "active",
+            CertStatus::Expiring => "expiring",
+            CertStatus::Expired => "expired",
+            CertStatus::Missing => "missing",
+        }
+    }
+}
+
+/// The parts of a leaf certificate shown on the SSL Details tab.
+#[derive(Clone, Debug)]
+pub struct CertificateInfo {
+    /// File the certificate was read from.
+    pub path: PathBuf,
+    pub subject: String,
+    pub issuer: String,
+    /// DNS names from the subjectAltName extension.
+    pub sans: Vec<String>,
+    pub serial: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// Who issued it through Caddy, e.g. "Let's Encrypt" (empty for certificates not from storage).
+    pub acme_issuer: String,
+}
+
+impl CertificateInfo {
+    /// Parse the first certificate of a PEM file (the leaf; the rest is the chain).
+    pub fn from_pem(path: &Path, pem: &[u8]) -> Result<Self, String> {
+        let (block, _) = Pem::read(std::io::Cursor::new(pem))
+            .map_err(|e| format!("not a PEM certificate ({})", e))?;
+        let cert = block
+            .parse_x509()
+            .map_err(|e| format!("invalid X.509 certificate ({})", e))?;
+        let sans = match cert.subject_alternative_name() {
+            Ok(Some(ext)) => ext
+                .value
+                .general_names
+                .iter()
+                .filter_map(|n| match n {
+                    GeneralName::DNSName(d) => Some(d.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let validity = cert.validity();
+        Ok(Self {
+            path: path.to_path_buf(),
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            sans,
+            serial: cert.raw_serial_as_string(),
+            not_before: timestamp(validity.not_before.timestamp()),
+            not_after: timestamp(validity.not_after.timestamp()),
+            acme_issuer: String::new(),
+        })
+    }
+
+    /// Whether one of the SANs matches `domain`; `*.example.com` covers exactly one extra label.
+    pub fn covers(&self, domain: &str) -> bool {
+        let domain = domain.to_ascii_lowercase();
+        self.sans.iter().any(|san| {
+            let san = san.to_ascii_lowercase();
+            if san == domain {
+                return true;
+            }
+            match (san.strip_prefix("*."), domain.split_once('.')) {
+                (Some(parent), Some((label, rest))) => {
+                    !label.is_empty() && label != "*" && rest == parent
+                }
+                _ => false,
+            }
+        })
+    }
+
+    pub fn days_until_expiry(&self) -> i64 {
+        (self.not_after - Utc::now()).num_days()
+    }
+
+    pub fn status(&self) -> CertStatus {
+        let now = Utc::now();
+        if self.not_after <= now {
+            CertStatus::Expired
+        } else if (self.not_after - now).num_days() < EXPIRING_DAYS {
+            CertStatus::Expiring
+        } else {
+            CertStatus::Active
+        }
+    }
+
+    pub fn not_before_display(&self) -> String {
+        self.not_before.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn not_after_display(&self) -> String {
+        self.not_after.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn sans_display(&self) -> String {
+        if self.sans.is_empty() {
+            "—".to_string()
+        } else {
+            self.sans.join(", ")
+        }
+    }
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+/// What the panel found for a domain in Caddy's certificate storage.
+#[derive(Clone, Debug)]
+pub struct CertificateReport {
+    pub status: CertStatus,
+    pub certificate: Option<CertificateInfo>,
+    /// Why nothing could be read (storage missing, permission denied, parse error).
+    pub note: String,
+}
+
+impl CertificateReport {
+    pub fn status_str(&self) -> &'static str {
+        self.status.as_str()
+    }
+}
+
+/// Read-only view of Caddy's file storage (`CADDY_DATA_DIR`), laid out as
+/// `certificates/<issuer>/<name>/<name>.crt` with `*` written as `wildcard_`.
+/// Caddy writes the storage with mode 0600, so when the panel user cannot read
+/// it the certificates are fetched through `CERT_READ_SCRIPT` via sudo.
+#[derive(Clone)]
+pub struct CertificateStore {
+    data_dir: PathBuf,
+    read_script: Option<PathBuf>,
+}
+
+/// One `<name>.crt` file found in the storage.
+struct StoredCert {
+    path: PathBuf,
+    pem: Vec<u8>,
+}
+
+impl CertificateStore {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            data_dir: config.caddy_data_dir.clone(),
+            read_script: config.cert_read_script.clone(),
+        }
+    }
+
+    /// Look up the certificate Caddy serves for `domain`: an exact match, or
+    /// else a wildcard for the parent domain. If several issuers hold one, the
+    /// certificate that expires last wins.
+    pub async fn inspect(&self, domain: &str) -> CertificateReport {
+        let root = self.data_dir.join("certificates");
+        match self.find(&root, &domain.to_ascii_lowercase()).await {
+            Ok(Some(cert)) => CertificateReport {
+                status: cert.status(),
+                certificate: Some(cert),
+                note: String::new(),
+            },
+            Ok(None) => CertificateReport {
+                status: CertStatus::Missing,
+                certificate: None,
+                note: format!(
+                    "No certificate for this domain in {}. Caddy obtains one on the first HTTPS request once DNS points here.",
+                    root.display()
+                ),
+            },
+            Err(e) => CertificateReport {
+                status: CertStatus::Missing,
+                certificate: None,
+                note: e,
+            },
+        }
+    }
+
+    /// Report on an uploaded certificate the site's snippet points at.
+    pub async fn inspect_custom(&self, path: &Path) -> CertificateReport {
+        let parsed = match tokio::fs::read(path).await {
+            Ok(pem) => CertificateInfo::from_pem(path, &pem),
+            Err(e) => Err(e.to_string()),
+        };
+        match parsed {
+            Ok(cert) => CertificateReport {
+                status: cert.status(),
+                certificate: Some(cert),
+                note: String::new(),
+            },
+            Err(e) => CertificateReport {
+                status: CertStatus::Missing,
+                certificate: None,
+                note: format!(
+                    "Could not read the uploaded certificate {}: {}",
+                    path.display(),
+                    e
+                ),
+            },
+        }
+    }
+
+    async fn find(&self, root: &Path, domain: &str) -> Result<Option<CertificateInfo>, String> {
+        let mut names = vec![storage_name(domain)];
+        if let Some((_, parent)) = domain.split_once('.') {
+            if parent.contains('.') && !domain.starts_with('*') {
+                names.push(format!("wildcard_.{}", parent));
+            }
+        }
+        for name in names {
+            let mut best: Option<CertificateInfo> = None;
+            for stored in self.read(root, &name).await? {
+                let mut cert = CertificateInfo::from_pem(&stored.path, &stored.pem)
+                    .map_err(|e| format!("{}: {}", stored.path.display(), e))?;
+                let key = stored
+                    .path
+                    .parent()
+                    .and_then(Path::parent)
+                    .and_then(Path::file_name)
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                cert.acme_issuer = issuer_display(&key);
+                if best.as_ref().is_none_or(|b| cert.not_after > b.not_after) {
+                    best = Some(cert);
+                }
+            }
+            if best.is_some() {
+                return Ok(best);
+            }
+        }
+        Ok(None)
+    }
+
+    async fn read(&self, root: &Path, name: &str) -> Result<Vec<StoredCert>, String> {
+        let dir = root.to_path_buf();
+        let file = name.to_string();
+        let direct = tokio::task::spawn_blocking(move || read_direct(&dir, &file))
+            .await
+            .map_err(|e| e.to_string())?;
+        match (direct, &self.read_script) {
+            (Err(e), Some(script)) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                read_via_script(script, name).await
+            }
+            (Err(e), _) => Err(format!(
+                "Could not read Caddy certificate storage {}: {}. Set CERT_READ_SCRIPT or give the panel user read access.",
+                root.display(),
+                e
+            )),
+            (Ok(found), _) => Ok(found),
+        }
+    }
+}
+
+fn storage_name(domain: &str) -> String {
+    domain.replace('*', "wildcard_")
+}
+
+fn read_direct(root: &Path, name: &str) -> std::io::Result<Vec<StoredCert>> {
+    let issuers = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut found = Vec::new();
+    for issuer in issuers.flatten() {
+        let path = issuer.path().join(name).join(format!("{}.crt", name));
+        match std::fs::read(&path) {
+            Ok(pem) => found.push(StoredCert { path, pem }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(found)
+}
+
+/// `sudo <script> <name>` prints `==> <path>` followed by the PEM for every match.
+async fn read_via_script(script: &Path, name: &str) -> Result<Vec<StoredCert>, String> {
+    let out = Command::new("sudo")
+        .arg("-n")
+        .arg(script)
+        .arg(name)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {}", script.display(), e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            script.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    let mut found: Vec<StoredCert> = Vec::new();
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        if let Some(path) = line.strip_prefix("==> ") {
+            found.push(StoredCert {
+                path: PathBuf::from(path),
+                pem: Vec::new(),
+            });
+        } else if let Some(current) = found.last_mut() {
+            current.pem.extend_from_slice(line.as_bytes());
+            current.pem.push(b'\n');
+        }
+    }
+    Ok(found)
+}
+
+/// Caddy names issuer directories after the ACME directory URL
+/// (e.g. `acme-v02.api.letsencrypt.org-directory`) or `local` for its internal CA.
+fn issuer_display(key: &str) -> String {
+    if key == "local" {
+        return "Caddy local CA (internal)".to_string();
+    }
+    let name = if key.contains("letsencrypt.org") {
+        if key.contains("staging") {
+            "Let's Encrypt (staging)"
+        } else {
+            "Let's Encrypt"
+        }
+    } else if key.contains("zerossl.com") {
+        "ZeroSSL"
+    } else if key.contains("pki.goog") {
+        "Google Trust Services"
+    } else {
+        return key.to_string();
+    };
+    format!("{} ({})", name, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::CertificateInfo;
+
+    #[test]
+    fn covers() {
+        let pem = include_bytes!("testdata/valid.pem");
+        let mut info = CertificateInfo::from_pem(Path::new("valid.pem"), pem).unwrap();
+        assert_eq!(info.sans, ["example.com", "*.example.com"]);
+        info.sans.push("Shop.Example.NET".to_string());
+        let cases = [
+            ("example.com", true),
+            ("EXAMPLE.com", true),
+            ("www.example.com", true),
+            ("shop.example.net", true),
+            // A wildcard stands for exactly one label.
+            ("a.b.example.com", false),
+            (".example.com", false),
+            ("*.example.com", true),
+            ("*.shop.example.net", false),
+            ("example.net", false),
+            ("wwwexample.com", false),
+            ("example.com.evil.org", false),
+        ];
+        for (domain, covered) in cases {
+            assert_eq!(info.covers(domain), covered, "{}", domain);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Talking to Caddy/FrankenPHP: the admin API, the per-site snippets the
+//! main Caddyfile imports, and the certificates Caddy keeps in its storage.
+
+mod admin;
+mod certs;
+mod site;
+mod snippets;
+mod tls;
+
+pub use admin::{AdminError, CaddyAdmin, WorkerRestart};
+pub use certs::{CertStatus, CertificateInfo, CertificateReport, CertificateStore, EXPIRING_DAYS};
+pub use site::{Encoding, HostRedirect, SiteCaddyConfig, TlsFiles};
+pub use snippets::{SiteConfigError, SiteConfigs};
+pub use tls::{check_upload, TlsStore};
@@ -0,0 +1,356 @@
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Site, SiteAlias};
+use crate::provision::NewSite;
+
+/// Compression formats for the `encode` directive, in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// An uploaded certificate chain and key, emitted as `tls <cert> <key>`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsFiles {
+    pub cert: String,
+    pub key: String,
+}
+
+impl TlsFiles {
+    /// The uploaded certificate recorded for `site`, if any.
+    pub fn for_site(site: &Site) -> Option<Self> {
+        match (&site.tls_cert_path, &site.tls_key_path) {
+            (Some(cert), Some(key)) => Some(Self {
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A hostname that only redirects to the site's canonical host.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostRedirect {
+    pub host: String,
+    /// 301 (permanent) or 308 (permanent, keeps the method and body).
+    pub status: u16,
+}
+
+/// Everything that goes into a site's snippet under `CADDY_SITES_DIR`.
+/// [`SiteCaddyConfig::render`] is the only place the Caddyfile syntax lives.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteCaddyConfig {
+    /// The site's domain as recorded by the panel; names the snippet file.
+    pub domain: String,
+    /// Addresses of the main site block. The first is the canonical host that
+    /// redirects point at; the rest are aliases served as the same site.
+    pub hosts: Vec<String>,
+    /// Names answered with a redirect to the canonical host (no TLS override,
+    /// so Caddy obtains their certificates via ACME).
+    pub redirects: Vec<HostRedirect>,
+    /// Document root (`root *`).
+    pub root: String,
+    /// Recorded in the header comment; FrankenPHP embeds a single PHP version.
+    pub php_version: String,
+    /// Empty means no `encode` directive.
+    pub encodings: Vec<Encoding>,
+    /// Serve PHP through FrankenPHP's `php_server` (front controller + static files).
+    pub php_server: bool,
+    /// Add a `file_server` for requests `php_server` does not handle.
+    pub file_server: bool,
+    /// Custom certificate; `None` lets Caddy obtain one via ACME.
+    pub tls: Option<TlsFiles>,
+}
+
+impl SiteCaddyConfig {
+    /// The defaults every panel site gets: zstd/gzip, `php_server` and `file_server`.
+    pub fn new(domain: &str, root: &str, php_version: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            hosts: vec![domain.to_string()],
+            redirects: Vec::new(),
+            root: root.to_string(),
+            php_version: php_version.to_string(),
+            encodings: vec![Encoding::Zstd, Encoding::Gzip],
+            php_server: true,
+            file_server: true,
+            tls: None,
+        }
+    }
+
+    pub fn for_new_site(site: &NewSite) -> Self {
+        Self::new(&site.domain, &site.folder_path, &site.php_version)
+    }
+
+    /// The snippet for a site already recorded by the panel, with its aliases
+    /// and www/apex policy. A name listed twice keeps its first role: the
+    /// canonical host, then served aliases, then redirects.
+    pub fn for_site(site: &Site, aliases: &[SiteAlias]) -> Self {
+        let mut config = Self::new(&site.domain, &site.folder_path, &site.php_version);
+        config.tls = TlsFiles::for_site(site);
+        config.hosts = vec![site.primary_host()];
+        for alias in aliases.iter().filter(|a| !a.is_redirect()) {
+            if !config.has_host(&alias.hostname) {
+                config.hosts.push(alias.hostname.clone());
+            }
+        }
+        let redirects = site
+            .redirected_host()
+            .map(|host| (host, 301))
+            .into_iter()
+            .chain(
+                aliases
+                    .iter()
+                    .filter(|a| a.is_redirect())
+                    .map(|a| (a.hostname.clone(), a.redirect_status as u16)),
+            );
+        for (host, status) in redirects {
+            if !config.has_host(&host) {
+                config.redirects.push(HostRedirect { host, status });
+            }
+        }
+        config
+    }
+
+    fn has_host(&self, host: &str) -> bool {
+        self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            || self.redirects.iter().any(|r| r.host.eq_ignore_ascii_case(host))
+    }
+
+    /// Snippet file name inside `CADDY_SITES_DIR` (`*` is not allowed in file names).
+    pub fn file_name(&self) -> String {
+        snippet_file_name(&self.domain)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# Generated by FrankenPHP Panel – do not edit manually (PHP: {})",
+            self.php_version
+        );
+        let _ = writeln!(out, "{} {{", self.hosts.join(", "));
+        if let Some(ref tls) = self.tls {
+            let _ = writeln!(out, "\ttls {} {}", quote(&tls.cert), quote(&tls.key));
+        }
+        let _ = writeln!(out, "\troot * {}", quote(&self.root));
+        if !self.encodings.is_empty() {
+            let names: Vec<&str> = self.encodings.iter().map(|e| e.as_str()).collect();
+            let _ = writeln!(out, "\tencode {}", names.join(" "));
+        }
+        if self.php_server {
+            out.push_str("\tphp_server\n");
+        }
+        if self.file_server {
+            out.push_str("\tfile_server\n");
+        }
+        out.push_str("}\n");
+
+        // One block per status code, in the order the codes first appear.
+        let canonical = self.hosts.first().unwrap_or(&self.domain);
+        let mut statuses: Vec<u16> = Vec::new();
+        for r in &self.redirects {
+            if !statuses.contains(&r.status) {
+                statuses.push(r.status);
+            }
+        }
+        for status in statuses {
+            let hosts: Vec<&str> = self
+                .redirects
+                .iter()
+                .filter(|r| r.status == status)
+                .map(|r| r.host.as_str())
+                .collect();
+            let _ = writeln!(out, "\n{} {{", hosts.join(", "));
+            let _ = writeln!(out, "\tredir https://{}{{uri}} {}", canonical, status);
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+pub fn snippet_file_name(domain: &str) -> String {
+    format!("{}.conf", domain.replace('*', "_"))
+}
+
+/// Quote a Caddyfile token if it contains whitespace or quotes.
+fn quote(token: &str) -> String {
+    if token.is_empty()
+        || token.contains(|c: char| c.is_whitespace() || c == '"' || c == '{' || c == '}')
+    {
+        format!("\"{}\"", token.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        token.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn site(domain: &str, canonical_host: &str) -> Site {
+        Site {
+            id: 1,
+            domain: domain.to_string(),
+            folder_path: "/var/www/example".to_string(),
+            wordpress_installed: false,
+            user_id: 1,
+            created_at: Utc::now(),
+            php_version: "8.3".to_string(),
+            last_restarted_at: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+            canonical_host: canonical_host.to_string(),
+            check_interval: None,
+            check_expected_status: None,
+            check_keyword: None,
+            backup_frequency: "off".to_string(),
+            backup_hour: 3,
+            backup_keep_daily: 7,
+            backup_keep_weekly: 4,
+        }
+    }
+
+    fn alias(hostname: &str, mode: &str, redirect_status: i32) -> SiteAlias {
+        SiteAlias {
+            id: 1,
+            site_id: 1,
+            hostname: hostname.to_string(),
+            mode: mode.to_string(),
+            redirect_status,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = SiteCaddyConfig::new("example.com", "/var/www/example", "8.3");
+        assert_eq!(config.file_name(), "example.com.conf");
+        assert_eq!(
+            config.render(),
+            "# Generated by FrankenPHP Panel – do not edit manually (PHP: 8.3)\n\
+             example.com {\n\
+             \troot * /var/www/example\n\
+             \tencode zstd gzip\n\
+             \tphp_server\n\
+             \tfile_server\n\
+             }\n"
+        );
+        assert_eq!(snippet_file_name("*.example.com"), "_.example.com.conf");
+    }
+
+    #[test]
+    fn tls_line() {
+        let mut site = site("example.com", "domain");
+        site.tls_cert_path = Some("/etc/panel/tls/example.com/cert.pem".to_string());
+        site.tls_key_path = Some("/etc/panel/tls/example.com/key.pem".to_string());
+        let mut config = SiteCaddyConfig::for_site(&site, &[]);
+        config.encodings.clear();
+        config.file_server = false;
+        assert_eq!(
+            config.render(),
+            "# Generated by FrankenPHP Panel – do not edit manually (PHP: 8.3)\n\
+             example.com {\n\
+             \ttls /etc/panel/tls/example.com/cert.pem /etc/panel/tls/example.com/key.pem\n\
+             \troot * /var/www/example\n\
+             \tphp_server\n\
+             }\n"
+        );
+        // Only a complete pair counts.
+        site.tls_key_path = None;
+        assert_eq!(SiteCaddyConfig::for_site(&site, &[]).tls, None);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("/var/www/plain"), "/var/www/plain");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("/var/www/my site"), "\"/var/www/my site\"");
+        assert_eq!(quote("/var/www/a\tb"), "\"/var/www/a\tb\"");
+        assert_eq!(quote("/var/www/\"q\""), "\"/var/www/\\\"q\\\"\"");
+        assert_eq!(quote("/var/www/{x}"), "\"/var/www/{x}\"");
+        assert_eq!(
+            quote("/var/www/back\\slash x"),
+            "\"/var/www/back\\\\slash x\""
+        );
+        // A backslash alone needs no quotes.
+        assert_eq!(quote("/var/www/back\\slash"), "/var/www/back\\slash");
+
+        let config = SiteCaddyConfig::new("example.com", "/var/www/my site", "8.3");
+        assert!(config.render().contains("\troot * \"/var/www/my site\"\n"));
+    }
+
+    #[test]
+    fn redirects_grouped_by_status() {
+        let site = site("example.com", "www");
+        let aliases = [
+            alias("example.net", "redirect", 308),
+            alias("shop.example.com", "serve", 301),
+            alias("example.org", "redirect", 301),
+            alias("old.example.com", "redirect", 308),
+        ];
+        let config = SiteCaddyConfig::for_site(&site, &aliases);
+        assert_eq!(
+            config.render(),
+            "# Generated by FrankenPHP Panel – do not edit manually (PHP: 8.3)\n\
+             www.example.com, shop.example.com {\n\
+             \troot * /var/www/example\n\
+             \tencode zstd gzip\n\
+             \tphp_server\n\
+             \tfile_server\n\
+             }\n\
+             \n\
+             example.com, example.org {\n\
+             \tredir https://www.example.com{uri} 301\n\
+             }\n\
+             \n\
+             example.net, old.example.com {\n\
+             \tredir https://www.example.com{uri} 308\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_host_keeps_first_role() {
+        let site = site("www.example.com", "apex");
+        let aliases = [
+            // The canonical host, in another case: dropped.
+            alias("EXAMPLE.com", "serve", 301),
+            // Served aliases win over the redirect the apex policy adds.
+            alias("www.example.com", "serve", 301),
+            alias("blog.example.com", "serve", 301),
+            // Already served as an alias: not redirected as well.
+            alias("Blog.example.com", "redirect", 308),
+            alias("example.net", "redirect", 308),
+            alias("example.net", "redirect", 301),
+        ];
+        let config = SiteCaddyConfig::for_site(&site, &aliases);
+        assert_eq!(
+            config.hosts,
+            ["example.com", "www.example.com", "blog.example.com"]
+        );
+        assert_eq!(
+            config.redirects,
+            [HostRedirect {
+                host: "example.net".to_string(),
+                status: 308,
+            },]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::admin::{AdminError, CaddyAdmin};
+use super::site::{snippet_file_name, SiteCaddyConfig};
+use crate::config::Config;
+
+#[derive(Error, Debug)]
+pub enum SiteConfigError {
+    #[error("could not write {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("could not run {program}: {source}")]
+    Spawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Caddy config did not validate:\n{output}")]
+    Invalid { output: String },
+
+    #[error("reload failed: {0}")]
+    Reload(#[from] AdminError),
+
+    #[error("CADDY_RELOAD_CMD failed:\n{output}")]
+    ReloadCmd { output: String },
+
+    /// Validation or reload failed and the old snippet could not be put back either.
+    #[error("{cause}; restoring the previous snippet at {path} also failed: {source}")]
+    Rollback {
+        path: String,
+        cause: Box<SiteConfigError>,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Owns the per-site snippets in `CADDY_SITES_DIR`. Every change is written
+/// atomically, checked with `frankenphp validate` against the main Caddyfile
+/// and reloaded; if either step fails the previous snippet is put back so the
+/// next reload does not pick up a broken file.
+#[derive(Clone)]
+pub struct SiteConfigs {
+    sites_dir: PathBuf,
+    caddyfile: PathBuf,
+    frankenphp_bin: String,
+    reload_cmd: Option<String>,
+    admin: CaddyAdmin,
+}
+
+impl SiteConfigs {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            sites_dir: config.caddy_sites_dir.clone(),
+            caddyfile: config.caddyfile.clone(),
+            frankenphp_bin: config.frankenphp_bin.clone(),
+            reload_cmd: config.caddy_reload_cmd.clone(),
+            admin: CaddyAdmin::from_config(config),
+        }
+    }
+
+    pub fn snippet_path(&self, domain: &str) -> PathBuf {
+        self.sites_dir.join(snippet_file_name(domain))
+    }
+
+    /// Write (or replace) the site's snippet, validate and reload.
+    pub async fn apply(&self, config: &SiteCaddyConfig) -> Result<PathBuf, SiteConfigError> {
+        tokio::fs::create_dir_all(&self.sites_dir)
+            .await
+            .map_err(|e| io_err(&self.sites_dir, e))?;
+        let path = self.sites_dir.join(config.file_name());
+        let previous = read_existing(&path).await?;
+        write_atomic(&path, config.render().as_bytes(), 0o644)
+            .await
+            .map_err(|e| io_err(&path, e))?;
+        self.commit(&path, previous).await?;
+        Ok(path)
+    }
+
+    /// Remove the site's snippet, validate and reload. Returns `None` if there was no snippet.
+    pub async fn remove(&self, domain: &str) -> Result<Option<PathBuf>, SiteConfigError> {
+        let path = self.snippet_path(domain);
+        let Some(previous) = read_existing(&path).await? else {
+            return Ok(None);
+        };
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| io_err(&path, e))?;
+        self.commit(&path, Some(previous)).await?;
+        Ok(Some(path))
+    }
+
+    /// Validate and reload; on failure put `previous` back at `path` (or remove
+    /// the file if there was none).
+    async fn commit(&self, path: &Path, previous: Option<Vec<u8>>) -> Result<(), SiteConfigError> {
+        let result = match self.validate().await {
+            Ok(()) => self.reload().await,
+            Err(e) => Err(e),
+        };
+        let Err(cause) = result else {
+            return Ok(());
+        };
+        let restored = match previous {
+            Some(bytes) => write_atomic(path, &bytes, 0o644).await,
+            None => match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        };
+        match restored {
+            Ok(()) => Err(cause),
+            Err(source) => Err(SiteConfigError::Rollback {
+                path: path.display().to_string(),
+                cause: Box::new(cause),
+                source,
+            }),
+        }
+    }
+
+    /// `frankenphp validate` parses the main Caddyfile with every imported snippet.
+    async fn validate(&self) -> Result<(), SiteConfigError> {
+        let out = Command::new(&self.frankenphp_bin)
+            .arg("validate")
+            .arg("--config")
+            .arg(&self.caddyfile)
+            .arg("--adapter")
+            .arg("caddyfile")
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| SiteConfigError::Spawn {
+                program: self.frankenphp_bin.clone(),
+                source: e,
+            })?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(SiteConfigError::Invalid {
+                output: process_output(&out),
+            })
+        }
+    }
+
+    /// `CADDY_RELOAD_CMD` if set, otherwise the admin API.
+    async fn reload(&self) -> Result<(), SiteConfigError> {
+        let Some(ref cmd) = self.reload_cmd else {
+            return Ok(self.admin.reload().await?);
+        };
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| SiteConfigError::Spawn {
+                program: "sh".to_string(),
+                source: e,
+            })?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(SiteConfigError::ReloadCmd {
+                output: process_output(&out),
+            })
+        }
+    }
+}
+
+fn io_err(path: &Path, source: std::io::Error) -> SiteConfigError {
+    SiteConfigError::Io {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+fn process_output(out: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    format!("{}\n{}", stderr.trim(), stdout.trim())
+        .trim()
+        .to_string()
+}
+
+async fn read_existing(path: &Path) -> Result<Option<Vec<u8>>, SiteConfigError> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_err(path, e)),
+    }
+}
+
+/// Write to a hidden temp file (created with `mode`) in the same directory,
+/// fsync, then rename over `path`. Caddy's `import dir/*` skips dotfiles, so
+/// the temp file is never loaded.
+pub(crate) async fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let write = async {
+        // A leftover temp file would keep its old mode.
+        let _ = tokio::fs::remove_file(&tmp).await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&tmp)
+            .await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, path).await
+    };
+    let result = write.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    result
+}
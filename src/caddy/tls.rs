@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use x509_parser::pem::Pem;
+
+use super::certs::CertificateInfo;
+use super::site::TlsFiles;
+use super::snippets::write_atomic;
+use crate::config::Config;
+
+/// Check an uploaded PEM chain and private key before they go anywhere near
+/// Caddy: the chain must parse, the key must belong to the leaf certificate,
+/// and the leaf must be currently valid for `domain`.
+pub fn check_upload(
+    domain: &str,
+    chain_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<CertificateInfo, String> {
+    let chain = CertificateDer::pem_slice_iter(chain_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("The certificate file is not valid PEM: {}", e))?;
+    if chain.is_empty() {
+        return Err("The certificate file contains no CERTIFICATE block.".to_string());
+    }
+    let info = CertificateInfo::from_pem(Path::new(""), chain_pem)
+        .map_err(|e| format!("The certificate could not be read: {}", e))?;
+
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|_| {
+        "The key file contains no unencrypted private key (PRIVATE KEY, RSA PRIVATE KEY or EC PRIVATE KEY).".to_string()
+    })?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|_| {
+        "Unsupported private key type. Use RSA, ECDSA (P-256/P-384) or Ed25519.".to_string()
+    })?;
+    let key_spki = signing_key
+        .public_key()
+        .ok_or_else(|| "Could not derive the public key from the private key.".to_string())?;
+    let (leaf, _) = Pem::read(std::io::Cursor::new(chain_pem))
+        .map_err(|e| format!("The certificate could not be read: {}", e))?;
+    let leaf = leaf
+        .parse_x509()
+        .map_err(|e| format!("The certificate could not be read: {}", e))?;
+    if leaf.public_key().raw != key_spki.as_ref() {
+        return Err("The private key does not match the certificate.".to_string());
+    }
+
+    if !info.covers(domain) {
+        return Err(format!(
+            "The certificate does not cover {} (it is valid for: {}).",
+            domain,
+            info.sans_display()
+        ));
+    }
+    let now = Utc::now();
+    if info.not_after <= now {
+        return Err(format!(
+            "The certificate expired on {}.",
+            info.not_after_display()
+        ));
+    }
+    if info.not_before > now {
+        return Err(format!(
+            "The certificate is not valid until {}.",
+            info.not_before_display()
+        ));
+    }
+    Ok(info)
+}
+
+/// Where uploaded certificates live (`PANEL_TLS_DIR`, default /etc/caddy/tls):
+/// one directory per domain (mode 0700) with timestamped `.crt` (0644) and
+/// `.key` (0600) files, so a new upload never overwrites the files the current
+/// snippet still points at.
+#[derive(Clone)]
+pub struct TlsStore {
+    dir: PathBuf,
+}
+
+impl TlsStore {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            dir: config.tls_dir.clone(),
+        }
+    }
+
+    pub async fn install(
+        &self,
+        domain: &str,
+        chain_pem: &[u8],
+        key_pem: &[u8],
+    ) -> std::io::Result<TlsFiles> {
+        let dir = self.dir.join(domain.replace('*', "_"));
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))
+            .await?;
+        let stamp = Utc::now().format("%Y%m%d%H%M%S%3f");
+        let cert = dir.join(format!("{}.crt", stamp));
+        let key = dir.join(format!("{}.key", stamp));
+        write_atomic(&key, key_pem, 0o600).await?;
+        if let Err(e) = write_atomic(&cert, chain_pem, 0o644).await {
+            let _ = tokio::fs::remove_file(&key).await;
+            return Err(e);
+        }
+        Ok(TlsFiles {
+            cert: cert.display().to_string(),
+            key: key.display().to_string(),
+        })
+    }
+
+    /// Delete certificate files no snippet refers to any more (best effort).
+    pub async fn remove(&self, files: &TlsFiles) {
+        for path in [&files.cert, &files.key] {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("could not remove {}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_upload;
+
+    // Self-signed P-256 certificates for example.com and *.example.com, all
+    // made from key.pem (openssl req -x509 -not_before/-not_after).
+    const VALID: &[u8] = include_bytes!("testdata/valid.pem");
+    const EXPIRED: &[u8] = include_bytes!("testdata/expired.pem");
+    const FUTURE: &[u8] = include_bytes!("testdata/future.pem");
+    const KEY: &[u8] = include_bytes!("testdata/key.pem");
+    const OTHER_KEY: &[u8] = include_bytes!("testdata/other-key.pem");
+
+    #[test]
+    fn accepts_a_matching_current_certificate() {
+        let info = check_upload("shop.example.com", VALID, KEY).unwrap();
+        assert_eq!(info.sans, ["example.com", "*.example.com"]);
+        assert!(check_upload("EXAMPLE.COM", VALID, KEY).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_uploads() {
+        let cases: [(&str, &[u8], &[u8], &str); 6] = [
+            (
+                "example.com",
+                VALID,
+                OTHER_KEY,
+                "The private key does not match the certificate.",
+            ),
+            ("example.com", EXPIRED, KEY, "The certificate expired on "),
+            (
+                "example.com",
+                FUTURE,
+                KEY,
+                "The certificate is not valid until ",
+            ),
+            (
+                "example.org",
+                VALID,
+                KEY,
+                "The certificate does not cover example.org ",
+            ),
+            (
+                "example.com",
+                b"",
+                KEY,
+                "The certificate file contains no CERTIFICATE block.",
+            ),
+            (
+                "example.com",
+                VALID,
+                VALID,
+                "The key file contains no unencrypted private key",
+            ),
+        ];
+        for (domain, chain, key, expected) in cases {
+            let err = check_upload(domain, chain, key).unwrap_err();
+            assert!(
+                err.starts_with(expected),
+                "{:?} does not start with {:?}",
+                err,
+                expected
+            );
+        }
+    }
+}
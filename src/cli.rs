@@ -0,0 +1,626 @@
+//! `frankenphp-panel sites|databases|users …`: manage the box over SSH when
+//! the web UI is unreachable.
+//!
+//! Changes go through [`crate::actions`], so they are validated exactly like
+//! the forms and the JSON API. The provisioning job is queued as usual and
+//! then run in this process; if a running panel's worker claims it first, the
+//! command waits for that worker to finish it; Ctrl-C fails a job this process
+//! is running. `--json` prints the same JSON the API returns.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::actions;
+use crate::audit::Actor;
+use crate::caddy::CaddyAdmin;
+use crate::config::Config;
+use crate::db;
+use crate::error::AppError;
+use crate::jobs;
+use crate::models::{
+    AddSiteForm, CreateDatabaseForm, Job, Site, SiteDatabase, User, JOB_COLUMNS, SITE_COLUMNS,
+    SITE_DATABASE_COLUMNS, USER_COLUMNS,
+};
+use crate::provision;
+use crate::state::AppState;
+
+pub const USAGE: &str = "\
+Usage:
+  frankenphp-panel sites list [--json]
+  frankenphp-panel sites show <id|domain> [--json]
+  frankenphp-panel sites create <domain> [--php <version>] [--wordpress --title <title>
+      --admin-user <user> --admin-email <email> [--admin-password <password>]] [--json]
+  frankenphp-panel sites delete <id|domain> --yes [--json]
+  frankenphp-panel databases list [--site <id|domain>] [--json]
+  frankenphp-panel databases create <site> <name> <user> [--password <password>] [--readonly] [--json]
+  frankenphp-panel databases delete <id> --yes [--json]
+  frankenphp-panel users list [--json]
+  frankenphp-panel users add <username> --role <admin|operator|readonly> [--password <password>] [--json]
+  frankenphp-panel users disable <username> [--json]
+
+Jobs are recorded as the user given with --as <username>, or the first active admin.
+Passwords that are not given are generated and printed once.";
+
+/// Flags that take no value.
+const SWITCHES: &[&str] = &["json", "yes", "wordpress", "readonly"];
+
+/// How often to look at a job that a running panel is working on.
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Positional arguments and `--flag [value]` options.
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                positional.push(arg.clone());
+                continue;
+            };
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None if SWITCHES.contains(&name) => (name, String::new()),
+                None => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| anyhow!("--{} needs a value.\n\n{}", name, USAGE))?;
+                    (name, value.clone())
+                }
+            };
+            options.insert(name.to_string(), value);
+        }
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    fn arg(&self, index: usize, name: &str) -> anyhow::Result<&str> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("Missing <{}>.\n\n{}", name, USAGE))
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+}
+
+/// Run a management command; `args` starts with the command group.
+pub async fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = Args::parse(args)?;
+    let group = args.arg(0, "command")?.to_string();
+    let command = args.arg(1, "command")?.to_string();
+    let state = connect().await?;
+    let json = args.flag("json");
+
+    match (group.as_str(), command.as_str()) {
+        ("sites", "list") => sites_list(&state, json).await,
+        ("sites", "show") => sites_show(&state, &args, json).await,
+        ("sites", "create") => sites_create(&state, &args, json).await,
+        ("sites", "delete") => sites_delete(&state, &args, json).await,
+        ("databases", "list") => databases_list(&state, &args, json).await,
+        ("databases", "create") => databases_create(&state, &args, json).await,
+        ("databases", "delete") => databases_delete(&state, &args, json).await,
+        ("users", "list") => users_list(&state, json).await,
+        ("users", "add") => users_add(&state, &args, json).await,
+        ("users", "disable") => users_disable(&state, &args, json).await,
+        _ => bail!("Unknown command '{} {}'.\n\n{}", group, command, USAGE),
+    }
+}
+
+async fn connect() -> anyhow::Result<AppState> {
+    Config::check_provisioner().map_err(|e| anyhow!(e))?;
+    let config = Config::from_env();
+    let pool = db::create_pool(&config.database_url)
+        .await
+        .context("could not connect to the database")?;
+    // Jobs of a CLI that was killed would otherwise block their site until the panel runs.
+    jobs::fail_stale(&pool).await.map_err(app_error)?;
+    Ok(AppState {
+        pool,
+        provisioner: provision::from_config(&config),
+        jobs: jobs::JobQueue::new(),
+        caddy: CaddyAdmin::from_config(&config),
+        config,
+    })
+}
+
+/// The message an operator should see; not-found and validation errors keep
+/// their text without the variant prefix.
+fn app_error(e: AppError) -> anyhow::Error {
+    match e {
+        AppError::Validation(msg) | AppError::NotFound(msg) => anyhow!(msg),
+        AppError::Internal(e) => e,
+        other => anyhow!("{}", other),
+    }
+}
+
+/// One line per invalid field, named by `name` after the argument or option
+/// that set it.
+fn invalid<'a>(
+    fields: impl IntoIterator<Item = (&'static str, &'a str)>,
+    name: fn(&'static str) -> &'static str,
+) -> anyhow::Error {
+    let lines: Vec<String> = fields
+        .into_iter()
+        .map(|(field, msg)| format!("  {}: {}", name(field), msg))
+        .collect();
+    anyhow!("Invalid input:\n{}", lines.join("\n"))
+}
+
+/// [`crate::templates::AddSiteErrors`] fields as `sites create` calls them.
+fn site_field(field: &'static str) -> &'static str {
+    match field {
+        // Taken domains are reported on the (derived) folder path.
+        "domain" | "folder_path" => "<domain>",
+        "wp_title" => "--title",
+        "wp_admin_user" => "--admin-user",
+        "wp_admin_password" => "--admin-password",
+        "wp_admin_email" => "--admin-email",
+        other => other,
+    }
+}
+
+/// [`crate::templates::AddDatabaseErrors`] fields as `databases create` calls them.
+fn database_field(field: &'static str) -> &'static str {
+    match field {
+        "site_id" => "<site>",
+        "database_name" => "<name>",
+        "username" => "<user>",
+        "password" => "--password",
+        other => other,
+    }
+}
+
+/// [`crate::templates::AddUserErrors`] fields as `users add` calls them.
+fn user_field(field: &'static str) -> &'static str {
+    match field {
+        "username" => "<username>",
+        "password" => "--password",
+        "role" => "--role",
+        other => other,
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn display_time(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// 96 random bits, for passwords the operator did not give.
+fn generate_password() -> String {
+    crate::csrf::generate_token()[..24].to_string()
+}
+
+/// `--as <username>`, or the first active admin. Must be allowed to manage sites.
+async fn acting_user(state: &AppState, args: &Args) -> anyhow::Result<User> {
+    let user = match args.option("as") {
+        Some(username) => sqlx::query_as::<_, User>(&format!(
+            "SELECT {} FROM users WHERE username = $1",
+            USER_COLUMNS
+        ))
+        .bind(username)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| anyhow!("No user '{}' found.", username))?,
+        None => sqlx::query_as::<_, User>(&format!(
+            "SELECT {} FROM users WHERE role = 'admin' AND disabled_at IS NULL ORDER BY id LIMIT 1",
+            USER_COLUMNS
+        ))
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| anyhow!("No active admin found; pass --as <username>."))?,
+    };
+    if user.is_disabled() {
+        bail!("User '{}' is disabled.", user.username);
+    }
+    if !user.role().can_manage() {
+        bail!("User '{}' is read-only.", user.username);
+    }
+    Ok(user)
+}
+
+/// A site by id or domain.
+async fn find_site(state: &AppState, key: &str) -> anyhow::Result<Site> {
+    let query = format!(
+        "SELECT {} FROM sites WHERE id = $1 OR domain = $2",
+        SITE_COLUMNS
+    );
+    sqlx::query_as::<_, Site>(&query)
+        .bind(key.parse::<i32>().ok())
+        .bind(key.to_ascii_lowercase())
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| anyhow!("Site '{}' not found.", key))
+}
+
+async fn fetch_job(state: &AppState, job_id: i32) -> anyhow::Result<Job> {
+    Ok(
+        sqlx::query_as::<_, Job>(&format!("SELECT {} FROM jobs WHERE id = $1", JOB_COLUMNS))
+            .bind(job_id)
+            .fetch_one(&state.pool)
+            .await?,
+    )
+}
+
+/// Run a queued job here, or wait for the panel's worker if it got there first,
+/// then report it. Fails if the job failed.
+async fn finish_job(state: &AppState, job_id: i32, json: bool) -> anyhow::Result<()> {
+    if !json {
+        eprintln!("Job #{} queued.", job_id);
+    }
+    let ran_here = tokio::select! {
+        ran = jobs::run_job(state, job_id) => ran.map_err(app_error)?,
+        _ = tokio::signal::ctrl_c() => {
+            jobs::interrupt(&state.pool, job_id, "Interrupted: the CLI running this job was stopped.")
+                .await
+                .map_err(app_error)?;
+            bail!("Interrupted; job #{} was marked failed and can be retried.", job_id);
+        }
+    };
+    if !ran_here && !json {
+        eprintln!("The panel's worker is running it; waiting…");
+    }
+    let job = loop {
+        let job = fetch_job(state, job_id).await?;
+        if job.is_finished() {
+            break job;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(JOB_POLL_INTERVAL) => {}
+            _ = tokio::signal::ctrl_c() => bail!("Stopped waiting; the panel's worker is still running job #{}.", job_id),
+        }
+    };
+
+    if json {
+        print_json(&job)?;
+    } else {
+        match &job.error {
+            Some(error) => eprintln!("Job #{} ({}) failed: {}", job.id, job.kind, error),
+            None => println!("Job #{} ({}) succeeded: {}", job.id, job.kind, job.summary),
+        }
+        for output in [&job.stdout, &job.stderr] {
+            if !output.trim().is_empty() {
+                println!("{}", output.trim_end());
+            }
+        }
+    }
+    if job.error.is_some() {
+        bail!("Job #{} failed.", job.id);
+    }
+    Ok(())
+}
+
+async fn sites_list(state: &AppState, json: bool) -> anyhow::Result<()> {
+    let sites = sqlx::query_as::<_, Site>(&format!(
+        "SELECT {} FROM sites ORDER BY domain",
+        SITE_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    if json {
+        return print_json(&sites);
+    }
+    println!(
+        "{:>5}  {:<40} {:<5} {:<9} CREATED",
+        "ID", "DOMAIN", "PHP", "WORDPRESS"
+    );
+    for site in &sites {
+        println!(
+            "{:>5}  {:<40} {:<5} {:<9} {}",
+            site.id,
+            site.domain,
+            site.php_version,
+            if site.wordpress_installed {
+                "yes"
+            } else {
+                "no"
+            },
+            display_time(site.created_at)
+        );
+    }
+    Ok(())
+}
+
+async fn sites_show(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let site = find_site(state, args.arg(2, "id|domain")?).await?;
+    let detail = actions::site_detail(&state.pool, site.id)
+        .await
+        .map_err(app_error)?;
+    if json {
+        return print_json(&detail);
+    }
+    let site = &detail.site;
+    println!("Site #{}: {}", site.id, site.domain);
+    println!("  Folder:     {}", site.folder_path);
+    println!("  PHP:        {}", site.php_version);
+    println!(
+        "  WordPress:  {}",
+        if site.wordpress_installed {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    println!("  Serves:     {}", site.primary_host());
+    println!(
+        "  TLS:        {}",
+        if site.has_custom_tls() {
+            "uploaded certificate"
+        } else {
+            "automatic (ACME)"
+        }
+    );
+    println!("  Created:    {}", display_time(site.created_at));
+    println!("  Restarted:  {}", site.last_restarted_display());
+    if !detail.aliases.is_empty() {
+        println!("Aliases:");
+        for alias in &detail.aliases {
+            println!("  {} ({})", alias.hostname, alias.mode_display());
+        }
+    }
+    if !detail.databases.is_empty() {
+        println!("Databases:");
+        for db in &detail.databases {
+            println!(
+                "  #{} {} (user {}, {})",
+                db.id, db.db_name, db.db_user, db.privileges
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn sites_create(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let user = acting_user(state, args).await?;
+    let wordpress = args.flag("wordpress");
+    let mut generated = None;
+    let wp_admin_password = match args.option("admin-password") {
+        Some(password) => password.to_string(),
+        None if wordpress => generated.insert(generate_password()).clone(),
+        None => String::new(),
+    };
+    let wp = |name: &str| wordpress.then(|| args.option(name).unwrap_or_default().to_string());
+    let form = AddSiteForm {
+        domain: args.arg(2, "domain")?.to_string(),
+        install_wordpress: wordpress.then(|| "1".to_string()),
+        php_version: args.option("php").map(str::to_string),
+        wp_title: wp("title"),
+        wp_admin_user: wp("admin-user"),
+        wp_admin_password: wordpress.then_some(wp_admin_password),
+        wp_admin_email: wp("admin-email"),
+    };
+    let job_id = actions::create_site(state, &Actor::cli(Some(user.id)), &form)
+        .await
+        .map_err(app_error)?
+        .map_err(|errors| invalid(errors.fields(), site_field))?;
+    if let Some(password) = generated {
+        eprintln!("WordPress admin password: {}", password);
+    }
+    finish_job(state, job_id, json).await
+}
+
+async fn sites_delete(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let site = find_site(state, args.arg(2, "id|domain")?).await?;
+    if !args.flag("yes") {
+        bail!(
+            "This deletes {} with its files and databases. Add --yes to confirm.",
+            site.domain
+        );
+    }
+    let user = acting_user(state, args).await?;
+    let job_id = actions::delete_site(state, &Actor::cli(Some(user.id)), site.id)
+        .await
+        .map_err(app_error)?;
+    finish_job(state, job_id, json).await
+}
+
+async fn databases_list(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let site_id = match args.option("site") {
+        Some(key) => Some(find_site(state, key).await?.id),
+        None => None,
+    };
+    let databases = sqlx::query_as::<_, SiteDatabase>(&format!(
+        "SELECT {} FROM site_databases WHERE $1::INTEGER IS NULL OR site_id = $1 ORDER BY site_id, db_name",
+        SITE_DATABASE_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(&state.pool)
+    .await?;
+    if json {
+        return print_json(&databases);
+    }
+    println!(
+        "{:>5}  {:>5}  {:<32} {:<24} {:<10} CREATED",
+        "ID", "SITE", "DATABASE", "USER", "ACCESS"
+    );
+    for db in &databases {
+        println!(
+            "{:>5}  {:>5}  {:<32} {:<24} {:<10} {}",
+            db.id,
+            db.site_id,
+            db.db_name,
+            db.db_user,
+            db.privileges,
+            display_time(db.created_at)
+        );
+    }
+    Ok(())
+}
+
+async fn databases_create(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let user = acting_user(state, args).await?;
+    let site = find_site(state, args.arg(2, "site")?).await?;
+    let mut generated = None;
+    let password = match args.option("password") {
+        Some(password) => password.to_string(),
+        None => generated.insert(generate_password()).clone(),
+    };
+    let form = CreateDatabaseForm {
+        site_id: site.id.to_string(),
+        database_name: args.arg(3, "name")?.to_string(),
+        username: args.arg(4, "user")?.to_string(),
+        password,
+        privileges: args.flag("readonly").then(|| "readonly".to_string()),
+    };
+    let job_id = actions::create_database(state, &Actor::cli(Some(user.id)), &form)
+        .await
+        .map_err(app_error)?
+        .map_err(|errors| invalid(errors.fields(), database_field))?;
+    if let Some(password) = generated {
+        eprintln!("Database password: {}", password);
+    }
+    finish_job(state, job_id, json).await
+}
+
+async fn databases_delete(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let id: i32 = args
+        .arg(2, "id")?
+        .parse()
+        .map_err(|_| anyhow!("<id> must be a database id."))?;
+    if !args.flag("yes") {
+        bail!(
+            "This drops database #{} and its user. Add --yes to confirm.",
+            id
+        );
+    }
+    let user = acting_user(state, args).await?;
+    let job_id = actions::delete_database(state, &Actor::cli(Some(user.id)), id)
+        .await
+        .map_err(app_error)?;
+    finish_job(state, job_id, json).await
+}
+
+/// A panel user without the password hash or TOTP secret.
+#[derive(Serialize)]
+struct UserSummary<'a> {
+    id: i32,
+    username: &'a str,
+    role: &'a str,
+    two_factor: bool,
+    created_at: DateTime<Utc>,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a User> for UserSummary<'a> {
+    fn from(u: &'a User) -> Self {
+        UserSummary {
+            id: u.id,
+            username: &u.username,
+            role: &u.role,
+            two_factor: u.has_totp(),
+            created_at: u.created_at,
+            disabled_at: u.disabled_at,
+        }
+    }
+}
+
+async fn find_user(state: &AppState, username: &str) -> anyhow::Result<User> {
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE username = $1",
+        USER_COLUMNS
+    ))
+    .bind(username)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| anyhow!("No user '{}' found.", username))
+}
+
+async fn users_list(state: &AppState, json: bool) -> anyhow::Result<()> {
+    let users = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users ORDER BY username",
+        USER_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    if json {
+        let summaries: Vec<UserSummary> = users.iter().map(UserSummary::from).collect();
+        return print_json(&summaries);
+    }
+    println!(
+        "{:>5}  {:<32} {:<10} {:<4} {:<8} CREATED",
+        "ID", "USERNAME", "ROLE", "2FA", "STATUS"
+    );
+    for user in &users {
+        println!(
+            "{:>5}  {:<32} {:<10} {:<4} {:<8} {}",
+            user.id,
+            user.username,
+            user.role,
+            if user.has_totp() { "on" } else { "off" },
+            if user.is_disabled() {
+                "disabled"
+            } else {
+                "active"
+            },
+            user.created_display()
+        );
+    }
+    Ok(())
+}
+
+async fn users_add(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let username = args.arg(2, "username")?;
+    let mut generated = None;
+    let password = match args.option("password") {
+        Some(password) => password.to_string(),
+        None => generated.insert(generate_password()).clone(),
+    };
+    let role = args.option("role").unwrap_or_default();
+    actions::create_user(&state.pool, &Actor::cli(None), username, &password, role)
+        .await
+        .map_err(app_error)?
+        .map_err(|errors| invalid(errors.fields(), user_field))?;
+    if json {
+        if let Some(password) = generated {
+            eprintln!("Password: {}", password);
+        }
+        return print_json(&UserSummary::from(&find_user(state, username.trim()).await?));
+    }
+    println!("User '{}' added as {}.", username.trim(), role);
+    if let Some(password) = generated {
+        println!("Password: {}", password);
+    }
+    Ok(())
+}
+
+async fn users_disable(state: &AppState, args: &Args, json: bool) -> anyhow::Result<()> {
+    let username = args.arg(2, "username")?;
+    let user = find_user(state, username).await?;
+    if user.is_disabled() {
+        if json {
+            return print_json(&UserSummary::from(&user));
+        }
+        println!("User '{}' is already disabled.", username);
+        return Ok(());
+    }
+    actions::disable_user(&state.pool, &Actor::cli(None), &user)
+        .await
+        .map_err(app_error)?
+        .map_err(|msg| anyhow!(msg))?;
+    if json {
+        return print_json(&UserSummary::from(&find_user(state, username).await?));
+    }
+    println!("User '{}' disabled and signed out everywhere.", username);
+    Ok(())
+}
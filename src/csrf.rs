@@ -0,0 +1,274 @@
+//! CSRF protection. Each session gets a random token when it is created; every
+//! page renders it into its forms as a hidden `csrf` field, and `require_csrf`
+//! rejects state-changing requests that do not send it back. Scripts can send
+//! it in an `X-CSRF-Token` header instead.
+//!
+//! The login form has no session yet, so it uses a double-submit cookie: the
+//! token is set as a cookie when the form is shown and must come back in the
+//! form as well.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRequest, Multipart, Request},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::Response,
+    Form,
+};
+use serde::Deserialize;
+
+use crate::error::{AppError, Result};
+
+pub const FIELD: &str = "csrf";
+pub const HEADER: &str = "x-csrf-token";
+/// Double-submit cookie for the login form.
+pub const LOGIN_COOKIE: &str = "panel_login_csrf";
+/// Largest body buffered to look for the token; the same as axum's default
+/// limit for `Form` and `Multipart`, so nothing the handlers accept is refused.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The signed-in session's token, added to requests by `auth::require_auth`.
+#[derive(Clone)]
+pub struct CsrfToken(pub String);
+
+impl CsrfToken {
+    pub fn value(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenField {
+    #[serde(default)]
+    csrf: String,
+}
+
+/// 32 random bytes, hex encoded.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut bytes)
+        .expect("system random number generator failed");
+    data_encoding::HEXLOWER.encode(&bytes)
+}
+
+/// Compare without returning early, so response times say nothing about how
+/// much of a guess was right.
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    !expected.is_empty()
+        && expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Read a cookie from the request headers.
+pub fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|c| c.split(';'))
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.trim_matches('"').to_string())
+}
+
+/// Reject POSTs (and other non-GET requests) whose `csrf` field or
+/// `X-CSRF-Token` header does not match the session's token. Runs inside
+/// `require_auth`. Form bodies are buffered to find the field and handed on
+/// unchanged.
+pub async fn require_csrf(request: Request, next: Next) -> Result<Response> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(next.run(request).await);
+    }
+    let expected = request
+        .extensions()
+        .get::<CsrfToken>()
+        .map(|t| t.0.clone())
+        .unwrap_or_default();
+
+    if let Some(given) = request.headers().get(HEADER).and_then(|v| v.to_str().ok()) {
+        if tokens_match(&expected, given) {
+            return Ok(next.run(request).await);
+        }
+        return Err(AppError::Csrf);
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|_| AppError::Validation("Request body too large.".to_string()))?;
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .cloned()
+        .unwrap_or(header::HeaderValue::from_static(""));
+    let given = form_token(&parts.method, content_type, bytes.clone()).await;
+    if !tokens_match(&expected, &given) {
+        tracing::warn!("csrf: rejected {} {}", parts.method, parts.uri.path());
+        return Err(AppError::Csrf);
+    }
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
+
+/// The `csrf` field of a urlencoded or multipart body; empty if there is none.
+async fn form_token(
+    method: &Method,
+    content_type: header::HeaderValue,
+    bytes: bytes::Bytes,
+) -> String {
+    let probe = || {
+        Request::builder()
+            .method(method.clone())
+            .header(header::CONTENT_TYPE, content_type.clone())
+            .body(Body::from(bytes.clone()))
+            .unwrap_or_default()
+    };
+    if let Ok(Form(field)) = Form::<TokenField>::from_request(probe(), &()).await {
+        return field.csrf;
+    }
+    let Ok(mut multipart) = Multipart::from_request(probe(), &()).await else {
+        return String::new();
+    };
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some(FIELD) {
+            return field.text().await.unwrap_or_default();
+        }
+    }
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, middleware, routing::post, Extension, Router};
+    use tower::Service;
+
+    use super::*;
+
+    const TOKEN: &str = "5f2b8c1e9a7d4e3f6b0c2a1d8e9f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f";
+
+    /// A route behind `require_csrf` that answers with the body it was handed.
+    async fn send(token: Option<&str>, request: Request) -> (StatusCode, String) {
+        let mut app = Router::new()
+            .route(
+                "/",
+                post(|body: String| async move { body }).get(|| async { "page" }),
+            )
+            .layer(middleware::from_fn(require_csrf));
+        if let Some(token) = token {
+            app = app.layer(Extension(CsrfToken(token.to_string())));
+        }
+        let response = app.call(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn form(body: impl Into<Body>) -> Request {
+        Request::post("/")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.into())
+            .unwrap()
+    }
+
+    fn multipart(token: &str) -> Request {
+        let body = format!(
+            "--XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.pem\"\r\n\r\n\
+             -----BEGIN-----\r\n--XYZ\r\nContent-Disposition: form-data; name=\"csrf\"\r\n\r\n\
+             {}\r\n--XYZ--\r\n",
+            token
+        );
+        Request::post("/")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XYZ")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_tokens_pass() {
+        // The body reaches the handler as it was sent.
+        let body = format!("name=a+b&csrf={}", TOKEN);
+        assert_eq!(
+            send(Some(TOKEN), form(body.clone())).await,
+            (StatusCode::OK, body)
+        );
+
+        let request = Request::post("/")
+            .header(HEADER, TOKEN)
+            .body(Body::from("{}"))
+            .unwrap();
+        assert_eq!(
+            send(Some(TOKEN), request).await,
+            (StatusCode::OK, "{}".to_string())
+        );
+
+        let (status, body) = send(Some(TOKEN), multipart(TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("-----BEGIN-----"));
+
+        // Reading pages needs no token.
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(
+            send(None, request).await,
+            (StatusCode::OK, "page".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_tokens_are_rejected() {
+        let wrong = TOKEN.replace('5', "6");
+        for request in [
+            form("name=x"),
+            form(format!("csrf={}", wrong)),
+            form(format!("csrf={}", &TOKEN[..63])),
+            form(""),
+            multipart(&wrong),
+            // A wrong header is not made up for by a right field.
+            Request::post("/")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header(HEADER, wrong.as_str())
+                .body(Body::from(format!("csrf={}", TOKEN)))
+                .unwrap(),
+        ] {
+            assert_eq!(send(Some(TOKEN), request).await.0, StatusCode::FORBIDDEN);
+        }
+        // Without a session token nothing matches, not even an empty field.
+        assert_eq!(send(None, form("csrf=")).await.0, StatusCode::FORBIDDEN);
+        let request = Request::post("/")
+            .header(HEADER, "")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(None, request).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_rejected() {
+        let mut body = format!("csrf={}&pad=", TOKEN).into_bytes();
+        body.resize(BODY_LIMIT + 1, b'x');
+        assert_eq!(
+            send(Some(TOKEN), form(body)).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        let mut body = format!("csrf={}&pad=", TOKEN).into_bytes();
+        body.resize(BODY_LIMIT, b'x');
+        assert_eq!(send(Some(TOKEN), form(body)).await.0, StatusCode::OK);
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(tokens_match(TOKEN, TOKEN));
+        assert!(!tokens_match(TOKEN, &TOKEN.to_uppercase()));
+        assert!(!tokens_match(TOKEN, &TOKEN[1..]));
+        assert!(!tokens_match(TOKEN, ""));
+        assert!(!tokens_match("", ""));
+        assert_eq!(generate_token().len(), 64);
+        assert_ne!(generate_token(), generate_token());
+    }
+}
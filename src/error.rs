@@ -9,12 +9,21 @@ pub enum AppError {
     #[error("Unauthorized")]
     Unauthorized,
 
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Missing or invalid CSRF token")]
+    Csrf,
+
     #[error("Invalid credentials")]
     InvalidCredentials,
 
     #[error("Validation error: {0}")]
     Validation(String),
 
+    #[error("Not found: {0}")]
+    NotFound(String),
+
     #[error("Database error: {0}")]
     Db(#[from] sqlx::Error),
 
@@ -22,16 +31,25 @@ pub enum AppError {
     Internal(#[from] anyhow::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message): (StatusCode, String) = match self {
+impl AppError {
+    /// Status code and the message the client sees.
+    pub fn status_and_message(self) -> (StatusCode, String) {
+        match self {
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".into()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "You do not have permission to do that.".into()),
+            AppError::Csrf => (StatusCode::FORBIDDEN, "This form has expired. Go back, reload the page and try again.".into()),
             AppError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid username or password".into()),
             AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             AppError::Db(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error".into()),
             AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong".into()),
-        };
-        (status, message).into_response()
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.status_and_message().into_response()
     }
 }
 
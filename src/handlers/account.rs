@@ -0,0 +1,281 @@
+use axum::{
+    extract::{Extension, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use bcrypt::verify;
+
+use crate::audit::{Actor, Event};
+use crate::auth::{Role, UserId};
+use crate::csrf::CsrfToken;
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::models::{ConfirmPasswordForm, TotpCodeForm, User, USER_COLUMNS};
+use crate::state::AppState;
+use crate::templates::AccountPage;
+use crate::totp;
+
+async fn fetch_user(pool: &DbPool, id: i32) -> Result<User> {
+    sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::Unauthorized)
+}
+
+async fn account_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    user_id: UserId,
+) -> Result<AccountPage> {
+    let user = fetch_user(&state.pool, user_id.value()).await?;
+    let recovery_codes_left = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await?;
+    // A secret without totp_enabled_at is an enrollment waiting for its first code.
+    let (qr_svg, secret) = match (&user.totp_secret, user.has_totp()) {
+        (Some(secret), false) => (
+            totp::qr_svg(&totp::provisioning_uri(&user.username, secret)),
+            totp::secret_display(secret),
+        ),
+        _ => (String::new(), String::new()),
+    };
+    Ok(AccountPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        username: user.username.clone(),
+        totp_enabled_at: user
+            .totp_enabled_at
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default(),
+        qr_svg,
+        secret,
+        recovery_codes: Vec::new(),
+        recovery_codes_left,
+        error_message: String::new(),
+    })
+}
+
+async fn account_error(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    user_id: UserId,
+    message: &str,
+) -> Result<Response> {
+    let mut page = account_page(state, role, csrf, user_id).await?;
+    page.error_message = message.to_string();
+    Ok(page.into_response())
+}
+
+/// Replace the user's recovery codes; the plain codes are only ever shown once.
+async fn new_recovery_codes(pool: &DbPool, user_id: i32) -> Result<Vec<String>> {
+    let codes = totp::generate_recovery_codes();
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code in &codes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(totp::hash_recovery_code(code))
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(codes)
+}
+
+/// Check a second factor for `user_id`: a current TOTP code (each accepted
+/// once) or an unused recovery code, which is used up.
+pub(crate) async fn check_second_factor(pool: &DbPool, user_id: i32, code: &str) -> Result<bool> {
+    let user = fetch_user(pool, user_id).await?;
+    let Some(secret) = user.totp_secret.as_deref().filter(|_| user.has_totp()) else {
+        return Ok(false);
+    };
+    let now = chrono::Utc::now().timestamp();
+    if let Some(step) = totp::verify(secret, code, now, user.totp_last_step) {
+        // Conditional so two requests racing with the same code cannot both pass.
+        let updated = sqlx::query(
+            "UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+        return Ok(updated.rows_affected() == 1);
+    }
+    let used = sqlx::query(
+        "UPDATE recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(totp::hash_recovery_code(code))
+    .execute(pool)
+    .await?;
+    Ok(used.rows_affected() > 0)
+}
+
+pub async fn account(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<impl IntoResponse> {
+    account_page(&state, role, &csrf, user_id).await
+}
+
+/// Start enrollment: store a fresh secret and show it as a QR code.
+pub async fn setup_totp(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+) -> Result<Response> {
+    let started =
+        sqlx::query("UPDATE users SET totp_secret = $1 WHERE id = $2 AND totp_enabled_at IS NULL")
+            .bind(totp::generate_secret())
+            .bind(user_id.value())
+            .execute(&state.pool)
+            .await?;
+    if started.rows_affected() > 0 {
+        Event::new("account.2fa.setup").record(&state.pool, &actor).await;
+    }
+    Ok(Redirect::to("/account").into_response())
+}
+
+pub async fn cancel_totp_setup(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+) -> Result<Response> {
+    let cancelled = sqlx::query(
+        "UPDATE users SET totp_secret = NULL WHERE id = $1 AND totp_secret IS NOT NULL AND totp_enabled_at IS NULL",
+    )
+    .bind(user_id.value())
+    .execute(&state.pool)
+    .await?;
+    if cancelled.rows_affected() > 0 {
+        Event::new("account.2fa.cancel").record(&state.pool, &actor).await;
+    }
+    Ok(Redirect::to("/account").into_response())
+}
+
+/// Finish enrollment with a code from the app, then show the recovery codes.
+pub async fn enable_totp(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<TotpCodeForm>,
+) -> Result<Response> {
+    let user = fetch_user(&state.pool, user_id.value()).await?;
+    let Some(secret) = user.totp_secret.as_deref().filter(|_| !user.has_totp()) else {
+        return Ok(Redirect::to("/account").into_response());
+    };
+    let now = chrono::Utc::now().timestamp();
+    let Some(step) = totp::verify(secret, &form.code, now, None) else {
+        Event::new("account.2fa.enable")
+            .failed("Invalid code")
+            .record(&state.pool, &actor)
+            .await;
+        return account_error(
+            &state,
+            role,
+            &csrf,
+            user_id,
+            "That code is not valid. Check that your device's clock is correct and enter the current code.",
+        )
+        .await;
+    };
+    sqlx::query("UPDATE users SET totp_enabled_at = NOW(), totp_last_step = $1 WHERE id = $2")
+        .bind(step)
+        .bind(user.id)
+        .execute(&state.pool)
+        .await?;
+    Event::new("account.2fa.enable").record(&state.pool, &actor).await;
+    let codes = new_recovery_codes(&state.pool, user.id).await?;
+    let mut page = account_page(&state, role, &csrf, user_id).await?;
+    page.recovery_codes = codes;
+    Ok(page.into_response())
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<ConfirmPasswordForm>,
+) -> Result<Response> {
+    let user = fetch_user(&state.pool, user_id.value()).await?;
+    if !user.has_totp() {
+        return Ok(Redirect::to("/account").into_response());
+    }
+    if !password_matches(&user, &form.password)? {
+        Event::new("account.recovery_codes")
+            .failed("Incorrect password")
+            .record(&state.pool, &actor)
+            .await;
+        return account_error(&state, role, &csrf, user_id, "Incorrect password.").await;
+    }
+    let codes = new_recovery_codes(&state.pool, user.id).await?;
+    Event::new("account.recovery_codes")
+        .record(&state.pool, &actor)
+        .await;
+    let mut page = account_page(&state, role, &csrf, user_id).await?;
+    page.recovery_codes = codes;
+    Ok(page.into_response())
+}
+
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<ConfirmPasswordForm>,
+) -> Result<Response> {
+    let user = fetch_user(&state.pool, user_id.value()).await?;
+    if !password_matches(&user, &form.password)? {
+        Event::new("account.2fa.disable")
+            .failed("Incorrect password")
+            .record(&state.pool, &actor)
+            .await;
+        return account_error(&state, role, &csrf, user_id, "Incorrect password.").await;
+    }
+    reset_totp(&state.pool, user.id).await?;
+    Event::new("account.2fa.disable").record(&state.pool, &actor).await;
+    Ok(Redirect::to("/account?totp=disabled").into_response())
+}
+
+fn password_matches(user: &User, password: &str) -> Result<bool> {
+    verify(password, &user.password_hash)
+        .map_err(|_| AppError::Internal(anyhow::anyhow!("bcrypt error")))
+}
+
+/// Turn 2FA off for a user and drop their recovery codes and pending logins.
+pub async fn reset_totp(pool: &DbPool, user_id: i32) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM login_challenges WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
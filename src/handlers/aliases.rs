@@ -0,0 +1,302 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use sqlx::PgConnection;
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::caddy::{CertificateStore, SiteCaddyConfig};
+use crate::csrf::CsrfToken;
+use crate::error::{AppError, Result};
+use crate::models::{
+    validate_domain, AddAliasForm, CanonicalHostForm, Site, SiteAlias, SITE_ALIAS_COLUMNS,
+};
+use crate::provision::{LogSink, ProvisionError};
+use crate::state::AppState;
+
+use super::sites::{fetch_site, site_detail_page};
+
+pub(crate) async fn site_aliases(conn: &mut PgConnection, site_id: i32) -> Result<Vec<SiteAlias>> {
+    Ok(sqlx::query_as::<_, SiteAlias>(&format!(
+        "SELECT {} FROM site_aliases WHERE site_id = $1 ORDER BY id",
+        SITE_ALIAS_COLUMNS
+    ))
+    .bind(site_id)
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Write the site's snippet and reload; the error text includes the
+/// validate/reload output so the user can see what Caddy rejected.
+pub(crate) async fn apply_site_config(
+    state: &AppState,
+    config: &SiteCaddyConfig,
+) -> std::result::Result<(), String> {
+    match state
+        .provisioner
+        .apply_site_config(config, &LogSink::default())
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(ProvisionError::Failed { output, .. }) => Err(output.stderr),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Re-render the site page on the Domains tab with `message`.
+async fn domains_error(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    id: i32,
+    message: String,
+    hostname: String,
+) -> Result<Response> {
+    let mut page = site_detail_page(state, role, csrf, id).await?;
+    page.domains_error = message;
+    page.alias_hostname = hostname;
+    page.active_tab = "domains".to_string();
+    Ok(page.into_response())
+}
+
+/// Which site (if any) already answers for `host`, by domain or alias.
+async fn host_owner(state: &AppState, host: &str) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT domain FROM sites WHERE domain = $1 \
+         UNION ALL SELECT s.domain FROM site_aliases a JOIN sites s ON s.id = a.site_id WHERE a.hostname = $1",
+    )
+    .bind(host)
+    .fetch_optional(&state.pool)
+    .await?)
+}
+
+/// With an uploaded certificate every name in the main site block is served
+/// with it, so it has to cover them; redirect-only names get ACME certificates.
+async fn uncovered_host(state: &AppState, site: &Site, hosts: &[String]) -> Option<String> {
+    let path = site.tls_cert_path.as_deref()?;
+    let report = CertificateStore::from_config(&state.config)
+        .inspect_custom(std::path::Path::new(path))
+        .await;
+    let cert = report.certificate?;
+    hosts.iter().find(|h| !cert.covers(h)).map(|h| {
+        format!(
+            "The uploaded certificate does not cover {} (it is valid for: {}). Upload a certificate that includes it.",
+            h,
+            cert.sans_display()
+        )
+    })
+}
+
+/// Add a hostname that serves the site or redirects to its canonical host.
+pub async fn add_alias(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<AddAliasForm>,
+) -> Result<Response> {
+    let site = fetch_site(&state, id).await?;
+    let hostname = form.hostname.trim().to_ascii_lowercase();
+    let (mode, status) = match form.mode.as_str() {
+        "serve" => ("serve", 301),
+        "301" => ("redirect", 301),
+        "308" => ("redirect", 308),
+        _ => {
+            return domains_error(
+                &state,
+                role,
+                &csrf,
+                id,
+                "Choose whether the alias serves the site or redirects.".to_string(),
+                hostname,
+            )
+            .await
+        }
+    };
+
+    if let Err(msg) = validate_domain(&hostname) {
+        return domains_error(&state, role, &csrf, id, msg, hostname).await;
+    }
+    let own = [
+        Some(site.domain.clone()),
+        Some(site.primary_host()),
+        site.redirected_host(),
+    ];
+    if own
+        .iter()
+        .flatten()
+        .any(|h| h.eq_ignore_ascii_case(&hostname))
+    {
+        return domains_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            format!("{} is already part of this site.", hostname),
+            hostname,
+        )
+        .await;
+    }
+    if let Some(owner) = host_owner(&state, &hostname).await? {
+        return domains_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            format!("{} already belongs to the site {}.", hostname, owner),
+            hostname,
+        )
+        .await;
+    }
+    if mode == "serve" {
+        if let Some(msg) = uncovered_host(&state, &site, std::slice::from_ref(&hostname)).await {
+            return domains_error(&state, role, &csrf, id, msg, hostname).await;
+        }
+    }
+
+    // The row and the snippet change together: roll the insert back if Caddy rejects it.
+    let mut tx = state.pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO site_aliases (site_id, hostname, mode, redirect_status) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(id)
+    .bind(&hostname)
+    .bind(mode)
+    .bind(status)
+    .execute(&mut *tx)
+    .await?;
+    let aliases = site_aliases(&mut tx, id).await?;
+    let event = Event::new("site.alias.add")
+        .target(Target::site(Some(id), &site.domain))
+        .detail(format!("{} ({})", hostname, form.mode));
+    if let Err(msg) = apply_site_config(&state, &SiteCaddyConfig::for_site(&site, &aliases)).await {
+        tx.rollback().await?;
+        event
+            .failed(format!("{}: {}", hostname, msg))
+            .record(&state.pool, &actor)
+            .await;
+        return domains_error(&state, role, &csrf, id, msg, hostname).await;
+    }
+    tx.commit().await?;
+    event.record(&state.pool, &actor).await;
+    Ok(Redirect::to(&format!("/sites/{}?alias=added#domains", id)).into_response())
+}
+
+pub async fn delete_alias(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path((id, alias_id)): Path<(i32, i32)>,
+) -> Result<Response> {
+    let site = fetch_site(&state, id).await?;
+    let mut tx = state.pool.begin().await?;
+    let hostname = sqlx::query_scalar::<_, String>(
+        "DELETE FROM site_aliases WHERE id = $1 AND site_id = $2 RETURNING hostname",
+    )
+    .bind(alias_id)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::Validation("Alias not found.".to_string()))?;
+    let aliases = site_aliases(&mut tx, id).await?;
+    let event = Event::new("site.alias.delete").target(Target::site(Some(id), &site.domain));
+    if let Err(msg) = apply_site_config(&state, &SiteCaddyConfig::for_site(&site, &aliases)).await {
+        tx.rollback().await?;
+        event
+            .failed(format!("{}: {}", hostname, msg))
+            .record(&state.pool, &actor)
+            .await;
+        return domains_error(&state, role, &csrf, id, msg, String::new()).await;
+    }
+    tx.commit().await?;
+    event.detail(hostname).record(&state.pool, &actor).await;
+    Ok(Redirect::to(&format!("/sites/{}?alias=removed#domains", id)).into_response())
+}
+
+/// Choose whether www.<domain> or the bare domain is canonical; the other one
+/// redirects to it with a 301.
+pub async fn set_canonical_host(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<CanonicalHostForm>,
+) -> Result<Response> {
+    let mut site = fetch_site(&state, id).await?;
+    let policy = form.canonical_host.as_str();
+    if !matches!(policy, "domain" | "www" | "apex") {
+        return domains_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            "Unknown canonical host setting.".to_string(),
+            String::new(),
+        )
+        .await;
+    }
+    if policy != "domain" && (site.domain.starts_with("*.") || !site.domain.contains('.')) {
+        return domains_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            "A www/apex preference needs a regular domain name.".to_string(),
+            String::new(),
+        )
+        .await;
+    }
+    if policy == site.canonical_host {
+        return Ok(Redirect::to(&format!("/sites/{}#domains", id)).into_response());
+    }
+    site.canonical_host = policy.to_string();
+
+    let mut tx = state.pool.begin().await?;
+    let aliases = site_aliases(&mut tx, id).await?;
+    // Names the policy adds to this site must not belong to another site.
+    for host in [Some(site.primary_host()), site.redirected_host()]
+        .into_iter()
+        .flatten()
+    {
+        if host == site.domain || aliases.iter().any(|a| a.hostname == host) {
+            continue;
+        }
+        if let Some(owner) = host_owner(&state, &host).await? {
+            return domains_error(
+                &state,
+                role,
+                &csrf,
+                id,
+                format!("{} already belongs to the site {}.", host, owner),
+                String::new(),
+            )
+            .await;
+        }
+    }
+    if let Some(msg) = uncovered_host(&state, &site, &[site.primary_host()]).await {
+        return domains_error(&state, role, &csrf, id, msg, String::new()).await;
+    }
+
+    sqlx::query("UPDATE sites SET canonical_host = $1 WHERE id = $2")
+        .bind(policy)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let event = Event::new("site.canonical").target(Target::site(Some(id), &site.domain));
+    if let Err(msg) = apply_site_config(&state, &SiteCaddyConfig::for_site(&site, &aliases)).await {
+        tx.rollback().await?;
+        event.failed(msg.clone()).record(&state.pool, &actor).await;
+        return domains_error(&state, role, &csrf, id, msg, String::new()).await;
+    }
+    tx.commit().await?;
+    event
+        .detail(format!("Serve {}", site.primary_host()))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to(&format!("/sites/{}?canonical=1#domains", id)).into_response())
+}
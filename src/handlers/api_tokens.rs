@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+
+use crate::api::{self, TokenScope};
+use crate::audit::{Actor, Event, Target};
+use crate::auth::{Role, UserId};
+use crate::csrf::CsrfToken;
+use crate::error::Result;
+use crate::models::{ApiToken, ApiTokenForm, API_TOKEN_COLUMNS};
+use crate::state::AppState;
+use crate::templates::ApiTokensPage;
+
+const MAX_TOKEN_NAME_LEN: usize = 64;
+
+async fn api_tokens_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    user_id: UserId,
+) -> Result<ApiTokensPage> {
+    let tokens = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+        API_TOKEN_COLUMNS
+    ))
+    .bind(user_id.value())
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(ApiTokensPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        tokens,
+        new_token: String::new(),
+        name: String::new(),
+        scope: TokenScope::Read.as_str().to_string(),
+        error_message: String::new(),
+    })
+}
+
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<impl IntoResponse> {
+    api_tokens_page(&state, role, &csrf, user_id).await
+}
+
+/// Create a token and show it once. Read-only users can only create read tokens.
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<ApiTokenForm>,
+) -> Result<Response> {
+    let name = form.name.trim();
+    let scope = TokenScope::parse(&form.scope);
+    let error = if name.is_empty() {
+        Some("Give the token a name.")
+    } else if name.chars().count() > MAX_TOKEN_NAME_LEN {
+        Some("Name must be 64 characters or less.")
+    } else if scope.is_none() {
+        Some("Pick a scope.")
+    } else if scope == Some(TokenScope::Write) && !role.can_manage() {
+        Some("Read-only users can only create read-only tokens.")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        let mut page = api_tokens_page(&state, role, &csrf, user_id).await?;
+        page.error_message = error.to_string();
+        page.name = name.to_string();
+        page.scope = form.scope;
+        return Ok(page.into_response());
+    }
+    let scope = scope.unwrap_or(TokenScope::Read);
+    let token = api::create_token(&state.pool, user_id.value(), name, scope).await?;
+    Event::new("api_token.create")
+        .target(Target::api_token(None, name))
+        .detail(scope.label())
+        .record(&state.pool, &actor)
+        .await;
+    let mut page = api_tokens_page(&state, role, &csrf, user_id).await?;
+    page.new_token = token;
+    Ok(page.into_response())
+}
+
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let name = sqlx::query_scalar::<_, String>(
+        "DELETE FROM api_tokens WHERE id = $1 AND user_id = $2 RETURNING name",
+    )
+    .bind(id)
+    .bind(user_id.value())
+    .fetch_optional(&state.pool)
+    .await?;
+    if let Some(name) = name {
+        Event::new("api_token.revoke")
+            .target(Target::api_token(Some(id), &name))
+            .record(&state.pool, &actor)
+            .await;
+    }
+    Ok(Redirect::to("/account/api-tokens?token=revoked").into_response())
+}
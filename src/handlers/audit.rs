@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Extension, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::audit::{self, AuditColumn, AuditQuery, EXPORT_LIMIT, PAGE_SIZE};
+use crate::auth::Role;
+use crate::csrf::CsrfToken;
+use crate::error::Result;
+use crate::state::AppState;
+use crate::templates::AuditPage;
+
+/// Link to another page of the same filtered list.
+fn page_href(query_string: &str, page: i64) -> String {
+    match (query_string.is_empty(), page) {
+        (true, 1) => "/audit".to_string(),
+        (true, _) => format!("/audit?page={}", page),
+        (false, 1) => format!("/audit?{}", query_string),
+        (false, _) => format!("/audit?{}&page={}", query_string, page),
+    }
+}
+
+pub async fn list_audit(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Query(query): Query<AuditQuery>,
+) -> Result<impl IntoResponse> {
+    role.require_admin()?;
+    let page = query.page();
+    let mut events = query
+        .fetch(&state.pool, PAGE_SIZE + 1, (page - 1) * PAGE_SIZE)
+        .await?;
+    let has_next = events.len() as i64 > PAGE_SIZE;
+    events.truncate(PAGE_SIZE as usize);
+    let query_string = query.query_string();
+    Ok(AuditPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        events,
+        usernames: audit::distinct(&state.pool, AuditColumn::Username).await?,
+        actions: audit::distinct(&state.pool, AuditColumn::Action).await?,
+        target_types: audit::distinct(&state.pool, AuditColumn::TargetType).await?,
+        error_message: query.validate().err().unwrap_or_default(),
+        prev_href: if page > 1 {
+            page_href(&query_string, page - 1)
+        } else {
+            String::new()
+        },
+        next_href: if has_next {
+            page_href(&query_string, page + 1)
+        } else {
+            String::new()
+        },
+        csv_href: format!("/audit.csv?{}", query_string),
+        page,
+        query,
+    })
+}
+
+/// The filtered events as a CSV download, newest first.
+pub async fn export_audit_csv(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let events = query.fetch(&state.pool, EXPORT_LIMIT, 0).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"audit.csv\"",
+            ),
+        ],
+        audit::to_csv(&events),
+    )
+        .into_response())
+}
@@ -0,0 +1,335 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::backup::{self, Restorable, RestoreTarget};
+use crate::csrf::CsrfToken;
+use crate::error::{AppError, Result};
+use crate::models::{BackupScheduleForm, DownloadBackupForm, RestoreBackupForm};
+use crate::state::AppState;
+use crate::templates::{OffsiteBackupsPage, RestoreBackupPage};
+
+use super::sites::site_detail_page;
+
+async fn backups_error(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    id: i32,
+    message: &str,
+) -> Result<Response> {
+    let mut page = site_detail_page(state, role, csrf, id).await?;
+    page.backup_error = message.to_string();
+    page.active_tab = "backups".to_string();
+    Ok(page.into_response())
+}
+
+/// Queue a backup of the site now, outside its schedule.
+pub async fn create_backup(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let user_id = actor.require_user()?;
+    let domain = sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("Site not found.".to_string()))?;
+    match backup::enqueue(&state, id, user_id, false).await {
+        Ok(job_id) => {
+            Event::new("site.backup")
+                .target(Target::site(Some(id), &domain))
+                .queued(job_id)
+                .record(&state.pool, &actor)
+                .await;
+            Ok(Redirect::to(&format!("/jobs/{}", job_id)).into_response())
+        }
+        Err(AppError::Validation(msg)) => backups_error(&state, role, &csrf, id, &msg).await,
+        Err(e) => Err(e),
+    }
+}
+
+/// Save the site's backup schedule and retention, and prune to the new retention.
+pub async fn update_backup_schedule(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<BackupScheduleForm>,
+) -> Result<Response> {
+    let domain = sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("Site not found.".to_string()))?;
+
+    let number = |value: &str, range: std::ops::RangeInclusive<i32>| {
+        value
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|n| range.contains(n))
+    };
+    let frequency = form.backup_frequency.as_str();
+    let error = match (
+        number(&form.backup_hour, 0..=23),
+        number(&form.backup_keep_daily, backup::KEEP_DAILY),
+        number(&form.backup_keep_weekly, backup::KEEP_WEEKLY),
+    ) {
+        _ if !backup::FREQUENCIES.contains(&frequency) => "Choose how often to back up.",
+        (None, _, _) => "The hour must be 0–23 (UTC).",
+        (_, None, _) => "Keep 1–90 daily backups.",
+        (_, _, None) => "Keep 0–52 weekly backups.",
+        (Some(hour), Some(keep_daily), Some(keep_weekly)) => {
+            sqlx::query(
+                "UPDATE sites SET backup_frequency = $1, backup_hour = $2, backup_keep_daily = $3, backup_keep_weekly = $4 WHERE id = $5",
+            )
+            .bind(frequency)
+            .bind(hour)
+            .bind(keep_daily)
+            .bind(keep_weekly)
+            .bind(id)
+            .execute(&state.pool)
+            .await?;
+            Event::new("site.backup.schedule")
+                .target(Target::site(Some(id), &domain))
+                .detail(format!(
+                    "{}, keep {} daily and {} weekly",
+                    frequency, keep_daily, keep_weekly
+                ))
+                .record(&state.pool, &actor)
+                .await;
+            backup::prune(&state, id).await?;
+            return Ok(
+                Redirect::to(&format!("/sites/{}?backup_schedule=1#backups", id)).into_response(),
+            );
+        }
+    };
+    backups_error(&state, role, &csrf, id, error).await
+}
+
+async fn restore_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    restorable: Restorable,
+    form: Option<RestoreBackupForm>,
+    error: String,
+) -> Result<Response> {
+    let Restorable { backup, manifest } = restorable;
+    let site_domain = match backup.site_id {
+        Some(site_id) => {
+            sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE id = $1")
+                .bind(site_id)
+                .fetch_optional(&state.pool)
+                .await?
+        }
+        None => None,
+    };
+    let (target, domain) = form
+        .map(|f| (f.target, f.domain))
+        .unwrap_or_else(|| ("in_place".to_string(), String::new()));
+    Ok(RestoreBackupPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        backup,
+        backup_domain: manifest.domain,
+        backup_databases: manifest.databases.into_iter().map(|d| d.db_name).collect(),
+        site_domain,
+        target,
+        domain,
+        error,
+    }
+    .into_response())
+}
+
+/// Show what a backup holds and where it can be restored to.
+pub async fn restore_backup_page(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let backup = backup::get(&state.pool, id).await?;
+    match backup::load_restorable(&state.pool, id).await {
+        Ok(restorable) => restore_page(&state, role, &csrf, restorable, None, String::new()).await,
+        Err(AppError::Validation(msg)) => match backup.site_id {
+            Some(site_id) => backups_error(&state, role, &csrf, site_id, &msg).await,
+            None => Err(AppError::Validation(msg)),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Queue the restore of a backup over its site or into a new one.
+pub async fn restore_backup(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<RestoreBackupForm>,
+) -> Result<Response> {
+    let user_id = actor.require_user()?;
+    let target = match form.target.as_str() {
+        "in_place" => RestoreTarget::InPlace,
+        "new_site" => RestoreTarget::NewSite(form.domain.trim().to_string()),
+        _ => {
+            return Err(AppError::Validation(
+                "Choose where to restore to.".to_string(),
+            ))
+        }
+    };
+    let backup = backup::get(&state.pool, id).await?;
+    match backup::enqueue_restore(&state, id, user_id, target).await {
+        Ok(job_id) => {
+            let target = match form.target.as_str() {
+                "in_place" => Target::site(backup.site_id, &backup.domain),
+                _ => Target::site(None, form.domain.trim()),
+            };
+            Event::new("site.restore")
+                .target(target)
+                .detail(format!("from {}", backup.file_name()))
+                .queued(job_id)
+                .record(&state.pool, &actor)
+                .await;
+            Ok(Redirect::to(&format!("/jobs/{}", job_id)).into_response())
+        }
+        Err(AppError::Validation(msg)) => match backup::load_restorable(&state.pool, id).await {
+            Ok(restorable) => restore_page(&state, role, &csrf, restorable, Some(form), msg).await,
+            Err(AppError::Validation(msg)) => match backup.site_id {
+                Some(site_id) => backups_error(&state, role, &csrf, site_id, &msg).await,
+                None => Err(AppError::Validation(msg)),
+            },
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete a backup and its bundle.
+pub async fn delete_backup(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    match backup::delete(&state, id).await {
+        Ok(deleted) => {
+            Event::new("site.backup.delete")
+                .target(Target::site(deleted.site_id, &deleted.domain))
+                .detail(deleted.file_name())
+                .record(&state.pool, &actor)
+                .await;
+            Ok(match deleted.site_id {
+                Some(site_id) => {
+                    Redirect::to(&format!("/sites/{}?backup_deleted=1#backups", site_id))
+                }
+                None => Redirect::to("/"),
+            }
+            .into_response())
+        }
+        Err(AppError::Validation(msg)) => {
+            let backup = backup::get(&state.pool, id).await?;
+            match backup.site_id {
+                Some(site_id) => backups_error(&state, role, &csrf, site_id, &msg).await,
+                None => Err(AppError::Validation(msg)),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Queue a test restore of a backup.
+pub async fn verify_backup(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let user_id = actor.require_user()?;
+    let backup = backup::get(&state.pool, id).await?;
+    match backup::enqueue_verify(&state, id, user_id).await {
+        Ok(job_id) => {
+            Event::new("site.backup.verify")
+                .target(Target::site(backup.site_id, &backup.domain))
+                .detail(backup.file_name())
+                .queued(job_id)
+                .record(&state.pool, &actor)
+                .await;
+            Ok(Redirect::to(&format!("/jobs/{}", job_id)).into_response())
+        }
+        Err(AppError::Validation(msg)) => match backup.site_id {
+            Some(site_id) => backups_error(&state, role, &csrf, site_id, &msg).await,
+            None => Err(AppError::Validation(msg)),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+async fn offsite_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    error: String,
+) -> Result<Response> {
+    let (domains, error) = match backup::list_remote(state).await {
+        Ok(domains) => (domains.unwrap_or_default(), error),
+        Err(AppError::Validation(msg)) if error.is_empty() => (Vec::new(), msg),
+        Err(AppError::Validation(_)) => (Vec::new(), error),
+        Err(e) => return Err(e),
+    };
+    Ok(OffsiteBackupsPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        target: backup::describe_remote(state),
+        domains,
+        error,
+    }
+    .into_response())
+}
+
+/// List the bundles in the off-site bucket, including those of deleted sites.
+pub async fn offsite_backups(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<Response> {
+    offsite_page(&state, role, &csrf, String::new()).await
+}
+
+/// Queue the download of a bundle from the off-site bucket.
+pub async fn download_backup(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<DownloadBackupForm>,
+) -> Result<Response> {
+    let user_id = actor.require_user()?;
+    match backup::enqueue_download(&state, &form.key, user_id).await {
+        Ok(download) => {
+            Event::new("site.backup.download")
+                .target(Target::site(download.site_id, &download.domain))
+                .detail(download.file_name)
+                .queued(download.job_id)
+                .record(&state.pool, &actor)
+                .await;
+            Ok(Redirect::to(&format!("/jobs/{}", download.job_id)).into_response())
+        }
+        Err(AppError::Validation(msg)) => offsite_page(&state, role, &csrf, msg).await,
+        Err(e) => Err(e),
+    }
+}
@@ -1,17 +1,21 @@
 use axum::extract::{Extension, State};
 
-use crate::auth::UserId;
+use crate::auth::{Role, UserId};
+use crate::csrf::CsrfToken;
 use crate::state::AppState;
 use crate::templates::{DashboardPage, DashboardSiteRow};
 
 pub async fn dashboard(
     State(state): State<AppState>,
     Extension(user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
 ) -> impl axum::response::IntoResponse {
     let sites: Vec<DashboardSiteRow> = sqlx::query_as::<_, crate::models::SiteWithStatus>(
-        "SELECT id, domain, folder_path, wordpress_installed, user_id, created_at, NULL::text AS status, php_version FROM sites WHERE user_id = $1 ORDER BY domain",
+        "SELECT s.id, s.domain, s.folder_path, s.wordpress_installed, s.user_id, s.created_at, c.status, s.php_version FROM sites s \
+         LEFT JOIN LATERAL (SELECT status FROM site_checks WHERE site_id = s.id ORDER BY checked_at DESC LIMIT 1) c ON TRUE \
+         ORDER BY s.domain",
     )
-    .bind(user_id.value())
     .fetch_all(&state.pool)
     .await
     .unwrap_or_default()
@@ -35,8 +39,15 @@ pub async fn dashboard(
         .ok()
         .flatten();
 
+    let failed_verifications = crate::backup::failed_verifications(&state.pool)
+        .await
+        .unwrap_or_default();
+
     DashboardPage {
         logged_in: username.is_some(),
+        role,
+        csrf: csrf.value(),
         sites,
+        failed_verifications,
     }
 }
@@ -0,0 +1,166 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Extension, Path, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
+};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::Stream;
+
+use crate::audit::{Actor, Event as AuditEvent, Target};
+use crate::auth::Role;
+use crate::csrf::CsrfToken;
+use crate::error::{AppError, Result};
+use crate::jobs::{FAILED, SUCCEEDED};
+use crate::models::{Job, JobLogLine, JOB_COLUMNS};
+use crate::state::AppState;
+use crate::templates::{JobDetailPage, JobsPage};
+
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<impl IntoResponse> {
+    let jobs = sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs ORDER BY id DESC LIMIT 100",
+        JOB_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(JobsPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        jobs,
+    })
+}
+
+pub async fn job_detail(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let job = sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs WHERE id = $1",
+        JOB_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::Validation("Job not found.".into()))?;
+    let lines = fetch_log_lines(&state.pool, id, 0).await?;
+
+    Ok(JobDetailPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        job,
+        lines,
+    }
+    .into_response())
+}
+
+async fn fetch_log_lines(
+    pool: &crate::db::DbPool,
+    job_id: i32,
+    after_seq: i32,
+) -> Result<Vec<JobLogLine>> {
+    sqlx::query_as::<_, JobLogLine>(
+        "SELECT seq, stream, line, logged_at FROM job_log_lines WHERE job_id = $1 AND seq > $2 ORDER BY seq",
+    )
+    .bind(job_id)
+    .bind(after_seq)
+    .fetch_all(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// Server-Sent Events for a job: stored output first, then each new line as
+/// the worker records it. Events: `line` (JSON [`JobLogLine`]), `state` while
+/// the job runs, and `done` with the final state.
+pub async fn job_events(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    sqlx::query_scalar::<_, i32>("SELECT id FROM jobs WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("Job not found.".into()))?;
+
+    // Subscribe before the first read so no change can slip in between.
+    let mut changes = state.jobs.subscribe();
+    let pool = state.pool.clone();
+    let stream = async_stream::stream! {
+        let mut last_seq = 0;
+        let mut last_state = String::new();
+        loop {
+            let Ok(lines) = fetch_log_lines(&pool, id, last_seq).await else {
+                break;
+            };
+            for line in lines {
+                last_seq = line.seq;
+                if let Ok(event) = Event::default().event("line").json_data(&line) {
+                    yield Ok(event);
+                }
+            }
+            let job_state = sqlx::query_scalar::<_, String>("SELECT state FROM jobs WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&pool)
+                .await;
+            let Ok(Some(job_state)) = job_state else {
+                break;
+            };
+            if job_state == SUCCEEDED || job_state == FAILED {
+                yield Ok(Event::default().event("done").data(job_state));
+                break;
+            }
+            if job_state != last_state {
+                yield Ok(Event::default().event("state").data(job_state.as_str()));
+                last_state = job_state;
+            }
+            // Wait until this job changes; re-check now and then regardless.
+            let wait = async {
+                loop {
+                    match changes.recv().await {
+                        Ok(changed) if changed == id => return true,
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(_)) => return true,
+                        Err(RecvError::Closed) => return false,
+                    }
+                }
+            };
+            if let Ok(false) = tokio::time::timeout(Duration::from_secs(15), wait).await {
+                break;
+            }
+        }
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn retry_job(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    if !state.jobs.retry(&state.pool, id).await? {
+        return Err(AppError::Validation(
+            "Only failed jobs can be retried.".into(),
+        ));
+    }
+    let summary = sqlx::query_scalar::<_, String>("SELECT summary FROM jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(&state.pool)
+        .await?;
+    AuditEvent::new("job.retry")
+        .target(Target::job(id, &summary))
+        .queued(id)
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to(&format!("/jobs/{}", id)).into_response())
+}
@@ -1,5 +1,9 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Extension, State},
+    http::HeaderMap,
     response::{IntoResponse, Redirect, Response},
     Form,
 };
@@ -7,22 +11,81 @@ use axum_extra::extract::cookie::{Cookie, SameSite};
 use bcrypt::verify;
 use validator::Validate;
 
-use crate::auth::{create_session, SESSION_COOKIE};
+use crate::audit::{Actor, Event, Target, Via};
+use crate::auth::{
+    client_ip, create_login_challenge, create_session, delete_login_challenge, delete_session,
+    fail_login_challenge, get_login_challenge, SessionId, SESSION_COOKIE,
+};
+use crate::csrf;
 use crate::error::{AppError, Result};
-use crate::models::LoginForm;
+use crate::models::{LoginForm, LoginTotpForm};
 use crate::state::AppState;
-use crate::templates::{LoginErrors, LoginPage};
+use crate::templates::{LoginErrors, LoginPage, LoginTotpPage};
+use crate::throttle::LoginThrottler;
+
+use super::account::check_second_factor;
+
+/// Checked in place of a password hash for unknown and disabled usernames, at
+/// the cost user passwords are hashed with, so they take as long to refuse as
+/// a wrong password.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| bcrypt::hash(csrf::generate_token(), 12).expect("bcrypt hash"));
+
+/// The login form's CSRF token: the one already in the browser's cookie, so
+/// several open login tabs keep working, or a new one.
+fn login_token(headers: &HeaderMap) -> String {
+    csrf::cookie_value(headers, csrf::LOGIN_COOKIE)
+        .filter(|t| t.len() == 64)
+        .unwrap_or_else(csrf::generate_token)
+}
 
-pub async fn get_login() -> LoginPage {
-    LoginPage::new(
-        String::new(),
+/// Render the login form and set its double-submit cookie.
+fn login_page(
+    headers: &HeaderMap,
+    username: String,
+    errors: LoginErrors,
+    error_message: String,
+) -> Response {
+    let token = login_token(headers);
+    let cookie = Cookie::build((csrf::LOGIN_COOKIE, token.clone()))
+        .path("/login")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .build();
+    let mut response = LoginPage::new(token, username, errors, error_message).into_response();
+    response
+        .headers_mut()
+        .append("Set-Cookie", cookie.to_string().parse().unwrap());
+    response
+}
+
+pub async fn get_login(headers: HeaderMap) -> Response {
+    login_page(&headers, String::new(), LoginErrors::default(), String::new())
+}
+
+/// Who to record a sign-in attempt against; failed attempts have no user yet.
+fn login_actor(user_id: Option<i32>, ip: IpAddr) -> Actor {
+    Actor {
+        user_id,
+        ip: Some(ip),
+        via: Via::Web,
+    }
+}
+
+/// The same answer for unknown users, wrong passwords and lockouts.
+fn invalid_login(headers: &HeaderMap, username: String) -> Response {
+    login_page(
+        headers,
+        username,
         LoginErrors::default(),
-        String::new(),
+        "Invalid username or password.".to_string(),
     )
 }
 
 pub async fn post_login(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Form(form): Form<LoginForm>,
 ) -> Result<Response> {
     if let Err(e) = form.validate() {
@@ -33,45 +96,160 @@ pub async fn post_login(
                 .and_then(|m| m.message.as_ref())
                 .map(|m| m.to_string())
                 .unwrap_or_else(|| "Invalid".to_string());
-            match field.as_ref() {
+            match field {
                 "username" => errors.username = msg,
                 "password" => errors.password = msg,
                 _ => {}
             }
         }
-        return Ok(LoginPage::new(form.username, errors, String::new()).into_response());
+        return Ok(login_page(&headers, form.username, errors, String::new()));
+    }
+    // The form must echo the cookie set when it was shown, so another site
+    // cannot sign the browser in to an account of its choosing.
+    let cookie = csrf::cookie_value(&headers, csrf::LOGIN_COOKIE).unwrap_or_default();
+    if !csrf::tokens_match(&cookie, &form.csrf) {
+        return Ok(login_page(
+            &headers,
+            form.username,
+            LoginErrors::default(),
+            "The sign-in form expired. Please try again.".to_string(),
+        ));
+    }
+
+    let ip = client_ip(peer.ip(), &headers, &state.config.trusted_proxies);
+    let throttler = LoginThrottler::from_config(&state.config);
+    let attempt = throttler.reserve(&state.pool, &form.username, ip).await?;
+    tokio::time::sleep(attempt.delay).await;
+    if attempt.locked {
+        Event::new("login")
+            .target(Target::user(None, &form.username))
+            .failed("Locked out")
+            .record(&state.pool, &login_actor(None, ip))
+            .await;
+        return Ok(invalid_login(&headers, form.username));
     }
 
-    let user = sqlx::query_as::<_, (i32, String)>(
-        "SELECT id, password_hash FROM users WHERE username = $1",
+    let user = sqlx::query_as::<_, (i32, String, bool)>(
+        "SELECT id, password_hash, totp_enabled_at IS NOT NULL FROM users WHERE username = $1 AND disabled_at IS NULL",
     )
     .bind(&form.username)
     .fetch_optional(&state.pool)
     .await?;
 
+    let hash = user.as_ref().map_or(DUMMY_HASH.as_str(), |u| u.1.as_str());
+    let valid = verify(&form.password, hash)
+        .map_err(|_| AppError::Internal(anyhow::anyhow!("bcrypt error")))?;
     let user = match user {
-        Some(u) => u,
-        None => {
-            return Ok(LoginPage::new(
-                form.username,
-                LoginErrors::default(),
-                "Invalid username or password.".to_string(),
-            )
-            .into_response());
+        Some(u) if valid => u,
+        _ => {
+            let (id, reason) = match user {
+                Some(u) => (Some(u.0), "Wrong password"),
+                None => (None, "Unknown or disabled user"),
+            };
+            Event::new("login")
+                .target(Target::user(id, &form.username))
+                .failed(reason)
+                .record(&state.pool, &login_actor(None, ip))
+                .await;
+            return Ok(invalid_login(&headers, form.username));
         }
     };
 
-    let valid = verify(&form.password, &user.1).map_err(|_| AppError::Internal(anyhow::anyhow!("bcrypt error")))?;
-    if !valid {
-        return Ok(LoginPage::new(
-            form.username,
+    if user.2 {
+        // The password was right; the code is a new attempt.
+        throttler.release(&state.pool, &form.username, ip).await?;
+        let challenge = create_login_challenge(&state.pool, user.0).await?;
+        return Ok(LoginTotpPage {
+            challenge,
+            error_message: String::new(),
+        }
+        .into_response());
+    }
+    signed_in(&state, &throttler, user.0, &form.username, ip, &headers).await
+}
+
+/// Second login step: a TOTP or recovery code for the challenge issued after the password.
+pub async fn post_login_totp(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<LoginTotpForm>,
+) -> Result<Response> {
+    let expired = || {
+        login_page(
+            &headers,
+            String::new(),
             LoginErrors::default(),
-            "Invalid username or password.".to_string(),
+            "Your sign-in expired. Enter your password again.".to_string(),
         )
+    };
+    let Some(user_id) = get_login_challenge(&state.pool, &form.challenge).await? else {
+        return Ok(expired());
+    };
+    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&state.pool)
+        .await?;
+    // Wrong codes count like wrong passwords, so new challenges cannot be used
+    // to keep guessing.
+    let ip = client_ip(peer.ip(), &headers, &state.config.trusted_proxies);
+    let throttler = LoginThrottler::from_config(&state.config);
+    let attempt = throttler.reserve(&state.pool, &username, ip).await?;
+    tokio::time::sleep(attempt.delay).await;
+    if attempt.locked {
+        Event::new("login")
+            .target(Target::user(Some(user_id), &username))
+            .failed("Locked out")
+            .record(&state.pool, &login_actor(None, ip))
+            .await;
+        delete_login_challenge(&state.pool, &form.challenge).await?;
+        return Ok(expired());
+    }
+    if !check_second_factor(&state.pool, user_id, &form.code).await? {
+        Event::new("login")
+            .target(Target::user(Some(user_id), &username))
+            .failed("Wrong authentication code")
+            .record(&state.pool, &login_actor(None, ip))
+            .await;
+        let left = fail_login_challenge(&state.pool, &form.challenge).await?;
+        if left == 0 {
+            delete_login_challenge(&state.pool, &form.challenge).await?;
+            return Ok(login_page(
+                &headers,
+                String::new(),
+                LoginErrors::default(),
+                "Too many wrong codes. Enter your password again.".to_string(),
+            ));
+        }
+        return Ok(LoginTotpPage {
+            challenge: form.challenge,
+            error_message: "Invalid authentication code.".to_string(),
+        }
         .into_response());
     }
+    delete_login_challenge(&state.pool, &form.challenge).await?;
+    signed_in(&state, &throttler, user_id, &username, ip, &headers).await
+}
 
-    let token = create_session(&state.pool, user.0).await?;
+/// Issue the session cookie and go to the dashboard.
+async fn signed_in(
+    state: &AppState,
+    throttler: &LoginThrottler,
+    user_id: i32,
+    username: &str,
+    ip: IpAddr,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    throttler.record_success(&state.pool, username, ip).await?;
+    Event::new("login")
+        .target(Target::user(Some(user_id), username))
+        .record(&state.pool, &login_actor(Some(user_id), ip))
+        .await;
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let token = create_session(&state.pool, &state.config.session_secret, user_id, ip, user_agent).await?;
     let cookie = Cookie::build((SESSION_COOKIE, token.clone()))
         .path("/")
         .http_only(true)
@@ -86,8 +264,18 @@ pub async fn post_login(
     Ok(response)
 }
 
-pub async fn logout(State(_state): State<AppState>) -> Result<Response> {
-    // Session is deleted when cookie is cleared; optional: delete by token if we had it
+pub async fn logout(
+    State(state): State<AppState>,
+    Extension(session_id): Extension<SessionId>,
+    Extension(actor): Extension<Actor>,
+) -> Result<Response> {
+    delete_session(&state.pool, session_id.value()).await?;
+    Event::new("logout").record(&state.pool, &actor).await;
+    Ok(signed_out())
+}
+
+/// Clear the session cookie and go to the login page.
+pub(crate) fn signed_out() -> Response {
     let mut response = Redirect::to("/login").into_response();
     response.headers_mut().insert(
         "Set-Cookie",
@@ -95,5 +283,5 @@ pub async fn logout(State(_state): State<AppState>) -> Result<Response> {
             .parse()
             .unwrap(),
     );
-    Ok(response)
+    response
 }
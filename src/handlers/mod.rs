@@ -2,8 +2,28 @@ mod login;
 mod dashboard;
 mod sites;
 mod databases;
+mod jobs;
+mod tls;
+mod aliases;
+mod monitoring;
+mod backups;
+mod users;
+mod account;
+mod sessions;
+mod api_tokens;
+mod audit;
 
 pub use login::*;
 pub use dashboard::*;
 pub use sites::*;
 pub use databases::*;
+pub use jobs::*;
+pub use tls::*;
+pub use aliases::*;
+pub use monitoring::*;
+pub use backups::*;
+pub use users::*;
+pub use account::*;
+pub use sessions::*;
+pub use api_tokens::*;
+pub use audit::*;
@@ -0,0 +1,76 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::csrf::CsrfToken;
+use crate::error::{AppError, Result};
+use crate::models::MonitoringForm;
+use crate::state::AppState;
+
+use super::sites::site_detail_page;
+
+/// Parse an optional whole number in `range`; empty means "use the default".
+fn optional_number(
+    value: &str,
+    range: std::ops::RangeInclusive<i32>,
+) -> std::result::Result<Option<i32>, ()> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse::<i32>() {
+        Ok(n) if range.contains(&n) => Ok(Some(n)),
+        _ => Err(()),
+    }
+}
+
+/// Save the site's health check interval, expected status code and body keyword.
+pub async fn update_monitoring(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<MonitoringForm>,
+) -> Result<Response> {
+    let domain = sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("Site not found.".to_string()))?;
+
+    let keyword = form.check_keyword.trim();
+    let error = match (
+        optional_number(&form.check_interval, 30..=86_400),
+        optional_number(&form.check_expected_status, 100..=599),
+    ) {
+        (Err(()), _) => "Check interval must be 30–86400 seconds, or empty for the default.",
+        (_, Err(())) => "Expected status must be an HTTP status code (100–599), or empty to accept anything below 500.",
+        _ if keyword.chars().count() > 200 => "The keyword can be at most 200 characters.",
+        (Ok(interval), Ok(expected)) => {
+            sqlx::query(
+                "UPDATE sites SET check_interval = $1, check_expected_status = $2, check_keyword = $3 WHERE id = $4",
+            )
+            .bind(interval)
+            .bind(expected)
+            .bind((!keyword.is_empty()).then_some(keyword))
+            .bind(id)
+            .execute(&state.pool)
+            .await?;
+            Event::new("site.monitoring")
+                .target(Target::site(Some(id), &domain))
+                .record(&state.pool, &actor)
+                .await;
+            return Ok(Redirect::to(&format!("/sites/{}?monitoring=1#monitoring", id)).into_response());
+        }
+    };
+
+    let mut page = site_detail_page(&state, role, &csrf, id).await?;
+    page.monitoring_error = error.to_string();
+    page.active_tab = "monitoring".to_string();
+    Ok(page.into_response())
+}
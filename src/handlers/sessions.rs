@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::{Role, SessionId, UserId};
+use crate::csrf::CsrfToken;
+use crate::error::Result;
+use crate::models::{Session, SESSION_COLUMNS};
+use crate::state::AppState;
+use crate::templates::SessionsPage;
+
+use super::login::signed_out;
+
+/// List the signed-in user's unexpired sessions, most recently used first.
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(session_id): Extension<SessionId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<impl IntoResponse> {
+    let sessions = sqlx::query_as::<_, Session>(&format!(
+        "SELECT {} FROM sessions WHERE user_id = $1 AND expires_at > NOW() ORDER BY last_seen_at DESC",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id.value())
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(SessionsPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        sessions,
+        current_session_id: session_id.value(),
+    })
+}
+
+/// Sign out one of the user's sessions. Revoking the current one is a logout.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(session_id): Extension<SessionId>,
+    Extension(actor): Extension<Actor>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    let revoked = sqlx::query("DELETE FROM sessions WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id.value())
+        .execute(&state.pool)
+        .await?;
+    if revoked.rows_affected() > 0 {
+        Event::new("session.revoke")
+            .target(Target::session(id))
+            .record(&state.pool, &actor)
+            .await;
+    }
+    if id == session_id.value() {
+        return Ok(signed_out());
+    }
+    Ok(Redirect::to("/account/sessions?session=revoked").into_response())
+}
+
+/// Sign out everywhere, this browser included.
+pub async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+) -> Result<Response> {
+    let revoked = sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(user_id.value())
+        .execute(&state.pool)
+        .await?;
+    Event::new("session.revoke_all")
+        .detail(format!("{} sessions", revoked.rows_affected()))
+        .record(&state.pool, &actor)
+        .await;
+    tracing::info!(
+        "user {}: signed out of {} sessions",
+        user_id.value(),
+        revoked.rows_affected()
+    );
+    Ok(signed_out())
+}
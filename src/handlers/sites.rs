@@ -3,13 +3,16 @@ use axum::{
     response::{IntoResponse, Redirect, Response},
     Form,
 };
-use std::process::Stdio;
-use tokio::process::Command;
-use validator::Validate;
 
-use crate::auth::UserId;
+use crate::actions;
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::backup;
+use crate::caddy::{CertificateStore, WorkerRestart};
+use crate::csrf::CsrfToken;
 use crate::error::{AppError, Result};
-use crate::models::{validate_domain, AddSiteForm};
+use crate::health;
+use crate::models::{AddSiteForm, Site, SITE_ALIAS_COLUMNS, SITE_CHECK_COLUMNS, SITE_COLUMNS};
 use crate::state::AppState;
 use crate::templates::{AddSiteErrors, AddSitePage, SiteDetailPage};
 
@@ -21,44 +24,19 @@ fn wp_form_values(form: &AddSiteForm) -> (String, String, String) {
     )
 }
 
-fn validate_wp_fields(form: &AddSiteForm) -> AddSiteErrors {
-    let mut e = AddSiteErrors::default();
-    let title = form.wp_title.as_deref().unwrap_or("").trim();
-    let user = form.wp_admin_user.as_deref().unwrap_or("").trim();
-    let pass = form.wp_admin_password.as_deref().unwrap_or("");
-    let email = form.wp_admin_email.as_deref().unwrap_or("").trim();
-    if title.is_empty() {
-        e.wp_title = "Site title is required.".to_string();
-    }
-    if user.is_empty() {
-        e.wp_admin_user = "Admin username is required.".to_string();
-    } else if user.len() > 60 || !user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
-        e.wp_admin_user = "Username: letters, numbers, and underscores only (max 60).".to_string();
-    }
-    if pass.len() < 8 {
-        e.wp_admin_password = "Password must be at least 8 characters.".to_string();
-    }
-    if email.is_empty() {
-        e.wp_admin_email = "Admin email is required.".to_string();
-    } else if !email.contains('@') || !email.split('@').nth(1).map_or(false, |after| after.contains('.')) {
-        e.wp_admin_email = "Enter a valid email address.".to_string();
-    }
-    e
-}
-
-fn has_wp_errors(e: &AddSiteErrors) -> bool {
-    !e.wp_title.is_empty() || !e.wp_admin_user.is_empty() || !e.wp_admin_password.is_empty() || !e.wp_admin_email.is_empty()
-}
-
 pub async fn new_site(
     State(_state): State<AppState>,
-    Extension(_user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
 ) -> Result<impl IntoResponse> {
+    role.require_manage()?;
     Ok(AddSitePage::new(
         true,
+        role,
+        csrf.value(),
         String::new(),
         false,
-        "8.2".to_string(),
+        actions::DEFAULT_PHP_VERSION.to_string(),
         String::new(),
         String::new(),
         String::new(),
@@ -69,195 +47,27 @@ pub async fn new_site(
 
 pub async fn create_site(
     State(state): State<AppState>,
-    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
     Form(form): Form<AddSiteForm>,
 ) -> Result<Response> {
-    let mut errors = AddSiteErrors::default();
-    if let Err(e) = form.validate() {
-        for (field, err) in e.field_errors() {
-            let msg = err
-                .first()
-                .and_then(|m| m.message.as_ref())
-                .map(|m| m.to_string())
-                .unwrap_or_else(|| "Invalid".to_string());
-            if field == "domain" {
-                errors.domain = msg;
-            }
-        }
-    }
-    let (wp_title, wp_admin_user, wp_admin_email) = wp_form_values(&form);
-    let php_version = form
-        .php_version
-        .as_deref()
-        .filter(|s| !s.is_empty())
-        .unwrap_or("8.2")
-        .to_string();
-    if !errors.domain.is_empty() {
-        return Ok(AddSitePage::new(
-            true,
-            form.domain,
-            form.install_wordpress.as_deref() == Some("1"),
-            php_version.clone(),
-            wp_title,
-            wp_admin_user,
-            wp_admin_email,
-            errors,
-            String::new(),
-        )
-        .into_response());
-    }
-
-    if let Err(msg) = validate_domain(&form.domain) {
-        return Ok(AddSitePage::new(
-            true,
-            form.domain,
-            form.install_wordpress.as_deref() == Some("1"),
-            php_version.clone(),
-            wp_title.clone(),
-            wp_admin_user.clone(),
-            wp_admin_email.clone(),
-            AddSiteErrors {
-                domain: msg,
-                ..Default::default()
-            },
-            String::new(),
-        )
-        .into_response());
-    }
-
-    let install_wp = form.install_wordpress.as_deref() == Some("1");
-    if install_wp {
-        let wp_errors = validate_wp_fields(&form);
-        if has_wp_errors(&wp_errors) {
-            return Ok(AddSitePage::new(
-                true,
-                form.domain,
-                true,
-                php_version.clone(),
-                wp_title,
-                wp_admin_user,
-                wp_admin_email,
-                wp_errors,
-                String::new(),
-            )
-            .into_response());
-        }
-    }
-
-    let folder_path = format!("/var/www/{}", form.domain.trim());
-
-    // Create site directory and Caddy config, reload Caddy (if script is configured)
-    if let Some(ref script) = state.config.site_create_script {
-        let script_path = script.as_os_str();
-        let domain = form.domain.trim().to_string();
-        let wp_arg = if install_wp { "1" } else { "0" };
-        let mut cmd = Command::new("sudo");
-        cmd.arg(script_path)
-            .arg(&domain)
-            .arg(&folder_path)
-            .arg(wp_arg)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        if install_wp {
-            cmd.arg(form.wp_title.as_deref().unwrap_or(""))
-                .arg(form.wp_admin_user.as_deref().unwrap_or(""))
-                .arg(form.wp_admin_password.as_deref().unwrap_or(""))
-                .arg(form.wp_admin_email.as_deref().unwrap_or(""));
-        } else {
-            cmd.arg("").arg("").arg("").arg("");
-        }
-        cmd.arg(&php_version);
-        let output = cmd.output().await;
-
-        match output {
-            Ok(out) if !out.status.success() => {
-                let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
-                let stdout = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                tracing::warn!("site-create script failed: {} {}", stdout, stderr);
-                let msg = if stderr.is_empty() && stdout.is_empty() {
-                    "Could not create site on server (folder/Caddy). Check server logs.".to_string()
-                } else {
-                    let detail: String = format!("{} {}", stderr, stdout).chars().take(350).collect();
-                    format!("Site setup failed: {}", detail.trim())
-                };
-                return Ok(AddSitePage::new(
-                    true,
-                    form.domain,
-                    install_wp,
-                    php_version.clone(),
-                    wp_title,
-                    wp_admin_user,
-                    wp_admin_email,
-                    AddSiteErrors {
-                        folder_path: msg,
-                        ..Default::default()
-                    },
-                    String::new(),
-                )
-                .into_response());
-            }
-            Err(e) => {
-                tracing::warn!("site-create script error: {}", e);
-                return Ok(AddSitePage::new(
-                    true,
-                    form.domain,
-                    install_wp,
-                    php_version.clone(),
-                    wp_title,
-                    wp_admin_user,
-                    wp_admin_email,
-                    AddSiteErrors {
-                        folder_path: format!("Could not run site setup: {}. Ensure SITE_CREATE_SCRIPT is correct and the panel user can run it with sudo.", e),
-                        ..Default::default()
-                    },
-                    String::new(),
-                )
-                .into_response());
-            }
-            _ => {}
-        }
-    }
-
-    let result = sqlx::query(
-        "INSERT INTO sites (domain, folder_path, wordpress_installed, user_id, php_version) VALUES ($1, $2, $3, $4, $5)",
-    )
-    .bind(&form.domain)
-    .bind(&folder_path)
-    .bind(install_wp)
-    .bind(user_id.value())
-    .bind(&php_version)
-    .execute(&state.pool)
-    .await;
-
-    match result {
-        Ok(_) => Ok(Redirect::to("/?created=1").into_response()),
-        Err(e) => {
-            let (err_msg, folder_error) = if let sqlx::Error::Database(db) = &e {
-                if db.is_unique_violation() {
-                    (
-                        String::new(),
-                        "A site with this domain or path already exists.".to_string(),
-                    )
-                } else {
-                    ("Database error. Please try again.".to_string(), String::new())
-                }
-            } else {
-                ("Failed to create site.".to_string(), String::new())
-            };
+    match actions::create_site(&state, &actor, &form).await? {
+        Ok(job_id) => Ok(Redirect::to(&format!("/jobs/{}", job_id)).into_response()),
+        Err(errors) => {
+            let (wp_title, wp_admin_user, wp_admin_email) = wp_form_values(&form);
             Ok(AddSitePage::new(
                 true,
-                form.domain,
-                install_wp,
-                php_version,
+                role,
+                csrf.value(),
+                form.domain.clone(),
+                form.install_wordpress.as_deref() == Some("1"),
+                actions::site_php_version(&form),
                 wp_title,
                 wp_admin_user,
                 wp_admin_email,
-                AddSiteErrors {
-                    folder_path: folder_error,
-                    ..Default::default()
-                },
-                err_msg,
+                errors,
+                String::new(),
             )
             .into_response())
         }
@@ -266,17 +76,28 @@ pub async fn create_site(
 
 pub async fn site_detail(
     State(state): State<AppState>,
-    Extension(user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
     Path(id): Path<i32>,
 ) -> Result<Response> {
-    let site = sqlx::query_as::<_, crate::models::Site>(
-        "SELECT id, domain, folder_path, wordpress_installed, user_id, created_at, php_version FROM sites WHERE id = $1 AND user_id = $2",
-    )
-    .bind(id)
-    .bind(user_id.value())
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or(AppError::Validation("Site not found.".to_string()))?;
+    Ok(site_detail_page(&state, role, &csrf, id).await?.into_response())
+}
+
+pub(crate) async fn fetch_site(state: &AppState, id: i32) -> Result<Site> {
+    sqlx::query_as::<_, Site>(&format!("SELECT {} FROM sites WHERE id = $1", SITE_COLUMNS))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("Site not found.".to_string()))
+}
+
+pub(crate) async fn site_detail_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    id: i32,
+) -> Result<SiteDetailPage> {
+    let site = fetch_site(state, id).await?;
 
     let databases = sqlx::query_as::<_, crate::models::SiteDatabase>(
         "SELECT id, site_id, db_name, db_user, privileges, created_at FROM site_databases WHERE site_id = $1",
@@ -285,6 +106,26 @@ pub async fn site_detail(
     .fetch_all(&state.pool)
     .await?;
 
+    let aliases = sqlx::query_as::<_, crate::models::SiteAlias>(&format!(
+        "SELECT {} FROM site_aliases WHERE site_id = $1 ORDER BY hostname",
+        SITE_ALIAS_COLUMNS
+    ))
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let last_check = sqlx::query_as::<_, crate::models::SiteCheck>(&format!(
+        "SELECT {} FROM site_checks WHERE site_id = $1 ORDER BY checked_at DESC LIMIT 1",
+        SITE_CHECK_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await?;
+    let uptime = health::uptime(&state.pool, id).await?;
+    let incidents = health::incidents(&state.pool, id).await?;
+    let response_chart = health::response_chart(&state.pool, id).await?;
+    let backups = backup::list(&state.pool, id).await?;
+
     let site_ip = state
         .config
         .server_ip
@@ -298,93 +139,99 @@ pub async fn site_detail(
         .unwrap_or("www-data")
         .to_string();
 
+    let certs = CertificateStore::from_config(&state.config);
+    let ssl = match site.tls_cert_path {
+        Some(ref path) => certs.inspect_custom(std::path::Path::new(path)).await,
+        None => certs.inspect(&site.primary_host()).await,
+    };
+
     Ok(SiteDetailPage {
         logged_in: true,
+        role,
+        csrf: csrf.value(),
         site,
         databases,
-        ssl_status: "active".to_string(), // TODO: real SSL check
+        aliases,
+        ssl,
         site_ip,
         site_user,
-    }
-    .into_response())
+        restart_error: String::new(),
+        tls_error: String::new(),
+        domains_error: String::new(),
+        alias_hostname: String::new(),
+        last_check,
+        uptime,
+        incidents,
+        response_chart,
+        default_check_interval: state.config.health_check_interval,
+        monitoring_error: String::new(),
+        backups,
+        backup_dir: state.config.backup_dir.display().to_string(),
+        offsite: backup::describe_remote(state),
+        backup_dedup: state.config.backup_dedup,
+        backup_verify_days: state.config.backup_verify_days,
+        backup_error: String::new(),
+        active_tab: String::new(),
+    })
 }
 
+/// Reload Caddy/FrankenPHP through the admin API and restart PHP workers where
+/// supported. Errors from the admin API are shown on the site page.
 pub async fn restart_site(
     State(state): State<AppState>,
-    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
     Path(id): Path<i32>,
 ) -> Result<Response> {
-    let _exists = sqlx::query_scalar::<_, i32>("SELECT 1 FROM sites WHERE id = $1 AND user_id = $2")
+    let domain = sqlx::query_scalar::<_, String>("SELECT domain FROM sites WHERE id = $1")
         .bind(id)
-        .bind(user_id.value())
         .fetch_optional(&state.pool)
         .await?
         .ok_or(AppError::Validation("Site not found.".to_string()))?;
-    // TODO: trigger FrankenPHP reload for this site
-    let path = format!("/sites/{}?restarted=1", id);
-    let res = axum::response::Response::builder()
-        .status(axum::http::StatusCode::FOUND)
-        .header(axum::http::header::LOCATION, path)
-        .body(axum::body::Body::empty())
-        .unwrap();
-    Ok(res)
+    let event = Event::new("site.restart").target(Target::site(Some(id), &domain));
+
+    if let Err(e) = state.caddy.reload().await {
+        tracing::warn!("site {} restart: reload failed: {}", id, e);
+        event
+            .failed(format!("Reload failed: {}", e))
+            .record(&state.pool, &actor)
+            .await;
+        let mut page = site_detail_page(&state, role, &csrf, id).await?;
+        page.restart_error = format!("Restart failed: {}", e);
+        return Ok(page.into_response());
+    }
+    let workers = match state.caddy.restart_workers().await {
+        Ok(w) => w,
+        Err(e) => {
+            tracing::warn!("site {} restart: worker restart failed: {}", id, e);
+            event
+                .failed(format!("Worker restart failed: {}", e))
+                .record(&state.pool, &actor)
+                .await;
+            let mut page = site_detail_page(&state, role, &csrf, id).await?;
+            page.restart_error = format!("Configuration reloaded, but restarting PHP workers failed: {}", e);
+            return Ok(page.into_response());
+        }
+    };
+
+    sqlx::query("UPDATE sites SET last_restarted_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    let (flag, detail) = match workers {
+        WorkerRestart::Restarted => ("workers", "Reloaded and restarted PHP workers"),
+        WorkerRestart::NotAvailable => ("1", "Reloaded"),
+    };
+    event.detail(detail).record(&state.pool, &actor).await;
+    Ok(Redirect::to(&format!("/sites/{}?restarted={}", id, flag)).into_response())
 }
 
 pub async fn delete_site(
     State(state): State<AppState>,
-    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
     Path(id): Path<i32>,
 ) -> Result<Response> {
-    let site = sqlx::query_as::<_, crate::models::Site>(
-        "SELECT id, domain, folder_path, wordpress_installed, user_id, created_at, php_version FROM sites WHERE id = $1 AND user_id = $2",
-    )
-    .bind(id)
-    .bind(user_id.value())
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or(AppError::Validation("Site not found.".into()))?;
-
-    let databases = sqlx::query_as::<_, crate::models::SiteDatabase>(
-        "SELECT id, site_id, db_name, db_user, privileges, created_at FROM site_databases WHERE site_id = $1",
-    )
-    .bind(id)
-    .fetch_all(&state.pool)
-    .await?;
-
-    if let Some(ref script) = state.config.site_delete_script {
-        let mut cmd = Command::new("sudo");
-        cmd.arg(script.as_os_str())
-            .arg(&site.domain)
-            .arg(&site.folder_path)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        for db in &databases {
-            cmd.arg(&db.db_name).arg(&db.db_user);
-        }
-        let output = cmd.output().await;
-        if let Ok(out) = output {
-            if !out.status.success() {
-                let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
-                let stdout = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                tracing::warn!("site-delete script failed: {} {}", stdout, stderr);
-            }
-        } else if let Err(e) = output {
-            tracing::warn!("site-delete script error: {}", e);
-        }
-    }
-
-    sqlx::query("DELETE FROM site_databases WHERE site_id = $1")
-        .bind(id)
-        .execute(&state.pool)
-        .await?;
-    let r = sqlx::query("DELETE FROM sites WHERE id = $1 AND user_id = $2")
-        .bind(id)
-        .bind(user_id.value())
-        .execute(&state.pool)
-        .await?;
-    if r.rows_affected() == 0 {
-        return Err(AppError::Validation("Site not found.".into()));
-    }
-    Ok(Redirect::to("/?deleted=1").into_response())
+    let job_id = actions::delete_site(&state, &actor, id).await?;
+    Ok(Redirect::to(&format!("/jobs/{}", job_id)).into_response())
 }
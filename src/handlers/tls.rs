@@ -0,0 +1,208 @@
+use axum::{
+    extract::{Extension, Multipart, Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::audit::{Actor, Event, Target};
+use crate::auth::Role;
+use crate::caddy::{check_upload, SiteCaddyConfig, TlsFiles, TlsStore};
+use crate::csrf::CsrfToken;
+use crate::error::Result;
+use crate::models::Site;
+use crate::state::AppState;
+
+use super::aliases::{apply_site_config, site_aliases};
+use super::sites::{fetch_site, site_detail_page};
+
+/// Re-render the site page on the SSL tab with `message`.
+async fn tls_error(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    id: i32,
+    message: String,
+) -> Result<Response> {
+    let mut page = site_detail_page(state, role, csrf, id).await?;
+    page.tls_error = message;
+    page.active_tab = "ssl".to_string();
+    Ok(page.into_response())
+}
+
+/// Write the site's snippet (aliases included) with `tls` and reload.
+async fn apply_tls(
+    state: &AppState,
+    site: &Site,
+    tls: Option<TlsFiles>,
+) -> Result<std::result::Result<(), String>> {
+    let mut conn = state.pool.acquire().await?;
+    let aliases = site_aliases(&mut conn, site.id).await?;
+    drop(conn);
+    let mut config = SiteCaddyConfig::for_site(site, &aliases);
+    config.tls = tls;
+    Ok(apply_site_config(state, &config).await)
+}
+
+/// Upload a PEM certificate chain and private key and serve the site with them
+/// instead of an ACME certificate.
+pub async fn upload_tls(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    mut multipart: Multipart,
+) -> Result<Response> {
+    let site = fetch_site(&state, id).await?;
+
+    let mut chain_pem = Vec::new();
+    let mut key_pem = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                return tls_error(
+                    &state,
+                    role,
+                    &csrf,
+                    id,
+                    format!("The upload could not be read: {}", e.body_text()),
+                )
+                .await;
+            }
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        let bytes = match field.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return tls_error(
+                    &state,
+                    role,
+                    &csrf,
+                    id,
+                    format!("The upload could not be read: {}", e.body_text()),
+                )
+                .await;
+            }
+        };
+        match name.as_str() {
+            "certificate" => chain_pem = bytes.to_vec(),
+            "private_key" => key_pem = bytes.to_vec(),
+            _ => {}
+        }
+    }
+    if chain_pem.is_empty() || key_pem.is_empty() {
+        return tls_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            "Choose both a certificate chain and a private key file.".to_string(),
+        )
+        .await;
+    }
+
+    let info = match check_upload(&site.primary_host(), &chain_pem, &key_pem) {
+        Ok(info) => info,
+        Err(msg) => return tls_error(&state, role, &csrf, id, msg).await,
+    };
+    // Served aliases share the site block, and with it the certificate.
+    let mut conn = state.pool.acquire().await?;
+    let aliases = site_aliases(&mut conn, id).await?;
+    drop(conn);
+    if let Some(alias) = aliases
+        .iter()
+        .find(|a| !a.is_redirect() && !info.covers(&a.hostname))
+    {
+        return tls_error(
+            &state,
+            role,
+            &csrf,
+            id,
+            format!(
+                "The certificate does not cover the alias {} (it is valid for: {}). Switch the alias to a redirect or include it in the certificate.",
+                alias.hostname,
+                info.sans_display()
+            ),
+        )
+        .await;
+    }
+
+    let store = TlsStore::from_config(&state.config);
+    let files = match store.install(&site.domain, &chain_pem, &key_pem).await {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("site {} tls upload: {}", id, e);
+            Event::new("site.tls.upload")
+                .target(Target::site(Some(id), &site.domain))
+                .failed(e.to_string())
+                .record(&state.pool, &actor)
+                .await;
+            return tls_error(
+                &state,
+                role,
+                &csrf,
+                id,
+                format!("Could not store the certificate: {}", e),
+            )
+            .await;
+        }
+    };
+    if let Err(msg) = apply_tls(&state, &site, Some(files.clone())).await? {
+        store.remove(&files).await;
+        Event::new("site.tls.upload")
+            .target(Target::site(Some(id), &site.domain))
+            .failed(&msg)
+            .record(&state.pool, &actor)
+            .await;
+        return tls_error(&state, role, &csrf, id, msg).await;
+    }
+
+    sqlx::query("UPDATE sites SET tls_cert_path = $1, tls_key_path = $2 WHERE id = $3")
+        .bind(&files.cert)
+        .bind(&files.key)
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    if let Some(old) = TlsFiles::for_site(&site) {
+        store.remove(&old).await;
+    }
+    Event::new("site.tls.upload")
+        .target(Target::site(Some(id), &site.domain))
+        .detail(format!("Valid for: {}", info.sans_display()))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to(&format!("/sites/{}?tls=custom#ssl", id)).into_response())
+}
+
+/// Drop the uploaded certificate and let Caddy obtain one via ACME again.
+pub async fn use_acme(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let site = fetch_site(&state, id).await?;
+    let Some(old) = TlsFiles::for_site(&site) else {
+        return Ok(Redirect::to(&format!("/sites/{}#ssl", id)).into_response());
+    };
+    if let Err(msg) = apply_tls(&state, &site, None).await? {
+        Event::new("site.tls.acme")
+            .target(Target::site(Some(id), &site.domain))
+            .failed(&msg)
+            .record(&state.pool, &actor)
+            .await;
+        return tls_error(&state, role, &csrf, id, msg).await;
+    }
+    sqlx::query("UPDATE sites SET tls_cert_path = NULL, tls_key_path = NULL WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    TlsStore::from_config(&state.config).remove(&old).await;
+    Event::new("site.tls.acme")
+        .target(Target::site(Some(id), &site.domain))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to(&format!("/sites/{}?tls=acme#ssl", id)).into_response())
+}
@@ -0,0 +1,249 @@
+use axum::{
+    extract::{Extension, Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+
+use crate::actions;
+use crate::audit::{Actor, Event, Target};
+use crate::auth::{Role, UserId};
+use crate::csrf::CsrfToken;
+use crate::error::{AppError, Result};
+use crate::models::{AddUserForm, UnlockForm, User, UserRoleForm, USER_COLUMNS};
+use crate::state::AppState;
+use crate::templates::{AddUserErrors, UsersPage};
+use crate::throttle;
+
+async fn users_page(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    user_id: UserId,
+) -> Result<UsersPage> {
+    let users = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users ORDER BY username",
+        USER_COLUMNS
+    ))
+    .fetch_all(&state.pool)
+    .await?;
+    let lockouts = throttle::active_lockouts(&state.pool).await?;
+    Ok(UsersPage {
+        logged_in: true,
+        role,
+        csrf: csrf.value(),
+        users,
+        current_user_id: user_id.value(),
+        lockouts,
+        username: String::new(),
+        new_role: Role::Operator.as_str().to_string(),
+        errors: AddUserErrors::default(),
+        error_message: String::new(),
+    })
+}
+
+async fn users_error(
+    state: &AppState,
+    role: Role,
+    csrf: &CsrfToken,
+    user_id: UserId,
+    message: &str,
+) -> Result<Response> {
+    let mut page = users_page(state, role, csrf, user_id).await?;
+    page.error_message = message.to_string();
+    Ok(page.into_response())
+}
+
+async fn fetch_user(state: &AppState, id: i32) -> Result<User> {
+    sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Validation("User not found.".to_string()))
+}
+
+pub async fn list_users(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+) -> Result<impl IntoResponse> {
+    role.require_admin()?;
+    users_page(&state, role, &csrf, user_id).await
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Form(form): Form<AddUserForm>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let created =
+        actions::create_user(&state.pool, &actor, &form.username, &form.password, &form.role)
+            .await?;
+    match created {
+        Ok(_) => Ok(Redirect::to("/users?user=created").into_response()),
+        Err(errors) => {
+            let mut page = users_page(&state, role, &csrf, user_id).await?;
+            page.username = form.username.trim().to_string();
+            page.new_role = form.role;
+            page.errors = errors;
+            Ok(page.into_response())
+        }
+    }
+}
+
+pub async fn update_user_role(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+    Form(form): Form<UserRoleForm>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let user = fetch_user(&state, id).await?;
+    let Some(new_role) = Role::parse(&form.role) else {
+        return users_error(&state, role, &csrf, user_id, "Choose a role.").await;
+    };
+    if user.id == user_id.value() {
+        return users_error(&state, role, &csrf, user_id, "You cannot change your own role.").await;
+    }
+    if user.role().is_admin()
+        && !new_role.is_admin()
+        && !actions::other_admin_exists(&state.pool, id).await?
+    {
+        return users_error(
+            &state,
+            role,
+            &csrf,
+            user_id,
+            "The panel needs at least one active admin.",
+        )
+        .await;
+    }
+    sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
+        .bind(new_role.as_str())
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    Event::new("user.role")
+        .target(Target::user(Some(id), &user.username))
+        .detail(format!("{} → {}", user.role().label(), new_role.label()))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to("/users?user=updated").into_response())
+}
+
+/// Disable an account and end its sessions; the user and everything they created stay.
+pub async fn disable_user(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let user = fetch_user(&state, id).await?;
+    if user.id == user_id.value() {
+        return users_error(
+            &state,
+            role,
+            &csrf,
+            user_id,
+            "You cannot disable your own account.",
+        )
+        .await;
+    }
+    if let Err(msg) = actions::disable_user(&state.pool, &actor, &user).await? {
+        return users_error(&state, role, &csrf, user_id, &msg).await;
+    }
+    Ok(Redirect::to("/users?user=disabled").into_response())
+}
+
+pub async fn enable_user(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let user = fetch_user(&state, id).await?;
+    sqlx::query("UPDATE users SET disabled_at = NULL WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    Event::new("user.enable")
+        .target(Target::user(Some(id), &user.username))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to("/users?user=enabled").into_response())
+}
+
+/// Delete a panel user. Sites and jobs are shared, so the ones they created
+/// are handed to the acting admin instead of cascading away with the user.
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Extension(csrf): Extension<CsrfToken>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    role.require_admin()?;
+    let user = fetch_user(&state, id).await?;
+    if user.id == user_id.value() {
+        return users_error(&state, role, &csrf, user_id, "You cannot delete your own account.").await;
+    }
+    if user.role().is_admin() && !actions::other_admin_exists(&state.pool, id).await? {
+        return users_error(
+            &state,
+            role,
+            &csrf,
+            user_id,
+            "The panel needs at least one active admin.",
+        )
+        .await;
+    }
+    let mut tx = state.pool.begin().await?;
+    for table in ["sites", "jobs"] {
+        sqlx::query(&format!(
+            "UPDATE {} SET user_id = $1 WHERE user_id = $2",
+            table
+        ))
+        .bind(user_id.value())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Event::new("user.delete")
+        .target(Target::user(Some(id), &user.username))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to("/users?user=deleted").into_response())
+}
+
+/// Lift a login lockout for a username or client IP before it runs out.
+pub async fn unlock_login(
+    State(state): State<AppState>,
+    Extension(actor): Extension<Actor>,
+    Extension(role): Extension<Role>,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response> {
+    role.require_admin()?;
+    throttle::unlock(&state.pool, &form.kind, &form.key).await?;
+    Event::new("login.unlock")
+        .detail(format!("{}: {}", form.kind, form.key))
+        .record(&state.pool, &actor)
+        .await;
+    Ok(Redirect::to("/users?unlocked=1").into_response())
+}
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::db::DbPool;
+use crate::error::Result;
+
+/// Share of checks in a window that found the site up (online or degraded).
+pub struct Uptime {
+    pub label: &'static str,
+    pub checks: i64,
+    pub percent: Option<f64>,
+}
+
+impl Uptime {
+    /// Two decimals, truncated: one failed check in 30 days is 99.99%, never 100.00%.
+    pub fn percent_display(&self) -> String {
+        match self.percent {
+            Some(p) if p >= 100.0 => "100%".to_string(),
+            // The nudge keeps e.g. 99.99 (stored as 99.98999…) from dropping to 99.98.
+            Some(p) => format!("{:.2}%", ((p * 100.0 + 1e-6).floor() / 100.0).min(99.99)),
+            None => "—".to_string(),
+        }
+    }
+
+    /// 99.9% and up is fine, 99% and up is worth a look, below that is a problem.
+    pub fn level(&self) -> &'static str {
+        match self.percent {
+            Some(p) if p >= 99.9 => "good",
+            Some(p) if p >= 99.0 => "warn",
+            Some(_) => "bad",
+            None => "none",
+        }
+    }
+}
+
+pub async fn uptime(pool: &DbPool, site_id: i32) -> Result<Vec<Uptime>> {
+    let (day, day_up, week, week_up, month, month_up) =
+        sqlx::query_as::<_, (i64, i64, i64, i64, i64, i64)>(
+            "SELECT \
+             COUNT(*) FILTER (WHERE checked_at > NOW() - INTERVAL '24 hours'), \
+             COUNT(*) FILTER (WHERE checked_at > NOW() - INTERVAL '24 hours' AND status <> 'offline'), \
+             COUNT(*) FILTER (WHERE checked_at > NOW() - INTERVAL '7 days'), \
+             COUNT(*) FILTER (WHERE checked_at > NOW() - INTERVAL '7 days' AND status <> 'offline'), \
+             COUNT(*), \
+             COUNT(*) FILTER (WHERE status <> 'offline') \
+             FROM site_checks WHERE site_id = $1 AND checked_at > NOW() - INTERVAL '30 days'",
+        )
+        .bind(site_id)
+        .fetch_one(pool)
+        .await?;
+    let window = |label, checks: i64, up: i64| Uptime {
+        label,
+        checks,
+        percent: (checks > 0).then(|| up as f64 * 100.0 / checks as f64),
+    };
+    Ok(vec![
+        window("24 hours", day, day_up),
+        window("7 days", week, week_up),
+        window("30 days", month, month_up),
+    ])
+}
+
+/// A run of consecutive failed checks.
+#[derive(sqlx::FromRow)]
+pub struct Incident {
+    /// First failed check.
+    pub started_at: DateTime<Utc>,
+    /// First successful check afterwards; `None` while the site is still down.
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Error recorded by the first failed check.
+    pub cause: Option<String>,
+}
+
+impl Incident {
+    pub fn is_ongoing(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    pub fn started_display(&self) -> String {
+        self.started_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn duration_display(&self) -> String {
+        let secs = (self.ended_at.unwrap_or_else(Utc::now) - self.started_at).num_seconds();
+        if secs < 60 {
+            format!("{}s", secs)
+        } else if secs < 3600 {
+            format!("{}m {}s", secs / 60, secs % 60)
+        } else if secs < 86_400 {
+            format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+        } else {
+            format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600)
+        }
+    }
+
+    pub fn cause_display(&self) -> &str {
+        self.cause.as_deref().unwrap_or("—")
+    }
+}
+
+/// Incidents of the last 30 days, newest first: each place the status flips
+/// between up and down starts a span, and the down spans are the incidents.
+pub async fn incidents(pool: &DbPool, site_id: i32) -> Result<Vec<Incident>> {
+    Ok(sqlx::query_as::<_, Incident>(
+        "WITH c AS ( \
+             SELECT checked_at, status <> 'offline' AS up, error, \
+                    LAG(status <> 'offline') OVER (ORDER BY checked_at) AS prev_up \
+             FROM site_checks WHERE site_id = $1 AND checked_at > NOW() - INTERVAL '30 days' \
+         ), spans AS ( \
+             SELECT checked_at AS started_at, LEAD(checked_at) OVER (ORDER BY checked_at) AS ended_at, up, error AS cause \
+             FROM c WHERE prev_up IS DISTINCT FROM up \
+         ) \
+         SELECT started_at, ended_at, cause FROM spans WHERE NOT up ORDER BY started_at DESC LIMIT 50",
+    )
+    .bind(site_id)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Average response time per 15 minutes over the last 24 hours, laid out for
+/// the inline SVG on the Monitoring tab (`viewBox="0 0 600 120"`).
+pub struct ResponseChart {
+    /// `points` attributes of the polylines; a bucket without checks starts a new line.
+    pub segments: Vec<String>,
+    /// Top of the y axis in milliseconds.
+    pub max_ms: i32,
+    pub avg_ms: Option<i32>,
+}
+
+const BUCKET_SECS: i64 = 900;
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 120.0;
+
+impl ResponseChart {
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+pub async fn response_chart(pool: &DbPool, site_id: i32) -> Result<ResponseChart> {
+    let buckets = sqlx::query_as::<_, (DateTime<Utc>, f64)>(
+        "SELECT to_timestamp(floor(extract(epoch FROM checked_at) / $2) * $2) AS bucket, AVG(latency_ms)::float8 \
+         FROM site_checks WHERE site_id = $1 AND latency_ms IS NOT NULL AND checked_at > NOW() - INTERVAL '24 hours' \
+         GROUP BY bucket ORDER BY bucket",
+    )
+    .bind(site_id)
+    .bind(BUCKET_SECS as f64)
+    .fetch_all(pool)
+    .await?;
+
+    let peak = buckets.iter().map(|(_, ms)| *ms).fold(0.0, f64::max);
+    // Round the axis up to a readable value.
+    let max_ms = [50, 100, 200, 250, 500, 1000, 2000, 2500, 5000, 10_000]
+        .into_iter()
+        .find(|&m| m as f64 >= peak)
+        .unwrap_or(peak.ceil() as i32)
+        .max(1);
+    let avg_ms = (!buckets.is_empty())
+        .then(|| (buckets.iter().map(|(_, ms)| ms).sum::<f64>() / buckets.len() as f64) as i32);
+
+    let start = Utc::now() - Duration::hours(24);
+    let span = Duration::hours(24).num_seconds() as f64;
+    let mut segments = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut previous: Option<DateTime<Utc>> = None;
+    for (bucket, ms) in &buckets {
+        if previous.is_some_and(|p| (*bucket - p).num_seconds() > BUCKET_SECS) {
+            segments.push(std::mem::take(&mut current).join(" "));
+        }
+        let x = ((*bucket - start).num_seconds() as f64 / span).clamp(0.0, 1.0) * WIDTH;
+        let y = HEIGHT - (ms / max_ms as f64).min(1.0) * HEIGHT;
+        current.push(format!("{:.1},{:.1}", x, y));
+        previous = Some(*bucket);
+    }
+    if !current.is_empty() {
+        segments.push(current.join(" "));
+    }
+    Ok(ResponseChart {
+        segments,
+        max_ms,
+        avg_ms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Uptime;
+
+    fn uptime(percent: Option<f64>) -> Uptime {
+        Uptime {
+            label: "30 days",
+            checks: 0,
+            percent,
+        }
+    }
+
+    #[test]
+    fn percent_display() {
+        let cases = [
+            (Some(100.0), "100%"),
+            (Some(99.996), "99.99%"),
+            (Some(100.0 * 86_399.0 / 86_400.0), "99.99%"),
+            (Some(100.0 - 1e-9), "99.99%"),
+            (Some(9_999.0 * 100.0 / 10_000.0), "99.99%"),
+            (Some(99.0), "99.00%"),
+            (Some(99.899), "99.89%"),
+            (Some(100.0 / 3.0), "33.33%"),
+            (Some(0.0), "0.00%"),
+            (None, "—"),
+        ];
+        for (percent, display) in cases {
+            assert_eq!(uptime(percent).percent_display(), display, "{:?}", percent);
+        }
+    }
+
+    #[test]
+    fn level() {
+        let cases = [
+            (Some(100.0), "good"),
+            (Some(99.9), "good"),
+            (Some(99.899), "warn"),
+            (Some(99.0), "warn"),
+            (Some(98.999), "bad"),
+            (Some(0.0), "bad"),
+            (None, "none"),
+        ];
+        for (percent, level) in cases {
+            assert_eq!(uptime(percent).level(), level, "{:?}", percent);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Background health checker.
+//!
+//! Each site is requested through the local FrankenPHP listener
+//! (`HEALTH_CHECK_ADDR`) with its canonical host as SNI and `Host` header, so
+//! the check exercises the same site block and certificate visitors get
+//! without depending on public DNS. A site is due once its own interval
+//! (default `HEALTH_CHECK_INTERVAL`) has passed since its last check. Results
+//! go into `site_checks`; the dashboard shows the status of the latest one
+//! and [`history`] turns them into uptime, incidents and response times.
+
+mod history;
+
+pub use history::{incidents, response_chart, uptime, Incident, ResponseChart, Uptime};
+
+use std::error::Error as StdError;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::error::Result;
+use crate::models::{Site, SITE_COLUMNS};
+use crate::state::AppState;
+
+/// Answered as expected with a valid certificate.
+pub const ONLINE: &str = "online";
+/// Answered as expected, but the certificate did not verify.
+pub const DEGRADED: &str = "degraded";
+/// No response, or not the expected status code or body.
+pub const OFFLINE: &str = "offline";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the scheduler looks for sites that are due.
+const TICK: Duration = Duration::from_secs(15);
+/// Only this much of the body is searched for the keyword.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+/// Sites checked at the same time.
+const CONCURRENCY: usize = 8;
+/// Checks older than this are deleted after each round.
+const RETENTION_DAYS: i32 = 35;
+
+/// What a response must look like for a site to count as up.
+#[derive(Clone, Debug, Default)]
+pub struct Expectations {
+    /// Exact status code; `None` accepts anything below 500.
+    pub status: Option<u16>,
+    /// Text the body must contain.
+    pub keyword: Option<String>,
+}
+
+impl Expectations {
+    pub fn for_site(site: &Site) -> Self {
+        Self {
+            status: site
+                .check_expected_status
+                .and_then(|s| u16::try_from(s).ok()),
+            keyword: site.check_keyword.clone().filter(|k| !k.is_empty()),
+        }
+    }
+
+    /// Why a response with `code` and `body` fails, if it does.
+    fn failure(&self, code: u16, body: Option<&str>) -> Option<String> {
+        match self.status {
+            Some(expected) if code != expected => {
+                return Some(format!("Expected status {}, got {}", expected, code))
+            }
+            None if code >= 500 => return Some(format!("Server error {}", code)),
+            _ => {}
+        }
+        match (&self.keyword, body) {
+            (Some(keyword), Some(body)) if !body.contains(keyword.as_str()) => {
+                Some(format!("Response does not contain \"{}\"", keyword))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of one request to a site.
+#[derive(Debug, Default)]
+pub struct CheckResult {
+    pub status_code: Option<u16>,
+    pub latency_ms: Option<i32>,
+    pub tls_error: Option<String>,
+    /// Connection error, timeout or failed expectation.
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn status(&self) -> &'static str {
+        if self.status_code.is_none() || self.error.is_some() {
+            OFFLINE
+        } else if self.tls_error.is_some() {
+            DEGRADED
+        } else {
+            ONLINE
+        }
+    }
+
+    fn answered(response: Response, expect: &Expectations, tls_error: Option<String>) -> Self {
+        Self {
+            status_code: Some(response.status),
+            latency_ms: Some(response.latency_ms),
+            tls_error,
+            error: expect.failure(response.status, response.body.as_deref()),
+        }
+    }
+}
+
+/// The parts of a response a check looks at.
+struct Response {
+    status: u16,
+    latency_ms: i32,
+    /// Only read when there is a keyword to look for.
+    body: Option<String>,
+}
+
+/// Requests `https://<host>/` from the local listener. Redirects are not
+/// followed: a site that answers with one is up.
+#[derive(Clone)]
+pub struct HealthChecker {
+    addr: SocketAddr,
+}
+
+impl HealthChecker {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    pub async fn check(&self, host: &str, expect: &Expectations) -> CheckResult {
+        let url = format!("https://{}/", host);
+        let read_body = expect.keyword.is_some();
+        match self.request(host, &url, false, read_body).await {
+            Ok(response) => CheckResult::answered(response, expect, None),
+            Err(e) if is_tls_error(&e) => {
+                // Record the certificate problem, then see whether the site answers at all.
+                let tls_error = Some(error_chain(&e));
+                match self.request(host, &url, true, read_body).await {
+                    Ok(response) => CheckResult::answered(response, expect, tls_error),
+                    Err(e) => CheckResult {
+                        tls_error,
+                        error: Some(error_chain(&e)),
+                        ..Default::default()
+                    },
+                }
+            }
+            Err(e) => CheckResult {
+                error: Some(error_chain(&e)),
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn request(
+        &self,
+        host: &str,
+        url: &str,
+        accept_invalid_certs: bool,
+        read_body: bool,
+    ) -> std::result::Result<Response, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .resolve(host, self.addr)
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(TIMEOUT)
+            .user_agent("FrankenPHP-Panel health check")
+            .danger_accept_invalid_certs(accept_invalid_certs)
+            .build()?;
+        let started = Instant::now();
+        let mut response = client.get(url).send().await?;
+        let latency_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+        let status = response.status().as_u16();
+        let body = if read_body {
+            let mut bytes = Vec::new();
+            // A body cut short (e.g. no TLS close_notify) still gets searched;
+            // the status line already arrived.
+            while let Ok(Some(chunk)) = response.chunk().await {
+                bytes.extend_from_slice(&chunk);
+                if bytes.len() >= MAX_BODY {
+                    break;
+                }
+            }
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        } else {
+            None
+        };
+        Ok(Response {
+            status,
+            latency_ms,
+            body,
+        })
+    }
+}
+
+/// The causes below `e`. `io::Error::source` skips the error it wraps (its
+/// Display already shows it), so step into wrapped errors instead; that is
+/// where hyper puts rustls' handshake errors.
+fn causes(e: &reqwest::Error) -> Vec<&(dyn StdError + 'static)> {
+    let mut found = Vec::new();
+    let mut current = e.source();
+    while let Some(err) = current {
+        found.push(err);
+        current = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+            .map(|inner| inner as &(dyn StdError + 'static))
+            .or_else(|| err.source());
+    }
+    found
+}
+
+/// Whether the request failed on the certificate or the TLS handshake.
+fn is_tls_error(e: &reqwest::Error) -> bool {
+    causes(e).iter().any(|err| err.is::<rustls::Error>())
+}
+
+/// reqwest's own message only names the URL; the causes say what went wrong.
+fn error_chain(e: &reqwest::Error) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for err in causes(e) {
+        let text = err.to_string();
+        if !parts.iter().any(|p| p.contains(&text)) {
+            parts.push(text);
+        }
+    }
+    if parts.is_empty() {
+        e.to_string()
+    } else {
+        parts.join(": ")
+    }
+}
+
+pub fn spawn_checker(state: AppState) {
+    if state.config.health_check_interval == 0 {
+        tracing::info!("health checker disabled (HEALTH_CHECK_INTERVAL=0)");
+        return;
+    }
+    let checker = HealthChecker::new(state.config.health_check_addr);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(TICK);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = run_due(&state, &checker).await {
+                tracing::warn!("health checker: {}", e);
+            }
+        }
+    });
+}
+
+/// Check every site whose interval has passed since its last check and store the results.
+async fn run_due(state: &AppState, checker: &HealthChecker) -> Result<()> {
+    let sites = sqlx::query_as::<_, Site>(&format!(
+        "SELECT {} FROM sites s WHERE NOT EXISTS (SELECT 1 FROM site_checks c WHERE c.site_id = s.id \
+         AND c.checked_at > NOW() - make_interval(secs => COALESCE(s.check_interval, $1)))",
+        SITE_COLUMNS
+    ))
+    .bind(state.config.health_check_interval as i64)
+    .fetch_all(&state.pool)
+    .await?;
+    if sites.is_empty() {
+        return Ok(());
+    }
+
+    let permits = Arc::new(Semaphore::new(CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for site in sites {
+        // A wildcard has no single name to request.
+        if site.domain.starts_with("*.") {
+            continue;
+        }
+        let checker = checker.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let expect = Expectations::for_site(&site);
+            let result = checker.check(&site.primary_host(), &expect).await;
+            (site.id, result)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((site_id, result)) = joined else {
+            continue;
+        };
+        // The site may have been deleted while it was being checked.
+        sqlx::query(
+            "INSERT INTO site_checks (site_id, status, status_code, latency_ms, tls_error, error) \
+             SELECT $1, $2, $3, $4, $5, $6 WHERE EXISTS (SELECT 1 FROM sites WHERE id = $1)",
+        )
+        .bind(site_id)
+        .bind(result.status())
+        .bind(result.status_code.map(i32::from))
+        .bind(result.latency_ms)
+        .bind(&result.tls_error)
+        .bind(&result.error)
+        .execute(&state.pool)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM site_checks WHERE checked_at < NOW() - make_interval(days => $1)")
+        .bind(RETENTION_DAYS)
+        .execute(&state.pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations_failure() {
+        let any = Expectations {
+            status: None,
+            keyword: None,
+        };
+        let ok_with_keyword = Expectations {
+            status: Some(200),
+            keyword: Some("Welcome".to_string()),
+        };
+        let cases = [
+            (&any, 200, None, None),
+            (&any, 404, None, None),
+            (&any, 499, None, None),
+            (&any, 500, None, Some("Server error 500")),
+            (&any, 503, Some("Welcome"), Some("Server error 503")),
+            (&ok_with_keyword, 200, Some("<h1>Welcome</h1>"), None),
+            (
+                &ok_with_keyword,
+                301,
+                Some("Welcome"),
+                Some("Expected status 200, got 301"),
+            ),
+            (
+                &ok_with_keyword,
+                200,
+                Some("<h1>welcome</h1>"),
+                Some("Response does not contain \"Welcome\""),
+            ),
+        ];
+        for (expect, code, body, failure) in cases {
+            assert_eq!(
+                expect.failure(code, body).as_deref(),
+                failure,
+                "{:?} {} {:?}",
+                expect.status,
+                code,
+                body
+            );
+        }
+        // An expected status may be a server error.
+        let maintenance = Expectations {
+            status: Some(503),
+            keyword: None,
+        };
+        assert_eq!(maintenance.failure(503, None), None);
+    }
+
+    #[test]
+    fn check_result_status() {
+        let answered = |tls_error: Option<&str>, error: Option<&str>| CheckResult {
+            status_code: Some(200),
+            latency_ms: Some(12),
+            tls_error: tls_error.map(str::to_string),
+            error: error.map(str::to_string),
+        };
+        assert_eq!(answered(None, None).status(), ONLINE);
+        assert_eq!(
+            answered(Some("certificate expired"), None).status(),
+            DEGRADED
+        );
+        assert_eq!(
+            answered(None, Some("Expected status 200, got 301")).status(),
+            OFFLINE
+        );
+        assert_eq!(
+            answered(Some("certificate expired"), Some("Server error 502")).status(),
+            OFFLINE
+        );
+        let unreachable = CheckResult {
+            error: Some("connection refused".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(unreachable.status(), OFFLINE);
+        assert_eq!(CheckResult::default().status(), OFFLINE);
+    }
+}
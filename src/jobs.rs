@@ -0,0 +1,1106 @@
+//! Background provisioning jobs.
+//!
+//! Handlers validate input, insert a row into `jobs` and redirect to
+//! `/jobs/:id`; a single worker task inside the panel process picks queued
+//! jobs up, runs them through the [`Provisioner`](crate::provision::Provisioner)
+//! and records the outcome. The CLI runs the jobs it queues itself, through
+//! [`run_job`], so it works while the panel is down. Panel rows (`sites`, `site_databases`) are only
+//! written or removed once provisioning succeeded; a backup's `site_backups`
+//! row is inserted with its job and gets its size once the bundle is written.
+//! Restores end with a request to the restored site, which only warns when it
+//! goes unanswered.
+//!
+//! A running job's `heartbeat_at` is refreshed by the process running it; a
+//! job whose heartbeat stops, because the panel or a CLI was killed, is
+//! failed by [`fail_stale`] so it can be retried.
+//!
+//! Output is stored in `job_log_lines` as it is produced and every change is
+//! announced on [`JobQueue::subscribe`], which the `/jobs/:id/events` SSE
+//! endpoint uses to stream the transcript live.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use tokio::sync::{broadcast, mpsc, Notify};
+
+use crate::audit;
+use crate::backup;
+use crate::caddy::{SiteCaddyConfig, TlsFiles, TlsStore};
+use crate::db::DbPool;
+use crate::error::{AppError, Result};
+use crate::provision::{
+    BackupVerification, DatabaseRef, LogLine, LogSink, NewBackup, NewDatabase, NewSite,
+    ProvisionError, ProvisionOutput, SiteRestore, Transcript,
+};
+use crate::state::AppState;
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const SUCCEEDED: &str = "succeeded";
+pub const FAILED: &str = "failed";
+
+/// How long the worker sleeps between polls when nobody wakes it up.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a running job's `heartbeat_at` is refreshed.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A running job without a heartbeat for this long was abandoned; see [`fail_stale`].
+const STALE_AFTER_SECS: f64 = 120.0;
+
+/// Hours the passwords of a failed or never-run job are kept for a retry.
+pub const SECRETS_KEPT_HOURS: i32 = 24;
+
+/// How often passwords past [`SECRETS_KEPT_HOURS`] are looked for.
+const SECRET_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Kinds whose payload can hold passwords; see [`JobPayload::redacted`].
+const KINDS_WITH_SECRETS: [&str; 3] = ["create_site", "create_database", "restore_new_site"];
+
+/// What a job does. Stored as JSON in `jobs.payload`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobPayload {
+    CreateSite {
+        site: NewSite,
+    },
+    DeleteSite {
+        site_id: i32,
+        domain: String,
+        folder_path: String,
+        databases: Vec<DatabaseRef>,
+    },
+    CreateDatabase {
+        site_id: i32,
+        database: NewDatabase,
+    },
+    DeleteDatabase {
+        site_id: i32,
+        database_id: i32,
+        database: DatabaseRef,
+    },
+    BackupSite {
+        site_id: i32,
+        backup: NewBackup,
+    },
+    /// Snapshot the site, then restore a backup over it.
+    RestoreSite {
+        site_id: i32,
+        snapshot: NewBackup,
+        restore: SiteRestore,
+    },
+    /// Create a site and its databases and restore a backup into them.
+    RestoreNewSite {
+        site: NewSite,
+        databases: Vec<NewDatabase>,
+        restore: SiteRestore,
+    },
+    /// Fetch a backup bundle from off-site storage into the backup directory.
+    DownloadBackup {
+        site_id: Option<i32>,
+        key: String,
+        archive_path: String,
+    },
+    /// Test-restore a backup into a temporary directory and scratch database.
+    VerifyBackup {
+        backup_id: i32,
+        site_id: Option<i32>,
+        check: BackupVerification,
+    },
+}
+
+impl JobPayload {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobPayload::CreateSite { .. } => "create_site",
+            JobPayload::DeleteSite { .. } => "delete_site",
+            JobPayload::CreateDatabase { .. } => "create_database",
+            JobPayload::DeleteDatabase { .. } => "delete_database",
+            JobPayload::BackupSite { .. } => "backup_site",
+            JobPayload::RestoreSite { .. } => "restore_site",
+            JobPayload::RestoreNewSite { .. } => "restore_new_site",
+            JobPayload::DownloadBackup { .. } => "download_backup",
+            JobPayload::VerifyBackup { .. } => "verify_backup",
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            JobPayload::CreateSite { site } => format!("Create site {}", site.domain),
+            JobPayload::DeleteSite { domain, .. } => format!("Delete site {}", domain),
+            JobPayload::CreateDatabase { database, .. } => {
+                format!("Create database {}", database.db_name)
+            }
+            JobPayload::DeleteDatabase { database, .. } => {
+                format!("Delete database {}", database.db_name)
+            }
+            JobPayload::BackupSite { backup, .. } => format!("Back up site {}", backup.domain),
+            JobPayload::RestoreSite { restore, .. } => {
+                format!("Restore site {} from a backup", restore.domain)
+            }
+            JobPayload::RestoreNewSite { restore, .. } => {
+                format!("Restore a backup into new site {}", restore.domain)
+            }
+            JobPayload::DownloadBackup { key, .. } => format!(
+                "Download backup {} from off-site storage",
+                key.rsplit('/').next().unwrap_or(key)
+            ),
+            JobPayload::VerifyBackup { check, .. } => format!(
+                "Verify backup {}",
+                check
+                    .archive_path
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or(&check.archive_path)
+            ),
+        }
+    }
+
+    pub fn site_id(&self) -> Option<i32> {
+        match self {
+            JobPayload::CreateSite { .. } | JobPayload::RestoreNewSite { .. } => None,
+            JobPayload::DeleteSite { site_id, .. }
+            | JobPayload::CreateDatabase { site_id, .. }
+            | JobPayload::DeleteDatabase { site_id, .. }
+            | JobPayload::BackupSite { site_id, .. }
+            | JobPayload::RestoreSite { site_id, .. } => Some(*site_id),
+            JobPayload::DownloadBackup { site_id, .. }
+            | JobPayload::VerifyBackup { site_id, .. } => *site_id,
+        }
+    }
+
+    /// Copy with passwords blanked. Stored once a job has succeeded so secrets
+    /// only stay in the table while a retry may still need them, which is
+    /// [`SECRETS_KEPT_HOURS`] at most; see [`spawn_secret_purge`].
+    fn redacted(&self) -> Self {
+        let mut p = self.clone();
+        match &mut p {
+            JobPayload::CreateSite { site } => {
+                if let Some(wp) = site.wordpress.as_mut() {
+                    wp.admin_password.clear();
+                }
+            }
+            JobPayload::CreateDatabase { database, .. } => database.password.clear(),
+            JobPayload::RestoreNewSite {
+                databases, restore, ..
+            } => {
+                for db in databases {
+                    db.password.clear();
+                }
+                if let Some(db) = restore.wp_config.as_mut() {
+                    db.password.clear();
+                }
+            }
+            JobPayload::DeleteSite { .. }
+            | JobPayload::DeleteDatabase { .. }
+            | JobPayload::BackupSite { .. }
+            | JobPayload::RestoreSite { .. }
+            | JobPayload::DownloadBackup { .. }
+            | JobPayload::VerifyBackup { .. } => {}
+        }
+        p
+    }
+}
+
+/// Handle used by handlers to enqueue work and wake the worker.
+#[derive(Clone)]
+pub struct JobQueue {
+    notify: Arc<Notify>,
+    /// Id of a job that has new output or changed state.
+    events: broadcast::Sender<i32>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            notify: Arc::new(Notify::new()),
+            events,
+        }
+    }
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive the id of every job that changes. Receivers re-read the job
+    /// from the database, so a lagged receiver only needs to re-query.
+    pub fn subscribe(&self) -> broadcast::Receiver<i32> {
+        self.events.subscribe()
+    }
+
+    fn announce(&self, job_id: i32) {
+        let _ = self.events.send(job_id);
+    }
+
+    /// Insert a queued job and return its id. When `db` is a transaction, call
+    /// [`JobQueue::wake`] after committing so the worker sees the job at once.
+    pub async fn enqueue<'e, E>(&self, db: E, user_id: i32, payload: JobPayload) -> Result<i32>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO jobs (kind, summary, payload, user_id, site_id) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        )
+        .bind(payload.kind())
+        .bind(payload.summary())
+        .bind(Json(&payload))
+        .bind(user_id)
+        .bind(payload.site_id())
+        .fetch_one(db)
+        .await?;
+        self.wake();
+        Ok(id)
+    }
+
+    /// Wake the worker to look for queued jobs.
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Put a failed job back in the queue. Returns false if it was not failed,
+    /// and a validation error if the passwords it needs were discarded.
+    pub async fn retry(&self, pool: &DbPool, job_id: i32) -> Result<bool> {
+        let r = sqlx::query(
+            "UPDATE jobs SET state = $1, stdout = '', stderr = '', error = NULL, started_at = NULL, finished_at = NULL \
+             WHERE id = $2 AND state = $3 AND secrets_purged_at IS NULL",
+        )
+        .bind(QUEUED)
+        .bind(job_id)
+        .bind(FAILED)
+        .execute(pool)
+        .await?;
+        if r.rows_affected() == 0 {
+            let purged = sqlx::query_scalar::<_, bool>(
+                "SELECT secrets_purged_at IS NOT NULL FROM jobs WHERE id = $1",
+            )
+            .bind(job_id)
+            .fetch_optional(pool)
+            .await?;
+            if purged == Some(true) {
+                return Err(AppError::Validation(format!(
+                    "The passwords this job needs were discarded {} hours after it failed. Start it again instead.",
+                    SECRETS_KEPT_HOURS
+                )));
+            }
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM job_log_lines WHERE job_id = $1")
+            .bind(job_id)
+            .execute(pool)
+            .await?;
+        self.notify.notify_one();
+        self.announce(job_id);
+        Ok(true)
+    }
+}
+
+/// Start the background worker. Jobs left `running` by a process that died,
+/// this panel's previous run or a killed CLI, are failed once their heartbeat
+/// is stale, so they can be retried and no longer block their site.
+pub async fn spawn_worker(state: AppState) -> Result<()> {
+    fail_stale(&state.pool).await?;
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = fail_stale(&state.pool).await {
+                tracing::warn!("job worker: {}", e);
+            }
+            match run_next(&state).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => tracing::warn!("job worker: {}", e),
+            }
+            let _ = tokio::time::timeout(POLL_INTERVAL, state.jobs.notify.notified()).await;
+        }
+    });
+    Ok(())
+}
+
+/// Fail running jobs whose heartbeat is older than [`STALE_AFTER_SECS`]: the
+/// process running them is gone. Returns how many were failed.
+pub async fn fail_stale(pool: &DbPool) -> Result<u64> {
+    let failed = sqlx::query(
+        "UPDATE jobs SET state = $1, error = 'Interrupted: the panel or CLI process running this job stopped.', finished_at = NOW() \
+         WHERE state = $2 AND COALESCE(heartbeat_at, started_at, created_at) < NOW() - make_interval(secs => $3)",
+    )
+    .bind(FAILED)
+    .bind(RUNNING)
+    .bind(STALE_AFTER_SECS)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if failed > 0 {
+        tracing::warn!("failed {} abandoned running jobs", failed);
+        fail_events(pool).await?;
+    }
+    Ok(failed)
+}
+
+/// Fail a job this process claimed but will not finish, e.g. because the CLI
+/// running it was interrupted.
+pub async fn interrupt(pool: &DbPool, job_id: i32, reason: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET state = $1, error = $2, finished_at = NOW() WHERE id = $3 AND state = $4",
+    )
+    .bind(FAILED)
+    .bind(reason)
+    .bind(job_id)
+    .bind(RUNNING)
+    .execute(pool)
+    .await?;
+    fail_events(pool).await
+}
+
+/// Give the audit events of jobs failed outside [`run_claimed`] their outcome.
+async fn fail_events(pool: &DbPool) -> Result<()> {
+    sqlx::query(
+        "UPDATE audit_events e SET outcome = $1, detail = j.error FROM jobs j \
+         WHERE e.job_id = j.id AND e.outcome = $2 AND j.state = $1",
+    )
+    .bind(FAILED)
+    .bind(audit::QUEUED)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Discard the passwords of failed and never-run jobs every hour, once they
+/// are [`SECRETS_KEPT_HOURS`] old.
+pub fn spawn_secret_purge(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SECRET_PURGE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = purge_secrets(&pool).await {
+                tracing::warn!("job secret purge: {}", e);
+            }
+        }
+    });
+}
+
+/// Blank the passwords of jobs that failed, or were queued and never ran,
+/// more than [`SECRETS_KEPT_HOURS`] ago. The queued ones are failed, since
+/// they could no longer do their work. Returns how many jobs were changed.
+async fn purge_secrets(pool: &DbPool) -> Result<u64> {
+    let stale = sqlx::query_as::<_, (i32, String, Json<JobPayload>)>(
+        "SELECT id, state, payload FROM jobs WHERE kind = ANY($1) AND secrets_purged_at IS NULL \
+         AND ((state = $2 AND finished_at < NOW() - make_interval(hours => $4)) \
+         OR (state = $3 AND created_at < NOW() - make_interval(hours => $4))) ORDER BY id",
+    )
+    .bind(&KINDS_WITH_SECRETS[..])
+    .bind(FAILED)
+    .bind(QUEUED)
+    .bind(SECRETS_KEPT_HOURS)
+    .fetch_all(pool)
+    .await?;
+    let mut purged = 0;
+    for (job_id, state, Json(payload)) in stale {
+        let redacted = payload.redacted();
+        if serde_json::to_value(&redacted).ok() == serde_json::to_value(&payload).ok() {
+            continue;
+        }
+        if state == QUEUED {
+            let error = format!(
+                "Not run within {} hours, so the passwords it needed were discarded.",
+                SECRETS_KEPT_HOURS
+            );
+            let r = sqlx::query(
+                "UPDATE jobs SET state = $1, error = $2, payload = $3, secrets_purged_at = NOW(), finished_at = NOW() \
+                 WHERE id = $4 AND state = $5",
+            )
+            .bind(FAILED)
+            .bind(&error)
+            .bind(Json(&redacted))
+            .bind(job_id)
+            .bind(QUEUED)
+            .execute(pool)
+            .await?;
+            if r.rows_affected() > 0 {
+                audit::finish_job(pool, job_id, FAILED, None, Some(&error)).await?;
+                purged += 1;
+            }
+        } else {
+            purged += sqlx::query(
+                "UPDATE jobs SET payload = $1, secrets_purged_at = NOW() WHERE id = $2 AND state = $3",
+            )
+            .bind(Json(&redacted))
+            .bind(job_id)
+            .bind(FAILED)
+            .execute(pool)
+            .await?
+            .rows_affected();
+        }
+    }
+    if purged > 0 {
+        tracing::info!("job secret purge: discarded the passwords of {} jobs", purged);
+    }
+    Ok(purged)
+}
+
+/// Claim and run one queued job. Returns false when the queue is empty.
+async fn run_next(state: &AppState) -> Result<bool> {
+    let claimed = sqlx::query_as::<_, (i32, i32, Json<JobPayload>)>(
+        "UPDATE jobs SET state = $1, started_at = NOW(), heartbeat_at = NOW(), attempts = attempts + 1 \
+         WHERE id = (SELECT id FROM jobs WHERE state = $2 ORDER BY id FOR UPDATE SKIP LOCKED LIMIT 1) \
+         RETURNING id, user_id, payload",
+    )
+    .bind(RUNNING)
+    .bind(QUEUED)
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some((job_id, user_id, Json(payload))) = claimed else {
+        return Ok(false);
+    };
+    run_claimed(state, job_id, user_id, payload).await?;
+    Ok(true)
+}
+
+/// Claim one particular queued job and run it in this process, for the CLI.
+/// Returns false if it was not queued, e.g. because a running panel's worker
+/// picked it up first.
+pub async fn run_job(state: &AppState, job_id: i32) -> Result<bool> {
+    let claimed = sqlx::query_as::<_, (i32, Json<JobPayload>)>(
+        "UPDATE jobs SET state = $1, started_at = NOW(), heartbeat_at = NOW(), attempts = attempts + 1 \
+         WHERE id = (SELECT id FROM jobs WHERE id = $2 AND state = $3 FOR UPDATE SKIP LOCKED) \
+         RETURNING user_id, payload",
+    )
+    .bind(RUNNING)
+    .bind(job_id)
+    .bind(QUEUED)
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some((user_id, Json(payload))) = claimed else {
+        return Ok(false);
+    };
+    run_claimed(state, job_id, user_id, payload).await?;
+    Ok(true)
+}
+
+async fn run_claimed(state: &AppState, job_id: i32, user_id: i32, payload: JobPayload) -> Result<()> {
+    tracing::info!("job {}: {}", job_id, payload.summary());
+    state.jobs.announce(job_id);
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let writer = tokio::spawn(store_log_lines(state.clone(), job_id, rx));
+    let sink = LogSink::new(tx);
+    let heartbeat = tokio::spawn(beat(state.pool.clone(), job_id));
+    let (output, exit_status, result) = execute(state, job_id, user_id, &payload, &sink).await;
+    heartbeat.abort();
+    drop(sink);
+    let _ = writer.await;
+
+    let (job_state, error, site_id, stored) = match result {
+        Ok(site_id) => (SUCCEEDED, None, site_id, payload.redacted()),
+        Err(msg) => {
+            tracing::warn!("job {} failed: {}", job_id, msg);
+            (FAILED, Some(msg), None, payload)
+        }
+    };
+    sqlx::query(
+        "UPDATE jobs SET state = $1, stdout = $2, stderr = $3, error = $4, payload = $5, \
+         site_id = COALESCE($6, site_id), finished_at = NOW() WHERE id = $7",
+    )
+    .bind(job_state)
+    .bind(&output.stdout)
+    .bind(&output.stderr)
+    .bind(&error)
+    .bind(Json(&stored))
+    .bind(site_id)
+    .bind(job_id)
+    .execute(&state.pool)
+    .await?;
+    audit::finish_job(&state.pool, job_id, job_state, exit_status, error.as_deref()).await?;
+    state.jobs.announce(job_id);
+    Ok(())
+}
+
+/// Refresh a running job's heartbeat until aborted.
+async fn beat(pool: DbPool, job_id: i32) {
+    let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let r = sqlx::query("UPDATE jobs SET heartbeat_at = NOW() WHERE id = $1 AND state = $2")
+            .bind(job_id)
+            .bind(RUNNING)
+            .execute(&pool)
+            .await;
+        if let Err(e) = r {
+            tracing::warn!("job {}: could not record heartbeat: {}", job_id, e);
+        }
+    }
+}
+
+/// Persist output lines as they arrive and tell listeners about each one.
+async fn store_log_lines(state: AppState, job_id: i32, mut rx: mpsc::UnboundedReceiver<LogLine>) {
+    let mut seq = 0;
+    while let Some(line) = rx.recv().await {
+        seq += 1;
+        let stored = sqlx::query(
+            "INSERT INTO job_log_lines (job_id, seq, stream, line) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(job_id)
+        .bind(seq)
+        .bind(line.stream.as_str())
+        .bind(&line.line)
+        .execute(&state.pool)
+        .await;
+        if let Err(e) = stored {
+            tracing::warn!("job {}: could not store output line: {}", job_id, e);
+        }
+        state.jobs.announce(job_id);
+    }
+}
+
+/// Run the provisioning step and the matching panel DB change.
+/// On success returns the site the job ended up being about (for newly created sites).
+/// Also returns the provisioning step's exit status: 0 when it succeeded, `None`
+/// if it never ran or ended without one.
+async fn execute(
+    state: &AppState,
+    job_id: i32,
+    user_id: i32,
+    payload: &JobPayload,
+    log: &LogSink,
+) -> (
+    ProvisionOutput,
+    Option<i32>,
+    std::result::Result<Option<i32>, String>,
+) {
+    let provisioned = match payload {
+        JobPayload::CreateSite { site } => {
+            let config = SiteCaddyConfig::for_new_site(site);
+            let files = state.provisioner.create_site(site, log).await;
+            then(files, || state.provisioner.apply_site_config(&config, log)).await
+        }
+        JobPayload::DeleteSite {
+            domain,
+            folder_path,
+            databases,
+            ..
+        } => {
+            // Stop serving the domain before its files disappear.
+            let config = state.provisioner.remove_site_config(domain, log).await;
+            then(config, || {
+                state
+                    .provisioner
+                    .delete_site(domain, folder_path, databases, log)
+            })
+            .await
+        }
+        JobPayload::CreateDatabase { database, .. } => {
+            state.provisioner.create_database(database, log).await
+        }
+        JobPayload::DeleteDatabase { database, .. } => {
+            state.provisioner.delete_database(database, log).await
+        }
+        JobPayload::BackupSite { site_id, backup } => {
+            if let Err(msg) = create_archive_dir(&backup.archive_path).await {
+                return (ProvisionOutput::default(), None, Err(msg));
+            }
+            let written = state.provisioner.backup_site(backup, log).await;
+            // A failed upload is only a warning: the bundle is good here.
+            let uploaded = then(written, || async {
+                Ok(backup::upload(state, *site_id, backup, log).await)
+            })
+            .await;
+            then(uploaded, || backup::deduplicate(state, backup, log)).await
+        }
+        JobPayload::RestoreSite {
+            site_id,
+            snapshot,
+            restore,
+        } => {
+            let snapshot = snapshot_before_restore(state, job_id, *site_id, snapshot, log).await;
+            let bundle = then(snapshot, || backup::assemble(state, &restore.archive_path, log)).await;
+            let restored = then(bundle, || state.provisioner.restore_site(restore, log)).await;
+            backup::discard_assembled(&restore.archive_path).await;
+            restored
+        }
+        JobPayload::RestoreNewSite {
+            site,
+            databases,
+            restore,
+        } => {
+            let bundle = backup::assemble(state, &restore.archive_path, log).await;
+            let mut provisioned = then(bundle, || state.provisioner.create_site(site, log)).await;
+            for db in databases {
+                provisioned =
+                    then(provisioned, || state.provisioner.create_database(db, log)).await;
+            }
+            let restored =
+                then(provisioned, || state.provisioner.restore_site(restore, log)).await;
+            backup::discard_assembled(&restore.archive_path).await;
+            let config = SiteCaddyConfig::for_new_site(site);
+            then(restored, || state.provisioner.apply_site_config(&config, log)).await
+        }
+        JobPayload::DownloadBackup {
+            key, archive_path, ..
+        } => {
+            if let Err(msg) = create_archive_dir(archive_path).await {
+                return (ProvisionOutput::default(), None, Err(msg));
+            }
+            backup::download(state, key, archive_path, log).await
+        }
+        JobPayload::VerifyBackup {
+            backup_id, check, ..
+        } => {
+            let bundle = backup::assemble(state, &check.archive_path, log).await;
+            let verified = then(bundle, || state.provisioner.verify_backup(check, log)).await;
+            backup::discard_assembled(&check.archive_path).await;
+            backup::verified(state, *backup_id, job_id, &verified).await;
+            verified
+        }
+    };
+    let output = match provisioned {
+        Ok(output) => output,
+        Err(ProvisionError::Failed { status, output }) => {
+            let msg = match status {
+                Some(code) => format!(
+                    "Provisioning failed with exit status {}. See output below.",
+                    code
+                ),
+                None => "Provisioning failed. See output below.".to_string(),
+            };
+            return (output, status, Err(msg));
+        }
+        Err(e @ ProvisionError::Spawn { .. }) => {
+            return (
+                ProvisionOutput::default(),
+                None,
+                Err(format!(
+                    "{}. Check PANEL_PROVISIONER and that the panel user can run the provisioning scripts with sudo.",
+                    e
+                )),
+            );
+        }
+    };
+    let recorded = record(state, user_id, payload).await;
+    let mut output = output;
+    if let (Ok(recorded), JobPayload::RestoreSite { .. } | JobPayload::RestoreNewSite { .. }) =
+        (&recorded, payload)
+    {
+        if let Some(site_id) = recorded.or(payload.site_id()) {
+            output.append(backup::verify_restore(state, site_id, log).await);
+        }
+    }
+    (output, Some(0), recorded)
+}
+
+/// Snapshot a site before a restore overwrites it. A retried restore keeps
+/// the first attempt's snapshot: the site may be half restored since.
+async fn snapshot_before_restore(
+    state: &AppState,
+    job_id: i32,
+    site_id: i32,
+    snapshot: &NewBackup,
+    log: &LogSink,
+) -> std::result::Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    let failed = |transcript: Transcript<'_>| ProvisionError::Failed {
+        status: None,
+        output: transcript.into_output(),
+    };
+    match backup::snapshot_recorded(&state.pool, &snapshot.archive_path).await {
+        Ok(true) => {
+            transcript.push_stdout(format!(
+                "Keeping the snapshot taken by the first attempt: {}",
+                snapshot.archive_path
+            ));
+            return Ok(transcript.into_output());
+        }
+        Ok(false) => {}
+        Err(e) => {
+            transcript.push_stderr(format!("Error: {}", e));
+            return Err(failed(transcript));
+        }
+    }
+    if let Err(msg) = create_archive_dir(&snapshot.archive_path).await {
+        transcript.push_stderr(format!("Error: {}", msg));
+        return Err(failed(transcript));
+    }
+    let mut output = state.provisioner.backup_site(snapshot, log).await?;
+    if let Err(e) = backup::record_snapshot(&state.pool, site_id, job_id, snapshot).await {
+        transcript.push_stderr(format!("Error: could not record the snapshot: {}", e));
+        output.append(transcript.into_output());
+        return Err(ProvisionError::Failed {
+            status: None,
+            output,
+        });
+    }
+    Ok(output)
+}
+
+/// Create the directory a bundle is written to. Done by the panel so the
+/// domain's directory belongs to the panel user, which prunes old bundles,
+/// even when a script writes them as root.
+async fn create_archive_dir(archive_path: &str) -> std::result::Result<(), String> {
+    let Some(dir) = std::path::Path::new(archive_path).parent() else {
+        return Ok(());
+    };
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|e| format!("Could not create {}: {}", dir.display(), e))
+}
+
+/// Run `next` if `first` succeeded, keeping the output of both steps.
+async fn then<F, Fut>(
+    first: std::result::Result<ProvisionOutput, ProvisionError>,
+    next: F,
+) -> std::result::Result<ProvisionOutput, ProvisionError>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<ProvisionOutput, ProvisionError>>,
+{
+    let mut output = first?;
+    match next().await {
+        Ok(more) => {
+            output.append(more);
+            Ok(output)
+        }
+        Err(ProvisionError::Failed {
+            status,
+            output: more,
+        }) => {
+            output.append(more);
+            Err(ProvisionError::Failed { status, output })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Apply the job to the panel's own tables once the host side is done.
+async fn record(
+    state: &AppState,
+    user_id: i32,
+    payload: &JobPayload,
+) -> std::result::Result<Option<i32>, String> {
+    let pool = &state.pool;
+    let unique_msg = |e: sqlx::Error, msg: &str| match e {
+        sqlx::Error::Database(ref db) if db.is_unique_violation() => msg.to_string(),
+        e => format!("Database error: {}", e),
+    };
+    match payload {
+        JobPayload::CreateSite { site } => sqlx::query_scalar::<_, i32>(
+            "INSERT INTO sites (domain, folder_path, wordpress_installed, user_id, php_version) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        )
+        .bind(&site.domain)
+        .bind(&site.folder_path)
+        .bind(site.wordpress.is_some())
+        .bind(user_id)
+        .bind(&site.php_version)
+        .fetch_one(pool)
+        .await
+        .map(Some)
+        .map_err(|e| unique_msg(e, "A site with this domain or path already exists.")),
+        JobPayload::DeleteSite { site_id, .. } => {
+            sqlx::query("DELETE FROM site_databases WHERE site_id = $1")
+                .bind(site_id)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+            let tls = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+                "DELETE FROM sites WHERE id = $1 RETURNING tls_cert_path, tls_key_path",
+            )
+            .bind(site_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+            // The snippet is gone, so an uploaded certificate is no longer used.
+            if let Some((Some(cert), Some(key))) = tls {
+                TlsStore::from_config(&state.config)
+                    .remove(&TlsFiles { cert, key })
+                    .await;
+            }
+            Ok(None)
+        }
+        JobPayload::CreateDatabase { site_id, database } => sqlx::query(
+            "INSERT INTO site_databases (site_id, db_name, db_user, privileges) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(site_id)
+        .bind(&database.db_name)
+        .bind(&database.db_user)
+        .bind(&database.privileges)
+        .execute(pool)
+        .await
+        .map(|_| None)
+        .map_err(|e| unique_msg(e, "This database name or user already exists for this site.")),
+        JobPayload::DeleteDatabase { database_id, .. } => {
+            sqlx::query("DELETE FROM site_databases WHERE id = $1")
+                .bind(database_id)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+            Ok(None)
+        }
+        JobPayload::BackupSite { site_id, backup } => {
+            // The bundle is written; failing to prune older ones is not this job's failure.
+            if let Err(e) = backup::written(state, *site_id, &backup.archive_path).await {
+                tracing::warn!("site {}: could not apply backup retention: {}", site_id, e);
+            }
+            Ok(None)
+        }
+        JobPayload::RestoreSite { .. } => Ok(None),
+        JobPayload::RestoreNewSite {
+            site,
+            databases,
+            restore,
+        } => {
+            let db_err =
+                |e: sqlx::Error| unique_msg(e, "A site with this domain or path already exists.");
+            let mut tx = pool.begin().await.map_err(db_err)?;
+            let site_id = sqlx::query_scalar::<_, i32>(
+                "INSERT INTO sites (domain, folder_path, wordpress_installed, user_id, php_version) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            )
+            .bind(&site.domain)
+            .bind(&site.folder_path)
+            .bind(restore.wp_config.is_some())
+            .bind(user_id)
+            .bind(&site.php_version)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_err)?;
+            for db in databases {
+                sqlx::query(
+                    "INSERT INTO site_databases (site_id, db_name, db_user, privileges) VALUES ($1, $2, $3, $4)",
+                )
+                .bind(site_id)
+                .bind(&db.db_name)
+                .bind(&db.db_user)
+                .bind(&db.privileges)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+            }
+            tx.commit().await.map_err(|e| format!("Database error: {}", e))?;
+            Ok(Some(site_id))
+        }
+        JobPayload::DownloadBackup { archive_path, .. } => {
+            backup::downloaded(pool, archive_path)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+            Ok(None)
+        }
+        // The outcome went on the backup whether the test restore passed or not.
+        JobPayload::VerifyBackup { .. } => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provision::NewDatabase;
+    use crate::test_support::TestPanel;
+
+    async fn queue_database(panel: &TestPanel, db_name: &str) -> i32 {
+        let site_id: i32 = sqlx::query_scalar(
+            "INSERT INTO sites (domain, folder_path, user_id) VALUES ($1, $2, $3) RETURNING id",
+        )
+        .bind(format!("{}.test", db_name))
+        .bind(format!("/var/www/{}.test", db_name))
+        .bind(panel.admin_id)
+        .fetch_one(&panel.state.pool)
+        .await
+        .unwrap();
+        panel
+            .state
+            .jobs
+            .enqueue(
+                &panel.state.pool,
+                panel.admin_id,
+                JobPayload::CreateDatabase {
+                    site_id,
+                    database: NewDatabase {
+                        db_name: db_name.to_string(),
+                        db_user: db_name.to_string(),
+                        password: "s3cret-db-pass".to_string(),
+                        privileges: "full".to_string(),
+                    },
+                },
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn set(panel: &TestPanel, job_id: i32, state: &str, hours_ago: i32) {
+        sqlx::query(
+            "UPDATE jobs SET state = $1, created_at = NOW() - make_interval(hours => $2), \
+             finished_at = CASE WHEN $1 = 'failed' THEN NOW() - make_interval(hours => $2) END \
+             WHERE id = $3",
+        )
+        .bind(state)
+        .bind(hours_ago)
+        .bind(job_id)
+        .execute(&panel.state.pool)
+        .await
+        .unwrap();
+    }
+
+    /// state, the stored password, and whether the secrets were purged.
+    async fn stored(panel: &TestPanel, job_id: i32) -> (String, String, bool) {
+        let (state, Json(payload), purged) = sqlx::query_as::<_, (String, Json<JobPayload>, bool)>(
+            "SELECT state, payload, secrets_purged_at IS NOT NULL FROM jobs WHERE id = $1",
+        )
+        .bind(job_id)
+        .fetch_one(&panel.state.pool)
+        .await
+        .unwrap();
+        let JobPayload::CreateDatabase { database, .. } = payload else {
+            panic!("not a create_database payload");
+        };
+        (state, database.password, purged)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn purge_discards_old_secrets_only() {
+        let panel = TestPanel::start().await;
+        let old_failed = queue_database(&panel, "old_failed").await;
+        set(&panel, old_failed, FAILED, SECRETS_KEPT_HOURS + 1).await;
+        let recent_failed = queue_database(&panel, "recent_failed").await;
+        set(&panel, recent_failed, FAILED, 1).await;
+        let old_queued = queue_database(&panel, "old_queued").await;
+        set(&panel, old_queued, QUEUED, SECRETS_KEPT_HOURS + 1).await;
+        let recent_queued = queue_database(&panel, "recent_queued").await;
+
+        assert_eq!(purge_secrets(&panel.state.pool).await.unwrap(), 2);
+        assert_eq!(
+            stored(&panel, old_failed).await,
+            (FAILED.to_string(), String::new(), true)
+        );
+        assert_eq!(
+            stored(&panel, old_queued).await,
+            (FAILED.to_string(), String::new(), true)
+        );
+        assert_eq!(
+            stored(&panel, recent_failed).await,
+            (FAILED.to_string(), "s3cret-db-pass".to_string(), false)
+        );
+        assert_eq!(
+            stored(&panel, recent_queued).await,
+            (QUEUED.to_string(), "s3cret-db-pass".to_string(), false)
+        );
+        // Nothing left to do on the next round.
+        assert_eq!(purge_secrets(&panel.state.pool).await.unwrap(), 0);
+
+        let jobs = &panel.state.jobs;
+        assert!(matches!(
+            jobs.retry(&panel.state.pool, old_failed).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(jobs.retry(&panel.state.pool, recent_failed).await.unwrap());
+        assert!(!jobs.retry(&panel.state.pool, recent_queued).await.unwrap());
+        panel.stop().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn abandoned_running_jobs_fail() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        let abandoned = queue_database(&panel, "abandoned").await;
+        let alive = queue_database(&panel, "alive").await;
+        for (job_id, secs_ago) in [(abandoned, STALE_AFTER_SECS + 30.0), (alive, 5.0)] {
+            sqlx::query(
+                "UPDATE jobs SET state = $1, started_at = NOW() - make_interval(secs => 600), \
+                 heartbeat_at = NOW() - make_interval(secs => $2) WHERE id = $3",
+            )
+            .bind(RUNNING)
+            .bind(secs_ago)
+            .bind(job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO audit_events (user_id, via, action, outcome, job_id) VALUES ($1, 'cli', 'database.create', 'queued', $2)",
+            )
+            .bind(panel.admin_id)
+            .bind(job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(fail_stale(pool).await.unwrap(), 1);
+        let outcome = |job_id: i32| async move {
+            sqlx::query_as::<_, (String, String)>(
+                "SELECT j.state, e.outcome FROM jobs j JOIN audit_events e ON e.job_id = j.id WHERE j.id = $1",
+            )
+            .bind(job_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+        };
+        assert_eq!(outcome(abandoned).await, (FAILED.to_string(), FAILED.to_string()));
+        assert_eq!(outcome(alive).await, (RUNNING.to_string(), "queued".to_string()));
+        assert!(panel.state.jobs.retry(pool, abandoned).await.unwrap());
+
+        interrupt(pool, alive, "Interrupted.").await.unwrap();
+        assert_eq!(outcome(alive).await, (FAILED.to_string(), FAILED.to_string()));
+        panel.stop().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn failed_upload_leaves_backup_succeeded() {
+        let mut panel = TestPanel::start().await;
+        // Nothing listens on port 1.
+        panel.state.config.s3 = Some(crate::config::S3Target {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            bucket: "backups".to_string(),
+            prefix: String::new(),
+            region: "us-east-1".to_string(),
+            access_key_id: "key".to_string(),
+            secret_access_key: "secret".to_string(),
+        });
+        let pool = &panel.state.pool;
+        let site_id: i32 = sqlx::query_scalar(
+            "INSERT INTO sites (domain, folder_path, user_id) VALUES ('example.test', '/var/www/example.test', $1) RETURNING id",
+        )
+        .bind(panel.admin_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let job_id = backup::enqueue(&panel.state, site_id, panel.admin_id, false)
+            .await
+            .unwrap();
+        let archive_path: String =
+            sqlx::query_scalar("SELECT archive_path FROM site_backups WHERE job_id = $1")
+                .bind(job_id)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        let folder = std::path::Path::new(&archive_path).parent().unwrap();
+        tokio::fs::create_dir_all(folder).await.unwrap();
+        // What a provisioner that does the work would have written.
+        tokio::fs::write(&archive_path, b"bundle").await.unwrap();
+
+        assert!(run_job(&panel.state, job_id).await.unwrap());
+        let (state, stderr): (String, String) =
+            sqlx::query_as("SELECT state, stderr FROM jobs WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        assert_eq!(state, SUCCEEDED);
+        assert!(stderr.contains("Warning: no off-site copy was made"), "{}", stderr);
+        let (size, remote_key, upload_error): (Option<i64>, Option<String>, Option<String>) =
+            sqlx::query_as(
+                "SELECT size_bytes, remote_key, upload_error FROM site_backups WHERE job_id = $1",
+            )
+            .bind(job_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(size, Some(6));
+        assert_eq!(remote_key, None);
+        assert!(upload_error.unwrap().contains("upload to"));
+        panel.stop().await;
+    }
+}
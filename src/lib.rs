@@ -1,8 +1,23 @@
+pub mod actions;
+pub mod alerts;
+pub mod api;
+pub mod audit;
+pub mod caddy;
+pub mod cli;
 pub mod config;
+pub mod csrf;
 pub mod error;
 pub mod auth;
+pub mod backup;
 pub mod db;
 pub mod handlers;
+pub mod health;
+pub mod jobs;
 pub mod models;
+pub mod provision;
 pub mod state;
 pub mod templates;
+#[cfg(test)]
+mod test_support;
+pub mod throttle;
+pub mod totp;
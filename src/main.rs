@@ -3,7 +3,7 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use frankenphp_panel::{config::Config, db, handlers, state::AppState};
+use frankenphp_panel::{api, audit, backup, caddy::CaddyAdmin, cli, config::Config, db, handlers, health, jobs, provision, state::AppState};
 use tower_http::services::ServeDir;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -19,6 +19,13 @@ async fn main() -> anyhow::Result<()> {
                 return run_set_admin_password(password).await;
             }
             "migrate" => return run_migrate_only().await,
+            "reset-2fa" => {
+                let username = args
+                    .get(2)
+                    .ok_or_else(|| anyhow::anyhow!("Usage: frankenphp-panel reset-2fa <username>"))?;
+                return run_reset_2fa(username).await;
+            }
+            "sites" | "databases" | "users" => return cli::run(&args[1..]).await,
             _ => {}
         }
     }
@@ -30,18 +37,29 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    Config::check_provisioner().map_err(|e| anyhow::anyhow!(e))?;
     let config = Config::from_env();
+    config.check_session_secret().map_err(|e| anyhow::anyhow!(e))?;
     let pool = db::create_pool(&config.database_url).await?;
     db::run_migrations(&pool).await?;
 
+    tracing::info!("Provisioner: {:?}", config.provisioner);
     let state = AppState {
         pool,
+        provisioner: provision::from_config(&config),
+        jobs: jobs::JobQueue::new(),
+        caddy: CaddyAdmin::from_config(&config),
         config: config.clone(),
     };
+    jobs::spawn_worker(state.clone()).await?;
+    jobs::spawn_secret_purge(state.pool.clone());
+    health::spawn_checker(state.clone());
+    backup::spawn_scheduler(state.clone());
+    frankenphp_panel::auth::spawn_session_purge(state.pool.clone());
 
     let public = Router::new()
         .route("/login", get(handlers::get_login).post(handlers::post_login))
-        .route("/logout", post(handlers::logout))
+        .route("/login/2fa", post(handlers::post_login_totp))
         .nest_service("/static", ServeDir::new("static"))
         .with_state(state.clone());
 
@@ -52,19 +70,65 @@ async fn main() -> anyhow::Result<()> {
         .route("/sites/:id", get(handlers::site_detail))
         .route("/sites/:id/restart", post(handlers::restart_site))
         .route("/sites/:id/delete", post(handlers::delete_site))
+        .route("/sites/:id/tls", post(handlers::upload_tls))
+        .route("/sites/:id/tls/acme", post(handlers::use_acme))
+        .route("/sites/:id/aliases", post(handlers::add_alias))
+        .route("/sites/:id/aliases/:alias_id/delete", post(handlers::delete_alias))
+        .route("/sites/:id/canonical", post(handlers::set_canonical_host))
+        .route("/sites/:id/monitoring", post(handlers::update_monitoring))
+        .route("/sites/:id/backups", post(handlers::create_backup))
+        .route("/sites/:id/backups/schedule", post(handlers::update_backup_schedule))
+        .route(
+            "/backups/:id/restore",
+            get(handlers::restore_backup_page).post(handlers::restore_backup),
+        )
+        .route("/backups/:id/delete", post(handlers::delete_backup))
+        .route("/backups/:id/verify", post(handlers::verify_backup))
+        .route("/backups", get(handlers::offsite_backups))
+        .route("/backups/remote/download", post(handlers::download_backup))
         .route("/databases/new", get(handlers::new_database))
         .route("/databases", post(handlers::create_database))
         .route("/databases/:id/delete", post(handlers::delete_database))
+        .route("/jobs", get(handlers::list_jobs))
+        .route("/jobs/:id", get(handlers::job_detail))
+        .route("/jobs/:id/events", get(handlers::job_events))
+        .route("/jobs/:id/retry", post(handlers::retry_job))
+        .route("/users", get(handlers::list_users).post(handlers::create_user))
+        .route("/users/:id/role", post(handlers::update_user_role))
+        .route("/users/:id/disable", post(handlers::disable_user))
+        .route("/users/:id/enable", post(handlers::enable_user))
+        .route("/users/:id/delete", post(handlers::delete_user))
+        .route("/users/lockouts/unlock", post(handlers::unlock_login))
+        .route("/audit", get(handlers::list_audit))
+        .route("/audit.csv", get(handlers::export_audit_csv))
+        .route("/account", get(handlers::account))
+        .route("/account/2fa/setup", post(handlers::setup_totp))
+        .route("/account/2fa/cancel", post(handlers::cancel_totp_setup))
+        .route("/account/2fa/enable", post(handlers::enable_totp))
+        .route("/account/2fa/recovery-codes", post(handlers::regenerate_recovery_codes))
+        .route("/account/2fa/disable", post(handlers::disable_totp))
+        .route("/account/sessions", get(handlers::list_sessions))
+        .route("/account/sessions/:id/revoke", post(handlers::revoke_session))
+        .route("/account/sessions/revoke-all", post(handlers::revoke_all_sessions))
+        .route("/account/api-tokens", get(handlers::list_api_tokens).post(handlers::create_api_token))
+        .route("/account/api-tokens/:id/revoke", post(handlers::revoke_api_token))
+        .route("/logout", post(handlers::logout))
+        .layer(middleware::from_fn(frankenphp_panel::csrf::require_csrf))
         .layer(middleware::from_fn_with_state(state.clone(), frankenphp_panel::auth::require_auth))
         .with_state(state.clone());
 
-    let app = public.merge(private);
+    // Bearer-token auth only; no cookies, so no CSRF check.
+    let api = Router::new()
+        .nest("/api/v1", api::router(state.clone()))
+        .merge(api::openapi::router());
+
+    let app = public.merge(private).merge(api);
 
     let addr = config.bind;
     tracing::info!("Panel listening on http://{}", addr);
     axum::serve(
         tokio::net::TcpListener::bind(addr).await?,
-        app,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
     )
     .await?;
     Ok(())
@@ -82,13 +146,36 @@ async fn run_set_admin_password(password: &str) -> anyhow::Result<()> {
     let config = Config::from_env();
     let pool = db::create_pool(&config.database_url).await?;
     let hash = bcrypt::hash(password, 12).map_err(|e| anyhow::anyhow!("bcrypt: {}", e))?;
-    let rows = sqlx::query("UPDATE users SET password_hash = $1 WHERE username = 'admin'")
-        .bind(&hash)
-        .execute(&pool)
-        .await?;
-    if rows.rows_affected() == 0 {
-        anyhow::bail!("No user 'admin' found. Run migrations first.");
-    }
+    let admin_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE users SET password_hash = $1 WHERE username = 'admin' RETURNING id",
+    )
+    .bind(&hash)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("No user 'admin' found. Run migrations first."))?;
+    audit::Event::new("user.password")
+        .target(audit::Target::user(Some(admin_id), "admin"))
+        .record(&pool, &audit::Actor::cli(None))
+        .await;
     println!("Admin password updated.");
     Ok(())
 }
+
+async fn run_reset_2fa(username: &str) -> anyhow::Result<()> {
+    let config = Config::from_env();
+    let pool = db::create_pool(&config.database_url).await?;
+    let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No user '{}' found.", username))?;
+    handlers::reset_totp(&pool, user_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    audit::Event::new("user.2fa.reset")
+        .target(audit::Target::user(Some(user_id), username))
+        .record(&pool, &audit::Actor::cli(None))
+        .await;
+    println!("Two-factor authentication reset for '{}'. They can sign in with their password and set it up again.", username);
+    Ok(())
+}
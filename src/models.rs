@@ -1,16 +1,255 @@
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use validator::Validate;
 
+/// Columns selected into [`User`].
+pub const USER_COLUMNS: &str =
+    "id, username, password_hash, created_at, role, disabled_at, totp_secret, totp_enabled_at, totp_last_step";
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub password_hash: String,
     pub created_at: DateTime<Utc>,
+    /// admin | operator | readonly
+    pub role: String,
+    /// Set while the account is disabled; disabled users cannot log in.
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// Base32 TOTP secret; set during enrollment, before 2FA is turned on.
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    pub totp_last_step: Option<i64>,
+}
+
+impl User {
+    pub fn role(&self) -> crate::auth::Role {
+        crate::auth::Role::from_db(&self.role)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    pub fn has_totp(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+}
+
+/// Columns selected into [`LoginThrottle`].
+pub const LOGIN_THROTTLE_COLUMNS: &str = "kind, key, failures, last_failure_at, locked_until";
+
+/// Failed logins for one username or client IP (`kind` is `username` or `ip`).
+#[derive(Debug, sqlx::FromRow)]
+pub struct LoginThrottle {
+    pub kind: String,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginThrottle {
+    pub fn kind_display(&self) -> &str {
+        match self.kind.as_str() {
+            "ip" => "IP address",
+            _ => "Username",
+        }
+    }
+
+    pub fn last_failure_display(&self) -> String {
+        self.last_failure_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn locked_until_display(&self) -> String {
+        self.locked_until
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Columns selected into [`Session`].
+pub const SESSION_COLUMNS: &str = "id, created_at, last_seen_at, expires_at, ip, user_agent";
+
+/// A signed-in browser, as listed on the Sessions page. The cookie token is never loaded.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Session {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Client IP of the latest request.
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
 }
 
+impl Session {
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn last_seen_display(&self) -> String {
+        self.last_seen_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn expires_display(&self) -> String {
+        self.expires_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn ip_display(&self) -> &str {
+        self.ip.as_deref().unwrap_or("—")
+    }
+
+    pub fn user_agent_display(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or("")
+    }
+
+    /// A short "Browser on OS" guess from the user agent.
+    pub fn device_display(&self) -> String {
+        let ua = self.user_agent_display();
+        let browser = [
+            ("Edg/", "Edge"),
+            ("OPR/", "Opera"),
+            ("Firefox/", "Firefox"),
+            ("Chrome/", "Chrome"),
+            ("Safari/", "Safari"),
+            ("curl/", "curl"),
+        ]
+        .into_iter()
+        .find(|(needle, _)| ua.contains(needle))
+        .map(|(_, name)| name);
+        let os = [
+            ("Windows", "Windows"),
+            ("iPhone", "iOS"),
+            ("iPad", "iOS"),
+            ("Mac OS X", "macOS"),
+            ("Android", "Android"),
+            ("Linux", "Linux"),
+        ]
+        .into_iter()
+        .find(|(needle, _)| ua.contains(needle))
+        .map(|(_, name)| name);
+        match (browser, os) {
+            (Some(b), Some(o)) => format!("{} on {}", b, o),
+            (Some(b), None) => b.to_string(),
+            (None, Some(o)) => o.to_string(),
+            (None, None) if ua.is_empty() => "Unknown device".to_string(),
+            (None, None) => ua.chars().take(60).collect(),
+        }
+    }
+}
+
+/// Columns selected into [`ApiToken`].
+pub const API_TOKEN_COLUMNS: &str = "id, name, token_prefix, scope, created_at, last_used_at";
+
+/// An API token as listed on the API tokens page. Only a hash of the secret is stored.
 #[derive(Debug, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    /// The first characters of the token, so users can tell their tokens apart.
+    pub token_prefix: String,
+    /// `read` or `write`
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn is_write(&self) -> bool {
+        self.scope == "write"
+    }
+
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn last_used_display(&self) -> String {
+        self.last_used_at
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| "Never".to_string())
+    }
+}
+
+/// Columns selected into [`AuditEvent`].
+pub const AUDIT_EVENT_COLUMNS: &str = "id, created_at, user_id, username, client_ip, via, action, target_type, target_id, target_label, outcome, job_id, exit_status, detail";
+
+/// One entry in the audit log; see [`crate::audit`].
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub user_id: Option<i32>,
+    /// The actor's username when the event was recorded.
+    pub username: Option<String>,
+    pub client_ip: Option<String>,
+    /// web | api | cli
+    pub via: String,
+    /// e.g. `site.delete`, `user.disable`
+    pub action: String,
+    /// site | database | user | job | api_token | session
+    pub target_type: Option<String>,
+    pub target_id: Option<i64>,
+    /// Domain, database name, username, … at the time of the event.
+    pub target_label: String,
+    /// succeeded | failed | queued (a job that has not finished yet)
+    pub outcome: String,
+    pub job_id: Option<i32>,
+    /// Exit status of the provisioning step, for job-backed events.
+    pub exit_status: Option<i32>,
+    pub detail: String,
+}
+
+impl AuditEvent {
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn username_display(&self) -> &str {
+        self.username.as_deref().unwrap_or("—")
+    }
+
+    pub fn client_ip_display(&self) -> &str {
+        self.client_ip.as_deref().unwrap_or("—")
+    }
+
+    /// e.g. "site example.com", or "—" for actions without a target.
+    pub fn target_display(&self) -> String {
+        match (&self.target_type, self.target_label.is_empty()) {
+            (Some(kind), false) => format!("{} {}", kind.replace('_', " "), self.target_label),
+            (Some(kind), true) => match self.target_id {
+                Some(id) => format!("{} #{}", kind.replace('_', " "), id),
+                None => kind.replace('_', " "),
+            },
+            (None, _) => "—".to_string(),
+        }
+    }
+
+    /// Link to the target's page, for targets that have one (it may be gone since).
+    pub fn target_href(&self) -> Option<String> {
+        match (self.target_type.as_deref(), self.target_id) {
+            (Some("site"), Some(id)) => Some(format!("/sites/{}", id)),
+            (Some("job"), Some(id)) => Some(format!("/jobs/{}", id)),
+            _ => None,
+        }
+    }
+
+    pub fn exit_status_display(&self) -> String {
+        self.exit_status
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "—".to_string())
+    }
+}
+
+/// Columns selected into [`Site`].
+pub const SITE_COLUMNS: &str = "id, domain, folder_path, wordpress_installed, user_id, created_at, php_version, last_restarted_at, tls_cert_path, tls_key_path, canonical_host, check_interval, check_expected_status, check_keyword, backup_frequency, backup_hour, backup_keep_daily, backup_keep_weekly";
+
+#[derive(Debug, Serialize, ToSchema, sqlx::FromRow)]
 pub struct Site {
     pub id: i32,
     pub domain: String,
@@ -19,6 +258,276 @@ pub struct Site {
     pub user_id: i32,
     pub created_at: DateTime<Utc>,
     pub php_version: String,
+    pub last_restarted_at: Option<DateTime<Utc>>,
+    /// Uploaded certificate chain and key; `None` means Caddy manages the certificate via ACME.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// `domain` (serve the domain as entered), `www` or `apex`; the other variant redirects.
+    pub canonical_host: String,
+    /// Seconds between health checks; `None` uses HEALTH_CHECK_INTERVAL.
+    pub check_interval: Option<i32>,
+    /// Status code a check must get; `None` accepts anything below 500.
+    pub check_expected_status: Option<i32>,
+    /// Text the response body must contain for the check to pass.
+    pub check_keyword: Option<String>,
+    /// `off`, `daily` or `weekly` (Sundays).
+    pub backup_frequency: String,
+    /// Hour of the day (UTC) scheduled backups start.
+    pub backup_hour: i32,
+    /// Days for which the newest backup is kept.
+    pub backup_keep_daily: i32,
+    /// Weeks for which the newest backup is kept.
+    pub backup_keep_weekly: i32,
+}
+
+impl Site {
+    /// The domain without a leading `www.`.
+    pub fn apex_host(&self) -> &str {
+        self.domain.strip_prefix("www.").unwrap_or(&self.domain)
+    }
+
+    pub fn www_host(&self) -> String {
+        format!("www.{}", self.apex_host())
+    }
+
+    /// The host the site is served on; every other name redirects here.
+    pub fn primary_host(&self) -> String {
+        match self.canonical_host.as_str() {
+            "www" => self.www_host(),
+            "apex" => self.apex_host().to_string(),
+            _ => self.domain.clone(),
+        }
+    }
+
+    /// The www/apex counterpart that redirects to [`Site::primary_host`], if a policy is set.
+    pub fn redirected_host(&self) -> Option<String> {
+        match self.canonical_host.as_str() {
+            "www" => Some(self.apex_host().to_string()),
+            "apex" => Some(self.www_host()),
+            _ => None,
+        }
+    }
+
+    pub fn has_custom_tls(&self) -> bool {
+        self.tls_cert_path.is_some() && self.tls_key_path.is_some()
+    }
+
+    /// Form value for the check interval (empty = default).
+    pub fn check_interval_value(&self) -> String {
+        self.check_interval.map(|s| s.to_string()).unwrap_or_default()
+    }
+
+    pub fn check_expected_status_value(&self) -> String {
+        self.check_expected_status
+            .map(|s| s.to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn check_keyword_value(&self) -> &str {
+        self.check_keyword.as_deref().unwrap_or("")
+    }
+
+    pub fn backup_schedule_display(&self) -> String {
+        match self.backup_frequency.as_str() {
+            "daily" => format!("Daily at {:02}:00 UTC", self.backup_hour),
+            "weekly" => format!("Sundays at {:02}:00 UTC", self.backup_hour),
+            _ => "Off".to_string(),
+        }
+    }
+
+    pub fn last_restarted_display(&self) -> String {
+        self.last_restarted_at
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| "Never".to_string())
+    }
+}
+
+pub const SITE_CHECK_COLUMNS: &str =
+    "id, site_id, checked_at, status, status_code, latency_ms, tls_error, error";
+
+/// One health check result (see `crate::health`).
+#[derive(Debug, sqlx::FromRow)]
+pub struct SiteCheck {
+    pub id: i64,
+    pub site_id: i32,
+    pub checked_at: DateTime<Utc>,
+    /// online | degraded | offline
+    pub status: String,
+    pub status_code: Option<i32>,
+    pub latency_ms: Option<i32>,
+    pub tls_error: Option<String>,
+    pub error: Option<String>,
+}
+
+impl SiteCheck {
+    pub fn checked_display(&self) -> String {
+        self.checked_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn status_code_display(&self) -> String {
+        self.status_code
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "—".to_string())
+    }
+
+    pub fn latency_display(&self) -> String {
+        self.latency_ms
+            .map(|ms| format!("{} ms", ms))
+            .unwrap_or_else(|| "—".to_string())
+    }
+}
+
+pub const SITE_ALIAS_COLUMNS: &str = "id, site_id, hostname, mode, redirect_status, created_at";
+
+#[derive(Debug, Serialize, ToSchema, sqlx::FromRow)]
+pub struct SiteAlias {
+    pub id: i32,
+    pub site_id: i32,
+    pub hostname: String,
+    /// `serve` or `redirect`
+    pub mode: String,
+    /// 301 or 308; only used when `mode` is `redirect`
+    pub redirect_status: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SiteAlias {
+    pub fn is_redirect(&self) -> bool {
+        self.mode == "redirect"
+    }
+
+    pub fn mode_display(&self) -> String {
+        if self.is_redirect() {
+            format!("Redirect ({})", self.redirect_status)
+        } else {
+            "Serve site".to_string()
+        }
+    }
+}
+
+/// A byte count for display, e.g. "12.3 MB".
+pub fn bytes_display(bytes: i64) -> String {
+    let bytes = bytes as f64;
+    if bytes >= 1024.0 * 1024.0 * 1024.0 {
+        format!("{:.1} GB", bytes / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1} MB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1} KB", bytes / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Columns selected into [`SiteBackup`]; the query joins `jobs j` on `b.job_id`.
+/// A snapshot's row only exists once it is written, whatever became of its restore.
+pub const SITE_BACKUP_COLUMNS: &str = "b.id, b.site_id, b.domain, b.job_id, b.scheduled, b.before_restore, b.downloaded, b.archive_path, b.size_bytes, b.created_at, \
+    b.stored_bytes, b.remote_key, b.sha256, b.upload_error, CASE WHEN b.before_restore THEN 'succeeded' ELSE j.state END AS state, j.started_at, j.finished_at, \
+    b.verify_job_id, (SELECT vj.state FROM jobs vj WHERE vj.id = b.verify_job_id) AS verify_job_state, b.verified_at, b.verify_passed, b.verify_message";
+
+/// One backup bundle (see `crate::backup`), with the state of the job writing it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SiteBackup {
+    pub id: i32,
+    /// `None` once the site is deleted.
+    pub site_id: Option<i32>,
+    pub domain: String,
+    pub job_id: i32,
+    /// Started by the schedule rather than "Back up now".
+    pub scheduled: bool,
+    /// Taken by a restore before it overwrote the site; its job is the restore.
+    pub before_restore: bool,
+    /// Fetched from off-site storage; its job is the download.
+    pub downloaded: bool,
+    pub archive_path: String,
+    /// Set when the bundle has been written.
+    pub size_bytes: Option<i64>,
+    /// Set when the bundle went into the chunk store: what its new chunks take.
+    pub stored_bytes: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    /// Key of the off-site copy, once uploaded and checked.
+    pub remote_key: Option<String>,
+    /// SHA-256 of the bundle, hex.
+    pub sha256: Option<String>,
+    /// Why the off-site copy could not be made.
+    pub upload_error: Option<String>,
+    /// queued | running | succeeded | failed
+    pub state: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Job of the latest test restore.
+    pub verify_job_id: Option<i32>,
+    pub verify_job_state: Option<String>,
+    /// Set when a test restore has run; the outcome is in `verify_passed`.
+    pub verified_at: Option<DateTime<Utc>>,
+    pub verify_passed: Option<bool>,
+    /// What failed, for a test restore that did not pass.
+    pub verify_message: Option<String>,
+}
+
+impl SiteBackup {
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.archive_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.archive_path)
+    }
+
+    pub fn size_display(&self) -> String {
+        self.size_bytes
+            .map(bytes_display)
+            .unwrap_or_else(|| "—".to_string())
+    }
+
+    /// Disk taken by a deduplicated backup's new chunks, e.g. "1.2 MB new".
+    pub fn stored_display(&self) -> Option<String> {
+        self.stored_bytes.map(|b| format!("{} new", bytes_display(b)))
+    }
+
+    pub fn trigger_display(&self) -> &'static str {
+        if self.before_restore {
+            "Before restore"
+        } else if self.downloaded {
+            "Downloaded"
+        } else if self.scheduled {
+            "Scheduled"
+        } else {
+            "Manual"
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == "succeeded" || self.state == "failed"
+    }
+
+    /// A test restore is queued or running.
+    pub fn is_verifying(&self) -> bool {
+        matches!(self.verify_job_state.as_deref(), Some("queued" | "running"))
+    }
+
+    pub fn verified_display(&self) -> String {
+        self.verified_at
+            .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn duration_display(&self) -> String {
+        let Some(started) = self.started_at.filter(|_| !self.before_restore) else {
+            return "—".to_string();
+        };
+        let secs = (self.finished_at.unwrap_or_else(Utc::now) - started)
+            .num_seconds()
+            .max(0);
+        if secs >= 60 {
+            format!("{}m {:02}s", secs / 60, secs % 60)
+        } else {
+            format!("{}s", secs)
+        }
+    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -33,7 +542,19 @@ pub struct SiteWithStatus {
     pub php_version: String,
 }
 
-#[derive(Debug, sqlx::FromRow)]
+/// A site with its databases and aliases.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SiteDetail {
+    #[serde(flatten)]
+    pub site: Site,
+    pub databases: Vec<SiteDatabase>,
+    pub aliases: Vec<SiteAlias>,
+}
+
+/// Columns selected into [`SiteDatabase`].
+pub const SITE_DATABASE_COLUMNS: &str = "id, site_id, db_name, db_user, privileges, created_at";
+
+#[derive(Debug, Serialize, ToSchema, sqlx::FromRow)]
 pub struct SiteDatabase {
     pub id: i32,
     pub site_id: i32,
@@ -43,14 +564,93 @@ pub struct SiteDatabase {
     pub created_at: DateTime<Utc>,
 }
 
+/// Columns selected into [`Job`].
+pub const JOB_COLUMNS: &str = "id, kind, summary, state, user_id, site_id, stdout, stderr, error, attempts, created_at, started_at, finished_at, secrets_purged_at";
+
+#[derive(Debug, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub summary: String,
+    /// queued | running | succeeded | failed
+    pub state: String,
+    pub user_id: i32,
+    pub site_id: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When the passwords it was queued with were discarded; it cannot be retried since.
+    pub secrets_purged_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        self.state == "succeeded" || self.state == "failed"
+    }
+
+    /// Run time so far (or in total once finished), e.g. "1m 05s"; "—" if not started.
+    pub fn duration_display(&self) -> String {
+        let Some(started) = self.started_at else {
+            return "—".to_string();
+        };
+        let secs = (self.finished_at.unwrap_or_else(Utc::now) - started)
+            .num_seconds()
+            .max(0);
+        if secs >= 60 {
+            format!("{}m {:02}s", secs / 60, secs % 60)
+        } else {
+            format!("{}s", secs)
+        }
+    }
+
+    pub fn created_display(&self) -> String {
+        self.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+}
+
+/// One stored line of a job's provisioning output.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct JobLogLine {
+    pub seq: i32,
+    /// stdout | stderr
+    pub stream: String,
+    pub line: String,
+    pub logged_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct LoginForm {
+    /// Must match the `panel_login_csrf` cookie.
+    #[serde(default)]
+    pub csrf: String,
     #[validate(length(min = 1, message = "Username is required"))]
     pub username: String,
     #[validate(length(min = 1, message = "Password is required"))]
     pub password: String,
 }
 
+/// Second login step: the challenge from the password step plus a TOTP or recovery code.
+#[derive(Debug, Deserialize)]
+pub struct LoginTotpForm {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeForm {
+    pub code: String,
+}
+
+/// Re-entering the password before turning 2FA off or replacing recovery codes.
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPasswordForm {
+    pub password: String,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct AddSiteForm {
     #[validate(length(min = 1, message = "Domain is required"))]
@@ -68,6 +668,91 @@ pub struct AddSiteForm {
     pub wp_admin_email: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CreateDatabaseForm {
+    pub site_id: String,
+    pub database_name: String,
+    pub username: String,
+    pub password: String,
+    /// full | readonly; anything else means full
+    pub privileges: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddAliasForm {
+    pub hostname: String,
+    /// serve | 301 | 308
+    pub mode: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CanonicalHostForm {
+    /// domain | www | apex
+    pub canonical_host: String,
+}
+
+/// Backup schedule and retention from the Backups tab.
+#[derive(Debug, Deserialize)]
+pub struct BackupScheduleForm {
+    /// off | daily | weekly
+    pub backup_frequency: String,
+    pub backup_hour: String,
+    pub backup_keep_daily: String,
+    pub backup_keep_weekly: String,
+}
+
+/// Restore confirmation form.
+#[derive(Debug, Deserialize)]
+pub struct RestoreBackupForm {
+    /// in_place | new_site
+    pub target: String,
+    /// Domain of the new site when `target` is `new_site`.
+    #[serde(default)]
+    pub domain: String,
+}
+
+/// Download of a bundle from off-site storage.
+#[derive(Debug, Deserialize)]
+pub struct DownloadBackupForm {
+    /// Object key in the bucket
+    pub key: String,
+}
+
+/// Health check settings from the Monitoring tab; empty fields reset to the default.
+#[derive(Debug, Deserialize)]
+pub struct MonitoringForm {
+    pub check_interval: String,
+    pub check_expected_status: String,
+    pub check_keyword: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddUserForm {
+    pub username: String,
+    pub password: String,
+    /// admin | operator | readonly
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRoleForm {
+    pub role: String,
+}
+
+/// Lift a login lockout from the users page.
+#[derive(Debug, Deserialize)]
+pub struct UnlockForm {
+    pub kind: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiTokenForm {
+    pub name: String,
+    /// read | write
+    pub scope: String,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct AddDatabaseForm {
     #[validate(length(min = 1, max = 64, message = "Database name: 1–64 characters"))]
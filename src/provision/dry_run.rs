@@ -0,0 +1,221 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{
+    BackupVerification, DatabaseRef, LogSink, NewBackup, NewDatabase, NewSite, ProvisionError,
+    ProvisionOutput, Provisioner, SiteRestore, Transcript,
+};
+use crate::caddy::SiteCaddyConfig;
+
+/// One recorded call to a [`DryRunProvisioner`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProvisionOp {
+    CreateSite {
+        domain: String,
+        folder_path: String,
+        php_version: String,
+        wordpress: bool,
+    },
+    DeleteSite {
+        domain: String,
+        folder_path: String,
+        databases: Vec<String>,
+    },
+    CreateDatabase {
+        db_name: String,
+        db_user: String,
+        privileges: String,
+    },
+    DeleteDatabase {
+        db_name: String,
+        db_user: String,
+    },
+    ApplySiteConfig {
+        domain: String,
+        snippet: String,
+    },
+    RemoveSiteConfig {
+        domain: String,
+    },
+    BackupSite {
+        domain: String,
+        archive_path: String,
+        databases: Vec<String>,
+    },
+    RestoreSite {
+        domain: String,
+        archive_path: String,
+        folder_path: String,
+        databases: Vec<String>,
+    },
+    VerifyBackup {
+        archive_path: String,
+        databases: Vec<String>,
+        wordpress: bool,
+    },
+}
+
+/// Records what would have been done and always succeeds. Lets the panel run
+/// without root (dev laptops) and lets tests assert on handler flows.
+#[derive(Default)]
+pub struct DryRunProvisioner {
+    ops: Mutex<Vec<ProvisionOp>>,
+}
+
+impl DryRunProvisioner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All operations recorded so far, oldest first.
+    pub fn operations(&self) -> Vec<ProvisionOp> {
+        self.ops.lock().unwrap().clone()
+    }
+
+    fn record(&self, op: ProvisionOp, log: &LogSink) -> ProvisionOutput {
+        let mut transcript = Transcript::new(log);
+        transcript.push_stdout(format!("dry-run: {:?}", op));
+        tracing::info!("dry-run provisioner: {:?}", op);
+        self.ops.lock().unwrap().push(op);
+        transcript.into_output()
+    }
+}
+
+#[async_trait]
+impl Provisioner for DryRunProvisioner {
+    async fn create_site(
+        &self,
+        site: &NewSite,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::CreateSite {
+                domain: site.domain.clone(),
+                folder_path: site.folder_path.clone(),
+                php_version: site.php_version.clone(),
+                wordpress: site.wordpress.is_some(),
+            },
+            log,
+        ))
+    }
+
+    async fn delete_site(
+        &self,
+        domain: &str,
+        folder_path: &str,
+        databases: &[DatabaseRef],
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::DeleteSite {
+                domain: domain.to_string(),
+                folder_path: folder_path.to_string(),
+                databases: databases.iter().map(|d| d.db_name.clone()).collect(),
+            },
+            log,
+        ))
+    }
+
+    async fn create_database(
+        &self,
+        db: &NewDatabase,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::CreateDatabase {
+                db_name: db.db_name.clone(),
+                db_user: db.db_user.clone(),
+                privileges: db.privileges.clone(),
+            },
+            log,
+        ))
+    }
+
+    async fn delete_database(
+        &self,
+        db: &DatabaseRef,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::DeleteDatabase {
+                db_name: db.db_name.clone(),
+                db_user: db.db_user.clone(),
+            },
+            log,
+        ))
+    }
+
+    async fn apply_site_config(
+        &self,
+        config: &SiteCaddyConfig,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::ApplySiteConfig {
+                domain: config.domain.clone(),
+                snippet: config.render(),
+            },
+            log,
+        ))
+    }
+
+    async fn remove_site_config(
+        &self,
+        domain: &str,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::RemoveSiteConfig {
+                domain: domain.to_string(),
+            },
+            log,
+        ))
+    }
+
+    async fn backup_site(
+        &self,
+        backup: &NewBackup,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::BackupSite {
+                domain: backup.domain.clone(),
+                archive_path: backup.archive_path.clone(),
+                databases: backup.databases.iter().map(|d| d.db_name.clone()).collect(),
+            },
+            log,
+        ))
+    }
+
+    async fn restore_site(
+        &self,
+        restore: &SiteRestore,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::RestoreSite {
+                domain: restore.domain.clone(),
+                archive_path: restore.archive_path.clone(),
+                folder_path: restore.folder_path.clone(),
+                databases: restore.databases.iter().map(|d| d.into.clone()).collect(),
+            },
+            log,
+        ))
+    }
+
+    async fn verify_backup(
+        &self,
+        check: &BackupVerification,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        Ok(self.record(
+            ProvisionOp::VerifyBackup {
+                archive_path: check.archive_path.clone(),
+                databases: check.databases.clone(),
+                wordpress: check.wordpress,
+            },
+            log,
+        ))
+    }
+}
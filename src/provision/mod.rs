@@ -0,0 +1,487 @@
+//! Provisioning backends.
+//!
+//! Everything that touches the host — site folders, Caddy snippets, MariaDB
+//! databases — goes through the [`Provisioner`] trait so handlers don't care
+//! whether the work is done by the bundled sudo scripts, natively in Rust, or
+//! not at all (dry-run on a dev laptop).
+
+mod dry_run;
+mod native;
+mod script;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use crate::caddy::{SiteCaddyConfig, SiteConfigError, SiteConfigs};
+use crate::config::{Config, ProvisionerKind};
+
+pub use dry_run::{DryRunProvisioner, ProvisionOp};
+pub use native::NativeProvisioner;
+pub use script::ScriptProvisioner;
+
+/// WordPress admin details used to complete `wp core install`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WordPressInstall {
+    pub title: String,
+    pub admin_user: String,
+    pub admin_password: String,
+    pub admin_email: String,
+}
+
+/// A site to create on the host.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NewSite {
+    pub domain: String,
+    pub folder_path: String,
+    pub php_version: String,
+    /// Set when WordPress should be downloaded and installed into the folder.
+    pub wordpress: Option<WordPressInstall>,
+}
+
+/// A MariaDB database and user to create.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NewDatabase {
+    pub db_name: String,
+    pub db_user: String,
+    pub password: String,
+    /// `full` or `readonly`
+    pub privileges: String,
+}
+
+/// An existing MariaDB database and its user.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatabaseRef {
+    pub db_name: String,
+    pub db_user: String,
+}
+
+/// Database and user named after a domain: `<prefix><domain>` with every
+/// character other than `[A-Za-z0-9]` replaced by `_`, cut to 64 characters
+/// (32 for the user) so that `suffix` still fits.
+pub fn domain_database(prefix: &str, domain: &str, suffix: &str) -> DatabaseRef {
+    let base: String = prefix
+        .chars()
+        .chain(
+            domain
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }),
+        )
+        .collect();
+    let cut = |max: usize| -> String {
+        let mut name: String = base.chars().take(max - suffix.len()).collect();
+        name.push_str(suffix);
+        name
+    };
+    DatabaseRef {
+        db_name: cut(64),
+        db_user: cut(32),
+    }
+}
+
+/// The database a WordPress site gets when it is created
+/// (same names as `scripts/site-create.sh`).
+pub fn wordpress_database(domain: &str) -> DatabaseRef {
+    domain_database("wp_", domain, "")
+}
+
+/// A backup bundle to write: a gzipped tar with `manifest.json`, a dump of
+/// each database under `databases/<db_name>.sql` and the site folder under `files/`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewBackup {
+    pub domain: String,
+    pub folder_path: String,
+    pub databases: Vec<DatabaseRef>,
+    /// Where the bundle goes; written under a temporary name and renamed when complete.
+    pub archive_path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Version of the bundle layout, recorded in the manifest.
+pub const BACKUP_FORMAT: u32 = 1;
+
+/// `manifest.json`, the first entry of a backup bundle.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: u32,
+    pub domain: String,
+    pub folder_path: String,
+    pub created_at: DateTime<Utc>,
+    pub databases: Vec<ManifestDatabase>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestDatabase {
+    pub db_name: String,
+    pub db_user: String,
+    /// Path of the dump inside the bundle, `databases/<db_name>.sql`.
+    pub file: String,
+}
+
+impl BackupManifest {
+    /// Read the manifest from the start of a bundle without unpacking the rest.
+    pub fn read(archive: &std::path::Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(archive)?;
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        for entry in tar.entries()? {
+            let entry = entry?;
+            if entry.path()?.as_os_str() == "manifest.json" {
+                return serde_json::from_reader(entry).map_err(std::io::Error::other);
+            }
+        }
+        Err(std::io::Error::other("no manifest.json in the bundle"))
+    }
+}
+
+impl NewBackup {
+    pub fn manifest(&self) -> BackupManifest {
+        BackupManifest {
+            format: BACKUP_FORMAT,
+            domain: self.domain.clone(),
+            folder_path: self.folder_path.clone(),
+            created_at: self.created_at,
+            databases: self
+                .databases
+                .iter()
+                .map(|db| ManifestDatabase {
+                    db_name: db.db_name.clone(),
+                    db_user: db.db_user.clone(),
+                    file: format!("databases/{}.sql", db.db_name),
+                })
+                .collect(),
+        }
+    }
+
+    /// What `manifest.json` in the bundle says about its contents.
+    pub fn manifest_json(&self) -> String {
+        serde_json::to_string(&self.manifest()).unwrap_or_default()
+    }
+}
+
+/// Replace a site's files and databases with the contents of a backup bundle.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SiteRestore {
+    pub archive_path: String,
+    /// The site restored into.
+    pub domain: String,
+    /// Emptied and filled with the bundle's `files/`.
+    pub folder_path: String,
+    pub databases: Vec<RestoreDatabase>,
+    /// When restoring under another domain: the credentials of the new
+    /// WordPress database, written into the restored `wp-config.php`.
+    pub wp_config: Option<NewDatabase>,
+    /// When restoring under another domain: the domain the backup was taken
+    /// from, replaced with `domain` in WordPress URLs (`wp search-replace`).
+    pub moved_from: Option<String>,
+}
+
+/// Import `databases/<from>.sql` from the bundle into the database `into`,
+/// which is dropped and created empty first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreDatabase {
+    pub from: String,
+    pub into: String,
+}
+
+/// Test-restore a backup bundle where it cannot harm the site: its files go
+/// into a temporary directory and each dump into a scratch database.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BackupVerification {
+    pub archive_path: String,
+    /// Databases the manifest lists; each must have a dump that imports.
+    pub databases: Vec<String>,
+    /// Each dump is imported into this database in turn; it is dropped afterwards.
+    pub scratch_database: String,
+    /// Require `wp-config.php` and [`WORDPRESS_CORE_FILES`] among the files.
+    pub wordpress: bool,
+}
+
+/// Files a WordPress site cannot run without, relative to its folder.
+pub const WORDPRESS_CORE_FILES: [&str; 6] = [
+    "wp-config.php",
+    "index.php",
+    "wp-load.php",
+    "wp-settings.php",
+    "wp-includes/version.php",
+    "wp-admin/index.php",
+];
+
+/// Captured output of a provisioning step.
+#[derive(Clone, Debug, Default)]
+pub struct ProvisionOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ProvisionOutput {
+    pub fn from_process(out: &std::process::Output) -> Self {
+        Self {
+            stdout: String::from_utf8_lossy(&out.stdout).trim().to_string(),
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+    }
+
+    pub fn push_stdout(&mut self, line: impl AsRef<str>) {
+        push_line(&mut self.stdout, line.as_ref());
+    }
+
+    pub fn push_stderr(&mut self, line: impl AsRef<str>) {
+        push_line(&mut self.stderr, line.as_ref());
+    }
+
+    /// Add the output of a later step.
+    pub fn append(&mut self, other: ProvisionOutput) {
+        self.push_stdout(&other.stdout);
+        self.push_stderr(&other.stderr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+}
+
+fn push_line(buf: &mut String, line: &str) {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return;
+    }
+    if !buf.is_empty() {
+        buf.push('\n');
+    }
+    buf.push_str(line);
+}
+
+/// Which output stream a line came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// One line of provisioning output, as it is produced.
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub line: String,
+}
+
+/// Where provisioners send output while they run (see [`crate::jobs`] for the
+/// consumer that stores and streams it). A default sink discards everything.
+#[derive(Clone, Default)]
+pub struct LogSink {
+    tx: Option<mpsc::UnboundedSender<LogLine>>,
+}
+
+impl LogSink {
+    pub fn new(tx: mpsc::UnboundedSender<LogLine>) -> Self {
+        Self { tx: Some(tx) }
+    }
+
+    pub fn emit(&self, stream: LogStream, line: &str) {
+        if let Some(ref tx) = self.tx {
+            let _ = tx.send(LogLine {
+                stream,
+                line: line.to_string(),
+            });
+        }
+    }
+}
+
+/// Output collected by a provisioner that is also forwarded to a [`LogSink`]
+/// line by line.
+pub struct Transcript<'a> {
+    output: ProvisionOutput,
+    sink: &'a LogSink,
+}
+
+impl<'a> Transcript<'a> {
+    pub fn new(sink: &'a LogSink) -> Self {
+        Self {
+            output: ProvisionOutput::default(),
+            sink,
+        }
+    }
+
+    pub fn push_stdout(&mut self, text: impl AsRef<str>) {
+        for line in text.as_ref().lines().filter(|l| !l.trim().is_empty()) {
+            self.sink.emit(LogStream::Stdout, line);
+            self.output.push_stdout(line);
+        }
+    }
+
+    pub fn push_stderr(&mut self, text: impl AsRef<str>) {
+        for line in text.as_ref().lines().filter(|l| !l.trim().is_empty()) {
+            self.sink.emit(LogStream::Stderr, line);
+            self.output.push_stderr(line);
+        }
+    }
+
+    pub fn into_output(self) -> ProvisionOutput {
+        self.output
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ProvisionError {
+    /// The command could not be started at all (missing binary, sudo not allowed…).
+    #[error("could not run {program}: {source}")]
+    Spawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The step ran but failed; `output` holds whatever it printed.
+    #[error("provisioning failed (exit status {})", .status.map(|s| s.to_string()).unwrap_or_else(|| "n/a".into()))]
+    Failed {
+        status: Option<i32>,
+        output: ProvisionOutput,
+    },
+}
+
+/// Every method streams its output to `log` while running and also returns it
+/// in full (or inside [`ProvisionError::Failed`]).
+#[async_trait]
+pub trait Provisioner: Send + Sync {
+    /// Create the site folder (and WordPress, if requested).
+    async fn create_site(
+        &self,
+        site: &NewSite,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Remove the site folder and drop the given databases.
+    async fn delete_site(
+        &self,
+        domain: &str,
+        folder_path: &str,
+        databases: &[DatabaseRef],
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    async fn create_database(
+        &self,
+        db: &NewDatabase,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    async fn delete_database(
+        &self,
+        db: &DatabaseRef,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Write (or replace) the site's Caddy snippet, validate and reload. The
+    /// previous snippet is restored if validation or reload fails.
+    async fn apply_site_config(
+        &self,
+        config: &SiteCaddyConfig,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Remove the site's Caddy snippet and reload, so the domain is no longer served.
+    async fn remove_site_config(
+        &self,
+        domain: &str,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Dump the site's databases and archive them with the site folder into
+    /// `backup.archive_path`. Nothing is left at that path if it fails.
+    async fn backup_site(
+        &self,
+        backup: &NewBackup,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Import the bundle's dumps, then swap the site folder for the bundle's
+    /// files. The folder is only replaced once everything is unpacked.
+    async fn restore_site(
+        &self,
+        restore: &SiteRestore,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+
+    /// Unpack the bundle into a temporary directory, import every dump into
+    /// the scratch database and check the WordPress files. Fails with the
+    /// first problem found; the site itself is not touched.
+    async fn verify_backup(
+        &self,
+        check: &BackupVerification,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError>;
+}
+
+/// Snippet handling shared by the script and native backends; the panel
+/// process writes the snippets itself in both cases.
+async fn apply_site_config(
+    configs: &SiteConfigs,
+    config: &SiteCaddyConfig,
+    log: &LogSink,
+) -> Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    match configs.apply(config).await {
+        Ok(path) => {
+            transcript.push_stdout(format!("Wrote Caddy config: {}", path.display()));
+            transcript.push_stdout("Validated and reloaded Caddy/FrankenPHP.");
+            Ok(transcript.into_output())
+        }
+        Err(e) => Err(site_config_failed(e, transcript)),
+    }
+}
+
+async fn remove_site_config(
+    configs: &SiteConfigs,
+    domain: &str,
+    log: &LogSink,
+) -> Result<ProvisionOutput, ProvisionError> {
+    let mut transcript = Transcript::new(log);
+    match configs.remove(domain).await {
+        Ok(Some(path)) => {
+            transcript.push_stdout(format!("Removed Caddy config: {}", path.display()));
+            transcript.push_stdout("Validated and reloaded Caddy/FrankenPHP.");
+            Ok(transcript.into_output())
+        }
+        Ok(None) => Ok(transcript.into_output()),
+        Err(e) => Err(site_config_failed(e, transcript)),
+    }
+}
+
+fn site_config_failed(e: SiteConfigError, mut transcript: Transcript<'_>) -> ProvisionError {
+    transcript.push_stderr(format!("Error: {}", e));
+    if matches!(
+        e,
+        SiteConfigError::Spawn { .. }
+            | SiteConfigError::Invalid { .. }
+            | SiteConfigError::Reload(_)
+            | SiteConfigError::ReloadCmd { .. }
+    ) {
+        transcript.push_stderr("The previous Caddy config was restored.");
+    }
+    ProvisionError::Failed {
+        status: None,
+        output: transcript.into_output(),
+    }
+}
+
+/// Build the provisioner selected by `PANEL_PROVISIONER`.
+pub fn from_config(config: &Config) -> Arc<dyn Provisioner> {
+    match config.provisioner {
+        ProvisionerKind::Script => Arc::new(ScriptProvisioner::from_config(config)),
+        ProvisionerKind::Native => Arc::new(NativeProvisioner::from_config(config)),
+        ProvisionerKind::DryRun => Arc::new(DryRunProvisioner::new()),
+    }
+}
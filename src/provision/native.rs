@@ -0,0 +1,954 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::{
+    apply_site_config, remove_site_config, wordpress_database, BackupManifest, BackupVerification,
+    DatabaseRef, LogSink, NewBackup, NewDatabase, NewSite, ProvisionError, ProvisionOutput,
+    Provisioner, SiteRestore, Transcript, WordPressInstall, WORDPRESS_CORE_FILES,
+};
+use crate::caddy::{SiteCaddyConfig, SiteConfigs};
+use crate::config::Config;
+
+const WORDPRESS_TARBALL_URL: &str = "https://wordpress.org/latest.tar.gz";
+const WORDPRESS_SALT_URL: &str = "https://api.wordpress.org/secret-key/1.1/salt/";
+
+/// Does the same work as the bundled scripts directly from the panel process.
+/// The panel user needs write access to the site root and the Caddy sites dir,
+/// and the `mysql` and `mysqldump` clients for database work.
+pub struct NativeProvisioner {
+    web_user: String,
+    site_configs: SiteConfigs,
+    mysql_root_password: Option<String>,
+}
+
+impl NativeProvisioner {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            web_user: config
+                .web_user
+                .clone()
+                .unwrap_or_else(|| "www-data".to_string()),
+            site_configs: SiteConfigs::from_config(config),
+            mysql_root_password: config.mysql_root_password.clone(),
+        }
+    }
+
+    /// Run `sql` as root. It goes to the client's stdin rather than its
+    /// arguments, which anyone on the server can read while it runs, since it
+    /// may set a password.
+    async fn mysql(&self, sql: &str, log: &mut Transcript<'_>) -> Result<(), String> {
+        let mut cmd = Command::new("mysql");
+        cmd.arg("-u")
+            .arg("root")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(ref pw) = self.mysql_root_password {
+            cmd.env("MYSQL_PWD", pw);
+        }
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Error: could not run mysql client: {}", e))?;
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        let write = async move {
+            stdin.write_all(sql.as_bytes()).await?;
+            stdin.shutdown().await
+        };
+        let (written, out) = tokio::join!(write, child.wait_with_output());
+        let out = out.map_err(|e| format!("Error: could not run mysql client: {}", e))?;
+        let captured = ProvisionOutput::from_process(&out);
+        log.push_stdout(&captured.stdout);
+        if !out.status.success() {
+            return Err(captured.stderr);
+        }
+        written.map_err(|e| format!("Error: could not send SQL to the mysql client: {}", e))
+    }
+
+    /// Dump one database into `dest`.
+    async fn mysqldump(&self, db_name: &str, dest: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(dest)
+            .map_err(|e| format!("Error: could not create {}: {}", dest.display(), e))?;
+        let mut cmd = Command::new("mysqldump");
+        cmd.arg("-u")
+            .arg("root")
+            .arg("--single-transaction")
+            .arg("--quick")
+            .arg("--routines")
+            .arg("--triggers")
+            .arg(db_name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(file))
+            .stderr(Stdio::piped());
+        if let Some(ref pw) = self.mysql_root_password {
+            cmd.env("MYSQL_PWD", pw);
+        }
+        let out = cmd
+            .output()
+            .await
+            .map_err(|e| format!("Error: could not run mysqldump: {}", e))?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Error: Could not dump database {}.\n{}",
+                db_name,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ))
+        }
+    }
+
+    async fn backup_site_inner(
+        &self,
+        backup: &NewBackup,
+        staging: &Path,
+        partial: &Path,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        let folder = PathBuf::from(&backup.folder_path);
+        if !folder.is_dir() {
+            return Err(format!(
+                "Error: site folder {} does not exist.",
+                folder.display()
+            ));
+        }
+        tokio::fs::create_dir_all(staging)
+            .await
+            .map_err(|e| format!("Error: could not create {}: {}", staging.display(), e))?;
+
+        let mut dumps = Vec::new();
+        for db in &backup.databases {
+            let dest = staging.join(format!("{}.sql", db.db_name));
+            self.mysqldump(&db.db_name, &dest).await?;
+            let size = tokio::fs::metadata(&dest)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            log.push_stdout(format!("Dumped database {} ({} bytes)", db.db_name, size));
+            dumps.push((db.db_name.clone(), dest));
+        }
+
+        let manifest = backup.manifest_json();
+        let dest = partial.to_path_buf();
+        tokio::task::spawn_blocking(move || write_bundle(&dest, &manifest, &dumps, &folder))
+            .await
+            .map_err(|e| format!("Error: archive task failed: {}", e))?
+            .map_err(|e| format!("Error: could not write {}: {}", partial.display(), e))?;
+        log.push_stdout(format!("Archived {}", backup.folder_path));
+
+        tokio::fs::rename(partial, &backup.archive_path)
+            .await
+            .map_err(|e| format!("Error: could not move the backup into place: {}", e))?;
+        let size = tokio::fs::metadata(&backup.archive_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        log.push_stdout(format!(
+            "Backup written: {} ({} bytes)",
+            backup.archive_path, size
+        ));
+        Ok(())
+    }
+
+    /// Replace the contents of `db_name` with a dump.
+    async fn import_dump(
+        &self,
+        db_name: &str,
+        dump: &Path,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        self.mysql(
+            &format!(
+                "DROP DATABASE IF EXISTS `{db}`; CREATE DATABASE `{db}`;",
+                db = db_name
+            ),
+            log,
+        )
+        .await
+        .map_err(|e| format!("Error: Could not recreate database {}.\n{}", db_name, e))?;
+        let file = std::fs::File::open(dump)
+            .map_err(|e| format!("Error: could not open {}: {}", dump.display(), e))?;
+        let mut cmd = Command::new("mysql");
+        cmd.arg("-u")
+            .arg("root")
+            .arg(db_name)
+            .stdin(Stdio::from(file))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(ref pw) = self.mysql_root_password {
+            cmd.env("MYSQL_PWD", pw);
+        }
+        let out = cmd
+            .output()
+            .await
+            .map_err(|e| format!("Error: could not run mysql client: {}", e))?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Error: Could not import into database {}.\n{}",
+                db_name,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ))
+        }
+    }
+
+    async fn restore_site_inner(
+        &self,
+        restore: &SiteRestore,
+        staging: &Path,
+        previous: &Path,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        let archive = PathBuf::from(&restore.archive_path);
+        if !archive.is_file() {
+            return Err(format!(
+                "Error: backup {} does not exist.",
+                archive.display()
+            ));
+        }
+        let dest = staging.to_path_buf();
+        let manifest = tokio::task::spawn_blocking(move || unpack_bundle(&archive, &dest))
+            .await
+            .map_err(|e| format!("Error: unpack task failed: {}", e))?
+            .map_err(|e| format!("Error: could not unpack {}: {}", restore.archive_path, e))?;
+        log.push_stdout(format!(
+            "Unpacked backup of {} taken {}",
+            manifest.domain,
+            manifest.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+
+        for db in &restore.databases {
+            let dump = staging.join("databases").join(format!("{}.sql", db.from));
+            if !dump.is_file() {
+                return Err(format!(
+                    "Error: the backup has no dump of database {}.",
+                    db.from
+                ));
+            }
+            self.import_dump(&db.into, &dump, log).await?;
+            log.push_stdout(format!("Imported database {} into {}", db.from, db.into));
+        }
+
+        let files = staging.join("files");
+        tokio::fs::create_dir_all(&files)
+            .await
+            .map_err(|e| format!("Error: could not create {}: {}", files.display(), e))?;
+        if let Some(ref db) = restore.wp_config {
+            let path = files.join("wp-config.php");
+            if let Ok(config) = tokio::fs::read_to_string(&path).await {
+                tokio::fs::write(&path, rewrite_wp_config(&config, db))
+                    .await
+                    .map_err(|e| format!("Error: could not write wp-config.php: {}", e))?;
+                log.push_stdout(format!("Pointed wp-config.php at database {}", db.db_name));
+            }
+        }
+
+        let folder = PathBuf::from(&restore.folder_path);
+        let had_folder = folder.exists();
+        if had_folder {
+            tokio::fs::rename(&folder, previous)
+                .await
+                .map_err(|e| format!("Error: could not move {} aside: {}", folder.display(), e))?;
+        }
+        if let Err(e) = tokio::fs::rename(&files, &folder).await {
+            if had_folder {
+                let _ = tokio::fs::rename(previous, &folder).await;
+            }
+            return Err(format!(
+                "Error: could not move the restored files into place: {}",
+                e
+            ));
+        }
+        self.chown_tree(&folder, log).await;
+        log.push_stdout(format!("Restored files into {}", restore.folder_path));
+
+        if let Some(ref from) = restore.moved_from {
+            self.search_replace(from, &restore.domain, &restore.folder_path, log)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn verify_backup_inner(
+        &self,
+        check: &BackupVerification,
+        scratch: &Path,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        let archive = PathBuf::from(&check.archive_path);
+        if !archive.is_file() {
+            return Err(format!(
+                "Error: backup {} does not exist.",
+                archive.display()
+            ));
+        }
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(scratch)
+            .map_err(|e| format!("Error: could not create {}: {}", scratch.display(), e))?;
+        let dest = scratch.to_path_buf();
+        let manifest = tokio::task::spawn_blocking(move || unpack_bundle(&archive, &dest))
+            .await
+            .map_err(|e| format!("Error: unpack task failed: {}", e))?
+            .map_err(|e| format!("Error: the bundle is incomplete or corrupt: {}", e))?;
+        log.push_stdout(format!(
+            "Unpacked backup of {} taken {} into {}",
+            manifest.domain,
+            manifest.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            scratch.display()
+        ));
+
+        for db_name in &check.databases {
+            let dump = scratch.join("databases").join(format!("{}.sql", db_name));
+            if !dump.is_file() {
+                return Err(format!(
+                    "Error: the backup has no dump of database {}.",
+                    db_name
+                ));
+            }
+            log.push_stdout(format!(
+                "Importing the dump of {} into scratch database {}",
+                db_name, check.scratch_database
+            ));
+            self.import_dump(&check.scratch_database, &dump, log)
+                .await?;
+            log.push_stdout(format!("Dump of {} imports cleanly", db_name));
+        }
+
+        if check.wordpress {
+            let files = scratch.join("files");
+            let missing: Vec<&str> = WORDPRESS_CORE_FILES
+                .iter()
+                .copied()
+                .filter(|f| !files.join(f).is_file())
+                .collect();
+            if !missing.is_empty() {
+                return Err(format!(
+                    "Error: WordPress files missing from the backup: {}.",
+                    missing.join(", ")
+                ));
+            }
+            log.push_stdout("wp-config.php and the WordPress core files are present");
+        }
+        Ok(())
+    }
+
+    /// Point WordPress at its new domain. Only warns when it cannot: the
+    /// files and databases are already restored.
+    async fn search_replace(
+        &self,
+        from: &str,
+        to: &str,
+        folder_path: &str,
+        log: &mut Transcript<'_>,
+    ) {
+        if !Path::new(folder_path).join("wp-config.php").is_file() {
+            return;
+        }
+        let out = Command::new("wp")
+            .arg("search-replace")
+            .arg(format!("//{}", from))
+            .arg(format!("//{}", to))
+            .arg("--all-tables")
+            .arg("--skip-columns=guid")
+            .arg(format!("--path={}", folder_path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await;
+        match out {
+            Ok(out) if out.status.success() => log.push_stdout(format!(
+                "Replaced {} with {} in the WordPress database.",
+                from, to
+            )),
+            _ => log.push_stderr(format!(
+                "Warning: could not run wp search-replace; WordPress URLs still point to {}. \
+                 Run: wp search-replace //{} //{} --all-tables --skip-columns=guid --path={}",
+                from, from, to, folder_path
+            )),
+        }
+    }
+
+    async fn install_wordpress(
+        &self,
+        site: &NewSite,
+        wp: &WordPressInstall,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        let DatabaseRef { db_name, db_user } = wordpress_database(&site.domain);
+        let db_pass: String = uuid::Uuid::new_v4()
+            .simple()
+            .to_string()
+            .chars()
+            .take(24)
+            .collect();
+
+        self.mysql(
+            &create_database_sql(&db_name, &db_user, &db_pass, "full"),
+            log,
+        )
+        .await
+        .map_err(|e| {
+            format!(
+                "Error: Could not create MySQL database for WordPress.\n{}",
+                e
+            )
+        })?;
+
+        let root = PathBuf::from(&site.folder_path);
+        let _ = tokio::fs::remove_file(root.join("index.php")).await;
+
+        log.push_stdout(format!("Downloading {}", WORDPRESS_TARBALL_URL));
+        let tarball = reqwest::get(WORDPRESS_TARBALL_URL)
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Error: could not download WordPress: {}", e))?
+            .bytes()
+            .await
+            .map_err(|e| format!("Error: could not download WordPress: {}", e))?;
+        let dest = root.clone();
+        tokio::task::spawn_blocking(move || unpack_wordpress(&tarball, &dest))
+            .await
+            .map_err(|e| format!("Error: unpack task failed: {}", e))?
+            .map_err(|e| format!("Error: could not unpack WordPress: {}", e))?;
+        log.push_stdout("Unpacked WordPress.");
+
+        let salts = reqwest::get(WORDPRESS_SALT_URL)
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Error: could not fetch WordPress salts: {}", e))?
+            .text()
+            .await
+            .map_err(|e| format!("Error: could not fetch WordPress salts: {}", e))?;
+        tokio::fs::write(
+            root.join("wp-config.php"),
+            wp_config(&db_name, &db_user, &db_pass, &salts),
+        )
+        .await
+        .map_err(|e| format!("Error: could not write wp-config.php: {}", e))?;
+        self.chown_tree(&root, log).await;
+
+        let install = Command::new("wp")
+            .arg("core")
+            .arg("install")
+            .arg(format!("--url=http://{}", site.domain))
+            .arg(format!("--title={}", wp.title))
+            .arg(format!("--admin_user={}", wp.admin_user))
+            .arg(format!("--admin_password={}", wp.admin_password))
+            .arg(format!("--admin_email={}", wp.admin_email))
+            .arg(format!("--path={}", site.folder_path))
+            .arg("--skip-email")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await;
+        match install {
+            Ok(out) if out.status.success() => {
+                self.chown_tree(&root, log).await;
+                log.push_stdout(format!(
+                    "WordPress installed. Log in at http://{}/wp-admin with {} and your chosen password.",
+                    site.domain, wp.admin_user
+                ));
+            }
+            _ => log.push_stdout(format!(
+                "WordPress files and database are ready. Open http://{}/wp-admin/install.php to complete the 5-minute setup.",
+                site.domain
+            )),
+        }
+        Ok(())
+    }
+
+    async fn chown_tree(&self, path: &Path, log: &mut Transcript<'_>) {
+        let Some((uid, gid)) = lookup_user(&self.web_user) else {
+            return;
+        };
+        let path = path.to_path_buf();
+        let res = tokio::task::spawn_blocking(move || chown_recursive(&path, uid, gid)).await;
+        if let Ok(Err(e)) = res {
+            log.push_stderr(format!(
+                "Warning: could not chown to {}: {}",
+                self.web_user, e
+            ));
+        }
+    }
+
+    async fn create_site_inner(
+        &self,
+        site: &NewSite,
+        log: &mut Transcript<'_>,
+    ) -> Result<(), String> {
+        let root = PathBuf::from(&site.folder_path);
+        tokio::fs::create_dir_all(&root)
+            .await
+            .map_err(|e| format!("Error: could not create {}: {}", root.display(), e))?;
+        self.chown_tree(&root, log).await;
+
+        match site.wordpress {
+            Some(ref wp) => self.install_wordpress(site, wp, log).await?,
+            None => {
+                let index = root.join("index.php");
+                if !index.exists() {
+                    tokio::fs::write(&index, placeholder_index(&site.domain))
+                        .await
+                        .map_err(|e| {
+                            format!("Error: could not write {}: {}", index.display(), e)
+                        })?;
+                    self.chown_tree(&index, log).await;
+                }
+            }
+        }
+
+        log.push_stdout(format!(
+            "Site created: {} -> {}",
+            site.domain, site.folder_path
+        ));
+        Ok(())
+    }
+}
+
+fn finish(
+    result: Result<(), String>,
+    mut log: Transcript<'_>,
+) -> Result<ProvisionOutput, ProvisionError> {
+    match result {
+        Ok(()) => Ok(log.into_output()),
+        Err(e) => {
+            log.push_stderr(e);
+            Err(ProvisionError::Failed {
+                status: None,
+                output: log.into_output(),
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl Provisioner for NativeProvisioner {
+    async fn create_site(
+        &self,
+        site: &NewSite,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let result = self.create_site_inner(site, &mut log).await;
+        finish(result, log)
+    }
+
+    async fn delete_site(
+        &self,
+        domain: &str,
+        folder_path: &str,
+        databases: &[DatabaseRef],
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let root = Path::new(folder_path);
+        if root.is_dir() {
+            if let Err(e) = tokio::fs::remove_dir_all(root).await {
+                return finish(
+                    Err(format!("Error: could not remove {}: {}", root.display(), e)),
+                    log,
+                );
+            }
+            log.push_stdout(format!("Removed site directory: {}", folder_path));
+        }
+        for db in databases {
+            // Best effort, like site-delete.sh: a missing DB must not block removing the site.
+            match self
+                .mysql(&drop_database_sql(&db.db_name, &db.db_user), &mut log)
+                .await
+            {
+                Ok(()) => log.push_stdout(format!(
+                    "Dropped database and user: {} / {}",
+                    db.db_name, db.db_user
+                )),
+                Err(e) => log.push_stderr(e),
+            }
+        }
+        log.push_stdout(format!("Site removed: {}", domain));
+        Ok(log.into_output())
+    }
+
+    async fn create_database(
+        &self,
+        db: &NewDatabase,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let sql = create_database_sql(&db.db_name, &db.db_user, &db.password, &db.privileges);
+        let result = self
+            .mysql(&sql, &mut log)
+            .await
+            .map_err(|e| format!("Error: Could not create database or user.\n{}", e));
+        if result.is_ok() {
+            log.push_stdout(format!(
+                "Database created: {} (user: {}, privileges: {})",
+                db.db_name, db.db_user, db.privileges
+            ));
+        }
+        finish(result, log)
+    }
+
+    async fn delete_database(
+        &self,
+        db: &DatabaseRef,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let result = self
+            .mysql(&drop_database_sql(&db.db_name, &db.db_user), &mut log)
+            .await
+            .map_err(|e| format!("Error: Could not drop database or user.\n{}", e));
+        if result.is_ok() {
+            log.push_stdout(format!("Database removed: {}", db.db_name));
+        }
+        finish(result, log)
+    }
+
+    async fn apply_site_config(
+        &self,
+        config: &SiteCaddyConfig,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        apply_site_config(&self.site_configs, config, sink).await
+    }
+
+    async fn remove_site_config(
+        &self,
+        domain: &str,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        remove_site_config(&self.site_configs, domain, sink).await
+    }
+
+    async fn backup_site(
+        &self,
+        backup: &NewBackup,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let archive = Path::new(&backup.archive_path);
+        let (Some(dir), Some(name)) = (archive.parent(), archive.file_name()) else {
+            return finish(
+                Err(format!("Error: invalid backup path {}", archive.display())),
+                log,
+            );
+        };
+        let name = name.to_string_lossy();
+        let staging = dir.join(format!(".{}.tmp", name));
+        let partial = dir.join(format!("{}.partial", name));
+        let result = self
+            .backup_site_inner(backup, &staging, &partial, &mut log)
+            .await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        finish(result, log)
+    }
+
+    async fn restore_site(
+        &self,
+        restore: &SiteRestore,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let folder = Path::new(&restore.folder_path);
+        let (Some(dir), Some(name)) = (folder.parent(), folder.file_name()) else {
+            return finish(
+                Err(format!("Error: invalid site folder {}", folder.display())),
+                log,
+            );
+        };
+        // Next to the site folder, so the swap is a rename on the same filesystem.
+        let name = name.to_string_lossy();
+        let staging = dir.join(format!(".{}.restore", name));
+        let previous = dir.join(format!(".{}.previous", name));
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let _ = tokio::fs::remove_dir_all(&previous).await;
+        let result = self
+            .restore_site_inner(restore, &staging, &previous, &mut log)
+            .await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let _ = tokio::fs::remove_dir_all(&previous).await;
+        finish(result, log)
+    }
+
+    async fn verify_backup(
+        &self,
+        check: &BackupVerification,
+        sink: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let mut log = Transcript::new(sink);
+        let scratch =
+            std::env::temp_dir().join(format!("panel-verify-{}", uuid::Uuid::new_v4().simple()));
+        let mut result = self.verify_backup_inner(check, &scratch, &mut log).await;
+        let _ = tokio::fs::remove_dir_all(&scratch).await;
+        if !check.databases.is_empty() {
+            let dropped = self
+                .mysql(
+                    &format!("DROP DATABASE IF EXISTS `{}`;", check.scratch_database),
+                    &mut log,
+                )
+                .await;
+            if let (Ok(()), Err(e)) = (&result, dropped) {
+                result = Err(format!(
+                    "Error: could not drop scratch database {}.\n{}",
+                    check.scratch_database, e
+                ));
+            }
+        }
+        if result.is_ok() {
+            log.push_stdout("Backup verified");
+        }
+        finish(result, log)
+    }
+}
+
+/// Escape a value for use inside a single-quoted MySQL string literal.
+fn sql_quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// Identifiers are validated to `[A-Za-z0-9_]` before they get here. The
+/// `ALTER USER`s give a user left over from a failed attempt this password.
+fn create_database_sql(db_name: &str, db_user: &str, password: &str, privileges: &str) -> String {
+    let grant = if privileges == "readonly" {
+        "SELECT"
+    } else {
+        "ALL PRIVILEGES"
+    };
+    let pw = sql_quote(password);
+    format!(
+        "CREATE DATABASE IF NOT EXISTS `{db}`; \
+         CREATE USER IF NOT EXISTS '{user}'@'localhost' IDENTIFIED BY '{pw}'; \
+         CREATE USER IF NOT EXISTS '{user}'@'127.0.0.1' IDENTIFIED BY '{pw}'; \
+         ALTER USER '{user}'@'localhost' IDENTIFIED BY '{pw}'; \
+         ALTER USER '{user}'@'127.0.0.1' IDENTIFIED BY '{pw}'; \
+         GRANT {grant} ON `{db}`.* TO '{user}'@'localhost'; \
+         GRANT {grant} ON `{db}`.* TO '{user}'@'127.0.0.1'; \
+         FLUSH PRIVILEGES;",
+        db = db_name,
+        user = db_user,
+    )
+}
+
+fn drop_database_sql(db_name: &str, db_user: &str) -> String {
+    format!(
+        "DROP DATABASE IF EXISTS `{db}`; \
+         DROP USER IF EXISTS '{user}'@'localhost'; \
+         DROP USER IF EXISTS '{user}'@'127.0.0.1'; \
+         FLUSH PRIVILEGES;",
+        db = db_name,
+        user = db_user,
+    )
+}
+
+fn placeholder_index(domain: &str) -> String {
+    format!(
+        "<?php echo \"<!DOCTYPE html><html><head><title>{d}</title></head><body><h1>Welcome to {d}</h1><p>PHP is working.</p></body></html>\";\n",
+        d = domain
+    )
+}
+
+fn wp_config(db_name: &str, db_user: &str, db_pass: &str, salts: &str) -> String {
+    format!(
+        "<?php
+define('DB_NAME', '{db_name}');
+define('DB_USER', '{db_user}');
+define('DB_PASSWORD', '{db_pass}');
+define('DB_HOST', '127.0.0.1');
+define('DB_CHARSET', 'utf8mb4');
+define('DB_COLLATE', '');
+{salts}
+
+$table_prefix = 'wp_';
+/* WordPress debug: log to wp-content/debug.log; set WP_DEBUG_DISPLAY to true only when fixing issues locally */
+define('WP_DEBUG', true);
+define('WP_DEBUG_LOG', true);
+define('WP_DEBUG_DISPLAY', false);
+@ini_set('display_errors', 0);
+define('SCRIPT_DEBUG', false);
+if ( ! defined( 'ABSPATH' ) ) {{ define( 'ABSPATH', __DIR__ . '/' ); }}
+require_once ABSPATH . 'wp-settings.php';
+",
+        salts = salts.trim()
+    )
+}
+
+/// Unpack the official tarball (`wordpress/...`) directly into `dest`.
+fn unpack_wordpress(tarball: &[u8], dest: &Path) -> std::io::Result<()> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Ok(rel) = path.strip_prefix("wordpress") else {
+            continue;
+        };
+        if rel.as_os_str().is_empty()
+            || rel
+                .components()
+                .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        {
+            continue;
+        }
+        let target = dest.join(rel);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.set_preserve_permissions(false);
+        entry.unpack(&target)?;
+    }
+    Ok(())
+}
+
+/// Set the database constants in a restored `wp-config.php`.
+fn rewrite_wp_config(config: &str, db: &NewDatabase) -> String {
+    let mut config = config.to_string();
+    for (constant, value) in [
+        ("DB_NAME", &db.db_name),
+        ("DB_USER", &db.db_user),
+        ("DB_PASSWORD", &db.password),
+    ] {
+        let pattern = format!(r#"(?m)^\s*define\(\s*['"]{}['"]\s*,.*$"#, constant);
+        if let Ok(re) = regex::Regex::new(&pattern) {
+            let line = format!("define('{}', '{}');", constant, sql_quote(value));
+            config = re.replace_all(&config, regex::NoExpand(&line)).into_owned();
+        }
+    }
+    config
+}
+
+/// Unpack a backup bundle into `dest` (`manifest.json`, `databases/`,
+/// `files/`) and return its manifest. Anything else in the archive is skipped,
+/// and nothing is written outside `dest`.
+fn unpack_bundle(archive: &Path, dest: &Path) -> std::io::Result<BackupManifest> {
+    std::fs::create_dir_all(dest)?;
+    let file = std::fs::File::open(archive)?;
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    tar.set_preserve_permissions(true);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let wanted = path.as_os_str() == "manifest.json"
+            || path.starts_with("databases")
+            || path.starts_with("files");
+        if wanted {
+            entry.unpack_in(dest)?;
+        }
+    }
+    // Read up to the gzip trailer, whose checksum covers the whole bundle.
+    std::io::copy(&mut tar.into_inner(), &mut std::io::sink())?;
+    let manifest = std::fs::read(dest.join("manifest.json"))?;
+    let manifest: BackupManifest =
+        serde_json::from_slice(&manifest).map_err(std::io::Error::other)?;
+    if manifest.format > super::BACKUP_FORMAT {
+        return Err(std::io::Error::other(format!(
+            "bundle format {} is newer than this panel supports",
+            manifest.format
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Write the backup tarball: manifest first, then the dumps, then the site
+/// folder. Symlinks are stored as links, not followed. Only the owner can read
+/// it, since dumps and `wp-config.php` hold credentials.
+fn write_bundle(
+    dest: &Path,
+    manifest: &str,
+    dumps: &[(String, PathBuf)],
+    folder: &Path,
+) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(dest)?;
+    let gz = flate2::write::GzEncoder::new(
+        std::io::BufWriter::new(file),
+        flate2::Compression::default(),
+    );
+    let mut tar = tar::Builder::new(gz);
+    tar.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, "manifest.json", manifest.as_bytes())?;
+    for (db_name, path) in dumps {
+        tar.append_path_with_name(path, format!("databases/{}.sql", db_name))?;
+    }
+    tar.append_dir_all("files", folder)?;
+
+    let mut out = tar.into_inner()?.finish()?;
+    out.flush()?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// Look up uid/gid for a system user from `/etc/passwd`.
+fn lookup_user(name: &str) -> Option<(u32, u32)> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        let _pw = fields.next()?;
+        let uid = fields.next()?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+        Some((uid, gid))
+    })
+}
+
+fn chown_recursive(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+    if path.is_dir() && !path.is_symlink() {
+        for entry in std::fs::read_dir(path)? {
+            chown_recursive(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_database_sql;
+
+    #[test]
+    fn create_database_sets_password_of_existing_user() {
+        let sql = create_database_sql("wp_example_com", "wp_example_com", "it's", "full");
+        for host in ["localhost", "127.0.0.1"] {
+            let create = format!(
+                "CREATE USER IF NOT EXISTS 'wp_example_com'@'{}' IDENTIFIED BY 'it\\'s';",
+                host
+            );
+            let alter = format!(
+                "ALTER USER 'wp_example_com'@'{}' IDENTIFIED BY 'it\\'s';",
+                host
+            );
+            let grant = format!(
+                "GRANT ALL PRIVILEGES ON `wp_example_com`.* TO 'wp_example_com'@'{}';",
+                host
+            );
+            let create_at = sql.find(&create).expect(&create);
+            let alter_at = sql.find(&alter).expect(&alter);
+            assert!(create_at < alter_at && alter_at < sql.find(&grant).expect(&grant));
+        }
+        assert!(create_database_sql("db", "u", "pw", "readonly").contains("GRANT SELECT ON `db`.*"));
+    }
+}
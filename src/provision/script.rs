@@ -0,0 +1,273 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use super::{
+    apply_site_config, remove_site_config, BackupVerification, DatabaseRef, LogSink, NewBackup,
+    NewDatabase, NewSite, ProvisionError, ProvisionOutput, Provisioner, SiteRestore, Transcript,
+};
+use crate::caddy::{SiteCaddyConfig, SiteConfigs};
+use crate::config::Config;
+
+/// Runs the bundled bash scripts via `sudo`. A script that is not configured
+/// is skipped, so the panel still records sites/databases without touching the host.
+/// Caddy snippets are written by the panel itself (see [`SiteConfigs`]), and
+/// only when the matching site script is configured.
+pub struct ScriptProvisioner {
+    site_create_script: Option<PathBuf>,
+    site_delete_script: Option<PathBuf>,
+    db_create_script: Option<PathBuf>,
+    db_delete_script: Option<PathBuf>,
+    site_backup_script: Option<PathBuf>,
+    site_restore_script: Option<PathBuf>,
+    site_verify_script: Option<PathBuf>,
+    site_configs: SiteConfigs,
+}
+
+impl ScriptProvisioner {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            site_create_script: config.site_create_script.clone(),
+            site_delete_script: config.site_delete_script.clone(),
+            db_create_script: config.db_create_script.clone(),
+            db_delete_script: config.db_delete_script.clone(),
+            site_backup_script: config.site_backup_script.clone(),
+            site_restore_script: config.site_restore_script.clone(),
+            site_verify_script: config.site_verify_script.clone(),
+            site_configs: SiteConfigs::from_config(config),
+        }
+    }
+}
+
+/// Fail a step whose script is required but not configured.
+fn not_configured(variable: &str, what: &str, log: &LogSink) -> ProvisionError {
+    let mut transcript = Transcript::new(log);
+    transcript.push_stderr(format!("Error: {} is not set, so {}.", variable, what));
+    ProvisionError::Failed {
+        status: None,
+        output: transcript.into_output(),
+    }
+}
+
+/// Run `sudo <script> <args>`, forwarding stdout/stderr to `log` line by line.
+async fn run_script(
+    script: &Path,
+    args: &[&str],
+    log: &LogSink,
+) -> Result<ProvisionOutput, ProvisionError> {
+    let spawn_err = |e| ProvisionError::Spawn {
+        program: script.display().to_string(),
+        source: e,
+    };
+    let mut child = Command::new("sudo")
+        .arg(script.as_os_str())
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_err)?;
+
+    let mut stdout = child.stdout.take().map(|s| BufReader::new(s).lines());
+    let mut stderr = child.stderr.take().map(|s| BufReader::new(s).lines());
+    let mut transcript = Transcript::new(log);
+    while stdout.is_some() || stderr.is_some() {
+        tokio::select! {
+            line = async { stdout.as_mut().unwrap().next_line().await }, if stdout.is_some() => match line {
+                Ok(Some(line)) => transcript.push_stdout(line),
+                _ => stdout = None,
+            },
+            line = async { stderr.as_mut().unwrap().next_line().await }, if stderr.is_some() => match line {
+                Ok(Some(line)) => transcript.push_stderr(line),
+                _ => stderr = None,
+            },
+        }
+    }
+    let status = child.wait().await.map_err(spawn_err)?;
+
+    let output = transcript.into_output();
+    if status.success() {
+        Ok(output)
+    } else {
+        Err(ProvisionError::Failed {
+            status: status.code(),
+            output,
+        })
+    }
+}
+
+#[async_trait]
+impl Provisioner for ScriptProvisioner {
+    async fn create_site(
+        &self,
+        site: &NewSite,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.site_create_script else {
+            return Ok(ProvisionOutput::default());
+        };
+        let mut args = vec![site.domain.as_str(), site.folder_path.as_str()];
+        match site.wordpress {
+            Some(ref wp) => args.extend([
+                "1",
+                wp.title.as_str(),
+                wp.admin_user.as_str(),
+                wp.admin_password.as_str(),
+                wp.admin_email.as_str(),
+            ]),
+            None => args.extend(["0", "", "", "", ""]),
+        }
+        args.push(&site.php_version);
+        run_script(script, &args, log).await
+    }
+
+    async fn delete_site(
+        &self,
+        domain: &str,
+        folder_path: &str,
+        databases: &[DatabaseRef],
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.site_delete_script else {
+            return Ok(ProvisionOutput::default());
+        };
+        let mut args = vec![domain, folder_path];
+        for db in databases {
+            args.push(&db.db_name);
+            args.push(&db.db_user);
+        }
+        run_script(script, &args, log).await
+    }
+
+    async fn create_database(
+        &self,
+        db: &NewDatabase,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.db_create_script else {
+            return Ok(ProvisionOutput::default());
+        };
+        run_script(
+            script,
+            &[&db.db_name, &db.db_user, &db.password, &db.privileges],
+            log,
+        )
+        .await
+    }
+
+    async fn delete_database(
+        &self,
+        db: &DatabaseRef,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.db_delete_script else {
+            return Ok(ProvisionOutput::default());
+        };
+        run_script(script, &[&db.db_name, &db.db_user], log).await
+    }
+
+    async fn apply_site_config(
+        &self,
+        config: &SiteCaddyConfig,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        if self.site_create_script.is_none() {
+            return Ok(ProvisionOutput::default());
+        }
+        apply_site_config(&self.site_configs, config, log).await
+    }
+
+    async fn remove_site_config(
+        &self,
+        domain: &str,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        if self.site_delete_script.is_none() {
+            return Ok(ProvisionOutput::default());
+        }
+        remove_site_config(&self.site_configs, domain, log).await
+    }
+
+    /// Unlike the other steps this fails when the script is not configured:
+    /// skipping it would record a backup that does not exist.
+    async fn backup_site(
+        &self,
+        backup: &NewBackup,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.site_backup_script else {
+            return Err(not_configured(
+                "SITE_BACKUP_SCRIPT",
+                "backups cannot be written",
+                log,
+            ));
+        };
+        let manifest = backup.manifest_json();
+        let mut args = vec![
+            backup.archive_path.as_str(),
+            backup.folder_path.as_str(),
+            manifest.as_str(),
+        ];
+        for db in &backup.databases {
+            args.push(&db.db_name);
+        }
+        run_script(script, &args, log).await
+    }
+
+    /// Fails when the script is not configured, like [`Self::backup_site`].
+    async fn restore_site(
+        &self,
+        restore: &SiteRestore,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.site_restore_script else {
+            return Err(not_configured(
+                "SITE_RESTORE_SCRIPT",
+                "backups cannot be restored",
+                log,
+            ));
+        };
+        let mut args = vec![restore.archive_path.clone(), restore.folder_path.clone()];
+        match restore.wp_config {
+            Some(ref db) => {
+                args.extend([db.db_name.clone(), db.db_user.clone(), db.password.clone()])
+            }
+            None => args.extend([String::new(), String::new(), String::new()]),
+        }
+        match restore.moved_from {
+            Some(ref from) => args.extend([from.clone(), restore.domain.clone()]),
+            None => args.extend([String::new(), String::new()]),
+        }
+        for db in &restore.databases {
+            args.push(format!("{}:{}", db.from, db.into));
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        run_script(script, &args, log).await
+    }
+
+    /// Fails when the script is not configured, like [`Self::backup_site`].
+    async fn verify_backup(
+        &self,
+        check: &BackupVerification,
+        log: &LogSink,
+    ) -> Result<ProvisionOutput, ProvisionError> {
+        let Some(ref script) = self.site_verify_script else {
+            return Err(not_configured(
+                "SITE_VERIFY_SCRIPT",
+                "backups cannot be verified",
+                log,
+            ));
+        };
+        let wordpress = if check.wordpress { "1" } else { "0" };
+        let mut args = vec![
+            check.archive_path.as_str(),
+            check.scratch_database.as_str(),
+            wordpress,
+        ];
+        args.extend(check.databases.iter().map(String::as_str));
+        run_script(script, &args, log).await
+    }
+}
@@ -1,8 +1,16 @@
+use std::sync::Arc;
+
+use crate::caddy::CaddyAdmin;
 use crate::config::Config;
 use crate::db::DbPool;
+use crate::jobs::JobQueue;
+use crate::provision::Provisioner;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: DbPool,
     pub config: Config,
+    pub provisioner: Arc<dyn Provisioner>,
+    pub jobs: JobQueue,
+    pub caddy: CaddyAdmin,
 }
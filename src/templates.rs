@@ -1,6 +1,15 @@
 use askama::Template;
 
-use crate::models::{Site, SiteDatabase};
+use crate::api::TokenScope;
+use crate::audit::AuditQuery;
+use crate::auth::Role;
+use crate::backup::{FailedVerification, RemoteDomain};
+use crate::caddy::CertificateReport;
+use crate::health::{Incident, ResponseChart, Uptime};
+use crate::models::{
+    ApiToken, AuditEvent, Job, JobLogLine, LoginThrottle, Session, Site, SiteAlias, SiteBackup,
+    SiteCheck, SiteDatabase, User,
+};
 
 /// View type for dashboard table rows (status as String for template display).
 pub struct DashboardSiteRow {
@@ -17,11 +26,21 @@ pub struct DashboardSiteRow {
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct LoginPage {
+    /// Double-submit token, also set as the `panel_login_csrf` cookie.
+    pub csrf: String,
     pub username: String,
     pub errors: LoginErrors,
     pub error_message: String,
 }
 
+/// Second login step for users with two-factor authentication.
+#[derive(Template)]
+#[template(path = "login_totp.html")]
+pub struct LoginTotpPage {
+    pub challenge: String,
+    pub error_message: String,
+}
+
 #[derive(Default)]
 pub struct LoginErrors {
     pub username: String,
@@ -32,13 +51,19 @@ pub struct LoginErrors {
 #[template(path = "dashboard.html")]
 pub struct DashboardPage {
     pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
     pub sites: Vec<DashboardSiteRow>,
+    /// Sites whose newest test restore of a backup failed
+    pub failed_verifications: Vec<FailedVerification>,
 }
 
 #[derive(Template)]
 #[template(path = "add_site.html")]
 pub struct AddSitePage {
     pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
     pub domain: String,
     pub install_wordpress: bool,
     pub php_version: String,
@@ -49,7 +74,7 @@ pub struct AddSitePage {
     pub error_message: String,
 }
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct AddSiteErrors {
     pub domain: String,
     pub folder_path: String,
@@ -59,10 +84,34 @@ pub struct AddSiteErrors {
     pub wp_admin_email: String,
 }
 
+impl AddSiteErrors {
+    /// Field name and message for every field that has an error.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("domain", &self.domain),
+            ("folder_path", &self.folder_path),
+            ("wp_title", &self.wp_title),
+            ("wp_admin_user", &self.wp_admin_user),
+            ("wp_admin_password", &self.wp_admin_password),
+            ("wp_admin_email", &self.wp_admin_email),
+        ]
+        .into_iter()
+        .filter(|(_, msg)| !msg.is_empty())
+        .map(|(field, msg)| (field, msg.as_str()))
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields().is_empty()
+    }
+}
+
 #[derive(Template)]
 #[template(path = "add_database.html")]
 pub struct AddDatabasePage {
     pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
     pub sites: Vec<Site>,
     pub site_id: i32,
     pub database_name: String,
@@ -72,7 +121,7 @@ pub struct AddDatabasePage {
     pub error_message: String,
 }
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct AddDatabaseErrors {
     pub site_id: String,
     pub database_name: String,
@@ -80,22 +129,234 @@ pub struct AddDatabaseErrors {
     pub password: String,
 }
 
+impl AddDatabaseErrors {
+    /// Field name and message for every field that has an error.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("site_id", &self.site_id),
+            ("database_name", &self.database_name),
+            ("username", &self.username),
+            ("password", &self.password),
+        ]
+        .into_iter()
+        .filter(|(_, msg)| !msg.is_empty())
+        .map(|(field, msg)| (field, msg.as_str()))
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields().is_empty()
+    }
+}
+
 #[derive(Template)]
 #[template(path = "site_detail.html")]
 pub struct SiteDetailPage {
     pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
     pub site: Site,
     pub databases: Vec<SiteDatabase>,
-    pub ssl_status: String,
+    pub aliases: Vec<SiteAlias>,
+    /// Certificate found in Caddy's storage for the site's canonical host
+    pub ssl: CertificateReport,
     /// Server IP or hostname (for display)
     pub site_ip: String,
     /// System user that owns site files (e.g. www-data)
     pub site_user: String,
+    /// Caddy admin API error from a failed restart (empty if none)
+    pub restart_error: String,
+    /// Error from a certificate upload or switching back to ACME (empty if none)
+    pub tls_error: String,
+    /// Error from adding/removing an alias or changing the canonical host (empty if none)
+    pub domains_error: String,
+    /// Hostname typed into the add-alias form, kept when it is rejected
+    pub alias_hostname: String,
+    /// Most recent health check, if the site has been checked yet
+    pub last_check: Option<SiteCheck>,
+    /// Uptime over 24h, 7d and 30d
+    pub uptime: Vec<Uptime>,
+    pub incidents: Vec<Incident>,
+    pub response_chart: ResponseChart,
+    /// Default check interval in seconds (HEALTH_CHECK_INTERVAL; 0 = checker off)
+    pub default_check_interval: u64,
+    /// Error from saving the check settings (empty if none)
+    pub monitoring_error: String,
+    /// Newest first
+    pub backups: Vec<SiteBackup>,
+    /// Where bundles are written (PANEL_BACKUP_DIR)
+    pub backup_dir: String,
+    /// Bucket bundles are uploaded to, if off-site backups are configured
+    pub offsite: Option<String>,
+    /// Backups go into the chunk store (PANEL_BACKUP_DEDUP)
+    pub backup_dedup: bool,
+    /// Days between test restores of the newest backup (PANEL_BACKUP_VERIFY_DAYS; 0 = off)
+    pub backup_verify_days: u32,
+    /// Error from "Back up now" or saving the schedule (empty if none)
+    pub backup_error: String,
+    /// Tab to open on load (settings, domains, monitoring, database, backups, ssl, security; empty = settings or URL hash)
+    pub active_tab: String,
+}
+
+#[derive(Template)]
+#[template(path = "restore_backup.html")]
+pub struct RestoreBackupPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub backup: SiteBackup,
+    /// Domain recorded in the bundle's manifest
+    pub backup_domain: String,
+    /// Databases dumped into the bundle
+    pub backup_databases: Vec<String>,
+    /// Domain of the site the backup was taken from, if it still exists
+    pub site_domain: Option<String>,
+    /// in_place | new_site
+    pub target: String,
+    /// Domain typed for a new site, kept when it is rejected
+    pub domain: String,
+    pub error: String,
+}
+
+#[derive(Template)]
+#[template(path = "offsite_backups.html")]
+pub struct OffsiteBackupsPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    /// Bucket and prefix, or `None` when off-site backups are not configured
+    pub target: Option<String>,
+    /// Bundles in the bucket by domain
+    pub domains: Vec<RemoteDomain>,
+    /// Error from listing the bucket or queueing a download (empty if none)
+    pub error: String,
+}
+
+#[derive(Template)]
+#[template(path = "job_detail.html")]
+pub struct JobDetailPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub job: Job,
+    /// Stored transcript so far; the page streams the rest over SSE.
+    pub lines: Vec<JobLogLine>,
+}
+
+#[derive(Template)]
+#[template(path = "jobs.html")]
+pub struct JobsPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+pub struct AuditPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub events: Vec<AuditEvent>,
+    pub query: AuditQuery,
+    pub usernames: Vec<String>,
+    pub actions: Vec<String>,
+    pub target_types: Vec<String>,
+    pub error_message: String,
+    pub page: i64,
+    pub prev_href: String,
+    pub next_href: String,
+    pub csv_href: String,
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+pub struct UsersPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub users: Vec<User>,
+    /// The signed-in admin, who cannot demote, disable or delete themselves.
+    pub current_user_id: i32,
+    /// Usernames and client IPs locked out after too many failed logins.
+    pub lockouts: Vec<LoginThrottle>,
+    pub username: String,
+    pub new_role: String,
+    pub errors: AddUserErrors,
+    pub error_message: String,
+}
+
+#[derive(Default)]
+pub struct AddUserErrors {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+}
+
+impl AddUserErrors {
+    /// Field name and message for every field that has an error.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("username", &self.username),
+            ("password", &self.password),
+            ("role", &self.role),
+        ]
+        .into_iter()
+        .filter(|(_, msg)| !msg.is_empty())
+        .map(|(field, msg)| (field, msg.as_str()))
+        .collect()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "account.html")]
+pub struct AccountPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub username: String,
+    /// When 2FA was turned on; empty while it is off.
+    pub totp_enabled_at: String,
+    /// Enrollment in progress: the QR code (inline SVG) and the secret for manual entry.
+    pub qr_svg: String,
+    pub secret: String,
+    /// Freshly generated recovery codes, shown once.
+    pub recovery_codes: Vec<String>,
+    pub recovery_codes_left: i64,
+    pub error_message: String,
+}
+
+/// The signed-in user's active sessions.
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub sessions: Vec<Session>,
+    /// The session viewing the page.
+    pub current_session_id: i64,
+}
+
+#[derive(Template)]
+#[template(path = "api_tokens.html")]
+pub struct ApiTokensPage {
+    pub logged_in: bool,
+    pub role: Role,
+    pub csrf: String,
+    pub tokens: Vec<ApiToken>,
+    /// A token that was just created; shown once, never stored.
+    pub new_token: String,
+    pub name: String,
+    pub scope: String,
+    pub error_message: String,
 }
 
 impl LoginPage {
-    pub fn new(username: String, errors: LoginErrors, error_message: String) -> Self {
+    pub fn new(csrf: String, username: String, errors: LoginErrors, error_message: String) -> Self {
         Self {
+            csrf,
             username,
             errors,
             error_message,
@@ -108,8 +369,11 @@ impl LoginPage {
 }
 
 impl AddSitePage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         logged_in: bool,
+        role: Role,
+        csrf: String,
         domain: String,
         install_wordpress: bool,
         php_version: String,
@@ -121,6 +385,8 @@ impl AddSitePage {
     ) -> Self {
         Self {
             logged_in,
+            role,
+            csrf,
             domain,
             install_wordpress,
             php_version,
@@ -134,8 +400,11 @@ impl AddSitePage {
 }
 
 impl AddDatabasePage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         logged_in: bool,
+        role: Role,
+        csrf: String,
         sites: Vec<Site>,
         site_id: i32,
         database_name: String,
@@ -146,6 +415,8 @@ impl AddDatabasePage {
     ) -> Self {
         Self {
             logged_in,
+            role,
+            csrf,
             sites,
             site_id,
             database_name,
@@ -0,0 +1,93 @@
+//! A panel backed by a throwaway database and the dry-run provisioner, for
+//! tests that go through the job queue.
+//!
+//! `DATABASE_URL` names a server to create `panel_test_*` databases on. The
+//! tests that need one are `#[ignore]`d, so a plain `cargo test` lists them as
+//! skipped; `cargo test -- --include-ignored` runs them, and they fail without
+//! `DATABASE_URL`.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Connection};
+
+use crate::caddy::CaddyAdmin;
+use crate::config::{Config, ProvisionerKind};
+use crate::db;
+use crate::jobs::JobQueue;
+use crate::provision::DryRunProvisioner;
+use crate::state::AppState;
+
+pub struct TestPanel {
+    pub state: AppState,
+    pub provisioner: Arc<DryRunProvisioner>,
+    /// Id of the `admin` user the migrations create.
+    pub admin_id: i32,
+    server: PgConnectOptions,
+    database: String,
+}
+
+impl TestPanel {
+    /// A migrated database of its own.
+    pub async fn start() -> Self {
+        let url = std::env::var("DATABASE_URL")
+            .expect("DATABASE_URL names the PostgreSQL server to test against");
+        let server = PgConnectOptions::from_str(&url).expect("DATABASE_URL");
+        let database = format!("panel_test_{}", uuid::Uuid::new_v4().simple());
+        let mut conn = server.connect().await.expect("connect to DATABASE_URL");
+        sqlx::query(&format!("CREATE DATABASE \"{}\"", database))
+            .execute(&mut conn)
+            .await
+            .expect("create test database");
+        conn.close().await.ok();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(server.clone().database(&database))
+            .await
+            .expect("connect to test database");
+        db::run_migrations(&pool).await.expect("migrations");
+        let admin_id = sqlx::query_scalar("SELECT id FROM users WHERE username = 'admin'")
+            .fetch_one(&pool)
+            .await
+            .expect("seeded admin user");
+
+        let mut config = Config::from_env();
+        config.provisioner = ProvisionerKind::DryRun;
+        config.backup_dir = std::env::temp_dir().join(&database);
+        config.s3 = None;
+        config.alert_webhook = None;
+        let provisioner = Arc::new(DryRunProvisioner::new());
+        let state = AppState {
+            pool,
+            provisioner: provisioner.clone(),
+            jobs: JobQueue::new(),
+            caddy: CaddyAdmin::from_config(&config),
+            config,
+        };
+        TestPanel {
+            state,
+            provisioner,
+            admin_id,
+            server,
+            database,
+        }
+    }
+
+    /// Drop the database. A test that panics first leaves it behind.
+    pub async fn stop(self) {
+        self.state.pool.close().await;
+        let _ = tokio::fs::remove_dir_all(&self.state.config.backup_dir).await;
+        let mut conn = self
+            .server
+            .connect()
+            .await
+            .expect("connect to DATABASE_URL");
+        sqlx::query(&format!("DROP DATABASE \"{}\" WITH (FORCE)", self.database))
+            .execute(&mut conn)
+            .await
+            .expect("drop test database");
+        conn.close().await.ok();
+    }
+}
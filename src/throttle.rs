@@ -0,0 +1,336 @@
+//! Login throttling: failed attempts are counted per username and per client
+//! IP. Each failure adds a growing delay before the next attempt is checked,
+//! and reaching the limit locks the username or IP for a while. Usernames
+//! that do not exist are counted the same way, so a lockout says nothing about
+//! which accounts are real.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::Result;
+use crate::models::{LoginThrottle, LOGIN_THROTTLE_COLUMNS};
+
+const KIND_USERNAME: &str = "username";
+const KIND_IP: &str = "ip";
+/// Delay after the first failure; doubles with every further one.
+const BASE_DELAY_MS: u64 = 250;
+const MAX_DELAY_MS: u64 = 8_000;
+
+/// What to do with a login attempt before looking at the password.
+pub struct Attempt {
+    pub locked: bool,
+    pub delay: Duration,
+}
+
+#[derive(Clone)]
+pub struct LoginThrottler {
+    max_failures: i32,
+    max_failures_per_ip: i32,
+    lockout_minutes: i64,
+}
+
+impl LoginThrottler {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_failures: config.login_max_failures,
+            max_failures_per_ip: config.login_max_failures_per_ip,
+            lockout_minutes: config.login_lockout_minutes,
+        }
+    }
+
+    /// Count an attempt as failed before its password is checked, so parallel
+    /// attempts cannot all get in before the first failure is recorded, and
+    /// say whether the username or IP is locked and how long to wait. A
+    /// locked attempt is not counted. Failures older than the lockout period
+    /// are forgotten. An attempt that turns out right is taken back with
+    /// [`release`](Self::release) or [`record_success`](Self::record_success).
+    pub async fn reserve(&self, pool: &DbPool, username: &str, ip: IpAddr) -> Result<Attempt> {
+        sqlx::query(
+            "DELETE FROM login_throttles WHERE last_failure_at <= NOW() - ($1 || ' minutes')::interval \
+             AND (locked_until IS NULL OR locked_until <= NOW())",
+        )
+        .bind(self.lockout_minutes)
+        .execute(pool)
+        .await?;
+        let mut tx = pool.begin().await?;
+        let counters = [
+            (KIND_USERNAME, username_key(username), self.max_failures),
+            (KIND_IP, ip.to_string(), self.max_failures_per_ip),
+        ];
+        // Holding both rows until commit puts parallel attempts in a row; a
+        // first attempt creates them to have something to hold.
+        let mut before = 0;
+        let mut locked = false;
+        for (kind, key, _) in &counters {
+            sqlx::query(
+                "INSERT INTO login_throttles (kind, key, failures) VALUES ($1, $2, 0) \
+                 ON CONFLICT (kind, key) DO NOTHING",
+            )
+            .bind(kind)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+            let (failures, held) = sqlx::query_as::<_, (i32, bool)>(
+                "SELECT CASE WHEN last_failure_at > NOW() - ($3 || ' minutes')::interval THEN failures ELSE 0 END, \
+                   COALESCE(locked_until > NOW(), FALSE) \
+                 FROM login_throttles WHERE kind = $1 AND key = $2 FOR UPDATE",
+            )
+            .bind(kind)
+            .bind(key)
+            .bind(self.lockout_minutes)
+            .fetch_one(&mut *tx)
+            .await?;
+            before = before.max(failures);
+            locked |= held;
+        }
+        if locked {
+            tx.rollback().await?;
+            return Ok(Attempt {
+                locked,
+                delay: delay(before),
+            });
+        }
+        for (kind, key, max) in &counters {
+            let failures = sqlx::query_scalar::<_, i32>(
+                "UPDATE login_throttles SET \
+                   failures = CASE WHEN last_failure_at > NOW() - ($3 || ' minutes')::interval \
+                                   THEN failures + 1 ELSE 1 END, \
+                   last_failure_at = NOW() \
+                 WHERE kind = $1 AND key = $2 RETURNING failures",
+            )
+            .bind(kind)
+            .bind(key)
+            .bind(self.lockout_minutes)
+            .fetch_one(&mut *tx)
+            .await?;
+            if failures >= *max {
+                sqlx::query(
+                    "UPDATE login_throttles SET locked_until = NOW() + ($3 || ' minutes')::interval \
+                     WHERE kind = $1 AND key = $2",
+                )
+                .bind(kind)
+                .bind(key)
+                .bind(self.lockout_minutes)
+                .execute(&mut *tx)
+                .await?;
+                tracing::warn!(
+                    "login: {} {} locked after {} failed attempts",
+                    kind,
+                    key,
+                    max
+                );
+            }
+        }
+        tx.commit().await?;
+        Ok(Attempt {
+            locked,
+            delay: delay(before),
+        })
+    }
+
+    /// Take back the attempt [`reserve`](Self::reserve) counted for a right
+    /// password, on the username and the IP, and lift a lock it set.
+    pub async fn release(&self, pool: &DbPool, username: &str, ip: IpAddr) -> Result<()> {
+        self.release_one(
+            pool,
+            KIND_USERNAME,
+            &username_key(username),
+            self.max_failures,
+        )
+        .await?;
+        self.release_one(pool, KIND_IP, &ip.to_string(), self.max_failures_per_ip)
+            .await
+    }
+
+    async fn release_one(&self, pool: &DbPool, kind: &str, key: &str, max: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE login_throttles SET failures = failures - 1, \
+               locked_until = CASE WHEN failures - 1 < $3 THEN NULL ELSE locked_until END \
+             WHERE kind = $1 AND key = $2 AND failures > 0",
+        )
+        .bind(kind)
+        .bind(key)
+        .bind(max)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// A successful login clears the username's failures and takes back the
+    /// attempt counted against the IP, which keeps its earlier failures.
+    pub async fn record_success(&self, pool: &DbPool, username: &str, ip: IpAddr) -> Result<()> {
+        unlock(pool, KIND_USERNAME, &username_key(username)).await?;
+        self.release_one(pool, KIND_IP, &ip.to_string(), self.max_failures_per_ip)
+            .await
+    }
+}
+
+/// How long to wait before checking the password after `failures` failures.
+fn delay(failures: i32) -> Duration {
+    let ms = match failures {
+        n if n <= 0 => 0,
+        n => (BASE_DELAY_MS << (n - 1).min(16)).min(MAX_DELAY_MS),
+    };
+    Duration::from_millis(ms)
+}
+
+fn username_key(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+/// Usernames and IPs currently locked out.
+pub async fn active_lockouts(pool: &DbPool) -> Result<Vec<LoginThrottle>> {
+    let rows = sqlx::query_as::<_, LoginThrottle>(&format!(
+        "SELECT {} FROM login_throttles WHERE locked_until > NOW() ORDER BY locked_until DESC",
+        LOGIN_THROTTLE_COLUMNS
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn unlock(pool: &DbPool, kind: &str, key: &str) -> Result<()> {
+    sqlx::query("DELETE FROM login_throttles WHERE kind = $1 AND key = $2")
+        .bind(kind)
+        .bind(key)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestPanel;
+
+    const THROTTLER: LoginThrottler = LoginThrottler {
+        max_failures: 3,
+        max_failures_per_ip: 5,
+        lockout_minutes: 15,
+    };
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    /// (locked, delay in ms) of the next attempt.
+    async fn attempt(pool: &DbPool, username: &str, ip: IpAddr) -> (bool, u128) {
+        let attempt = THROTTLER.reserve(pool, username, ip).await.unwrap();
+        (attempt.locked, attempt.delay.as_millis())
+    }
+
+    async fn failures(pool: &DbPool, kind: &str, key: &str) -> Option<i32> {
+        sqlx::query_scalar("SELECT failures FROM login_throttles WHERE kind = $1 AND key = $2")
+            .bind(kind)
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn delay_curve() {
+        let cases = [
+            (0, 0),
+            (-1, 0),
+            (1, 250),
+            (2, 500),
+            (3, 1000),
+            (5, 4000),
+            (6, 8000),
+        ];
+        for (failures, ms) in cases {
+            assert_eq!(delay(failures), Duration::from_millis(ms), "{}", failures);
+        }
+        // Capped, without overflowing the shift.
+        assert_eq!(delay(7), Duration::from_millis(MAX_DELAY_MS));
+        assert_eq!(delay(i32::MAX), Duration::from_millis(MAX_DELAY_MS));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn lockout_and_expiry() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        // The third failure locks the username; its own attempt still gets checked.
+        assert_eq!(attempt(pool, "Alice", ip(1)).await, (false, 0));
+        assert_eq!(attempt(pool, "alice", ip(1)).await, (false, 250));
+        assert_eq!(attempt(pool, " alice", ip(1)).await, (false, 500));
+        assert_eq!(attempt(pool, "alice", ip(2)).await, (true, 1000));
+        // Locked attempts are not counted.
+        assert_eq!(failures(pool, KIND_USERNAME, "alice").await, Some(3));
+        assert_eq!(failures(pool, KIND_IP, "192.0.2.2").await, None);
+        assert_eq!(active_lockouts(pool).await.unwrap().len(), 1);
+
+        // Other usernames from the same IP until the IP reaches its limit.
+        assert_eq!(attempt(pool, "bob", ip(1)).await, (false, 1000));
+        assert_eq!(attempt(pool, "carol", ip(1)).await, (false, 2000));
+        assert_eq!(attempt(pool, "dave", ip(1)).await, (true, 4000));
+
+        // Once the lock is over, one more attempt gets checked, and a
+        // failure locks again.
+        sqlx::query("UPDATE login_throttles SET locked_until = NOW() - interval '1 second'")
+            .execute(pool)
+            .await
+            .unwrap();
+        assert_eq!(attempt(pool, "alice", ip(3)).await, (false, 1000));
+        assert_eq!(attempt(pool, "alice", ip(3)).await, (true, 2000));
+
+        // Failures older than the lockout period are forgotten.
+        sqlx::query(
+            "UPDATE login_throttles SET locked_until = NULL, \
+             last_failure_at = NOW() - interval '16 minutes'",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        assert_eq!(attempt(pool, "alice", ip(1)).await, (false, 0));
+        assert_eq!(failures(pool, KIND_USERNAME, "alice").await, Some(1));
+        panel.stop().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn success_takes_the_attempt_back() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        attempt(pool, "alice", ip(1)).await;
+        attempt(pool, "alice", ip(1)).await;
+        // A right password on the attempt that reached the limit lifts the
+        // lock it set.
+        assert_eq!(attempt(pool, "alice", ip(1)).await, (false, 500));
+        THROTTLER.release(pool, "alice", ip(1)).await.unwrap();
+        assert_eq!(failures(pool, KIND_USERNAME, "alice").await, Some(2));
+        assert!(active_lockouts(pool).await.unwrap().is_empty());
+
+        // Signing in clears the username; the IP keeps its earlier failures.
+        assert_eq!(attempt(pool, "alice", ip(1)).await, (false, 500));
+        THROTTLER
+            .record_success(pool, "alice", ip(1))
+            .await
+            .unwrap();
+        assert_eq!(failures(pool, KIND_USERNAME, "alice").await, None);
+        assert_eq!(failures(pool, KIND_IP, "192.0.2.1").await, Some(2));
+        assert_eq!(attempt(pool, "alice", ip(1)).await, (false, 500));
+        panel.stop().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in DATABASE_URL"]
+    async fn parallel_attempts_are_all_counted() {
+        let panel = TestPanel::start().await;
+        let pool = &panel.state.pool;
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let pool = pool.clone();
+            tasks.spawn(async move { attempt(&pool, "alice", ip(i)).await });
+        }
+        let results = tasks.join_all().await;
+        let checked = results.iter().filter(|(locked, _)| !locked).count();
+        assert_eq!(checked, THROTTLER.max_failures as usize);
+        assert_eq!(failures(pool, KIND_USERNAME, "alice").await, Some(3));
+        panel.stop().await;
+    }
+}
@@ -0,0 +1,241 @@
+//! TOTP (RFC 6238) for panel logins: HMAC-SHA1, 6 digits, 30-second steps,
+//! which is what Google Authenticator, Aegis, 1Password etc. expect.
+
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+pub const ISSUER: &str = "FrankenPHP Panel";
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Steps accepted either side of the current one, for clock drift.
+const SKEW_STEPS: i64 = 1;
+const SECRET_BYTES: usize = 20;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut buf)
+        .expect("system random number generator failed");
+    buf
+}
+
+/// A new random shared secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    BASE32_NOPAD.encode(&random_bytes::<SECRET_BYTES>())
+}
+
+/// The code for time step `step` (HOTP with the step as counter).
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let tag = hmac::sign(&key, &(step as u64).to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// Check `code` against `secret` at unix time `now`. Returns the matching time
+/// step, which must be stored and passed back as `last_step` so a code cannot
+/// be used twice.
+pub fn verify(secret: &str, code: &str, now: i64, last_step: Option<i64>) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current = now / STEP_SECS;
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(&key, *step) == code)
+}
+
+/// `otpauth://` URI encoded in the enrollment QR code.
+pub fn provisioning_uri(account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(ISSUER),
+        encode(account),
+        secret,
+        encode(ISSUER),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters.
+fn encode(value: &str) -> String {
+    let mut out = String::new();
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// The provisioning URI as an inline SVG QR code.
+pub fn qr_svg(uri: &str) -> String {
+    match QrCode::new(uri.as_bytes()) {
+        Ok(code) => code
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .quiet_zone(true)
+            .build(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Show a base32 secret in groups of four so it can be typed in by hand.
+pub fn secret_display(secret: &str) -> String {
+    secret
+        .as_bytes()
+        .chunks(4)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Fresh one-time recovery codes like `k3v9q-7xw2m`. Only their hashes are stored.
+pub fn generate_recovery_codes() -> Vec<String> {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = random_bytes::<10>()
+                .iter()
+                .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough that a plain SHA-256 is a safe way to store them.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    HEXLOWER.encode(ring::digest::digest(&ring::digest::SHA256, normalized.as_bytes()).as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 6238 appendix B SHA-1 secret, "12345678901234567890", in base32.
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc6238_vectors() {
+        // (time, 8-digit code from the RFC); we show its last six digits.
+        let cases = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for (time, code) in cases {
+            let step = time / STEP_SECS;
+            assert_eq!(
+                code_at(b"12345678901234567890", step),
+                code % 1_000_000,
+                "{}",
+                time
+            );
+            let code = format!("{:06}", code % 1_000_000);
+            assert_eq!(
+                verify(RFC_SECRET, &code, time, None),
+                Some(step),
+                "{}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn skew_window() {
+        // The code for step 1000, entered at times around it.
+        let code = format!("{:06}", code_at(b"12345678901234567890", 1000));
+        let at = |step: i64| verify(RFC_SECRET, &code, step * STEP_SECS + 7, None);
+        assert_eq!(at(999), Some(1000));
+        assert_eq!(at(1000), Some(1000));
+        assert_eq!(at(1001), Some(1000));
+        assert_eq!(at(998), None);
+        assert_eq!(at(1002), None);
+    }
+
+    #[test]
+    fn replay_rejected() {
+        let code = |step: i64| format!("{:06}", code_at(b"12345678901234567890", step));
+        let now = 1000 * STEP_SECS;
+        assert_eq!(verify(RFC_SECRET, &code(1000), now, Some(999)), Some(1000));
+        // The same code again, or an older one still inside the window.
+        assert_eq!(verify(RFC_SECRET, &code(1000), now, Some(1000)), None);
+        assert_eq!(verify(RFC_SECRET, &code(999), now, Some(1000)), None);
+        // The next step's code is still good.
+        assert_eq!(verify(RFC_SECRET, &code(1001), now, Some(1000)), Some(1001));
+    }
+
+    #[test]
+    fn code_format() {
+        assert_eq!(verify(RFC_SECRET, " 287 082 ", 59, None), Some(1));
+        for code in ["28708", "2870822", "28708a", "", "-87082"] {
+            assert_eq!(verify(RFC_SECRET, code, 59, None), None, "{:?}", code);
+        }
+        assert_eq!(verify("not base32!", "287082", 59, None), None);
+    }
+
+    #[test]
+    fn uri_and_display() {
+        assert_eq!(
+            provisioning_uri("alice@example.com", RFC_SECRET),
+            "otpauth://totp/FrankenPHP%20Panel:alice%40example.com\
+             ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=FrankenPHP%20Panel\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+        assert_eq!(encode("a-b.c_d~e/f?g é"), "a-b.c_d~e%2Ff%3Fg%20%C3%A9");
+        assert_eq!(secret_display("ABCDEFGHIJ"), "ABCD EFGH IJ");
+        let secret = generate_secret();
+        assert_eq!(
+            BASE32_NOPAD.decode(secret.as_bytes()).unwrap().len(),
+            SECRET_BYTES
+        );
+    }
+
+    #[test]
+    fn recovery_codes() {
+        // sha256("k3v9q7xw2m"), by hashlib; case, dashes and spaces do not count.
+        let hash = "92422f08e4aedd0823fd5215567ece3407ce5ee30d9e1e564588a064dcd369ee";
+        for code in ["k3v9q-7xw2m", "K3V9Q-7XW2M", " k3v9q 7xw2m ", "k3v9q7xw2m"] {
+            assert_eq!(hash_recovery_code(code), hash, "{:?}", code);
+        }
+        assert_ne!(hash_recovery_code("k3v9q-7xw2n"), hash);
+
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            let (a, b) = code.split_once('-').unwrap();
+            assert_eq!((a.len(), b.len()), (5, 5), "{}", code);
+            assert!(code
+                .chars()
+                .all(|c| c == '-' || "abcdefghjkmnpqrstuvwxyz23456789".contains(c)));
+        }
+        let unique: std::collections::HashSet<&String> = codes.iter().collect();
+        assert_eq!(unique.len(), codes.len());
+    }
+}
@@ -35,7 +35,25 @@
   if (query.deleted === '1') showToast('Site deleted.', 'success');
   if (query.db_created === '1') showToast('Database created successfully.', 'success');
   if (query.db_deleted === '1') showToast('Database deleted.', 'success');
-  if (query.restarted === '1') showToast('Site restart requested.', 'success');
+  if (query.restarted === '1') showToast('Site configuration reloaded.', 'success');
+  if (query.tls === 'custom') showToast('Custom certificate installed.', 'success');
+  if (query.tls === 'acme') showToast('Switched back to automatic (ACME) certificates.', 'success');
+  if (query.restarted === 'workers') showToast('Site configuration reloaded and PHP workers restarted.', 'success');
+  if (query.alias === 'added') showToast('Alias added.', 'success');
+  if (query.alias === 'removed') showToast('Alias removed.', 'success');
+  if (query.canonical === '1') showToast('Canonical host updated.', 'success');
+  if (query.monitoring === '1') showToast('Check settings saved.', 'success');
+  if (query.backup_schedule === '1') showToast('Backup schedule saved.', 'success');
+  if (query.backup_deleted === '1') showToast('Backup deleted.', 'success');
+  if (query.user === 'created') showToast('User created.', 'success');
+  if (query.user === 'updated') showToast('Role updated.', 'success');
+  if (query.user === 'disabled') showToast('User disabled and signed out.', 'success');
+  if (query.user === 'enabled') showToast('User enabled.', 'success');
+  if (query.user === 'deleted') showToast('User deleted.', 'success');
+  if (query.unlocked === '1') showToast('Lockout lifted.', 'success');
+  if (query.totp === 'disabled') showToast('Two-factor authentication turned off.', 'success');
+  if (query.session === 'revoked') showToast('Session signed out.', 'success');
+  if (query.token === 'revoked') showToast('API token revoked.', 'success');
 
   var loginForm = document.getElementById('login-form');
   if (loginForm) {
@@ -104,6 +122,11 @@
       var form = document.createElement('form');
       form.method = 'POST';
       form.action = '/sites/' + id + '/delete';
+      var csrf = document.createElement('input');
+      csrf.type = 'hidden';
+      csrf.name = 'csrf';
+      csrf.value = btn.getAttribute('data-csrf') || '';
+      form.appendChild(csrf);
       document.body.appendChild(form);
       form.submit();
     });